@@ -0,0 +1,293 @@
+//! Time-based overlay animations.
+//!
+//! Everything here is a pure function of the `now` passed in, so the values do
+//! not depend on how often frames are actually drawn. The Windows side only has
+//! to tick a frame timer and redraw while [`OverlayAnimations::needs_frames`] is true.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dualsense::BatteryStatus,
+    overlay_layout::{ControllerRow, RowAnimation},
+};
+
+pub const FILL_TRANSITION_DURATION: Duration = Duration::from_millis(600);
+pub const CHARGING_SWEEP_PERIOD: Duration = Duration::from_millis(1600);
+
+/// Fast start, gentle landing.
+pub fn ease_out_cubic(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    1.0 - (1.0 - t).powi(3)
+}
+
+/// Position in a repeating cycle of length `period`, as a fraction in `0.0..1.0`.
+pub fn cycle_phase(elapsed: Duration, period: Duration) -> f32 {
+    if period.is_zero() {
+        return 0.0;
+    }
+    (elapsed.as_secs_f64() % period.as_secs_f64() / period.as_secs_f64()) as f32
+}
+
+/// An eased move from one value to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition {
+    from: f32,
+    to: f32,
+    started: Instant,
+    duration: Duration,
+}
+
+impl Transition {
+    pub fn new(from: f32, to: f32, started: Instant, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            started,
+            duration,
+        }
+    }
+
+    /// A transition that is already at `value`.
+    pub fn settled(value: f32, now: Instant) -> Self {
+        Self::new(value, value, now, Duration::ZERO)
+    }
+
+    pub fn progress(&self, now: Instant) -> f32 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        let elapsed = now.saturating_duration_since(self.started);
+        (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+
+    pub fn value(&self, now: Instant) -> f32 {
+        self.from + (self.to - self.from) * ease_out_cubic(self.progress(now))
+    }
+
+    pub fn target(&self) -> f32 {
+        self.to
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        self.progress(now) >= 1.0
+    }
+}
+
+/// Per-controller fill transitions plus the shared charging sweep clock.
+pub struct OverlayAnimations {
+    fills: HashMap<String, Transition>,
+    epoch: Instant,
+}
+
+impl OverlayAnimations {
+    pub fn new(epoch: Instant) -> Self {
+        Self {
+            fills: HashMap::new(),
+            epoch,
+        }
+    }
+
+    /// Eases the fill from whatever is on screen right now to `level`. Controllers
+    /// seen for the first time fill up from empty.
+    pub fn animate_fill(&mut self, path: &str, level: f32, now: Instant) {
+        if self
+            .fills
+            .get(path)
+            .is_some_and(|fill| fill.target() == level)
+        {
+            return;
+        }
+        let from = self.fill_level(path, now).unwrap_or(0.0);
+        self.fills.insert(
+            path.to_string(),
+            Transition::new(from, level, now, FILL_TRANSITION_DURATION),
+        );
+    }
+
+    /// Jumps straight to `level`, for updates that arrive while nothing is shown.
+    pub fn set_fill(&mut self, path: &str, level: f32, now: Instant) {
+        self.fills
+            .insert(path.to_string(), Transition::settled(level, now));
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.fills.remove(path);
+    }
+
+    pub fn fill_level(&self, path: &str, now: Instant) -> Option<f32> {
+        self.fills.get(path).map(|fill| fill.value(now))
+    }
+
+    pub fn is_transitioning(&self, now: Instant) -> bool {
+        self.fills.values().any(|fill| !fill.is_finished(now))
+    }
+
+    pub fn charging_phase(&self, now: Instant) -> f32 {
+        cycle_phase(
+            now.saturating_duration_since(self.epoch),
+            CHARGING_SWEEP_PERIOD,
+        )
+    }
+
+    /// What to animate on `row`, which belongs to the controller at `path`.
+    pub fn row_animation(&self, path: &str, row: &ControllerRow, now: Instant) -> RowAnimation {
+        let charging = row.connected
+            && row
+                .report
+                .as_ref()
+                .is_some_and(|report| report.battery_status == BatteryStatus::Charging);
+        RowAnimation {
+            fill_level: self.fill_level(path, now),
+            charging_phase: charging.then(|| self.charging_phase(now)),
+        }
+    }
+
+    /// Whether another frame is needed to keep things moving.
+    pub fn needs_frames(&self, any_charging: bool, now: Instant) -> bool {
+        any_charging || self.is_transitioning(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn ease_out_cubic_endpoints_and_clamping() {
+        assert_eq!(ease_out_cubic(0.0), 0.0);
+        assert_eq!(ease_out_cubic(1.0), 1.0);
+        assert!(close(ease_out_cubic(0.5), 0.875));
+        assert_eq!(ease_out_cubic(-1.0), 0.0);
+        assert_eq!(ease_out_cubic(2.0), 1.0);
+    }
+
+    #[test]
+    fn ease_out_cubic_is_monotonic() {
+        let values: Vec<f32> = (0..=100)
+            .map(|i| ease_out_cubic(i as f32 / 100.0))
+            .collect();
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn cycle_phase_wraps() {
+        let period = Duration::from_millis(1000);
+        assert_eq!(cycle_phase(Duration::ZERO, period), 0.0);
+        assert!(close(cycle_phase(Duration::from_millis(500), period), 0.5));
+        assert_eq!(cycle_phase(period, period), 0.0);
+        assert!(close(
+            cycle_phase(Duration::from_millis(2250), period),
+            0.25
+        ));
+        assert_eq!(cycle_phase(Duration::from_secs(3), Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn transition_endpoints() {
+        let start = Instant::now();
+        let duration = Duration::from_millis(600);
+        let transition = Transition::new(20.0, 80.0, start, duration);
+        assert_eq!(transition.value(start), 20.0);
+        assert!(!transition.is_finished(start));
+        assert_eq!(transition.value(start + duration), 80.0);
+        assert!(transition.is_finished(start + duration));
+        assert_eq!(transition.target(), 80.0);
+    }
+
+    #[test]
+    fn transition_clamps_past_its_duration() {
+        let start = Instant::now();
+        let transition = Transition::new(20.0, 80.0, start, Duration::from_millis(600));
+        let later = start + Duration::from_secs(10);
+        assert_eq!(transition.progress(later), 1.0);
+        assert_eq!(transition.value(later), 80.0);
+        // A `now` from before the start, as a stale frame might pass.
+        let earlier = Instant::now();
+        let transition = Transition::new(
+            20.0,
+            80.0,
+            earlier + Duration::from_secs(1),
+            Duration::from_millis(600),
+        );
+        assert_eq!(transition.value(earlier), 20.0);
+    }
+
+    #[test]
+    fn transition_moves_monotonically() {
+        let start = Instant::now();
+        let transition = Transition::new(80.0, 20.0, start, Duration::from_millis(600));
+        let values: Vec<f32> = (0..=60)
+            .map(|i| transition.value(start + Duration::from_millis(i * 10)))
+            .collect();
+        assert!(values.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn settled_transition_is_finished() {
+        let now = Instant::now();
+        let transition = Transition::settled(42.0, now);
+        assert!(transition.is_finished(now));
+        assert_eq!(transition.value(now), 42.0);
+    }
+
+    #[test]
+    fn new_controller_fills_from_empty() {
+        let now = Instant::now();
+        let mut animations = OverlayAnimations::new(now);
+        animations.animate_fill("pad", 60.0, now);
+        assert_eq!(animations.fill_level("pad", now), Some(0.0));
+        assert!(animations.is_transitioning(now));
+        let done = now + FILL_TRANSITION_DURATION;
+        assert_eq!(animations.fill_level("pad", done), Some(60.0));
+        assert!(!animations.is_transitioning(done));
+    }
+
+    #[test]
+    fn retargeting_starts_from_the_value_on_screen() {
+        let now = Instant::now();
+        let mut animations = OverlayAnimations::new(now);
+        animations.set_fill("pad", 20.0, now);
+        animations.animate_fill("pad", 80.0, now);
+
+        let midway = now + FILL_TRANSITION_DURATION / 2;
+        let on_screen = animations.fill_level("pad", midway).unwrap();
+        assert!(on_screen > 20.0 && on_screen < 80.0);
+        animations.animate_fill("pad", 40.0, midway);
+        assert!(close(
+            animations.fill_level("pad", midway).unwrap(),
+            on_screen
+        ));
+
+        let done = midway + FILL_TRANSITION_DURATION;
+        assert_eq!(animations.fill_level("pad", done), Some(40.0));
+    }
+
+    #[test]
+    fn same_target_keeps_the_running_transition() {
+        let now = Instant::now();
+        let mut animations = OverlayAnimations::new(now);
+        animations.animate_fill("pad", 80.0, now);
+        let midway = now + FILL_TRANSITION_DURATION / 2;
+        animations.animate_fill("pad", 80.0, midway);
+        assert!(!animations.is_transitioning(now + FILL_TRANSITION_DURATION));
+    }
+
+    #[test]
+    fn needs_frames_while_charging_or_moving() {
+        let now = Instant::now();
+        let mut animations = OverlayAnimations::new(now);
+        assert!(!animations.needs_frames(false, now));
+        assert!(animations.needs_frames(true, now));
+        animations.animate_fill("pad", 50.0, now);
+        assert!(animations.needs_frames(false, now));
+        animations.remove("pad");
+        assert!(!animations.needs_frames(false, now));
+    }
+}
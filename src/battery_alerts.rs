@@ -0,0 +1,368 @@
+//! Decides when a controller's battery is low enough to warn about.
+//!
+//! Readings come in 10% steps and often bounce between neighbouring steps, so
+//! an alert only re-arms once the level has climbed more than `hysteresis`
+//! points above the threshold that fired it, or the pad reports full.
+//!
+//! Charging gets its own tracker: one alert when the level reaches the charge
+//! ceiling (to unplug before the battery sits at 100%), another when the status
+//! turns to full.
+
+use crate::dualsense::{BatteryReport, BatteryStatus};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlertThresholds {
+    /// Warn at or below this percentage.
+    pub low: u8,
+    /// Warn again, more urgently, at or below this percentage.
+    pub critical: u8,
+    /// The level must climb more than this far above a threshold before it
+    /// can fire again.
+    pub hysteresis: u8,
+    /// Say "unplug now" once a charging pad reaches this percentage.
+    pub charge_ceiling: Option<u8>,
+    /// Say so when the status turns to full.
+    pub notify_when_full: bool,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        Self {
+            low: 20,
+            critical: 10,
+            hysteresis: 10,
+            charge_ceiling: Some(80),
+            notify_when_full: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertLevel {
+    #[default]
+    Normal,
+    Low,
+    Critical,
+}
+
+/// Alert state for one controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatteryAlertTracker {
+    level: AlertLevel,
+}
+
+impl BatteryAlertTracker {
+    /// Feeds in a new reading. Returns the level just escalated to, if this
+    /// reading should raise an alert.
+    pub fn update(
+        &mut self,
+        report: &BatteryReport,
+        thresholds: &AlertThresholds,
+    ) -> Option<AlertLevel> {
+        let capacity = report.battery_capacity;
+
+        // Recover one step at a time, each past its own threshold plus the
+        // margin. With 10% steps and a 10 point margin, bouncing between the
+        // threshold and the step above it doesn't count.
+        if self.level == AlertLevel::Critical
+            && capacity > thresholds.critical.saturating_add(thresholds.hysteresis)
+        {
+            self.level = AlertLevel::Low;
+        }
+        if self.level == AlertLevel::Low
+            && capacity > thresholds.low.saturating_add(thresholds.hysteresis)
+        {
+            self.level = AlertLevel::Normal;
+        }
+        if report.battery_status == BatteryStatus::Full {
+            self.level = AlertLevel::Normal;
+        }
+
+        // Only a draining battery is worth a warning; charging or error states
+        // have their own visuals.
+        if report.battery_status != BatteryStatus::Discharging {
+            return None;
+        }
+        let reached = if capacity <= thresholds.critical {
+            AlertLevel::Critical
+        } else if capacity <= thresholds.low {
+            AlertLevel::Low
+        } else {
+            AlertLevel::Normal
+        };
+        if reached > self.level {
+            self.level = reached;
+            Some(reached)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeAlert {
+    CeilingReached,
+    FullyCharged,
+}
+
+/// Charging alert state for one controller.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChargeAlertTracker {
+    previous_status: Option<BatteryStatus>,
+    ceiling_alerted: bool,
+}
+
+impl ChargeAlertTracker {
+    /// Feeds in a new reading. Returns the alert this reading should raise, if any.
+    pub fn update(
+        &mut self,
+        report: &BatteryReport,
+        thresholds: &AlertThresholds,
+    ) -> Option<ChargeAlert> {
+        let previous = self.previous_status.replace(report.battery_status.clone());
+
+        match report.battery_status {
+            // Only a change to full counts; a pad that connects already full
+            // has nothing new to say.
+            BatteryStatus::Full => {
+                let became_full = previous.is_some_and(|status| status != BatteryStatus::Full);
+                (thresholds.notify_when_full && became_full).then_some(ChargeAlert::FullyCharged)
+            }
+            BatteryStatus::Charging => {
+                let ceiling = thresholds.charge_ceiling?;
+                if self.ceiling_alerted || report.battery_capacity < ceiling {
+                    return None;
+                }
+                self.ceiling_alerted = true;
+                Some(ChargeAlert::CeilingReached)
+            }
+            // Unplugged: the next charge may alert again.
+            _ => {
+                self.ceiling_alerted = false;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `readings` through a fresh tracker with the default thresholds
+    /// and returns what each one raised.
+    fn battery_alerts(readings: &[(u8, BatteryStatus)]) -> Vec<Option<AlertLevel>> {
+        let thresholds = AlertThresholds::default();
+        let mut tracker = BatteryAlertTracker::default();
+        readings
+            .iter()
+            .map(|(capacity, status)| {
+                tracker.update(&BatteryReport::new(*capacity, status.clone()), &thresholds)
+            })
+            .collect()
+    }
+
+    fn discharging(readings: &[u8]) -> Vec<(u8, BatteryStatus)> {
+        readings
+            .iter()
+            .map(|capacity| (*capacity, BatteryStatus::Discharging))
+            .collect()
+    }
+
+    #[test]
+    fn flapping_around_the_threshold_fires_once() {
+        use AlertLevel::*;
+        for (readings, expected) in [
+            (
+                discharging(&[30, 20, 30, 20, 30, 20]),
+                vec![None, Some(Low), None, None, None, None],
+            ),
+            // Climbing past the margin re-arms.
+            (
+                discharging(&[20, 30, 40, 30, 20]),
+                vec![Some(Low), None, None, None, Some(Low)],
+            ),
+            (
+                discharging(&[20, 10, 20, 10, 20, 10]),
+                vec![Some(Low), Some(Critical), None, None, None, None],
+            ),
+        ] {
+            assert_eq!(battery_alerts(&readings), expected, "{:?}", readings);
+        }
+    }
+
+    #[test]
+    fn recovers_from_critical_one_step_at_a_time() {
+        use AlertLevel::*;
+        assert_eq!(
+            battery_alerts(&discharging(&[10, 30, 20, 10])),
+            [Some(Critical), None, None, Some(Critical)]
+        );
+        // Past both margins, so low fires again on the way down.
+        assert_eq!(
+            battery_alerts(&discharging(&[10, 40, 20, 10])),
+            [Some(Critical), None, Some(Low), Some(Critical)]
+        );
+        // Dropping straight to critical skips low.
+        assert_eq!(
+            battery_alerts(&discharging(&[50, 0])),
+            [None, Some(Critical)]
+        );
+    }
+
+    #[test]
+    fn charging_or_full_rearms() {
+        use AlertLevel::*;
+        use BatteryStatus::*;
+        for (readings, expected) in [
+            // Charged past the margin and unplugged.
+            (
+                vec![(20, Discharging), (40, Charging), (20, Discharging)],
+                vec![Some(Low), None, Some(Low)],
+            ),
+            // Plugged in only briefly.
+            (
+                vec![(20, Discharging), (30, Charging), (20, Discharging)],
+                vec![Some(Low), None, None],
+            ),
+            // Full re-arms whatever it reads.
+            (
+                vec![(10, Discharging), (0, Full), (10, Discharging)],
+                vec![Some(Critical), None, Some(Critical)],
+            ),
+        ] {
+            assert_eq!(battery_alerts(&readings), expected, "{:?}", readings);
+        }
+    }
+
+    #[test]
+    fn only_discharging_fires() {
+        use BatteryStatus::*;
+        for status in [Charging, Full, ChargingError, Unknown] {
+            assert_eq!(
+                battery_alerts(&[(20, status.clone()), (0, status.clone())]),
+                [None, None],
+                "{:?}",
+                status
+            );
+        }
+        // A reading that couldn't be interpreted doesn't count as having
+        // warned, so the next discharging one still does.
+        assert_eq!(
+            battery_alerts(&[(10, Unknown), (10, Discharging)]),
+            [None, Some(AlertLevel::Critical)]
+        );
+    }
+
+    /// Feeds `readings` through a fresh charge tracker and returns what each
+    /// one raised.
+    fn charge_alerts(
+        readings: &[(u8, BatteryStatus)],
+        thresholds: &AlertThresholds,
+    ) -> Vec<Option<ChargeAlert>> {
+        let mut tracker = ChargeAlertTracker::default();
+        readings
+            .iter()
+            .map(|(capacity, status)| {
+                tracker.update(&BatteryReport::new(*capacity, status.clone()), thresholds)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ceiling_fires_once_per_charge() {
+        use BatteryStatus::*;
+        use ChargeAlert::*;
+        assert_eq!(
+            charge_alerts(
+                &[
+                    (60, Charging),
+                    (70, Charging),
+                    (80, Charging),
+                    (90, Charging),
+                    (80, Charging)
+                ],
+                &AlertThresholds::default()
+            ),
+            [None, None, Some(CeilingReached), None, None]
+        );
+        // Connecting while already past the ceiling still says so.
+        assert_eq!(
+            charge_alerts(&[(90, Charging)], &AlertThresholds::default()),
+            [Some(CeilingReached)]
+        );
+    }
+
+    #[test]
+    fn ceiling_rearms_after_unplugging() {
+        use BatteryStatus::*;
+        use ChargeAlert::*;
+        assert_eq!(
+            charge_alerts(
+                &[
+                    (80, Charging),
+                    (80, Discharging),
+                    (80, Charging),
+                    (90, Charging)
+                ],
+                &AlertThresholds::default()
+            ),
+            [Some(CeilingReached), None, Some(CeilingReached), None]
+        );
+        // Full isn't unplugged, so topping off doesn't fire again.
+        assert_eq!(
+            charge_alerts(
+                &[(80, Charging), (100, Full), (90, Charging)],
+                &AlertThresholds {
+                    notify_when_full: false,
+                    ..AlertThresholds::default()
+                }
+            ),
+            [Some(CeilingReached), None, None]
+        );
+    }
+
+    #[test]
+    fn no_ceiling_never_fires() {
+        let thresholds = AlertThresholds {
+            charge_ceiling: None,
+            ..AlertThresholds::default()
+        };
+        assert_eq!(
+            charge_alerts(&[(100, BatteryStatus::Charging)], &thresholds),
+            [None]
+        );
+    }
+
+    #[test]
+    fn fully_charged_fires_on_the_change_to_full() {
+        use BatteryStatus::*;
+        use ChargeAlert::*;
+        let thresholds = AlertThresholds {
+            charge_ceiling: None,
+            ..AlertThresholds::default()
+        };
+        assert_eq!(
+            charge_alerts(&[(90, Charging), (100, Full), (100, Full)], &thresholds),
+            [None, Some(FullyCharged), None]
+        );
+        // Connecting at full has nothing new to say.
+        assert_eq!(
+            charge_alerts(&[(100, Full), (100, Full)], &thresholds),
+            [None, None]
+        );
+        // Unplugged and plugged back in at full.
+        assert_eq!(
+            charge_alerts(&[(100, Full), (100, Discharging), (100, Full)], &thresholds),
+            [None, None, Some(FullyCharged)]
+        );
+        let quiet = AlertThresholds {
+            notify_when_full: false,
+            ..thresholds
+        };
+        assert_eq!(
+            charge_alerts(&[(90, Charging), (100, Full)], &quiet),
+            [None, None]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Battery wear metrics from recorded history.
+//!
+//! Batteries don't report their own health, so wear is judged against each
+//! controller's own past: how long a full discharge lasted in its earliest
+//! recorded runs compared with its latest ones. Full-discharge runtime and
+//! full-charge duration are extrapolated from the timed steps of each run, the
+//! same way the runtime estimate works, so partial runs count too.
+
+use crate::{
+    battery_history::BatterySample,
+    dualsense::BatteryStatus,
+    runtime_estimate::{self, StepInterval},
+};
+
+/// A run needs at least this many timed points before it says anything about
+/// the battery; one step alone is mostly noise.
+const MIN_RUN_POINTS: u32 = 20;
+/// Runs averaged for the baseline and for the recent value.
+const RUNS_PER_WINDOW: usize = 3;
+
+/// Runtime drop, as a fraction of the baseline, that marks a pad as degraded.
+pub const DEFAULT_DEGRADED_DROP: f64 = 0.2;
+
+/// One charge or discharge run, extrapolated to the full 0-100% range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunDuration {
+    pub started: u64,
+    pub full_range_secs: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatteryHealth {
+    /// Total points discharged, in units of a full 100% discharge.
+    pub equivalent_cycles: f64,
+    pub discharge_runs: Vec<RunDuration>,
+    pub charge_runs: Vec<RunDuration>,
+}
+
+/// Where a metric started and where it is now, each averaged over a few runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trend {
+    pub baseline_secs: f64,
+    pub recent_secs: f64,
+}
+
+impl Trend {
+    /// Relative change from the baseline; negative means shorter.
+    pub fn change(&self) -> f64 {
+        self.recent_secs / self.baseline_secs - 1.0
+    }
+}
+
+impl BatteryHealth {
+    /// Metrics for one controller's samples, oldest first.
+    pub fn from_samples(samples: &[BatterySample]) -> Self {
+        // Only drops within a discharge run count; the level read after a
+        // reconnect or an unplug says nothing about what was used in between.
+        let discharged: u32 = samples
+            .windows(2)
+            .filter(|pair| {
+                runtime_estimate::continues_run(&pair[0], &pair[1], &BatteryStatus::Discharging)
+            })
+            .map(|pair| u32::from(pair[0].capacity.saturating_sub(pair[1].capacity)))
+            .sum();
+        Self {
+            equivalent_cycles: f64::from(discharged) / 100.0,
+            discharge_runs: run_durations(samples, &BatteryStatus::Discharging),
+            charge_runs: run_durations(samples, &BatteryStatus::Charging),
+        }
+    }
+
+    /// Full-discharge runtime, first runs against latest. `None` until there
+    /// are enough runs for the two windows not to overlap.
+    pub fn runtime_trend(&self) -> Option<Trend> {
+        trend(&self.discharge_runs)
+    }
+
+    pub fn charge_time_trend(&self) -> Option<Trend> {
+        trend(&self.charge_runs)
+    }
+
+    /// Whether runtime dropped by more than `max_drop` (a fraction) from the
+    /// controller's own baseline.
+    pub fn is_degraded(&self, max_drop: f64) -> bool {
+        self.runtime_trend()
+            .is_some_and(|trend| trend.change() < -max_drop)
+    }
+}
+
+fn run_durations(samples: &[BatterySample], status: &BatteryStatus) -> Vec<RunDuration> {
+    let intervals = runtime_estimate::step_intervals(samples, status);
+    let mut runs = Vec::new();
+    for run in intervals.chunk_by(|a, b| a.run_started == b.run_started) {
+        let points: u32 = run.iter().map(|interval| interval.points).sum();
+        if points < MIN_RUN_POINTS {
+            continue;
+        }
+        let seconds: u64 = run
+            .iter()
+            .map(|interval: &StepInterval| interval.ended - interval.started)
+            .sum();
+        runs.push(RunDuration {
+            started: run[0].run_started,
+            full_range_secs: seconds as f64 / f64::from(points) * 100.0,
+        });
+    }
+    runs
+}
+
+fn trend(runs: &[RunDuration]) -> Option<Trend> {
+    if runs.len() < RUNS_PER_WINDOW * 2 {
+        return None;
+    }
+    let mean = |runs: &[RunDuration]| {
+        runs.iter().map(|run| run.full_range_secs).sum::<f64>() / runs.len() as f64
+    };
+    Some(Trend {
+        baseline_secs: mean(&runs[..RUNS_PER_WINDOW]),
+        recent_secs: mean(&runs[runs.len() - RUNS_PER_WINDOW..]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::ConnectionType;
+
+    fn sample(timestamp: u64, capacity: u8, status: BatteryStatus) -> BatterySample {
+        BatterySample {
+            timestamp,
+            capacity,
+            status,
+            transport: ConnectionType::Bluetooth,
+        }
+    }
+
+    /// A run with `status` from `from` to `to` percent, one 10% step every
+    /// `secs_per_point * 10` seconds.
+    fn run(
+        start: u64,
+        secs_per_point: u64,
+        from: u8,
+        to: u8,
+        status: BatteryStatus,
+    ) -> Vec<BatterySample> {
+        let steps = u64::from(from.abs_diff(to) / 10);
+        (0..=steps)
+            .map(|step| {
+                let capacity = if from > to {
+                    from - step as u8 * 10
+                } else {
+                    from + step as u8 * 10
+                };
+                sample(start + step * secs_per_point * 10, capacity, status.clone())
+            })
+            .collect()
+    }
+
+    /// Discharge runs from 100% to 50%, a day apart, at each rate.
+    fn discharges(secs_per_point: &[u64]) -> Vec<BatterySample> {
+        secs_per_point
+            .iter()
+            .enumerate()
+            .flat_map(|(day, rate)| {
+                run(
+                    day as u64 * 86_400,
+                    *rate,
+                    100,
+                    50,
+                    BatteryStatus::Discharging,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cycles_only_count_drops_within_a_discharge() {
+        use BatteryStatus::*;
+        let mut samples = run(0, 60, 100, 60, Discharging);
+        // Off overnight, and read lower on reconnecting.
+        samples.push(sample(100_000, 50, Discharging));
+        // Charged, then unplugged and read a step lower.
+        samples.push(sample(100_600, 80, Charging));
+        samples.push(sample(100_700, 70, Discharging));
+        samples.push(sample(101_300, 60, Discharging));
+
+        let health = BatteryHealth::from_samples(&samples);
+        assert_eq!(health.equivalent_cycles, 0.5);
+    }
+
+    #[test]
+    fn runs_are_extrapolated_to_the_full_range() {
+        let samples = discharges(&[60]);
+        let health = BatteryHealth::from_samples(&samples);
+        // Timed from the first step at 90% to the last at 50%.
+        assert_eq!(
+            health.discharge_runs,
+            [RunDuration {
+                started: 0,
+                full_range_secs: 6000.0,
+            }]
+        );
+        assert!(health.charge_runs.is_empty());
+
+        let charge = run(0, 90, 20, 80, BatteryStatus::Charging);
+        let health = BatteryHealth::from_samples(&charge);
+        assert_eq!(health.charge_runs[0].full_range_secs, 9000.0);
+    }
+
+    #[test]
+    fn short_runs_are_ignored() {
+        // Only 20 timed points: 90 to 70.
+        let samples = run(0, 60, 100, 70, BatteryStatus::Discharging);
+        assert_eq!(
+            BatteryHealth::from_samples(&samples).discharge_runs.len(),
+            1
+        );
+        // Only 10: 90 to 80.
+        let samples = run(0, 60, 100, 80, BatteryStatus::Discharging);
+        assert!(
+            BatteryHealth::from_samples(&samples)
+                .discharge_runs
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn trend_needs_two_full_windows() {
+        let health = BatteryHealth::from_samples(&discharges(&[60; 5]));
+        assert_eq!(health.discharge_runs.len(), 5);
+        assert_eq!(health.runtime_trend(), None);
+        assert!(!health.is_degraded(DEFAULT_DEGRADED_DROP));
+        assert_eq!(health.charge_time_trend(), None);
+    }
+
+    #[test]
+    fn trend_compares_first_and_latest_runs() {
+        let health = BatteryHealth::from_samples(&discharges(&[60, 62, 58, 50, 45, 45, 45]));
+        let trend = health.runtime_trend().unwrap();
+        assert_eq!(trend.baseline_secs, 6000.0);
+        assert_eq!(trend.recent_secs, 4500.0);
+        assert!((trend.change() + 0.25).abs() < 1e-9);
+
+        assert!(health.is_degraded(DEFAULT_DEGRADED_DROP));
+        assert!(!health.is_degraded(0.3));
+    }
+
+    #[test]
+    fn steady_runtime_is_not_degraded() {
+        let health = BatteryHealth::from_samples(&discharges(&[60, 60, 60, 58, 59, 60]));
+        assert!(!health.is_degraded(DEFAULT_DEGRADED_DROP));
+        // Longer than before is fine too.
+        let health = BatteryHealth::from_samples(&discharges(&[60, 60, 60, 80, 80, 80]));
+        assert!(health.runtime_trend().unwrap().change() > 0.0);
+        assert!(!health.is_degraded(DEFAULT_DEGRADED_DROP));
+    }
+}
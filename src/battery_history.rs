@@ -0,0 +1,519 @@
+//! On-disk battery history, one file per controller.
+//!
+//! Each controller identity gets a text file under the data directory with one
+//! `timestamp,capacity,status,transport` line per sample. New samples are
+//! appended; the file is rewritten only when it's compacted. Readings only
+//! arrive when something changed, so the files stay small, and compaction
+//! drops anything past the retention limits.
+
+use crate::{
+    dualsense::{BatteryReport, BatteryStatus, ConnectionType},
+    paths,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HISTORY_DIR_NAME: &str = "history";
+const HISTORY_FILE_EXTENSION: &str = "csv";
+
+/// Appends between compactions, as a fraction of `max_samples`.
+const COMPACT_EVERY_DIVISOR: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatterySample {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub capacity: u8,
+    pub status: BatteryStatus,
+    pub transport: ConnectionType,
+}
+
+impl BatterySample {
+    pub fn now(report: &BatteryReport, transport: ConnectionType) -> Self {
+        Self {
+            timestamp: unix_now(),
+            capacity: report.battery_capacity,
+            status: report.battery_status.clone(),
+            transport,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.timestamp,
+            self.capacity,
+            status_name(&self.status),
+            transport_name(self.transport)
+        )
+    }
+
+    fn parse_line(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(',');
+        let sample = Self {
+            timestamp: fields.next()?.parse().ok()?,
+            capacity: fields.next()?.parse().ok()?,
+            status: parse_status(fields.next()?)?,
+            transport: parse_transport(fields.next()?)?,
+        };
+        fields.next().is_none().then_some(sample)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+    /// Samples older than this many seconds are dropped.
+    pub max_age_secs: u64,
+    /// At most this many samples are kept per controller, newest first.
+    pub max_samples: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 180 * 24 * 60 * 60,
+            max_samples: 20_000,
+        }
+    }
+}
+
+/// Applies `retention` to samples in time order. Repeated identical readings
+/// are merged into the first one, since only changes carry information.
+pub fn compact(samples: &[BatterySample], retention: &Retention, now: u64) -> Vec<BatterySample> {
+    let cutoff = now.saturating_sub(retention.max_age_secs);
+    let mut kept: Vec<BatterySample> = Vec::new();
+    for sample in samples.iter().filter(|sample| sample.timestamp >= cutoff) {
+        let repeat = kept.last().is_some_and(|last| {
+            last.capacity == sample.capacity
+                && last.status == sample.status
+                && last.transport == sample.transport
+        });
+        if !repeat {
+            kept.push(sample.clone());
+        }
+    }
+    let excess = kept.len().saturating_sub(retention.max_samples);
+    kept.drain(..excess);
+    kept
+}
+
+pub struct HistoryStore {
+    dir: PathBuf,
+    retention: Retention,
+    /// Appends per identity since its file was last compacted.
+    appends_since_compaction: HashMap<String, usize>,
+}
+
+impl HistoryStore {
+    pub fn new(dir: PathBuf, retention: Retention) -> Self {
+        Self {
+            dir,
+            retention,
+            appends_since_compaction: HashMap::new(),
+        }
+    }
+
+    /// The store under the user data directory.
+    pub fn open_default(retention: Retention) -> Option<Self> {
+        paths::data_dir().map(|dir| Self::new(dir.join(HISTORY_DIR_NAME), retention))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn append(&mut self, identity: &str, sample: &BatterySample) -> Result<(), String> {
+        // Compact on the first write of a run too, so old files shrink even
+        // for pads that rarely change.
+        let appends = self
+            .appends_since_compaction
+            .get(identity)
+            .copied()
+            .unwrap_or(usize::MAX);
+        if appends >= (self.retention.max_samples / COMPACT_EVERY_DIVISOR).max(1) {
+            self.compact(identity)?;
+        }
+
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let path = self.file_path(identity);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        writeln!(file, "{}", sample.to_line())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        *self
+            .appends_since_compaction
+            .entry(identity.to_string())
+            .or_default() += 1;
+        Ok(())
+    }
+
+    /// Every stored sample for a controller, oldest first. Unreadable lines are
+    /// skipped.
+    pub fn load(&self, identity: &str) -> Result<Vec<BatterySample>, String> {
+        let path = self.file_path(identity);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let mut samples: Vec<BatterySample> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                let sample = BatterySample::parse_line(line);
+                if sample.is_none() {
+                    eprintln!("Skipping bad history line in {}: {}", path.display(), line);
+                }
+                sample
+            })
+            .collect();
+        // Clock changes can leave lines out of order.
+        samples.sort_by_key(|sample| sample.timestamp);
+        Ok(samples)
+    }
+
+    /// Identities that have a history file.
+    pub fn identities(&self) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+        let mut identities: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != HISTORY_FILE_EXTENSION {
+                    return None;
+                }
+                decode_identity(path.file_stem()?.to_str()?)
+            })
+            .collect();
+        identities.sort();
+        Ok(identities)
+    }
+
+    /// Rewrites a controller's file with only what the retention limits keep.
+    pub fn compact(&mut self, identity: &str) -> Result<(), String> {
+        let samples = self.load(identity)?;
+        let kept = compact(&samples, &self.retention, unix_now());
+        self.appends_since_compaction
+            .insert(identity.to_string(), 0);
+        if kept.len() == samples.len() {
+            return Ok(());
+        }
+
+        let path = self.file_path(identity);
+        let mut text = String::new();
+        for sample in &kept {
+            text.push_str(&sample.to_line());
+            text.push('\n');
+        }
+        paths::write_atomically(&path, text.as_bytes())
+    }
+
+    fn file_path(&self, identity: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{}",
+            encode_identity(identity),
+            HISTORY_FILE_EXTENSION
+        ))
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Makes an identity safe to use as a file name. Letters, digits and `-` are
+/// kept; everything else becomes `_` and two hex digits per byte, so the name
+/// can be turned back into the identity.
+fn encode_identity(identity: &str) -> String {
+    let mut encoded = String::new();
+    for byte in identity.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    encoded
+}
+
+fn decode_identity(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'_' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+pub fn status_name(status: &BatteryStatus) -> &'static str {
+    match status {
+        BatteryStatus::Discharging => "discharging",
+        BatteryStatus::Charging => "charging",
+        BatteryStatus::Full => "full",
+        BatteryStatus::ChargingError => "charging_error",
+        BatteryStatus::Unknown => "unknown",
+    }
+}
+
+fn parse_status(name: &str) -> Option<BatteryStatus> {
+    Some(match name {
+        "discharging" => BatteryStatus::Discharging,
+        "charging" => BatteryStatus::Charging,
+        "full" => BatteryStatus::Full,
+        "charging_error" => BatteryStatus::ChargingError,
+        "unknown" => BatteryStatus::Unknown,
+        _ => return None,
+    })
+}
+
+pub fn transport_name(transport: ConnectionType) -> &'static str {
+    match transport {
+        ConnectionType::Usb => "usb",
+        ConnectionType::Bluetooth => "bluetooth",
+    }
+}
+
+fn parse_transport(name: &str) -> Option<ConnectionType> {
+    match name {
+        "usb" => Some(ConnectionType::Usb),
+        "bluetooth" => Some(ConnectionType::Bluetooth),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, capacity: u8, status: BatteryStatus) -> BatterySample {
+        BatterySample {
+            timestamp,
+            capacity,
+            status,
+            transport: ConnectionType::Usb,
+        }
+    }
+
+    /// An empty directory unique to this test run.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "ds-battery-history-{}-{}",
+            std::process::id(),
+            test
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let bluetooth = BatterySample {
+            timestamp: 1_792_315_800,
+            capacity: 70,
+            status: BatteryStatus::ChargingError,
+            transport: ConnectionType::Bluetooth,
+        };
+        assert_eq!(
+            bluetooth.to_line(),
+            "1792315800,70,charging_error,bluetooth"
+        );
+        assert_eq!(
+            BatterySample::parse_line(&bluetooth.to_line()),
+            Some(bluetooth)
+        );
+        assert_eq!(
+            BatterySample::parse_line("  10,50,full,usb\r"),
+            Some(sample(10, 50, BatteryStatus::Full))
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        for line in [
+            "",
+            "10,50,full",
+            "10,50,full,usb,extra",
+            "x,50,full,usb",
+            "10,256,full,usb",
+            "10,-1,full,usb",
+            "10,50,Full,usb",
+            "10,50,full,serial",
+        ] {
+            assert_eq!(BatterySample::parse_line(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn compact_merges_repeated_readings() {
+        use BatteryStatus::*;
+        let samples = [
+            sample(100, 80, Discharging),
+            sample(200, 80, Discharging),
+            sample(300, 70, Discharging),
+            sample(400, 70, Charging),
+            sample(500, 70, Charging),
+            sample(600, 70, Discharging),
+        ];
+        let kept = compact(&samples, &Retention::default(), 600);
+        assert_eq!(
+            kept,
+            [
+                samples[0].clone(),
+                samples[2].clone(),
+                samples[3].clone(),
+                samples[5].clone(),
+            ]
+        );
+
+        // A change of transport is a change too.
+        let replugged = BatterySample {
+            transport: ConnectionType::Bluetooth,
+            ..samples[1].clone()
+        };
+        assert_eq!(
+            compact(
+                &[samples[0].clone(), replugged.clone()],
+                &Retention::default(),
+                600
+            ),
+            [samples[0].clone(), replugged]
+        );
+    }
+
+    #[test]
+    fn compact_applies_age_and_count_limits() {
+        let samples: Vec<BatterySample> = (0..10)
+            .map(|i| sample(i * 100, 100 - i as u8 * 10, BatteryStatus::Discharging))
+            .collect();
+        let by_age = Retention {
+            max_age_secs: 300,
+            max_samples: 100,
+        };
+        // Samples at exactly the cutoff are kept.
+        assert_eq!(compact(&samples, &by_age, 900), samples[6..]);
+
+        let by_count = Retention {
+            max_age_secs: u64::MAX,
+            max_samples: 3,
+        };
+        assert_eq!(compact(&samples, &by_count, 900), samples[7..]);
+    }
+
+    #[test]
+    fn identities_round_trip_through_file_names() {
+        for identity in [
+            "aa:bb:cc:dd:ee:ff",
+            r"\\?\hid#vid_054c&pid_0ce6#7&1a2b3c4d&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}",
+            "/dev/hidraw3",
+            "under_score",
+            "Ünïcode pad",
+            "",
+        ] {
+            let encoded = encode_identity(identity);
+            assert!(
+                encoded
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'),
+                "{}",
+                encoded
+            );
+            assert_eq!(decode_identity(&encoded).as_deref(), Some(identity));
+        }
+        assert_eq!(encode_identity("aa:bb"), "aa_3abb");
+    }
+
+    #[test]
+    fn malformed_file_names_are_not_identities() {
+        for encoded in ["_", "_3", "_zz", "ab_3", "_ff"] {
+            assert_eq!(decode_identity(encoded), None, "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn store_appends_compacts_and_loads() {
+        let dir = temp_dir("store");
+        let retention = Retention {
+            max_age_secs: 60 * 60,
+            max_samples: 3,
+        };
+        let mut store = HistoryStore::new(dir.clone(), retention);
+        let now = unix_now();
+        let identity = "aa:bb:cc:dd:ee:ff";
+
+        // Too old to survive the next compaction.
+        store
+            .append(
+                identity,
+                &sample(now - 2 * 60 * 60, 100, BatteryStatus::Full),
+            )
+            .unwrap();
+        for (offset, capacity) in [(30, 90), (20, 90), (10, 80), (0, 70)] {
+            store
+                .append(
+                    identity,
+                    &sample(now - offset, capacity, BatteryStatus::Discharging),
+                )
+                .unwrap();
+        }
+        // Every append but the last was compacted away or merged.
+        store.compact(identity).unwrap();
+        assert_eq!(
+            store.load(identity).unwrap(),
+            [
+                sample(now - 30, 90, BatteryStatus::Discharging),
+                sample(now - 10, 80, BatteryStatus::Discharging),
+                sample(now, 70, BatteryStatus::Discharging),
+            ]
+        );
+        assert_eq!(store.identities().unwrap(), [identity]);
+        assert!(!dir.join("aa_3abb_3acc_3add_3aee_3aff.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_skips_bad_lines_and_sorts() {
+        let dir = temp_dir("load");
+        let store = HistoryStore::new(dir.clone(), Retention::default());
+        assert_eq!(store.load("pad").unwrap(), []);
+        assert_eq!(store.identities().unwrap(), Vec::<String>::new());
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("pad.csv"),
+            "20,40,discharging,usb\nnot a sample\n\n10,50,full,usb\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        assert_eq!(
+            store.load("pad").unwrap(),
+            [
+                sample(10, 50, BatteryStatus::Full),
+                sample(20, 40, BatteryStatus::Discharging)
+            ]
+        );
+        assert_eq!(store.identities().unwrap(), ["pad"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
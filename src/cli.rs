@@ -0,0 +1,710 @@
+//! Command-line entry points that run without the overlay window.
+
+use std::{path::PathBuf, time::Duration};
+
+use serde_json::{Value, json};
+
+use crate::{
+    battery_health::{self, BatteryHealth, Trend},
+    battery_history::{self, HistoryStore},
+    config::Config,
+    controllers::ControllerEntry,
+    dualsense::{BatteryReport, BatteryStatus, ConnectionType, ControllerInfo, ControllerModel},
+    history_export::{self, ExportFilter, ExportFormat, ExportRow},
+    ipc,
+    output_report::Rgb,
+    overlay_layout::{BatteryVisual, ControllerRow, OverlayLayout, RowAnimation},
+    png,
+    polling::DirectController,
+    runtime_estimate::{self, Estimate, EstimateKind},
+    software_renderer, tray_icon,
+    web_api::ControllerJson,
+};
+
+/// How long `status` waits for each pad's first input report when it reads
+/// them itself.
+const DIRECT_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Range of `render --scale`; past it the canvas gets uselessly small or too
+/// big to allocate.
+const MIN_RENDER_SCALE: f32 = 0.5;
+const MAX_RENDER_SCALE: f32 = 8.0;
+
+const USAGE: &str = "\
+Usage:
+  ds-battery                       Run the battery overlay
+  ds-battery render [OPTIONS] OUT  Write an overlay snapshot to OUT as PNG
+  ds-battery history               List the controllers with recorded battery history
+                                   and their runtime estimates
+  ds-battery history export [OPTIONS]
+                                   Export battery history as CSV or JSON
+  ds-battery health [--threshold <PERCENT>]
+                                   Report battery wear per controller, flagging pads
+                                   whose runtime dropped by more than PERCENT
+                                   (default: 20) from their own baseline
+  ds-battery status [--json]       List controllers and their batteries, asking the
+                                   running overlay if there is one
+  ds-battery show [CONTROLLER]     Pop up the running overlay
+  ds-battery lightbar CONTROLLER #RRGGBB
+                                   Set a controller's lightbar colour
+  ds-battery watch                 Print controller events from the running overlay
+                                   as JSON lines
+  ds-battery help                  Show this message
+
+Render options:
+  --battery <0-100>   Battery percentage to show (default: 100)
+  --status <STATUS>   discharging, charging, full, error, unknown or disconnected
+                      (default: discharging)
+  --scale <FACTOR>    DPI scale factor, 0.5 to 8 (default: 1.0)
+  --controllers <N>   Number of connected controllers to show (default: 1)
+  --estimate <MIN>    Show this many minutes remaining, or until full when charging
+  --icon <SIZE>       Render the SIZE x SIZE tray icon instead of the overlay;
+                      OUT ending in .ico writes an icon file instead of PNG
+
+Export options:
+  --controller <ID>   Only this controller, by identity or name
+  --since <TIME>      Only samples at or after TIME (ISO-8601, UTC)
+  --until <TIME>      Only samples at or before TIME (ISO-8601, UTC)
+  --transitions-only  Only samples where the charging status changed
+  --format <FORMAT>   csv or json (default: from the output extension, else csv)
+  --output <FILE>     Write to FILE instead of standard output
+
+CONTROLLER is a player number, identity, HID path or name.";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render(RenderArgs),
+    History,
+    Export(ExportArgs),
+    Health { max_drop: f64 },
+    Status { json: bool },
+    Show(Option<String>),
+    Lightbar { controller: String, color: Rgb },
+    Watch,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExportArgs {
+    /// Identity or name of the one controller to export.
+    pub controller: Option<String>,
+    pub filter: ExportFilter,
+    pub format: ExportFormat,
+    /// Standard output when not given.
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RenderArgs {
+    pub report: BatteryReport,
+    pub connected: bool,
+    pub scale: f32,
+    pub controllers: u8,
+    /// Minutes to show as a runtime estimate.
+    pub estimate: Option<u64>,
+    pub icon_size: Option<u32>,
+    pub output: PathBuf,
+}
+
+/// Parses the arguments after the program name. `Ok(None)` means no command was
+/// given and the overlay should start as usual.
+pub fn parse_args(args: &[String]) -> Result<Option<Command>, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(None);
+    };
+
+    match command.as_str() {
+        "render" => parse_render_args(rest).map(|args| Some(Command::Render(args))),
+        "history" => match rest.split_first() {
+            None => Ok(Some(Command::History)),
+            Some((sub, args)) if sub == "export" => {
+                parse_export_args(args).map(|args| Some(Command::Export(args)))
+            }
+            Some((extra, _)) => Err(format!("Unexpected argument '{}'", extra)),
+        },
+        "health" => parse_health_args(rest).map(Some),
+        "status" => match rest {
+            [] => Ok(Some(Command::Status { json: false })),
+            [flag] if flag == "--json" => Ok(Some(Command::Status { json: true })),
+            [extra, ..] => Err(format!("Unexpected argument '{}'", extra)),
+        },
+        "show" => match rest {
+            [] => Ok(Some(Command::Show(None))),
+            [controller] => Ok(Some(Command::Show(Some(controller.clone())))),
+            [_, extra, ..] => Err(format!("Unexpected argument '{}'", extra)),
+        },
+        "lightbar" => match rest {
+            [controller, color] => Ok(Some(Command::Lightbar {
+                controller: controller.clone(),
+                color: color.parse()?,
+            })),
+            [_, _, extra, ..] => Err(format!("Unexpected argument '{}'", extra)),
+            _ => Err("Expected a controller and a colour".to_string()),
+        },
+        "watch" => match rest {
+            [] => Ok(Some(Command::Watch)),
+            [extra, ..] => Err(format!("Unexpected argument '{}'", extra)),
+        },
+        "help" | "--help" | "-h" => Ok(Some(Command::Help)),
+        other => Err(format!("Unknown command '{}'", other)),
+    }
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs, String> {
+    let mut battery = 100u8;
+    let mut status = BatteryStatus::Discharging;
+    let mut connected = true;
+    let mut scale = 1.0f32;
+    let mut controllers = 1u8;
+    let mut estimate = None;
+    let mut icon_size = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--battery" => {
+                let value = option_value(&mut args, arg)?;
+                battery = value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|v| *v <= 100)
+                    .ok_or_else(|| format!("Invalid battery percentage '{}'", value))?;
+            }
+            "--status" => {
+                let value = option_value(&mut args, arg)?;
+                connected = !value.eq_ignore_ascii_case("disconnected");
+                if connected {
+                    status = parse_status(value)?;
+                }
+            }
+            "--scale" => {
+                let value = option_value(&mut args, arg)?;
+                scale = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| (MIN_RENDER_SCALE..=MAX_RENDER_SCALE).contains(v))
+                    .ok_or_else(|| format!("Invalid scale '{}'", value))?;
+            }
+            "--controllers" => {
+                let value = option_value(&mut args, arg)?;
+                controllers = value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|v| *v >= 1)
+                    .ok_or_else(|| format!("Invalid controller count '{}'", value))?;
+            }
+            "--estimate" => {
+                let value = option_value(&mut args, arg)?;
+                estimate = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid estimate '{}'", value))?,
+                );
+            }
+            "--icon" => {
+                let value = option_value(&mut args, arg)?;
+                icon_size = Some(
+                    value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|v| (1..=256).contains(v))
+                        .ok_or_else(|| format!("Invalid icon size '{}'", value))?,
+                );
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            path if output.is_none() => output = Some(PathBuf::from(path)),
+            extra => return Err(format!("Unexpected argument '{}'", extra)),
+        }
+    }
+
+    let output = output.ok_or("Missing output path")?;
+    Ok(RenderArgs {
+        report: BatteryReport::new(battery, status),
+        connected,
+        scale,
+        controllers,
+        estimate,
+        icon_size,
+        output,
+    })
+}
+
+fn parse_export_args(args: &[String]) -> Result<ExportArgs, String> {
+    let mut controller = None;
+    let mut filter = ExportFilter::default();
+    let mut format = None;
+    let mut output: Option<PathBuf> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--controller" => controller = Some(option_value(&mut args, arg)?.to_string()),
+            "--since" | "--until" => {
+                let value = option_value(&mut args, arg)?;
+                let time = history_export::parse_iso8601(value)
+                    .ok_or_else(|| format!("Invalid time '{}'", value))?;
+                if arg == "--since" {
+                    filter.since = Some(time);
+                } else {
+                    filter.until = Some(time);
+                }
+            }
+            "--transitions-only" => filter.transitions_only = true,
+            "--format" => {
+                let value = option_value(&mut args, arg)?;
+                format = Some(
+                    ExportFormat::parse(value)
+                        .ok_or_else(|| format!("Unknown format '{}'", value))?,
+                );
+            }
+            "--output" => output = Some(PathBuf::from(option_value(&mut args, arg)?)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            extra => return Err(format!("Unexpected argument '{}'", extra)),
+        }
+    }
+
+    let format = format.unwrap_or_else(|| {
+        let is_json = output
+            .as_ref()
+            .and_then(|path| path.extension())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if is_json {
+            ExportFormat::Json
+        } else {
+            ExportFormat::Csv
+        }
+    });
+    Ok(ExportArgs {
+        controller,
+        filter,
+        format,
+        output,
+    })
+}
+
+fn parse_health_args(args: &[String]) -> Result<Command, String> {
+    let mut max_drop = battery_health::DEFAULT_DEGRADED_DROP;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => {
+                let value = option_value(&mut args, arg)?;
+                max_drop = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| (0.0..100.0).contains(v))
+                    .ok_or_else(|| format!("Invalid threshold '{}'", value))?
+                    / 100.0;
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            extra => return Err(format!("Unexpected argument '{}'", extra)),
+        }
+    }
+    Ok(Command::Health { max_drop })
+}
+
+fn option_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+) -> Result<&'a str, String> {
+    args.next()
+        .map(String::as_str)
+        .ok_or_else(|| format!("Missing value for {}", option))
+}
+
+fn parse_status(value: &str) -> Result<BatteryStatus, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "discharging" => Ok(BatteryStatus::Discharging),
+        "charging" => Ok(BatteryStatus::Charging),
+        "full" => Ok(BatteryStatus::Full),
+        "error" => Ok(BatteryStatus::ChargingError),
+        "unknown" => Ok(BatteryStatus::Unknown),
+        _ => Err(format!("Unknown battery status '{}'", value)),
+    }
+}
+
+pub fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Render(args) => render(&args),
+        Command::History => list_history(),
+        Command::Export(args) => export_history(&args),
+        Command::Health { max_drop } => health_report(max_drop),
+        Command::Status { json } => status(json),
+        Command::Show(controller) => show_overlay(controller),
+        Command::Lightbar { controller, color } => set_lightbar(&controller, color),
+        Command::Watch => watch(),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+pub fn print_usage_error(error: &str) {
+    eprintln!("Error: {}\n\n{}", error, USAGE);
+}
+
+fn render(args: &RenderArgs) -> Result<(), String> {
+    if let Some(size) = args.icon_size {
+        return render_icon(args, size);
+    }
+
+    // Every pad shows the same report; the first one is the highlighted trigger.
+    let rows: Vec<ControllerRow> = (1..=args.controllers)
+        .map(|player_number| ControllerRow {
+            player_number,
+            info: ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Usb,
+                serial: None,
+                firmware: None,
+            },
+            name: None,
+            report: Some(args.report.clone()),
+            estimate: args.estimate.map(|minutes| Estimate {
+                kind: match args.report.battery_status {
+                    BatteryStatus::Charging => EstimateKind::UntilFull,
+                    _ => EstimateKind::Remaining,
+                },
+                seconds: minutes * 60,
+                confidence: 1.0,
+            }),
+            connected: args.connected,
+            highlighted: player_number == 1,
+            animation: RowAnimation::default(),
+        })
+        .collect();
+    let layout = OverlayLayout::for_rows(rows.len(), args.scale);
+    let scene = layout.build_scene(&rows);
+    let canvas = software_renderer::render_scene(&scene);
+
+    std::fs::write(&args.output, canvas.to_png())
+        .map_err(|e| format!("Failed to write {}: {}", args.output.display(), e))?;
+    println!(
+        "Wrote {}x{} overlay snapshot to {}",
+        canvas.width(),
+        canvas.height(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn render_icon(args: &RenderArgs, size: u32) -> Result<(), String> {
+    let visual = if args.connected {
+        BatteryVisual::for_report(&args.report)
+    } else {
+        BatteryVisual {
+            dimmed: true,
+            ..BatteryVisual::message("No controller")
+        }
+    };
+    let rgba = tray_icon::render_rgba(&visual, size);
+    let is_ico = args
+        .output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ico"));
+    let bytes = if is_ico {
+        tray_icon::encode_ico(size, &rgba)
+    } else {
+        png::encode_rgba8(size, size, &rgba)
+    };
+
+    std::fs::write(&args.output, bytes)
+        .map_err(|e| format!("Failed to write {}: {}", args.output.display(), e))?;
+    println!(
+        "Wrote {}x{} tray icon to {}",
+        size,
+        size,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn open_history(config: &Config) -> Result<HistoryStore, String> {
+    HistoryStore::open_default(config.history.retention())
+        .ok_or_else(|| "No data directory available".to_string())
+}
+
+fn list_history() -> Result<(), String> {
+    let config = Config::load()?;
+    let store = open_history(&config)?;
+    let identities = store.identities()?;
+    if identities.is_empty() {
+        println!("No battery history in {}", store.dir().display());
+        return Ok(());
+    }
+
+    for identity in identities {
+        let samples = store.load(&identity)?;
+        let Some(latest) = samples.last() else {
+            continue;
+        };
+        let name = config
+            .controller(&identity)
+            .and_then(|settings| settings.name.as_deref())
+            .unwrap_or("-");
+        let estimate = runtime_estimate::estimate(&samples, battery_history::unix_now())
+            .map(|estimate| {
+                format!(
+                    ", {} (confidence {:.0}%)",
+                    estimate.text(),
+                    estimate.confidence * 100.0
+                )
+            })
+            .unwrap_or_default();
+        println!(
+            "{}  {}  {} samples, last {}% {} over {}{}",
+            identity,
+            name,
+            samples.len(),
+            latest.capacity,
+            battery_history::status_name(&latest.status),
+            battery_history::transport_name(latest.transport),
+            estimate
+        );
+    }
+    Ok(())
+}
+
+fn export_history(args: &ExportArgs) -> Result<(), String> {
+    let config = Config::load()?;
+    let store = open_history(&config)?;
+
+    let mut rows = Vec::new();
+    let mut matched = false;
+    for identity in store.identities()? {
+        let name = config
+            .controller(&identity)
+            .and_then(|settings| settings.name.as_deref());
+        if let Some(wanted) = &args.controller {
+            let is_match = identity.eq_ignore_ascii_case(wanted)
+                || name.is_some_and(|name| name.eq_ignore_ascii_case(wanted));
+            if !is_match {
+                continue;
+            }
+        }
+        matched = true;
+        let samples = store.load(&identity)?;
+        rows.extend(
+            args.filter
+                .apply(&samples)
+                .into_iter()
+                .map(|sample| ExportRow::new(&identity, name, sample)),
+        );
+    }
+    if let (Some(wanted), false) = (&args.controller, matched) {
+        return Err(format!("No battery history for controller '{}'", wanted));
+    }
+
+    let text = history_export::format_rows(&rows, args.format)?;
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, text)
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            eprintln!("Exported {} samples to {}", rows.len(), path.display());
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn health_report(max_drop: f64) -> Result<(), String> {
+    let config = Config::load()?;
+    let store = open_history(&config)?;
+    let identities = store.identities()?;
+    if identities.is_empty() {
+        println!("No battery history in {}", store.dir().display());
+        return Ok(());
+    }
+
+    let mut degraded = 0;
+    for identity in identities {
+        let health = BatteryHealth::from_samples(&store.load(&identity)?);
+        match config
+            .controller(&identity)
+            .and_then(|settings| settings.name.as_deref())
+        {
+            Some(name) => println!("{} ({})", identity, name),
+            None => println!("{}", identity),
+        }
+        println!("  Equivalent cycles: {:.1}", health.equivalent_cycles);
+        let flag = if health.is_degraded(max_drop) {
+            degraded += 1;
+            "  DEGRADED"
+        } else {
+            ""
+        };
+        println!(
+            "  Full discharge:    {}{}",
+            trend_text(health.runtime_trend()),
+            flag
+        );
+        println!(
+            "  Full charge:       {}",
+            trend_text(health.charge_time_trend())
+        );
+        println!(
+            "  Timed runs:        {} discharge, {} charge",
+            health.discharge_runs.len(),
+            health.charge_runs.len()
+        );
+    }
+
+    if degraded > 0 {
+        println!(
+            "\n{} controller(s) lost more than {:.0}% of their runtime.",
+            degraded,
+            max_drop * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// "8h 20m -> 6h 10m (-26%)"
+fn trend_text(trend: Option<Trend>) -> String {
+    match trend {
+        Some(trend) => format!(
+            "{} -> {} ({:+.0}%)",
+            runtime_estimate::format_duration(trend.baseline_secs.round() as u64),
+            runtime_estimate::format_duration(trend.recent_secs.round() as u64),
+            trend.change() * 100.0
+        ),
+        None => "not enough runs yet".to_string(),
+    }
+}
+
+fn status(as_json: bool) -> Result<(), String> {
+    let controllers = match ipc::Client::connect() {
+        Some(mut client) => client.call("list_controllers", Value::Null)?,
+        None => json!(read_controllers_directly()?),
+    };
+    if as_json {
+        println!("{}", controllers);
+        return Ok(());
+    }
+
+    let controllers = controllers.as_array().cloned().unwrap_or_default();
+    if controllers.is_empty() {
+        println!("No controllers connected");
+    }
+    for controller in controllers {
+        let battery = match &controller["battery"] {
+            _ if controller["connected"] == json!(false) => "disconnected".to_string(),
+            Value::Null => "-".to_string(),
+            battery => format!(
+                "{}% {}",
+                battery["capacity"],
+                battery["status"].as_str().unwrap_or_default()
+            ),
+        };
+        println!(
+            "{}  {}  {}  {}  {}",
+            controller["player"],
+            controller["name"].as_str().unwrap_or_default(),
+            controller["identity"].as_str().unwrap_or_default(),
+            controller["transport"].as_str().unwrap_or_default(),
+            battery
+        );
+    }
+    Ok(())
+}
+
+/// Opens the pads ourselves, for when no overlay is running to ask.
+fn read_controllers_directly() -> Result<Vec<ControllerJson>, String> {
+    let config = Config::load()?;
+    let controllers = DirectController::open_all()?;
+    Ok(direct_entries(&config, &controllers)
+        .iter()
+        .map(ControllerJson::new)
+        .collect())
+}
+
+/// Registry-like entries for directly opened pads, numbered in path order.
+fn direct_entries(config: &Config, controllers: &[DirectController]) -> Vec<ControllerEntry> {
+    controllers
+        .iter()
+        .zip(1..)
+        .map(|(controller, player_number)| {
+            let mut entry = ControllerEntry {
+                path: controller.path.clone(),
+                info: controller.info.clone(),
+                player_number,
+                name: None,
+                battery: controller.read_battery(DIRECT_READ_TIMEOUT),
+                connected: true,
+            };
+            entry.name = config
+                .controller(entry.identity())
+                .and_then(|settings| settings.name.clone());
+            entry
+        })
+        .collect()
+}
+
+fn show_overlay(controller: Option<String>) -> Result<(), String> {
+    let mut client = ipc::Client::connect().ok_or("The overlay isn't running")?;
+    client.call("show_overlay", json!({ "controller": controller }))?;
+    Ok(())
+}
+
+fn set_lightbar(controller: &str, color: Rgb) -> Result<(), String> {
+    if let Some(mut client) = ipc::Client::connect() {
+        let params = json!({ "controller": controller, "color": color.to_string() });
+        return client.call("set_lightbar", params).map(|_| ());
+    }
+
+    let mut config = Config::load()?;
+    let controllers = DirectController::open_all()?;
+    let entries = direct_entries(&config, &controllers);
+    let index = entries
+        .iter()
+        .position(|entry| {
+            entry.player_number.to_string() == controller
+                || entry.identity().eq_ignore_ascii_case(controller)
+                || entry.path == controller
+                || entry.display_name().eq_ignore_ascii_case(controller)
+        })
+        .ok_or_else(|| format!("No controller '{}'", controller))?;
+    controllers[index].set_lightbar(color)?;
+    // Remembered so the overlay sets it again the next time it starts.
+    config.controller_mut(entries[index].identity()).lightbar = Some(color);
+    config.save()
+}
+
+fn watch() -> Result<(), String> {
+    let mut client = ipc::Client::connect().ok_or("The overlay isn't running")?;
+    client.call("subscribe", Value::Null)?;
+    loop {
+        println!("{}", client.next_notification()?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_args(args: &[&str]) -> Result<RenderArgs, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_render_args(&args)
+    }
+
+    #[test]
+    fn scale_accepts_the_supported_range() {
+        for (value, scale) in [("0.5", 0.5), ("1.25", 1.25), ("8", 8.0)] {
+            let args = render_args(&["--scale", value, "out.png"]).unwrap();
+            assert_eq!(args.scale, scale);
+        }
+        assert_eq!(render_args(&["out.png"]).unwrap().scale, 1.0);
+    }
+
+    #[test]
+    fn scale_rejects_values_outside_the_range() {
+        for value in ["0", "-1", "0.49", "8.01", "1e30", "inf", "NaN", "big"] {
+            assert_eq!(
+                render_args(&["--scale", value, "out.png"]),
+                Err(format!("Invalid scale '{}'", value)),
+            );
+        }
+    }
+}
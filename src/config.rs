@@ -0,0 +1,470 @@
+//! User settings, stored as TOML in the config directory.
+//!
+//! Missing keys fall back to their defaults, so an empty or partial file is
+//! always valid. Settings the app changes itself, like controller names, are
+//! written back with [`Config::save`].
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery_alerts::AlertThresholds,
+    battery_history::Retention,
+    dualsense::Button,
+    gestures::{Gesture, GestureDetector, GestureKind},
+    hooks::HookKind,
+    notifications::NotificationKind,
+    output_report::Rgb,
+    paths,
+    rules::Rule,
+    webhook::WebhookFormat,
+};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub alerts: AlertSettings,
+    pub notifications: NotificationSettings,
+    pub history: HistorySettings,
+    pub metrics: MetricsSettings,
+    pub web: WebSettings,
+    pub mqtt: MqttSettings,
+    pub webhook: WebhookSettings,
+    pub hooks: HookSettings,
+    pub mute_button: MuteButtonSettings,
+    /// See [`crate::gestures`] for the format.
+    pub gestures: GestureSettings,
+    /// See [`crate::rules`] for the format.
+    pub rules: Vec<Rule>,
+    /// Per-controller settings, keyed by controller identity.
+    pub controllers: BTreeMap<String, ControllerSettings>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertSettings {
+    pub low_threshold: u8,
+    pub critical_threshold: u8,
+    /// The level must climb more than this many points above a threshold
+    /// before it can fire again.
+    pub hysteresis: u8,
+    /// Charge level at which to say "unplug now"; 0 turns the alert off.
+    pub charge_ceiling: u8,
+    pub notify_when_full: bool,
+    /// Pop the overlay up when an alert fires.
+    pub show_overlay: bool,
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        let thresholds = AlertThresholds::default();
+        Self {
+            low_threshold: thresholds.low,
+            critical_threshold: thresholds.critical,
+            hysteresis: thresholds.hysteresis,
+            charge_ceiling: thresholds.charge_ceiling.unwrap_or(0),
+            notify_when_full: thresholds.notify_when_full,
+            show_overlay: true,
+        }
+    }
+}
+
+impl AlertSettings {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn thresholds(&self) -> AlertThresholds {
+        AlertThresholds {
+            low: self.low_threshold,
+            critical: self.critical_threshold,
+            hysteresis: self.hysteresis,
+            charge_ceiling: (self.charge_ceiling > 0).then_some(self.charge_ceiling),
+            notify_when_full: self.notify_when_full,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Desktop notifications, where the platform has them.
+    pub desktop: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self { desktop: true }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Record every battery reading to disk.
+    pub enabled: bool,
+    pub retention_days: u32,
+    /// Samples kept per controller.
+    pub max_samples: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        let retention = Retention::default();
+        Self {
+            enabled: true,
+            retention_days: (retention.max_age_secs / SECONDS_PER_DAY) as u32,
+            max_samples: retention.max_samples,
+        }
+    }
+}
+
+impl HistorySettings {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_age_secs: u64::from(self.retention_days) * SECONDS_PER_DAY,
+            max_samples: self.max_samples,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Serve Prometheus metrics over HTTP.
+    pub enabled: bool,
+    /// Address to listen on; loopback keeps the endpoint private to this machine.
+    pub listen: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:9477".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSettings {
+    /// Serve the stream overlay page, JSON snapshot and WebSocket events.
+    pub enabled: bool,
+    pub port: u16,
+    /// Listen on every interface instead of only loopback, for a streaming PC
+    /// that isn't the one the controllers are plugged into.
+    pub allow_remote: bool,
+}
+
+impl Default for WebSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9478,
+            allow_remote: false,
+        }
+    }
+}
+
+impl WebSettings {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn address(&self) -> SocketAddr {
+        let ip = if self.allow_remote {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        };
+        SocketAddr::from((ip, self.port))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttSettings {
+    /// Publish controller state to an MQTT broker.
+    pub enabled: bool,
+    pub host: String,
+    /// Usually 1883, or 8883 with TLS.
+    pub port: u16,
+    pub tls: bool,
+    /// Leave empty for brokers that allow anonymous clients.
+    pub username: String,
+    pub password: String,
+    pub client_id: String,
+    pub topic_prefix: String,
+    /// Publish Home Assistant discovery configs so pads show up on their own.
+    pub discovery: bool,
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            tls: false,
+            username: String::new(),
+            password: String::new(),
+            client_id: "ds-battery".to_string(),
+            topic_prefix: "ds-battery".to_string(),
+            discovery: true,
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookSettings {
+    /// POST notifications to `url`.
+    pub enabled: bool,
+    pub url: String,
+    /// `generic`, `slack` or `ntfy`.
+    pub format: WebhookFormat,
+    /// Custom JSON body instead of the format's. `{event}`, `{urgency}`,
+    /// `{title}`, `{message}`, `{controller}`, `{path}`, `{capacity}` and
+    /// `{status}` are replaced with JSON-escaped text.
+    pub template: String,
+    /// Notifications to send.
+    pub events: Vec<NotificationKind>,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            format: WebhookFormat::Generic,
+            template: String::new(),
+            events: vec![
+                NotificationKind::LowBattery,
+                NotificationKind::CriticalBattery,
+                NotificationKind::Disconnected,
+            ],
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Commands to run on controller events, listed per event as
+/// `[[hooks.low_battery]]` tables.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookSettings {
+    /// Seconds a hook may run before it's killed.
+    pub timeout_secs: u64,
+    /// Hooks allowed to run at once; a few more can wait their turn, and
+    /// events past that are skipped.
+    pub max_concurrent: usize,
+    /// Minimum seconds between runs for the same event on the same controller.
+    pub min_interval_secs: u64,
+    pub connected: Vec<HookCommand>,
+    pub disconnected: Vec<HookCommand>,
+    pub low_battery: Vec<HookCommand>,
+    pub critical_battery: Vec<HookCommand>,
+    pub charging_started: Vec<HookCommand>,
+    pub charge_ceiling_reached: Vec<HookCommand>,
+    pub fully_charged: Vec<HookCommand>,
+    pub mute_pressed: Vec<HookCommand>,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            max_concurrent: 2,
+            min_interval_secs: 2,
+            connected: Vec::new(),
+            disconnected: Vec::new(),
+            low_battery: Vec::new(),
+            critical_battery: Vec::new(),
+            charging_started: Vec::new(),
+            charge_ceiling_reached: Vec::new(),
+            fully_charged: Vec::new(),
+            mute_pressed: Vec::new(),
+        }
+    }
+}
+
+impl HookSettings {
+    pub fn commands(&self, kind: HookKind) -> &[HookCommand] {
+        match kind {
+            HookKind::Connected => &self.connected,
+            HookKind::Disconnected => &self.disconnected,
+            HookKind::LowBattery => &self.low_battery,
+            HookKind::CriticalBattery => &self.critical_battery,
+            HookKind::ChargingStarted => &self.charging_started,
+            HookKind::ChargeCeilingReached => &self.charge_ceiling_reached,
+            HookKind::FullyCharged => &self.fully_charged,
+            HookKind::MutePressed => &self.mute_pressed,
+        }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn is_empty(&self) -> bool {
+        HookKind::ALL
+            .iter()
+            .all(|kind| self.commands(*kind).is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookCommand {
+    /// Program to run; it isn't passed through a shell.
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+/// What the controller's mute button does, e.g.
+/// `action = { type = "toggle_microphone" }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MuteButtonSettings {
+    pub action: ButtonAction,
+    /// While a button or gesture toggles the microphone, light the mute
+    /// button's LED whenever the microphone is muted, however it got muted.
+    pub mic_led: bool,
+}
+
+impl Default for MuteButtonSettings {
+    fn default() -> Self {
+        Self {
+            action: ButtonAction::ToggleOverlay,
+            mic_led: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureSettings {
+    /// Milliseconds buttons have to be held for a `long_press`.
+    pub long_press_ms: u64,
+    /// Most milliseconds between the two presses of a `double_press`.
+    pub double_press_ms: u64,
+    pub bindings: Vec<GestureBinding>,
+}
+
+impl Default for GestureSettings {
+    fn default() -> Self {
+        Self {
+            long_press_ms: 1000,
+            double_press_ms: 300,
+            bindings: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GestureBinding {
+    /// Held together as a chord when there's more than one.
+    pub buttons: Vec<Button>,
+    /// `press`, `long_press` or `double_press`.
+    #[serde(default)]
+    pub gesture: GestureKind,
+    pub action: ButtonAction,
+}
+
+impl GestureSettings {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn detector(&self) -> GestureDetector {
+        GestureDetector::new(
+            self.bindings.iter().map(GestureBinding::gesture),
+            Duration::from_millis(self.long_press_ms),
+            Duration::from_millis(self.double_press_ms),
+        )
+    }
+}
+
+impl GestureBinding {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn gesture(&self) -> Gesture {
+        Gesture {
+            buttons: self.buttons.iter().copied().collect(),
+            kind: self.gesture,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ButtonAction {
+    ToggleOverlay,
+    /// Mutes or unmutes the system's default communications microphone.
+    ToggleMicrophone,
+    /// Runs a command with the event described the way hooks get it.
+    RunHook(HookCommand),
+    None,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lightbar: Option<Rgb>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        paths::config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// What the mute button and every gesture are bound to.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn button_actions(&self) -> impl Iterator<Item = &ButtonAction> {
+        std::iter::once(&self.mute_button.action)
+            .chain(self.gestures.bindings.iter().map(|binding| &binding.action))
+    }
+
+    /// Loads the config file, falling back to defaults when it doesn't exist.
+    pub fn load() -> Result<Self, String> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No config directory available")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, self.to_toml()?)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn controller(&self, identity: &str) -> Option<&ControllerSettings> {
+        self.controllers.get(identity)
+    }
+
+    pub fn controller_mut(&mut self, identity: &str) -> &mut ControllerSettings {
+        self.controllers.entry(identity.to_string()).or_default()
+    }
+}
@@ -0,0 +1,273 @@
+//! Tracks the controllers the UI knows about and hands out player numbers.
+
+use crate::{
+    dualsense::{BatteryReport, ControllerInfo},
+    overlay_layout::{ControllerRow, RowAnimation},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControllerEntry {
+    pub path: String,
+    pub info: ControllerInfo,
+    pub player_number: u8,
+    /// Name the user gave this pad, shown instead of the model name.
+    pub name: Option<String>,
+    pub battery: Option<BatteryReport>,
+    /// Pads that dropped stay listed as disconnected until
+    /// [`ControllerRegistry::remove_disconnected`] is called.
+    pub connected: bool,
+}
+
+/// Key for per-controller settings: the MAC address when we could read it,
+/// which survives switching between USB and Bluetooth, or else the HID path.
+pub fn identity<'a>(path: &'a str, info: &'a ControllerInfo) -> &'a str {
+    info.serial.as_deref().unwrap_or(path)
+}
+
+impl ControllerEntry {
+    /// See [`identity`].
+    pub fn identity(&self) -> &str {
+        identity(&self.path, &self.info)
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.info.model.name())
+    }
+}
+
+/// Known controllers, kept sorted by player number.
+#[derive(Default)]
+pub struct ControllerRegistry {
+    entries: Vec<ControllerEntry>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl ControllerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a controller and returns its player number. New controllers get
+    /// the lowest free number, so a pad that reconnects usually gets its slot back.
+    pub fn connect(&mut self, path: String, info: ControllerInfo) -> u8 {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.path == path) {
+            entry.info = info;
+            entry.connected = true;
+            return entry.player_number;
+        }
+
+        let player_number = (1..=u8::MAX)
+            .find(|number| !self.entries.iter().any(|e| e.player_number == *number))
+            .unwrap_or(u8::MAX);
+        let index = self
+            .entries
+            .partition_point(|entry| entry.player_number < player_number);
+        self.entries.insert(
+            index,
+            ControllerEntry {
+                path,
+                info,
+                player_number,
+                name: None,
+                battery: None,
+                connected: true,
+            },
+        );
+        player_number
+    }
+
+    pub fn disconnect(&mut self, path: &str) -> Option<ControllerEntry> {
+        let index = self.entries.iter().position(|entry| entry.path == path)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Keeps the entry around, flagged as disconnected, so the overlay can tell
+    /// the user which pad dropped. Returns `false` for unknown controllers.
+    pub fn mark_disconnected(&mut self, path: &str) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.path == path) {
+            Some(entry) => {
+                entry.connected = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops every entry flagged by [`Self::mark_disconnected`] and returns them.
+    pub fn remove_disconnected(&mut self) -> Vec<ControllerEntry> {
+        let (connected, disconnected) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| entry.connected);
+        self.entries = connected;
+        disconnected
+    }
+
+    /// Stores the latest battery report. Returns `false` for unknown controllers.
+    pub fn update_battery(&mut self, path: &str, report: BatteryReport) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.path == path) {
+            Some(entry) => {
+                entry.battery = Some(report);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_name(&mut self, path: &str, name: Option<String>) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.path == path) {
+            Some(entry) => {
+                entry.name = name;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&ControllerEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ControllerEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// One overlay row per controller in player order, highlighting `highlighted_path`.
+    pub fn overlay_rows(&self, highlighted_path: Option<&str>) -> Vec<ControllerRow> {
+        self.entries
+            .iter()
+            .map(|entry| ControllerRow {
+                player_number: entry.player_number,
+                info: entry.info.clone(),
+                name: entry.name.clone(),
+                report: entry.battery.clone(),
+                estimate: None,
+                connected: entry.connected,
+                highlighted: Some(entry.path.as_str()) == highlighted_path,
+                animation: RowAnimation::default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{BatteryStatus, ConnectionType, ControllerModel};
+
+    fn info(serial: Option<&str>) -> ControllerInfo {
+        ControllerInfo {
+            model: ControllerModel::DualSense,
+            connection_type: ConnectionType::Usb,
+            serial: serial.map(str::to_string),
+            firmware: None,
+        }
+    }
+
+    fn connect(registry: &mut ControllerRegistry, path: &str) -> u8 {
+        registry.connect(path.to_string(), info(None))
+    }
+
+    fn players(registry: &ControllerRegistry) -> Vec<(&str, u8)> {
+        registry
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.player_number))
+            .collect()
+    }
+
+    #[test]
+    fn new_pads_get_consecutive_numbers() {
+        let mut registry = ControllerRegistry::new();
+        assert_eq!(connect(&mut registry, "a"), 1);
+        assert_eq!(connect(&mut registry, "b"), 2);
+        assert_eq!(connect(&mut registry, "c"), 3);
+        assert_eq!(players(&registry), [("a", 1), ("b", 2), ("c", 3)]);
+    }
+
+    #[test]
+    fn a_freed_number_goes_to_the_next_pad_in_player_order() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "a");
+        connect(&mut registry, "b");
+        connect(&mut registry, "c");
+        assert_eq!(registry.disconnect("b").unwrap().player_number, 2);
+        assert_eq!(players(&registry), [("a", 1), ("c", 3)]);
+
+        assert_eq!(connect(&mut registry, "d"), 2);
+        assert_eq!(players(&registry), [("a", 1), ("d", 2), ("c", 3)]);
+        assert_eq!(connect(&mut registry, "e"), 4);
+        assert_eq!(registry.len(), 4);
+    }
+
+    #[test]
+    fn a_pad_reconnecting_at_the_same_path_keeps_its_slot() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "a");
+        connect(&mut registry, "b");
+        registry.set_name("b", Some("Couch".to_string()));
+        assert!(registry.mark_disconnected("b"));
+        // The dropped pad's number stays taken while it's still listed.
+        assert_eq!(connect(&mut registry, "c"), 3);
+
+        assert_eq!(registry.connect("b".to_string(), info(Some("aa:bb"))), 2);
+        let entry = registry.get("b").unwrap();
+        assert!(entry.connected);
+        assert_eq!(entry.name.as_deref(), Some("Couch"));
+        assert_eq!(entry.info.serial.as_deref(), Some("aa:bb"));
+        assert_eq!(players(&registry), [("a", 1), ("b", 2), ("c", 3)]);
+    }
+
+    #[test]
+    fn a_pad_reconnecting_after_removal_gets_the_lowest_free_number() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "a");
+        connect(&mut registry, "b");
+        registry.mark_disconnected("a");
+        let removed = registry.remove_disconnected();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, "a");
+        assert_eq!(players(&registry), [("b", 2)]);
+
+        assert_eq!(connect(&mut registry, "a"), 1);
+        assert_eq!(players(&registry), [("a", 1), ("b", 2)]);
+    }
+
+    #[test]
+    fn unknown_paths_are_reported() {
+        let mut registry = ControllerRegistry::new();
+        assert!(registry.disconnect("a").is_none());
+        assert!(!registry.mark_disconnected("a"));
+        assert!(!registry.update_battery("a", BatteryReport::new(50, BatteryStatus::Full)));
+        assert!(!registry.set_name("a", None));
+        assert!(registry.remove_disconnected().is_empty());
+    }
+
+    #[test]
+    fn identity_prefers_the_serial() {
+        let mut registry = ControllerRegistry::new();
+        registry.connect("a".to_string(), info(Some("aa:bb")));
+        connect(&mut registry, "b");
+        assert_eq!(registry.get("a").unwrap().identity(), "aa:bb");
+        assert_eq!(registry.get("b").unwrap().identity(), "b");
+    }
+
+    #[test]
+    fn overlay_rows_follow_player_order() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "a");
+        connect(&mut registry, "b");
+        registry.disconnect("a");
+        connect(&mut registry, "c");
+        registry.update_battery("b", BatteryReport::new(40, BatteryStatus::Charging));
+
+        let rows = registry.overlay_rows(Some("b"));
+        let summary: Vec<(u8, bool, bool)> = rows
+            .iter()
+            .map(|row| (row.player_number, row.highlighted, row.report.is_some()))
+            .collect();
+        assert_eq!(summary, [(1, false, false), (2, true, true)]);
+    }
+}
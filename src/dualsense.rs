@@ -1,16 +1,64 @@
 use std::ffi::CStr;
-use std::time::Instant;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery_alerts::{BatteryAlertTracker, ChargeAlertTracker},
+    checksum::crc32_update,
+    gestures::{Gesture, GestureDetector},
+    output_report::{DEFAULT_LIGHTBAR, Rgb},
+};
 
 pub(crate) const VENDOR_ID_SONY: u16 = 0x054C;
 pub(crate) const PRODUCT_ID_DUALSENSE: u16 = 0x0CE6;
 pub(crate) const PRODUCT_ID_DUALSENSE_EDGE: u16 = 0x0DF2;
 
 const _USB_INPUT_REPORT_ID: u8 = 0x01;
-const _BLUETOOTH_INPUT_REPORT_ID: u8 = 0x31;
+const BLUETOOTH_INPUT_REPORT_ID: u8 = 0x31;
+/// Full Bluetooth input report, including its trailing CRC-32.
+pub(crate) const BLUETOOTH_INPUT_REPORT_SIZE: usize = 78;
+/// Prepended to input reports when computing their CRC, but never sent.
+const BLUETOOTH_INPUT_CRC_SEED: u8 = 0xA1;
+
+pub(crate) const FEATURE_REPORT_PAIRING_INFO: u8 = 0x09;
+pub(crate) const FEATURE_REPORT_PAIRING_INFO_SIZE: usize = 20;
+pub(crate) const FEATURE_REPORT_FIRMWARE_INFO: u8 = 0x20;
+pub(crate) const FEATURE_REPORT_FIRMWARE_INFO_SIZE: usize = 64;
 
-const USB_MIC_MUTE_BYTE_INDEX: usize = 10;
-const BLUETOOTH_MIC_MUTE_BYTE_INDEX: usize = 11;
-const MIC_MUTE_BUTTON_MASK: u8 = 0x04;
+const PAIRING_INFO_MAC_OFFSET: usize = 1;
+const FIRMWARE_INFO_HARDWARE_VERSION_OFFSET: usize = 24;
+const FIRMWARE_INFO_FIRMWARE_VERSION_OFFSET: usize = 28;
+const FIRMWARE_INFO_UPDATE_VERSION_OFFSET: usize = 44;
+
+/// Three bytes of buttons, the first starting with the d-pad as a hat switch.
+const USB_BUTTONS_OFFSET: usize = 8;
+const BLUETOOTH_BUTTONS_OFFSET: usize = 9;
+const DPAD_MASK: u8 = 0x0F;
+/// Face buttons, then shoulder and menu buttons, then the PS, touchpad and mute
+/// buttons and the Edge's extra ones, in the order the report packs them.
+const BUTTON_BITS: [(usize, u8, Button); 19] = [
+    (0, 0x10, Button::Square),
+    (0, 0x20, Button::Cross),
+    (0, 0x40, Button::Circle),
+    (0, 0x80, Button::Triangle),
+    (1, 0x01, Button::L1),
+    (1, 0x02, Button::R1),
+    (1, 0x04, Button::L2),
+    (1, 0x08, Button::R2),
+    (1, 0x10, Button::Create),
+    (1, 0x20, Button::Options),
+    (1, 0x40, Button::L3),
+    (1, 0x80, Button::R3),
+    (2, 0x01, Button::Ps),
+    (2, 0x02, Button::Touchpad),
+    (2, 0x04, Button::Mute),
+    (2, 0x10, Button::LeftFunction),
+    (2, 0x20, Button::RightFunction),
+    (2, 0x40, Button::LeftPaddle),
+    (2, 0x80, Button::RightPaddle),
+];
 
 const BLUETOOTH_BATTERY_BYTE_INDEX: usize = 54;
 const USB_BATTERY_BYTE_INDEX: usize = 53;
@@ -49,30 +97,328 @@ impl BatteryReport {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControllerModel {
+    DualSense,
+    DualSenseEdge,
+}
+
+impl ControllerModel {
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        match product_id {
+            PRODUCT_ID_DUALSENSE => Some(ControllerModel::DualSense),
+            PRODUCT_ID_DUALSENSE_EDGE => Some(ControllerModel::DualSenseEdge),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControllerModel::DualSense => "DualSense",
+            ControllerModel::DualSenseEdge => "DualSense Edge",
+        }
+    }
+
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            ControllerModel::DualSense => "DualSense",
+            ControllerModel::DualSenseEdge => "Edge",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionType {
+    Usb,
+    Bluetooth,
+}
+
+impl ConnectionType {
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            ConnectionType::Usb => "USB",
+            ConnectionType::Bluetooth => "BT",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    pub hardware_version: u32,
+    pub firmware_version: u32,
+    /// The version the PlayStation shows in its device settings.
+    pub update_version: u16,
+}
+
+impl FirmwareInfo {
+    pub fn display_version(&self) -> String {
+        format!(
+            "{:X}.{:02X}",
+            self.update_version >> 8,
+            self.update_version & 0xFF
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControllerInfo {
+    pub model: ControllerModel,
+    pub connection_type: ConnectionType,
+    /// Bluetooth MAC address, the same over USB and Bluetooth. `None` if the
+    /// pairing report couldn't be read.
+    pub serial: Option<String>,
+    pub firmware: Option<FirmwareInfo>,
+}
+
+/// Written in snake case in the config, e.g. `dpad_up` or `ps`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Button {
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    Square,
+    Cross,
+    Circle,
+    Triangle,
+    L1,
+    R1,
+    L2,
+    R2,
+    Create,
+    Options,
+    L3,
+    R3,
+    Ps,
+    Touchpad,
+    Mute,
+    /// The DualSense Edge's function buttons and back paddles.
+    LeftFunction,
+    RightFunction,
+    LeftPaddle,
+    RightPaddle,
+}
+
+impl Button {
+    pub const ALL: [Button; 23] = [
+        Button::DpadUp,
+        Button::DpadDown,
+        Button::DpadLeft,
+        Button::DpadRight,
+        Button::Square,
+        Button::Cross,
+        Button::Circle,
+        Button::Triangle,
+        Button::L1,
+        Button::R1,
+        Button::L2,
+        Button::R2,
+        Button::Create,
+        Button::Options,
+        Button::L3,
+        Button::R3,
+        Button::Ps,
+        Button::Touchpad,
+        Button::Mute,
+        Button::LeftFunction,
+        Button::RightFunction,
+        Button::LeftPaddle,
+        Button::RightPaddle,
+    ];
+
+    /// Name used in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::DpadUp => "dpad_up",
+            Button::DpadDown => "dpad_down",
+            Button::DpadLeft => "dpad_left",
+            Button::DpadRight => "dpad_right",
+            Button::Square => "square",
+            Button::Cross => "cross",
+            Button::Circle => "circle",
+            Button::Triangle => "triangle",
+            Button::L1 => "l1",
+            Button::R1 => "r1",
+            Button::L2 => "l2",
+            Button::R2 => "r2",
+            Button::Create => "create",
+            Button::Options => "options",
+            Button::L3 => "l3",
+            Button::R3 => "r3",
+            Button::Ps => "ps",
+            Button::Touchpad => "touchpad",
+            Button::Mute => "mute",
+            Button::LeftFunction => "left_function",
+            Button::RightFunction => "right_function",
+            Button::LeftPaddle => "left_paddle",
+            Button::RightPaddle => "right_paddle",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// A set of buttons, such as the ones held down in an input report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Buttons(u32);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+
+    pub fn contains(self, button: Button) -> bool {
+        self.0 & button.bit() != 0
+    }
+
+    pub fn contains_all(self, other: Buttons) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn union(self, other: Buttons) -> Buttons {
+        Buttons(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Buttons) -> Buttons {
+        Buttons(self.0 & other.0)
+    }
+
+    pub fn difference(self, other: Buttons) -> Buttons {
+        Buttons(self.0 & !other.0)
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Button> {
+        Button::ALL
+            .into_iter()
+            .filter(move |button| self.contains(*button))
+    }
+}
+
+impl FromIterator<Button> for Buttons {
+    fn from_iter<I: IntoIterator<Item = Button>>(buttons: I) -> Self {
+        Buttons(
+            buttons
+                .into_iter()
+                .fold(0, |bits, button| bits | button.bit()),
+        )
+    }
+}
+
+/// Joined with `+`, like `ps+options`.
+impl fmt::Display for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.iter().map(Button::name).collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ControllerEvent {
-    DeviceConnected(String),
+    DeviceConnected(String, ControllerInfo),
     DeviceDisconnected(String),
     BatteryUpdate(String, BatteryReport),
     MuteButtonPressed(String),
+    /// A configured button gesture was recognised.
+    Gesture(String, Gesture),
+    /// Capacity dropped to the low threshold while discharging.
+    LowBattery(String, BatteryReport),
+    /// Capacity dropped to the critical threshold while discharging.
+    CriticalBattery(String, BatteryReport),
+    /// Charging reached the configured ceiling.
+    ChargeCeilingReached(String, BatteryReport),
+    /// Status changed to full.
+    FullyCharged(String, BatteryReport),
+    /// Input report counts since the previous stats event for this controller.
+    ReportStats(String, ReportStats),
+}
+
+impl ControllerEvent {
+    /// HID path of the controller the event is about.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn path(&self) -> &str {
+        match self {
+            ControllerEvent::DeviceConnected(path, _)
+            | ControllerEvent::DeviceDisconnected(path)
+            | ControllerEvent::BatteryUpdate(path, _)
+            | ControllerEvent::MuteButtonPressed(path)
+            | ControllerEvent::Gesture(path, _)
+            | ControllerEvent::LowBattery(path, _)
+            | ControllerEvent::CriticalBattery(path, _)
+            | ControllerEvent::ChargeCeilingReached(path, _)
+            | ControllerEvent::FullyCharged(path, _)
+            | ControllerEvent::ReportStats(path, _) => path,
+        }
+    }
+}
+
+/// What the polling thread saw from one controller over a stretch of time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReportStats {
+    pub reports: u64,
+    pub read_errors: u64,
+    /// Bluetooth reports dropped because their checksum didn't match.
+    pub crc_failures: u64,
+    /// Time since the last input report when the stats were sent.
+    pub last_report_age: Option<Duration>,
+}
+
+/// Requests from the UI to the polling thread, which owns the devices.
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Debug, Clone)]
+pub enum ControllerCommand {
+    SetLightbar(String, Rgb),
+    /// Lights the player LEDs in this bit mask.
+    SetPlayerLeds(String, u8),
+    /// Lights or clears the LED in the mute button.
+    SetMicLed(String, bool),
+    /// Flashes the lightbar for a moment so a pad can be picked out of a pile.
+    Identify(String),
 }
 
 pub(crate) struct ConnectedControllerState {
     pub device: hidapi::HidDevice,
     pub is_bluetooth: bool,
-    pub previous_mute_state: bool,
+    pub previous_buttons: Buttons,
+    pub gestures: GestureDetector,
     pub last_battery_poll: Instant,
     pub last_battery_report: Option<BatteryReport>,
+    pub battery_alerts: BatteryAlertTracker,
+    pub charge_alerts: ChargeAlertTracker,
+    /// Bluetooth output reports carry a 4-bit sequence number.
+    pub output_sequence: u8,
+    /// The colour to go back to after identifying.
+    pub lightbar: Rgb,
+    pub identify_started: Option<Instant>,
+    pub identify_color: Option<Rgb>,
+    /// Counts not yet sent in a [`ControllerEvent::ReportStats`].
+    pub pending_stats: ReportStats,
+    pub last_report: Option<Instant>,
+    pub last_stats_sent: Instant,
 }
 
 impl ConnectedControllerState {
-    pub fn new(device: hidapi::HidDevice, is_bluetooth: bool) -> Self {
+    pub fn new(device: hidapi::HidDevice, is_bluetooth: bool, gestures: GestureDetector) -> Self {
         Self {
             device,
             is_bluetooth,
-            previous_mute_state: false,
-            last_battery_poll: Instant::now() - std::time::Duration::from_secs(1000),
+            previous_buttons: Buttons::NONE,
+            gestures,
+            last_battery_poll: Instant::now() - Duration::from_secs(1000),
             last_battery_report: None,
+            battery_alerts: BatteryAlertTracker::default(),
+            charge_alerts: ChargeAlertTracker::default(),
+            output_sequence: 0,
+            lightbar: DEFAULT_LIGHTBAR,
+            identify_started: None,
+            identify_color: None,
+            pending_stats: ReportStats::default(),
+            last_report: None,
+            last_stats_sent: Instant::now(),
         }
     }
 }
@@ -111,21 +457,82 @@ pub(crate) fn parse_battery(report: &[u8], is_bluetooth: bool) -> Option<Battery
     Some(BatteryReport::new(battery_capacity, battery_status))
 }
 
-/// Checks if the mute button is currently pressed based on an HID input report.
-pub(crate) fn mute_button_pressed(report: &[u8], is_bluetooth: bool) -> Option<bool> {
-    let mic_byte_index = if is_bluetooth {
-        BLUETOOTH_MIC_MUTE_BYTE_INDEX
+/// Whether a Bluetooth input report's CRC-32 matches its contents, or `None`
+/// for reports that don't carry one.
+pub(crate) fn bluetooth_report_crc_valid(report: &[u8]) -> Option<bool> {
+    if report.len() != BLUETOOTH_INPUT_REPORT_SIZE || report[0] != BLUETOOTH_INPUT_REPORT_ID {
+        return None;
+    }
+    let crc_offset = report.len() - 4;
+    let expected = u32::from_le_bytes(report[crc_offset..].try_into().ok()?);
+    let crc = crc32_update(
+        crc32_update(0, &[BLUETOOTH_INPUT_CRC_SEED]),
+        &report[..crc_offset],
+    );
+    Some(crc == expected)
+}
+
+/// Decodes the buttons held down in an HID input report.
+pub(crate) fn parse_buttons(report: &[u8], is_bluetooth: bool) -> Option<Buttons> {
+    let offset = if is_bluetooth {
+        BLUETOOTH_BUTTONS_OFFSET
     } else {
-        USB_MIC_MUTE_BYTE_INDEX
+        USB_BUTTONS_OFFSET
     };
+    let bytes = report.get(offset..offset + 3)?;
 
-    if report.len() <= mic_byte_index {
-        return None; // Report too short
-    }
+    let dpad: &[Button] = match bytes[0] & DPAD_MASK {
+        0 => &[Button::DpadUp],
+        1 => &[Button::DpadUp, Button::DpadRight],
+        2 => &[Button::DpadRight],
+        3 => &[Button::DpadDown, Button::DpadRight],
+        4 => &[Button::DpadDown],
+        5 => &[Button::DpadDown, Button::DpadLeft],
+        6 => &[Button::DpadLeft],
+        7 => &[Button::DpadUp, Button::DpadLeft],
+        _ => &[],
+    };
+    let pressed = BUTTON_BITS
+        .iter()
+        .filter(|(index, mask, _)| bytes[*index] & mask != 0)
+        .map(|(_, _, button)| *button);
+    Some(dpad.iter().copied().chain(pressed).collect())
+}
 
-    let is_pressed = (report[mic_byte_index] & MIC_MUTE_BUTTON_MASK) != 0;
+/// Reads the controller's MAC address from a pairing info feature report. The
+/// report stores it least significant byte first.
+pub(crate) fn parse_mac_address(report: &[u8]) -> Option<String> {
+    let bytes = report.get(PAIRING_INFO_MAC_OFFSET..PAIRING_INFO_MAC_OFFSET + 6)?;
+    if report[0] != FEATURE_REPORT_PAIRING_INFO || bytes.iter().all(|b| *b == 0) {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
 
-    Some(is_pressed)
+pub(crate) fn parse_firmware_info(report: &[u8]) -> Option<FirmwareInfo> {
+    if report.first() != Some(&FEATURE_REPORT_FIRMWARE_INFO) {
+        return None;
+    }
+    let le_u32 = |offset: usize| {
+        report
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let update_version = report
+        .get(FIRMWARE_INFO_UPDATE_VERSION_OFFSET..FIRMWARE_INFO_UPDATE_VERSION_OFFSET + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))?;
+    Some(FirmwareInfo {
+        hardware_version: le_u32(FIRMWARE_INFO_HARDWARE_VERSION_OFFSET)?,
+        firmware_version: le_u32(FIRMWARE_INFO_FIRMWARE_VERSION_OFFSET)?,
+        update_version,
+    })
 }
 
 /// Helper to convert CStr to String, handling potential errors.
@@ -0,0 +1,389 @@
+//! Recognises button gestures from the stream of held buttons: presses, long
+//! presses, double presses, and chords of several buttons doing any of those.
+//!
+//! ```toml
+//! [gestures]
+//! long_press_ms = 1000
+//!
+//! [[gestures.bindings]]
+//! buttons = ["ps", "options"]
+//! gesture = "long_press"
+//! action = { type = "toggle_overlay" }
+//! ```
+//!
+//! Only buttons that appear in some binding count. A gesture's buttons have to
+//! be the only counted buttons held, so `ps` alone doesn't fire while
+//! `ps+options` is being pressed, and a press is cancelled when another
+//! counted button joins in. A press fires on release, or once the double
+//! press window has passed if its buttons also have a double press bound; a
+//! long press fires as soon as the buttons have been held long enough, and a
+//! double press as soon as the second press lands. The detector is pure: the
+//! polling thread feeds it every report with the time it arrived.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dualsense::Buttons;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureKind {
+    #[default]
+    Press,
+    LongPress,
+    DoublePress,
+}
+
+impl GestureKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            GestureKind::Press => "press",
+            GestureKind::LongPress => "long_press",
+            GestureKind::DoublePress => "double_press",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Gesture {
+    pub buttons: Buttons,
+    pub kind: GestureKind,
+}
+
+/// Like `ps+options long_press`.
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.buttons, self.kind.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Down {
+        since: Instant,
+    },
+    /// Released after a press, waiting to see whether a second one follows.
+    Released {
+        at: Instant,
+    },
+    /// The press already fired or was cancelled; waiting for the buttons to
+    /// be let go.
+    Spent,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Timing {
+    long_press: Duration,
+    double_press: Duration,
+}
+
+/// The bound gestures that share one set of buttons.
+#[derive(Clone, Debug)]
+struct Group {
+    buttons: Buttons,
+    press: bool,
+    long_press: bool,
+    double_press: bool,
+    phase: Phase,
+}
+
+#[derive(Clone, Debug)]
+pub struct GestureDetector {
+    timing: Timing,
+    groups: Vec<Group>,
+    /// Every button in some gesture.
+    counted: Buttons,
+    previous: Buttons,
+}
+
+impl GestureDetector {
+    /// `long_press` is how long buttons have to be held for a long press, and
+    /// `double_press` the most time between releasing the first press and
+    /// starting the second.
+    pub fn new(
+        gestures: impl IntoIterator<Item = Gesture>,
+        long_press: Duration,
+        double_press: Duration,
+    ) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        for gesture in gestures {
+            if gesture.buttons.is_empty() {
+                continue;
+            }
+            let index = match groups
+                .iter()
+                .position(|group| group.buttons == gesture.buttons)
+            {
+                Some(index) => index,
+                None => {
+                    groups.push(Group {
+                        buttons: gesture.buttons,
+                        press: false,
+                        long_press: false,
+                        double_press: false,
+                        phase: Phase::Idle,
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            match gesture.kind {
+                GestureKind::Press => group.press = true,
+                GestureKind::LongPress => group.long_press = true,
+                GestureKind::DoublePress => group.double_press = true,
+            }
+        }
+        let counted = groups
+            .iter()
+            .fold(Buttons::NONE, |counted, group| counted.union(group.buttons));
+        Self {
+            timing: Timing {
+                long_press,
+                double_press,
+            },
+            groups,
+            counted,
+            previous: Buttons::NONE,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Takes the buttons held in a report received at `now` and returns the
+    /// gestures that completed, in the order their groups were bound.
+    pub fn update(&mut self, held: Buttons, now: Instant) -> Vec<Gesture> {
+        let held = held.intersection(self.counted);
+        let previous = std::mem::replace(&mut self.previous, held);
+        let mut gestures = Vec::new();
+        for group in &mut self.groups {
+            group.update(held, previous, now, self.timing, &mut gestures);
+        }
+        gestures
+    }
+}
+
+impl Group {
+    fn update(
+        &mut self,
+        held: Buttons,
+        previous: Buttons,
+        now: Instant,
+        timing: Timing,
+        gestures: &mut Vec<Gesture>,
+    ) {
+        let buttons = self.buttons;
+        let mut fire = |kind: GestureKind| gestures.push(Gesture { buttons, kind });
+        let all_held = held.contains_all(self.buttons);
+        let pressed = all_held && !previous.contains_all(self.buttons);
+        let others_held = !held.difference(self.buttons).is_empty();
+
+        // A pending press fires once the double press window has passed or
+        // something else gets pressed.
+        if let Phase::Released { at } = self.phase
+            && (now.saturating_duration_since(at) > timing.double_press || others_held)
+        {
+            if self.press {
+                fire(GestureKind::Press);
+            }
+            self.phase = Phase::Idle;
+        }
+
+        self.phase = match self.phase {
+            Phase::Released { .. } if pressed => {
+                fire(GestureKind::DoublePress);
+                Phase::Spent
+            }
+            Phase::Idle if pressed && !others_held => Phase::Down { since: now },
+            Phase::Down { .. } if !all_held => {
+                if self.double_press {
+                    Phase::Released { at: now }
+                } else {
+                    if self.press {
+                        fire(GestureKind::Press);
+                    }
+                    Phase::Idle
+                }
+            }
+            Phase::Down { .. } if others_held => Phase::Spent,
+            Phase::Down { since }
+                if self.long_press && now.saturating_duration_since(since) >= timing.long_press =>
+            {
+                fire(GestureKind::LongPress);
+                Phase::Spent
+            }
+            Phase::Spent if !all_held => Phase::Idle,
+            phase => phase,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::Button::{self, Cross, Options, Ps};
+    use GestureKind::{DoublePress, LongPress, Press};
+
+    const LONG_PRESS: Duration = Duration::from_millis(1000);
+    const DOUBLE_PRESS: Duration = Duration::from_millis(300);
+
+    fn buttons(buttons: &[Button]) -> Buttons {
+        buttons.iter().copied().collect()
+    }
+
+    fn gesture(held: &[Button], kind: GestureKind) -> Gesture {
+        Gesture {
+            buttons: buttons(held),
+            kind,
+        }
+    }
+
+    /// Feeds reports to a detector at millisecond offsets from a fixed start.
+    struct Reports {
+        detector: GestureDetector,
+        start: Instant,
+    }
+
+    impl Reports {
+        fn new(gestures: &[Gesture]) -> Self {
+            Self {
+                detector: GestureDetector::new(gestures.iter().copied(), LONG_PRESS, DOUBLE_PRESS),
+                start: Instant::now(),
+            }
+        }
+
+        fn at(&mut self, ms: u64, held: &[Button]) -> Vec<Gesture> {
+            self.detector
+                .update(buttons(held), self.start + Duration::from_millis(ms))
+        }
+    }
+
+    #[test]
+    fn press_fires_on_release() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press)]);
+        assert_eq!(reports.at(0, &[Ps]), []);
+        assert_eq!(reports.at(5000, &[Ps]), []);
+        assert_eq!(reports.at(5010, &[]), [gesture(&[Ps], Press)]);
+        assert_eq!(reports.at(5020, &[]), []);
+    }
+
+    #[test]
+    fn press_waits_out_the_double_press_window() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps], DoublePress)]);
+        assert_eq!(reports.at(0, &[Ps]), []);
+        assert_eq!(reports.at(100, &[]), []);
+        assert_eq!(reports.at(400, &[]), []);
+        assert_eq!(reports.at(401, &[]), [gesture(&[Ps], Press)]);
+        assert_eq!(reports.at(500, &[]), []);
+    }
+
+    #[test]
+    fn second_press_in_the_window_is_a_double_press() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps], DoublePress)]);
+        reports.at(0, &[Ps]);
+        reports.at(100, &[]);
+        assert_eq!(reports.at(300, &[Ps]), [gesture(&[Ps], DoublePress)]);
+        // Neither letting go nor waiting turns it into a press afterwards.
+        assert_eq!(reports.at(350, &[]), []);
+        assert_eq!(reports.at(1000, &[]), []);
+    }
+
+    #[test]
+    fn slow_second_press_is_two_presses() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps], DoublePress)]);
+        reports.at(0, &[Ps]);
+        reports.at(100, &[]);
+        assert_eq!(reports.at(500, &[Ps]), [gesture(&[Ps], Press)]);
+        assert_eq!(reports.at(600, &[]), []);
+        assert_eq!(reports.at(901, &[]), [gesture(&[Ps], Press)]);
+    }
+
+    #[test]
+    fn pending_press_fires_when_another_button_is_pressed() {
+        let mut reports = Reports::new(&[
+            gesture(&[Ps], Press),
+            gesture(&[Ps], DoublePress),
+            gesture(&[Cross], Press),
+        ]);
+        reports.at(0, &[Ps]);
+        reports.at(100, &[]);
+        assert_eq!(reports.at(150, &[Cross]), [gesture(&[Ps], Press)]);
+        assert_eq!(reports.at(200, &[]), [gesture(&[Cross], Press)]);
+    }
+
+    #[test]
+    fn long_press_fires_while_held() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps], LongPress)]);
+        assert_eq!(reports.at(0, &[Ps]), []);
+        assert_eq!(reports.at(999, &[Ps]), []);
+        assert_eq!(reports.at(1000, &[Ps]), [gesture(&[Ps], LongPress)]);
+        assert_eq!(reports.at(2000, &[Ps]), []);
+        // Letting go afterwards isn't a press as well.
+        assert_eq!(reports.at(2010, &[]), []);
+    }
+
+    #[test]
+    fn press_is_cancelled_when_another_counted_button_joins() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Cross], Press)]);
+        reports.at(0, &[Ps]);
+        assert_eq!(reports.at(50, &[Ps, Cross]), []);
+        assert_eq!(reports.at(100, &[Cross]), []);
+        assert_eq!(reports.at(150, &[]), []);
+    }
+
+    #[test]
+    fn uncounted_buttons_are_ignored() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press)]);
+        reports.at(0, &[Ps]);
+        assert_eq!(reports.at(50, &[Ps, Cross]), []);
+        assert_eq!(reports.at(100, &[Cross]), [gesture(&[Ps], Press)]);
+    }
+
+    #[test]
+    fn chord_pressed_across_two_reports() {
+        let mut reports =
+            Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps, Options], LongPress)]);
+        assert_eq!(reports.at(0, &[Ps]), []);
+        // The chord's hold starts once both are down.
+        assert_eq!(reports.at(20, &[Ps, Options]), []);
+        assert_eq!(reports.at(1019, &[Ps, Options]), []);
+        assert_eq!(
+            reports.at(1020, &[Ps, Options]),
+            [gesture(&[Ps, Options], LongPress)]
+        );
+        // Releasing one at a time doesn't leave `ps` to fire on its own.
+        assert_eq!(reports.at(1100, &[Ps]), []);
+        assert_eq!(reports.at(1200, &[]), []);
+    }
+
+    #[test]
+    fn short_chord_press_fires_on_release() {
+        let mut reports = Reports::new(&[gesture(&[Ps], Press), gesture(&[Ps, Options], Press)]);
+        reports.at(0, &[Ps, Options]);
+        assert_eq!(
+            reports.at(100, &[Options]),
+            [gesture(&[Ps, Options], Press)]
+        );
+        assert_eq!(reports.at(150, &[]), []);
+    }
+
+    #[test]
+    fn gestures_on_empty_button_sets_are_dropped() {
+        let detector = GestureDetector::new([gesture(&[], Press)], LONG_PRESS, DOUBLE_PRESS);
+        assert!(detector.is_empty());
+    }
+
+    #[test]
+    fn display_names_buttons_and_kind() {
+        assert_eq!(
+            gesture(&[Ps, Options], LongPress).to_string(),
+            "options+ps long_press"
+        );
+    }
+}
@@ -25,10 +25,12 @@ use windows::{
             },
             Dxgi::{
                 Common::{
-                    DXGI_ALPHA_MODE_PREMULTIPLIED, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC,
+                    DXGI_ALPHA_MODE_PREMULTIPLIED, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_UNKNOWN,
+                    DXGI_SAMPLE_DESC,
                 },
-                DXGI_SCALING_STRETCH, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
-                DXGI_USAGE_RENDER_TARGET_OUTPUT, IDXGIDevice, IDXGIFactory2, IDXGISwapChain1,
+                DXGI_SCALING_STRETCH, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_CHAIN_FLAG,
+                DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL, DXGI_USAGE_RENDER_TARGET_OUTPUT, IDXGIDevice,
+                IDXGIFactory2, IDXGISwapChain1,
             },
         },
     },
@@ -37,7 +39,8 @@ use windows::{
 
 use windows::core::Interface;
 
-const FADE_DURATION_SEC: f64 = 0.5;
+pub const FADE_IN_DURATION_SEC: f64 = 0.15;
+pub const FADE_OUT_DURATION_SEC: f64 = 0.5;
 
 pub struct GraphicsResources {
     pub d3d_device: ID3D11Device,
@@ -52,6 +55,7 @@ pub struct GraphicsResources {
     pub d2d_device_context: ID2D1DeviceContext,
     pub dwrite_factory: IDWriteFactory,
     pub text_format: IDWriteTextFormat,
+    pub fade_in_animation: Option<IDCompositionAnimation>,
     pub fade_out_animation: Option<IDCompositionAnimation>,
 }
 
@@ -85,7 +89,9 @@ pub fn initialize_graphics(
         // Initial commit happens after returning to main
     };
 
-    let fade_out_animation = create_opacity_animation(&dcomp_device, FADE_DURATION_SEC, 1.0, 0.0);
+    let fade_in_animation = create_opacity_animation(&dcomp_device, FADE_IN_DURATION_SEC, 0.0, 1.0);
+    let fade_out_animation =
+        create_opacity_animation(&dcomp_device, FADE_OUT_DURATION_SEC, 1.0, 0.0);
 
     Ok(GraphicsResources {
         d3d_device,
@@ -100,6 +106,7 @@ pub fn initialize_graphics(
         d2d_device_context: d2d_context,
         dwrite_factory,
         text_format,
+        fade_in_animation: Some(fade_in_animation),
         fade_out_animation: Some(fade_out_animation),
     })
 }
@@ -208,6 +215,21 @@ pub fn create_composition_swapchain(
     }
 }
 
+/// Resizes the swap chain buffers; callers must not hold any buffer references.
+pub fn resize_swap_chain(swap_chain: &IDXGISwapChain1, width: u32, height: u32) {
+    unsafe {
+        if let Err(e) = swap_chain.ResizeBuffers(
+            0,
+            width,
+            height,
+            DXGI_FORMAT_UNKNOWN,
+            DXGI_SWAP_CHAIN_FLAG(0),
+        ) {
+            eprintln!("Failed to resize swap chain: {}", e);
+        }
+    }
+}
+
 pub fn create_dwrite_factory() -> IDWriteFactory {
     unsafe {
         DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED).expect("Failed to create dwrite factory")
@@ -259,6 +281,10 @@ pub fn create_opacity_animation(
         animation
             .AddCubic(0.0, start_opacity, linear_coefficient, 0.0, 0.0)
             .expect("Failed to add cubic");
+        // Hold the end value instead of extrapolating the ramp past it.
+        animation
+            .End(duration_sec, end_opacity)
+            .expect("Failed to end animation");
 
         animation
     }
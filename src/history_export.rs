@@ -0,0 +1,368 @@
+//! Battery history as CSV or JSON for spreadsheets and dashboards.
+//!
+//! Both formats share one schema, in this order: `controller`, `name`,
+//! `timestamp`, `capacity`, `status`, `transport`. Timestamps are ISO-8601 in
+//! UTC. Columns are only ever added at the end, so existing consumers keep
+//! working.
+
+use serde::Serialize;
+
+use crate::battery_history::{self, BatterySample};
+
+pub const CSV_HEADER: &str = "controller,name,timestamp,capacity,status,transport";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Which samples to export. Times are Unix seconds, both ends inclusive.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportFilter {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Keep only samples whose status differs from the one before, plus the first.
+    pub transitions_only: bool,
+}
+
+impl ExportFilter {
+    /// Applies the filter to one controller's samples, oldest first.
+    pub fn apply<'a>(&self, samples: &'a [BatterySample]) -> Vec<&'a BatterySample> {
+        let mut previous: Option<&BatterySample> = None;
+        let mut kept = Vec::new();
+        for sample in samples {
+            // Transitions are judged against the full series, so a range that
+            // starts mid-run doesn't turn its first sample into one.
+            let is_transition = previous.is_none_or(|previous| previous.status != sample.status);
+            previous = Some(sample);
+            if self.since.is_some_and(|since| sample.timestamp < since)
+                || self.until.is_some_and(|until| sample.timestamp > until)
+                || (self.transitions_only && !is_transition)
+            {
+                continue;
+            }
+            kept.push(sample);
+        }
+        kept
+    }
+}
+
+/// One exported sample.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExportRow {
+    pub controller: String,
+    /// Name the user gave the pad, empty when it has none.
+    pub name: String,
+    pub timestamp: String,
+    pub capacity: u8,
+    pub status: &'static str,
+    pub transport: &'static str,
+}
+
+impl ExportRow {
+    pub fn new(controller: &str, name: Option<&str>, sample: &BatterySample) -> Self {
+        Self {
+            controller: controller.to_string(),
+            name: name.unwrap_or_default().to_string(),
+            timestamp: format_iso8601(sample.timestamp),
+            capacity: sample.capacity,
+            status: battery_history::status_name(&sample.status),
+            transport: battery_history::transport_name(sample.transport),
+        }
+    }
+}
+
+pub fn format_rows(rows: &[ExportRow], format: ExportFormat) -> Result<String, String> {
+    match format {
+        ExportFormat::Csv => Ok(format_csv(rows)),
+        ExportFormat::Json => {
+            let mut json = serde_json::to_string_pretty(rows).map_err(|e| e.to_string())?;
+            json.push('\n');
+            Ok(json)
+        }
+    }
+}
+
+fn format_csv(rows: &[ExportRow]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            csv_field(&row.controller),
+            csv_field(&row.name),
+            row.timestamp,
+            row.capacity,
+            row.status,
+            row.transport
+        ));
+    }
+    csv
+}
+
+/// Quotes a field when it contains anything CSV treats specially (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// "2026-10-18T09:30:00Z"
+pub fn format_iso8601(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses "2026-10-18", "2026-10-18T09:30", "2026-10-18T09:30:00" or the same
+/// with a trailing `Z`, all as UTC, into Unix seconds.
+pub fn parse_iso8601(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    let mut date_parts = date.split('-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || day < 1 {
+        return None;
+    }
+    if day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let mut time_parts = time.split(':');
+        let hour: u64 = time_parts.next()?.parse().ok()?;
+        let minute: u64 = time_parts.next()?.parse().ok()?;
+        let second: u64 = time_parts.next().map_or(Some(0), |s| s.parse().ok())?;
+        if time_parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+
+    let days = days_from_civil(year, month, day);
+    u64::try_from(days).ok().map(|days| days * 86_400 + seconds)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Date conversions from Howard Hinnant's `chrono`-compatible algorithms.
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{BatteryStatus, ConnectionType};
+
+    fn sample(timestamp: u64, capacity: u8, status: BatteryStatus) -> BatterySample {
+        BatterySample {
+            timestamp,
+            capacity,
+            status,
+            transport: ConnectionType::Bluetooth,
+        }
+    }
+
+    #[test]
+    fn csv_quotes_only_fields_that_need_it() {
+        let sample = sample(1_792_315_800, 70, BatteryStatus::Charging);
+        let rows = [
+            ExportRow::new("aa:bb", Some("Plain"), &sample),
+            ExportRow::new("aa:bb", Some("Left, blue"), &sample),
+            ExportRow::new("aa:bb", Some("The \"good\" one"), &sample),
+            ExportRow::new("aa:bb", Some("Two\nlines"), &sample),
+            ExportRow::new("a,b", None, &sample),
+        ];
+        let csv = format_rows(&rows, ExportFormat::Csv).unwrap();
+        assert_eq!(
+            csv,
+            "controller,name,timestamp,capacity,status,transport\n\
+             aa:bb,Plain,2026-10-18T09:30:00Z,70,charging,bluetooth\n\
+             aa:bb,\"Left, blue\",2026-10-18T09:30:00Z,70,charging,bluetooth\n\
+             aa:bb,\"The \"\"good\"\" one\",2026-10-18T09:30:00Z,70,charging,bluetooth\n\
+             aa:bb,\"Two\nlines\",2026-10-18T09:30:00Z,70,charging,bluetooth\n\
+             \"a,b\",,2026-10-18T09:30:00Z,70,charging,bluetooth\n"
+        );
+    }
+
+    #[test]
+    fn csv_without_rows_is_just_the_header() {
+        assert_eq!(
+            format_rows(&[], ExportFormat::Csv).unwrap(),
+            format!("{}\n", CSV_HEADER)
+        );
+    }
+
+    #[test]
+    fn json_follows_the_csv_schema() {
+        let rows = [ExportRow::new(
+            "aa:bb",
+            Some("The \"good\" one"),
+            &sample(1_792_315_800, 70, BatteryStatus::ChargingError),
+        )];
+        let json = format_rows(&rows, ExportFormat::Json).unwrap();
+        assert!(json.ends_with("]\n"));
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([{
+                "controller": "aa:bb",
+                "name": "The \"good\" one",
+                "timestamp": "2026-10-18T09:30:00Z",
+                "capacity": 70,
+                "status": "charging_error",
+                "transport": "bluetooth",
+            }])
+        );
+        // Keys come out in the documented column order.
+        let keys: Vec<&str> = json
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('"')?.split('"').next())
+            .collect();
+        assert_eq!(keys, CSV_HEADER.split(',').collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn formats_are_case_insensitive() {
+        assert_eq!(ExportFormat::parse("CSV"), Some(ExportFormat::Csv));
+        assert_eq!(ExportFormat::parse("Json"), Some(ExportFormat::Json));
+        assert_eq!(ExportFormat::parse("xml"), None);
+    }
+
+    #[test]
+    fn filter_keeps_the_inclusive_range() {
+        let samples = [
+            sample(100, 90, BatteryStatus::Discharging),
+            sample(200, 80, BatteryStatus::Discharging),
+            sample(300, 70, BatteryStatus::Discharging),
+        ];
+        let filter = ExportFilter {
+            since: Some(200),
+            until: Some(300),
+            transitions_only: false,
+        };
+        assert_eq!(filter.apply(&samples), [&samples[1], &samples[2]]);
+    }
+
+    #[test]
+    fn transitions_are_judged_against_the_full_series() {
+        let samples = [
+            sample(100, 90, BatteryStatus::Discharging),
+            sample(200, 80, BatteryStatus::Discharging),
+            sample(300, 80, BatteryStatus::Charging),
+            sample(400, 90, BatteryStatus::Charging),
+        ];
+        let all = ExportFilter {
+            transitions_only: true,
+            ..ExportFilter::default()
+        };
+        assert_eq!(all.apply(&samples), [&samples[0], &samples[2]]);
+
+        let later = ExportFilter {
+            since: Some(200),
+            ..all
+        };
+        assert_eq!(later.apply(&samples), [&samples[2]]);
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        for (text, timestamp) in [
+            ("1970-01-01T00:00:00Z", 0),
+            ("2024-02-29T00:00:00Z", 1_709_164_800),
+            ("2000-03-01T23:59:59Z", 951_955_199),
+            ("2026-10-18T09:30:00Z", 1_792_315_800),
+        ] {
+            assert_eq!(format_iso8601(timestamp), text);
+            assert_eq!(parse_iso8601(text), Some(timestamp));
+        }
+    }
+
+    #[test]
+    fn parses_shorter_timestamps() {
+        assert_eq!(parse_iso8601("2026-10-18"), Some(1_792_281_600));
+        assert_eq!(parse_iso8601("2026-10-18T09:30"), Some(1_792_315_800));
+        assert_eq!(parse_iso8601("2026-10-18T09:30:00"), Some(1_792_315_800));
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        for text in [
+            "",
+            "2026-10",
+            "2026-13-01",
+            "2026-02-29",
+            "2026-04-31",
+            "2026-10-18T24:00",
+            "2026-10-18T09:60",
+            "2026-10-18T09:30:00:00",
+            "1969-12-31",
+        ] {
+            assert_eq!(parse_iso8601(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,465 @@
+//! Runs the user's own commands when controller events happen.
+//!
+//! Each hook gets the event as `DS_BATTERY_*` environment variables and as
+//! JSON on stdin: `{"event", "controller"}`, with the controller in the same
+//! shape as the web API snapshot. Rules and gestures can run hooks too; see
+//! [`HookRunner::run_for_rule`] and [`HookRunner::run_for_gesture`].
+//!
+//! Hooks run on a small pool of worker threads so a slow script never holds up
+//! the UI; they're killed once they exceed the timeout.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config::{HookCommand, HookSettings},
+    controllers::ControllerEntry,
+    dualsense::{BatteryStatus, ControllerEvent},
+    gestures::Gesture,
+    web_api::ControllerJson,
+};
+
+/// Hooks that can wait for a free worker; events past this are skipped.
+const QUEUE_LENGTH: usize = 16;
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Written in snake case in the config, e.g. `low_battery`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    Connected,
+    Disconnected,
+    LowBattery,
+    CriticalBattery,
+    /// The pad went from a known non-charging status to charging.
+    ChargingStarted,
+    ChargeCeilingReached,
+    FullyCharged,
+    MutePressed,
+}
+
+impl HookKind {
+    pub const ALL: [HookKind; 8] = [
+        HookKind::Connected,
+        HookKind::Disconnected,
+        HookKind::LowBattery,
+        HookKind::CriticalBattery,
+        HookKind::ChargingStarted,
+        HookKind::ChargeCeilingReached,
+        HookKind::FullyCharged,
+        HookKind::MutePressed,
+    ];
+
+    /// Name used in the config and passed to hooks.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookKind::Connected => "connected",
+            HookKind::Disconnected => "disconnected",
+            HookKind::LowBattery => "low_battery",
+            HookKind::CriticalBattery => "critical_battery",
+            HookKind::ChargingStarted => "charging_started",
+            HookKind::ChargeCeilingReached => "charge_ceiling_reached",
+            HookKind::FullyCharged => "fully_charged",
+            HookKind::MutePressed => "mute_pressed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookEvent {
+    pub kind: HookKind,
+    pub controller: ControllerJson,
+}
+
+impl HookEvent {
+    pub fn new(kind: HookKind, entry: &ControllerEntry) -> Self {
+        let mut controller = ControllerJson::new(entry);
+        if kind == HookKind::Disconnected {
+            controller.connected = false;
+        }
+        Self { kind, controller }
+    }
+
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        environment(self.kind.name(), &self.controller)
+    }
+
+    pub fn stdin_json(&self) -> String {
+        json!({ "event": self.kind.name(), "controller": self.controller }).to_string()
+    }
+}
+
+fn environment(event: &str, controller: &ControllerJson) -> Vec<(&'static str, String)> {
+    let mut variables = vec![
+        ("DS_BATTERY_EVENT", event.to_string()),
+        ("DS_BATTERY_PATH", controller.path.clone()),
+        ("DS_BATTERY_IDENTITY", controller.identity.clone()),
+        ("DS_BATTERY_NAME", controller.name.clone()),
+        ("DS_BATTERY_MODEL", controller.model.to_string()),
+        ("DS_BATTERY_TRANSPORT", controller.transport.to_string()),
+        ("DS_BATTERY_PLAYER", controller.player.to_string()),
+    ];
+    if let Some(battery) = &controller.battery {
+        variables.push(("DS_BATTERY_CAPACITY", battery.capacity.to_string()));
+        variables.push(("DS_BATTERY_STATUS", battery.status.to_string()));
+    }
+    variables
+}
+
+/// Which hook event a controller event is, if any, and the controller's entry
+/// to describe it with. `before` is the entry before the UI applied the event
+/// and `after` the one after, since connects add the entry and disconnects may
+/// remove it.
+pub fn event_kind<'a>(
+    event: &ControllerEvent,
+    before: Option<&'a ControllerEntry>,
+    after: Option<&'a ControllerEntry>,
+) -> Option<(HookKind, &'a ControllerEntry)> {
+    let (kind, entry) = match event {
+        ControllerEvent::DeviceConnected(..) => (HookKind::Connected, after),
+        ControllerEvent::DeviceDisconnected(_) => (HookKind::Disconnected, before),
+        ControllerEvent::BatteryUpdate(_, report) => {
+            let was_charging = before?.battery.as_ref()?.battery_status == BatteryStatus::Charging;
+            if was_charging || report.battery_status != BatteryStatus::Charging {
+                return None;
+            }
+            (HookKind::ChargingStarted, after)
+        }
+        ControllerEvent::MuteButtonPressed(_) => (HookKind::MutePressed, after),
+        ControllerEvent::LowBattery(..) => (HookKind::LowBattery, after),
+        ControllerEvent::CriticalBattery(..) => (HookKind::CriticalBattery, after),
+        ControllerEvent::ChargeCeilingReached(..) => (HookKind::ChargeCeilingReached, after),
+        ControllerEvent::FullyCharged(..) => (HookKind::FullyCharged, after),
+        ControllerEvent::Gesture(..) | ControllerEvent::ReportStats(..) => return None,
+    };
+    Some((kind, entry?))
+}
+
+/// Lets an event through at most once per interval for each key.
+pub struct RateLimiter<K> {
+    min_interval: Duration,
+    last_allowed: HashMap<K, Instant>,
+}
+
+impl<K: std::hash::Hash + Eq> RateLimiter<K> {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_allowed: HashMap::new(),
+        }
+    }
+
+    pub fn allow(&mut self, key: K, now: Instant) -> bool {
+        if let Some(last) = self.last_allowed.get(&key)
+            && now.saturating_duration_since(*last) < self.min_interval
+        {
+            return false;
+        }
+        self.last_allowed.insert(key, now);
+        true
+    }
+}
+
+struct Job {
+    command: HookCommand,
+    environment: Vec<(&'static str, String)>,
+    stdin: String,
+}
+
+pub struct HookRunner {
+    settings: HookSettings,
+    limiter: RateLimiter<(HookKind, String)>,
+    jobs: mpsc::SyncSender<Job>,
+}
+
+impl HookRunner {
+    /// Starts the worker threads.
+    pub fn spawn(settings: HookSettings) -> Result<Self, String> {
+        let (jobs, receiver) = mpsc::sync_channel::<Job>(QUEUE_LENGTH);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let timeout = Duration::from_secs(settings.timeout_secs);
+        for index in 0..settings.max_concurrent.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("hook_worker_{}", index))
+                .spawn(move || {
+                    loop {
+                        // The lock is only held while waiting, not while the hook runs.
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => return,
+                        };
+                        let Ok(job) = job else {
+                            return;
+                        };
+                        if let Err(e) = run(&job, timeout) {
+                            eprintln!("Hook '{}': {}", job.command.command, e);
+                        }
+                    }
+                })
+                .map_err(|e| format!("Failed to start hook worker: {}", e))?;
+        }
+        Ok(Self {
+            limiter: RateLimiter::new(Duration::from_secs(settings.min_interval_secs)),
+            settings,
+            jobs,
+        })
+    }
+
+    /// Queues the hooks configured for the event, unless it fired too
+    /// recently for this controller. Never blocks.
+    pub fn fire(&mut self, event: &HookEvent, now: Instant) {
+        let commands = self.settings.commands(event.kind);
+        if commands.is_empty()
+            || !self
+                .limiter
+                .allow((event.kind, event.controller.identity.clone()), now)
+        {
+            return;
+        }
+        for command in commands {
+            self.run(command, event);
+        }
+    }
+
+    /// Queues one command for the event, whether or not the config lists it
+    /// for that event.
+    pub fn run(&self, command: &HookCommand, event: &HookEvent) {
+        self.queue(Job {
+            command: command.clone(),
+            environment: event.environment(),
+            stdin: event.stdin_json(),
+        });
+    }
+
+    /// Runs a rule's hook action, with `DS_BATTERY_EVENT` set to `rule` and
+    /// the rule's name in `DS_BATTERY_RULE`. Rules aren't rate limited here;
+    /// they only fire again once their conditions have cleared.
+    pub fn run_for_rule(&self, command: &HookCommand, rule: &str, entry: &ControllerEntry) {
+        let controller = ControllerJson::new(entry);
+        let mut environment = environment("rule", &controller);
+        environment.push(("DS_BATTERY_RULE", rule.to_string()));
+        self.queue(Job {
+            command: command.clone(),
+            environment,
+            stdin: json!({ "event": "rule", "rule": rule, "controller": controller }).to_string(),
+        });
+    }
+
+    /// Runs a gesture's hook action, with `DS_BATTERY_EVENT` set to `gesture`
+    /// and the gesture, like `ps+options long_press`, in `DS_BATTERY_GESTURE`.
+    pub fn run_for_gesture(
+        &self,
+        command: &HookCommand,
+        gesture: &Gesture,
+        entry: &ControllerEntry,
+    ) {
+        let controller = ControllerJson::new(entry);
+        let gesture = gesture.to_string();
+        let mut environment = environment("gesture", &controller);
+        environment.push(("DS_BATTERY_GESTURE", gesture.clone()));
+        self.queue(Job {
+            command: command.clone(),
+            environment,
+            stdin: json!({ "event": "gesture", "gesture": gesture, "controller": controller })
+                .to_string(),
+        });
+    }
+
+    fn queue(&self, job: Job) {
+        let command = job.command.command.clone();
+        if self.jobs.try_send(job).is_err() {
+            eprintln!("Hooks: Too many hooks running, skipping '{}'", command);
+        }
+    }
+}
+
+fn run(job: &Job, timeout: Duration) -> Result<(), String> {
+    let mut command = Command::new(&job.command.command);
+    command
+        .args(&job.command.args)
+        .envs(job.environment.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // We have no console of our own, so console scripts would open one.
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks that exit without reading stdin close the pipe; that's fine.
+        let _ = stdin.write_all(job.stdin.as_bytes());
+    }
+    wait_with_timeout(&mut child, timeout)
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("Exited with {}", status)),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("Killed after {} seconds", timeout.as_secs()));
+            }
+            Ok(None) => thread::sleep(EXIT_POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed to wait: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{BatteryReport, ConnectionType, ControllerInfo, ControllerModel};
+
+    fn entry(battery: Option<BatteryStatus>) -> ControllerEntry {
+        ControllerEntry {
+            path: "pad".to_string(),
+            info: ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Usb,
+                serial: None,
+                firmware: None,
+            },
+            player_number: 1,
+            name: None,
+            battery: battery.map(|status| BatteryReport::new(50, status)),
+            connected: true,
+        }
+    }
+
+    fn battery_update(status: BatteryStatus) -> ControllerEvent {
+        ControllerEvent::BatteryUpdate("pad".to_string(), BatteryReport::new(50, status))
+    }
+
+    /// The hook kind for `event` with the same entry before and after.
+    fn kind(event: &ControllerEvent, before: Option<BatteryStatus>) -> Option<HookKind> {
+        let before = entry(before);
+        event_kind(event, Some(&before), Some(&before)).map(|(kind, _)| kind)
+    }
+
+    #[test]
+    fn charging_started_fires_on_the_edge_only() {
+        let charging = battery_update(BatteryStatus::Charging);
+        assert_eq!(
+            kind(&charging, Some(BatteryStatus::Discharging)),
+            Some(HookKind::ChargingStarted)
+        );
+        assert_eq!(
+            kind(&charging, Some(BatteryStatus::Full)),
+            Some(HookKind::ChargingStarted)
+        );
+        // Already charging.
+        assert_eq!(kind(&charging, Some(BatteryStatus::Charging)), None);
+        // The first reading after connecting says nothing about a change.
+        assert_eq!(kind(&charging, None), None);
+        // Other updates aren't hook events at all.
+        assert_eq!(
+            kind(
+                &battery_update(BatteryStatus::Discharging),
+                Some(BatteryStatus::Charging)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn connects_use_the_new_entry_and_disconnects_the_old_one() {
+        let entry = entry(None);
+        let connected = ControllerEvent::DeviceConnected("pad".to_string(), entry.info.clone());
+        assert_eq!(
+            event_kind(&connected, None, Some(&entry)),
+            Some((HookKind::Connected, &entry))
+        );
+        assert_eq!(event_kind(&connected, Some(&entry), None), None);
+
+        let disconnected = ControllerEvent::DeviceDisconnected("pad".to_string());
+        assert_eq!(
+            event_kind(&disconnected, Some(&entry), None),
+            Some((HookKind::Disconnected, &entry))
+        );
+    }
+
+    #[test]
+    fn maps_battery_events() {
+        let report = BatteryReport::new(10, BatteryStatus::Discharging);
+        for (event, expected) in [
+            (
+                ControllerEvent::LowBattery("pad".to_string(), report.clone()),
+                Some(HookKind::LowBattery),
+            ),
+            (
+                ControllerEvent::CriticalBattery("pad".to_string(), report.clone()),
+                Some(HookKind::CriticalBattery),
+            ),
+            (
+                ControllerEvent::ChargeCeilingReached("pad".to_string(), report.clone()),
+                Some(HookKind::ChargeCeilingReached),
+            ),
+            (
+                ControllerEvent::FullyCharged("pad".to_string(), report.clone()),
+                Some(HookKind::FullyCharged),
+            ),
+            (
+                ControllerEvent::MuteButtonPressed("pad".to_string()),
+                Some(HookKind::MutePressed),
+            ),
+            (
+                ControllerEvent::ReportStats("pad".to_string(), Default::default()),
+                None,
+            ),
+        ] {
+            assert_eq!(kind(&event, Some(BatteryStatus::Discharging)), expected);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_allows_once_per_interval_per_key() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(limiter.allow("a", start));
+        assert!(!limiter.allow("a", start + Duration::from_secs(9)));
+        // Each key has its own interval.
+        assert!(limiter.allow("b", start + Duration::from_secs(9)));
+        assert!(limiter.allow("a", start + Duration::from_secs(10)));
+        // Blocked attempts don't push the interval back.
+        assert!(!limiter.allow("a", start + Duration::from_secs(15)));
+        assert!(limiter.allow("a", start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn rate_limiter_with_no_interval_allows_everything() {
+        let mut limiter = RateLimiter::new(Duration::ZERO);
+        let now = Instant::now();
+        assert!(limiter.allow("a", now));
+        assert!(limiter.allow("a", now));
+    }
+
+    #[test]
+    fn names_match_the_config() {
+        for kind in HookKind::ALL {
+            assert_eq!(
+                serde_json::to_string(&kind).unwrap(),
+                format!("\"{}\"", kind.name())
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Just enough HTTP/1.1 for the local status endpoints.
+//!
+//! Each connection carries one request and is closed after the response, unless
+//! it's upgraded to a WebSocket. The clients are dashboards, browser sources and
+//! scrapers on the same machine, so there's no keep-alive, chunking or request
+//! bodies.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEADER_LINES: usize = 100;
+/// Longest request or header line, including the line break.
+const MAX_LINE_BYTES: u64 = 8192;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// First header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn read_request(stream: &TcpStream) -> Result<Request, String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(format!("Malformed request line '{}'", request_line.trim()));
+    };
+    let path = target.split('?').next().unwrap_or(target);
+
+    let mut headers = Vec::new();
+    for _ in 0..MAX_HEADER_LINES {
+        let line = read_line(&mut reader)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(Request {
+                method: method.to_string(),
+                path: path.to_string(),
+                headers,
+            });
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    Err("Too many header lines".to_string())
+}
+
+/// Reads one line, refusing to buffer more than [`MAX_LINE_BYTES`] of it.
+fn read_line(reader: &mut impl BufRead) -> Result<String, String> {
+    let mut line = String::new();
+    let read = reader
+        .take(MAX_LINE_BYTES)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    if read as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err("Line too long".to_string());
+    }
+    Ok(line)
+}
+
+pub fn write_response(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+pub fn write_not_found(stream: &TcpStream) -> std::io::Result<()> {
+    write_response(
+        stream,
+        "404 Not Found",
+        "text/plain; charset=utf-8",
+        b"Not found\n",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Shutdown, TcpListener};
+
+    use super::*;
+
+    /// Sends `raw` over a loopback connection and reads it back as a request.
+    fn read(raw: &[u8]) -> Result<Request, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        // Big requests are written from another thread, so a server that
+        // stops reading early can't block the test.
+        let raw = raw.to_vec();
+        let writer = std::thread::spawn(move || {
+            let _ = client.write_all(&raw);
+            let _ = client.shutdown(Shutdown::Write);
+        });
+        let request = read_request(&server);
+        drop(server);
+        writer.join().unwrap();
+        request
+    }
+
+    #[test]
+    fn reads_method_path_and_headers() {
+        let request = read(
+            b"GET /api/events?x=1 HTTP/1.1\r\nHost: localhost:9478\r\nUpgrade:  websocket \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/api/events");
+        assert_eq!(
+            request.headers,
+            [
+                ("Host".to_string(), "localhost:9478".to_string()),
+                ("Upgrade".to_string(), "websocket".to_string()),
+            ]
+        );
+        assert_eq!(request.header("upgrade"), Some("websocket"));
+        assert_eq!(request.header("Origin"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        assert!(read(b"GET\r\n\r\n").is_err());
+        assert!(read(b"").is_err());
+    }
+
+    #[test]
+    fn rejects_overlong_lines() {
+        let mut raw = b"GET / HTTP/1.1\r\nCookie: ".to_vec();
+        raw.extend(std::iter::repeat_n(b'a', MAX_LINE_BYTES as usize));
+        raw.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(read(&raw), Err("Line too long".to_string()));
+
+        let mut raw = b"GET /".to_vec();
+        raw.extend(std::iter::repeat_n(b'a', MAX_LINE_BYTES as usize));
+        assert_eq!(read(&raw), Err("Line too long".to_string()));
+    }
+
+    #[test]
+    fn rejects_too_many_headers() {
+        let mut raw = b"GET / HTTP/1.1\r\n".to_vec();
+        for _ in 0..=MAX_HEADER_LINES {
+            raw.extend_from_slice(b"X-Padding: 1\r\n");
+        }
+        raw.extend_from_slice(b"\r\n");
+        assert_eq!(read(&raw), Err("Too many header lines".to_string()));
+    }
+}
@@ -0,0 +1,138 @@
+//! Just enough of an HTTP/1.1 client to POST webhooks, over plain TCP or TLS.
+//!
+//! One request per connection; only the status line of the response is read.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    time::Duration,
+};
+
+use crate::net;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid URL '{}'", url);
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("Unsupported URL '{}', expected http or https", url));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            // A colon inside brackets is part of an IPv6 address.
+            Some((host, port)) if !port.ends_with(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, default_port),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// The `Host` header value: the host, bracketed when it's an IPv6
+    /// address, with the port unless it's the scheme's default.
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let default_port = if self.tls { 443 } else { 80 };
+        if self.port == default_port {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/// Sends a JSON body and returns the response status code.
+pub fn post_json(url: &Url, body: &str, timeout: Duration) -> Result<u16, String> {
+    let mut stream = net::connect(&url.host, url.port, url.tls, timeout)?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ds-battery\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host_header(),
+        body.len(),
+        body
+    );
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("Invalid response '{}'", status_line.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            Url::parse("https://hooks.example/services/x?y=1").unwrap(),
+            Url {
+                tls: true,
+                host: "hooks.example".to_string(),
+                port: 443,
+                path: "/services/x?y=1".to_string(),
+            }
+        );
+        assert_eq!(
+            Url::parse("http://[::1]:8080").unwrap(),
+            Url {
+                tls: false,
+                host: "::1".to_string(),
+                port: 8080,
+                path: "/".to_string(),
+            }
+        );
+        assert!(Url::parse("ftp://example").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+        assert!(Url::parse("http://example:port/").is_err());
+    }
+
+    #[test]
+    fn host_header_keeps_non_default_ports() {
+        let host_header = |url: &str| Url::parse(url).unwrap().host_header();
+        assert_eq!(host_header("http://example/hook"), "example");
+        assert_eq!(host_header("https://example:443/hook"), "example");
+        assert_eq!(host_header("http://example:8080/hook"), "example:8080");
+        assert_eq!(host_header("https://example:80/hook"), "example:80");
+        assert_eq!(host_header("http://[::1]/hook"), "[::1]");
+        assert_eq!(host_header("http://[fe80::1]:8080/hook"), "[fe80::1]:8080");
+    }
+}
@@ -0,0 +1,308 @@
+//! Local socket the running app listens on, so scripts and the CLI can ask it
+//! about controllers instead of opening the devices themselves. It's a named
+//! pipe on Windows and a Unix domain socket elsewhere.
+//!
+//! The protocol is JSON-RPC 2.0 with one message per line; see
+//! [`crate::ipc_server`] for the methods.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+};
+
+use serde_json::{Value, json};
+
+#[cfg(windows)]
+pub use self::pipe::{Listener, Stream, connect};
+
+#[cfg(unix)]
+pub use self::socket::{Listener, Stream, connect};
+
+/// Why [`Listener::bind`] failed.
+#[derive(Debug)]
+pub enum BindError {
+    /// Another instance already holds the name.
+    InUse(String),
+    Other(String),
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindError::InUse(message) | BindError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Client side of the protocol.
+pub struct Client {
+    stream: BufReader<Stream>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the running app, or returns `None` when there isn't one.
+    pub fn connect() -> Option<Self> {
+        connect().ok().map(Self::new)
+    }
+
+    pub fn new(stream: Stream) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            next_id: 1,
+        }
+    }
+
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let line = format!("{}\n", request);
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(line.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        loop {
+            let mut message = self.read_message()?;
+            // Notifications from an earlier subscribe don't answer anything.
+            if message.get("id") != Some(&json!(id)) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                let text = error["message"].as_str().unwrap_or("Unknown error");
+                return Err(text.to_string());
+            }
+            return Ok(message["result"].take());
+        }
+    }
+
+    /// Waits for the next notification; used after `subscribe`.
+    pub fn next_notification(&mut self) -> Result<Value, String> {
+        loop {
+            let mut message = self.read_message()?;
+            if message.get("id").is_none() {
+                return Ok(message["params"].take());
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Value, String> {
+        let mut line = String::new();
+        match self.stream.read_line(&mut line) {
+            Ok(0) => Err("The running instance closed the connection".to_string()),
+            Ok(_) => serde_json::from_str(&line).map_err(|e| format!("Invalid response: {}", e)),
+            Err(e) => Err(format!("Failed to read response: {}", e)),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod pipe {
+    use std::{fs::File, io, os::windows::io::FromRawHandle};
+
+    use windows::{
+        Win32::{
+            Foundation::{CloseHandle, ERROR_ACCESS_DENIED, ERROR_PIPE_CONNECTED, HANDLE},
+            Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+            System::Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+        },
+        core::HSTRING,
+    };
+
+    use super::BindError;
+
+    const PIPE_NAME: &str = r"\\.\pipe\ds-battery";
+    const PIPE_BUFFER_SIZE: u32 = 4096;
+
+    pub type Stream = File;
+
+    /// Each client gets its own pipe instance; the next one is created once the
+    /// previous is taken.
+    pub struct Listener {
+        next_instance: Option<HANDLE>,
+    }
+
+    // The handle is only ever used by the thread that owns the listener.
+    unsafe impl Send for Listener {}
+
+    impl Listener {
+        /// Claims the pipe name. Fails if another process already has it.
+        pub fn bind() -> Result<Self, BindError> {
+            match create_instance(true) {
+                Ok(handle) => Ok(Self {
+                    next_instance: Some(handle),
+                }),
+                // What creating the first instance of a taken name fails with.
+                Err(e) if e.code() == ERROR_ACCESS_DENIED.to_hresult() => Err(BindError::InUse(
+                    format!("Another instance has the pipe {}", PIPE_NAME),
+                )),
+                Err(e) => Err(BindError::Other(format!(
+                    "Failed to create pipe {}: {}",
+                    PIPE_NAME, e
+                ))),
+            }
+        }
+
+        pub fn accept(&mut self) -> Result<Stream, String> {
+            let handle = match self.next_instance.take() {
+                Some(handle) => handle,
+                None => create_instance(false)
+                    .map_err(|e| format!("Failed to create pipe {}: {}", PIPE_NAME, e))?,
+            };
+            match unsafe { ConnectNamedPipe(handle, None) } {
+                Ok(()) => {}
+                // The client connected between creating the instance and now.
+                Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => {}
+                Err(e) => {
+                    let _ = unsafe { CloseHandle(handle) };
+                    return Err(format!("Failed to accept pipe client: {}", e));
+                }
+            }
+            Ok(unsafe { File::from_raw_handle(handle.0) })
+        }
+    }
+
+    fn create_instance(first: bool) -> windows::core::Result<HANDLE> {
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                &HSTRING::from(PIPE_NAME),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                PIPE_BUFFER_SIZE,
+                PIPE_BUFFER_SIZE,
+                0,
+                None,
+            )
+        };
+        if handle.is_invalid() {
+            return Err(windows::core::Error::from_win32());
+        }
+        Ok(handle)
+    }
+
+    pub fn connect() -> io::Result<Stream> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(PIPE_NAME)
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::{
+        fs, io,
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+    };
+
+    use super::BindError;
+    use crate::paths;
+
+    const SOCKET_NAME: &str = "ds-battery.sock";
+
+    pub type Stream = UnixStream;
+
+    pub struct Listener {
+        listener: UnixListener,
+    }
+
+    impl Listener {
+        /// Binds the socket, replacing one left behind by a crashed instance.
+        /// Fails if another process is still listening on it.
+        // Only the overlay listens, and it's Windows-only for now.
+        #[allow(dead_code)]
+        pub fn bind() -> Result<Self, BindError> {
+            let path = socket_path().ok_or_else(|| {
+                BindError::Other("Couldn't find a directory for the socket".to_string())
+            })?;
+            Self::bind_at(&path)
+        }
+
+        pub fn bind_at(path: &Path) -> Result<Self, BindError> {
+            if path.exists() {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(BindError::InUse(format!(
+                        "Another instance is listening on {}",
+                        path.display()
+                    )));
+                }
+                fs::remove_file(path).map_err(|e| {
+                    BindError::Other(format!("Failed to remove stale {}: {}", path.display(), e))
+                })?;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| BindError::Other(e.to_string()))?;
+            }
+            let listener = UnixListener::bind(path).map_err(|e| {
+                BindError::Other(format!("Failed to listen on {}: {}", path.display(), e))
+            })?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+                .map_err(|e| BindError::Other(e.to_string()))?;
+            Ok(Self { listener })
+        }
+
+        pub fn accept(&mut self) -> Result<Stream, String> {
+            self.listener
+                .accept()
+                .map(|(stream, _)| stream)
+                .map_err(|e| format!("Failed to accept socket client: {}", e))
+        }
+    }
+
+    pub fn connect() -> io::Result<Stream> {
+        let path = socket_path().ok_or(io::ErrorKind::NotFound)?;
+        UnixStream::connect(path)
+    }
+
+    /// `$XDG_RUNTIME_DIR`, which only the user can read, or the data directory.
+    fn socket_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(paths::data_dir)
+            .map(|dir| dir.join(SOCKET_NAME))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::process;
+
+        use super::*;
+
+        fn temp_socket(test: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("ds-battery-ipc-{}-{}.sock", process::id(), test))
+        }
+
+        #[test]
+        fn a_live_socket_is_in_use() {
+            let path = temp_socket("live");
+            let _listener = Listener::bind_at(&path).unwrap();
+            assert!(matches!(Listener::bind_at(&path), Err(BindError::InUse(_))));
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn a_stale_socket_is_replaced() {
+            let path = temp_socket("stale");
+            drop(Listener::bind_at(&path).unwrap());
+            let mut listener = Listener::bind_at(&path).unwrap();
+            let _client = UnixStream::connect(&path).unwrap();
+            assert!(listener.accept().is_ok());
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
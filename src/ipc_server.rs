@@ -0,0 +1,480 @@
+//! JSON-RPC server on the IPC socket.
+//!
+//! Methods, with `controller` being a player number, identity, HID path or
+//! name:
+//!
+//! - `list_controllers`: every controller, as in the web API snapshot.
+//! - `get_battery {controller}`: `{"capacity", "status"}`, or `null` before
+//!   the first reading.
+//! - `show_overlay {controller?}`: pops the overlay up.
+//! - `set_lightbar {controller, color}`: sets and remembers the lightbar
+//!   colour, given as `#rrggbb`.
+//! - `subscribe`: turns the connection into a stream of `event`
+//!   notifications, starting with a snapshot. Requests sent after it are
+//!   ignored.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    ipc::{self, Listener},
+    output_report::Rgb,
+    web_api::WebApiState,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const UNKNOWN_CONTROLLER: i64 = -32000;
+
+/// Requests that need the UI thread, which owns the overlay and the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UiRequest {
+    /// Shows the overlay, highlighting the controller at this HID path if any.
+    ShowOverlay(Option<String>),
+    SetLightbar(String, Rgb),
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// What to do with the connection after answering a request.
+enum Next {
+    Continue,
+    Subscribe,
+}
+
+/// Serves the already bound IPC socket from a background thread, giving each
+/// client a thread of its own.
+pub fn spawn(
+    mut listener: Listener,
+    state: Arc<Mutex<WebApiState>>,
+    ui_requests: mpsc::Sender<UiRequest>,
+) -> Result<(), String> {
+    thread::Builder::new()
+        .name("ipc_server".to_string())
+        .spawn(move || {
+            loop {
+                let stream = match listener.accept() {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("IPC server: {}", e);
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                let ui_requests = ui_requests.clone();
+                let spawned = thread::Builder::new()
+                    .name("ipc_client".to_string())
+                    .spawn(move || serve_client(stream, &state, &ui_requests));
+                if let Err(e) = spawned {
+                    eprintln!("IPC server: Failed to start client thread: {}", e);
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start IPC server: {}", e))?;
+    Ok(())
+}
+
+fn serve_client(
+    stream: ipc::Stream,
+    state: &Mutex<WebApiState>,
+    ui_requests: &mpsc::Sender<UiRequest>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line.trim().is_empty() => continue,
+            Ok(_) => {}
+        }
+
+        let (response, next) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle_request(&request, state, ui_requests),
+            Err(e) => (
+                Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                )),
+                Next::Continue,
+            ),
+        };
+        if let Some(response) = response
+            && write_line(reader.get_mut(), &response.to_string()).is_err()
+        {
+            return;
+        }
+
+        if let Next::Subscribe = next {
+            let Ok(messages) = state.lock().map(|mut state| state.subscribe()) else {
+                return;
+            };
+            for message in messages {
+                let notification = format!(
+                    "{{\"jsonrpc\":\"2.0\",\"method\":\"event\",\"params\":{}}}",
+                    message
+                );
+                if write_line(reader.get_mut(), &notification).is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+    }
+}
+
+fn write_line(stream: &mut ipc::Stream, line: &str) -> std::io::Result<()> {
+    stream.write_all(format!("{}\n", line).as_bytes())?;
+    stream.flush()
+}
+
+/// Answers one request. Notifications, which have no `id`, get no response.
+fn handle_request(
+    request: &Value,
+    state: &Mutex<WebApiState>,
+    ui_requests: &mpsc::Sender<UiRequest>,
+) -> (Option<Value>, Next) {
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let error = RpcError::new(INVALID_REQUEST, "Missing method");
+        return (
+            Some(error_response(id.unwrap_or(Value::Null), error)),
+            Next::Continue,
+        );
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let mut next = Next::Continue;
+    let result = match method {
+        "subscribe" => {
+            next = Next::Subscribe;
+            Ok(json!(true))
+        }
+        _ => call(method, &params, state, ui_requests),
+    };
+    let response = id.map(|id| match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    });
+    (response, next)
+}
+
+fn call(
+    method: &str,
+    params: &Value,
+    state: &Mutex<WebApiState>,
+    ui_requests: &mpsc::Sender<UiRequest>,
+) -> Result<Value, RpcError> {
+    let state = state
+        .lock()
+        .map_err(|_| RpcError::new(INTERNAL_ERROR, "Controller state unavailable"))?;
+    let find = |required: bool| -> Result<Option<String>, RpcError> {
+        let Some(query) = params.get("controller").and_then(json_to_query) else {
+            return if required {
+                Err(RpcError::new(INVALID_PARAMS, "Missing controller"))
+            } else {
+                Ok(None)
+            };
+        };
+        state
+            .find_controller(&query)
+            .map(|controller| Some(controller.path.clone()))
+            .ok_or_else(|| RpcError::new(UNKNOWN_CONTROLLER, format!("No controller '{}'", query)))
+    };
+
+    match method {
+        "list_controllers" => Ok(json!(state.controllers())),
+        "get_battery" => {
+            let path = find(true)?.unwrap_or_default();
+            let controller = state.find_controller(&path);
+            Ok(json!(controller.and_then(|c| c.battery.as_ref())))
+        }
+        "show_overlay" => {
+            let path = find(false)?;
+            send_to_ui(ui_requests, UiRequest::ShowOverlay(path))
+        }
+        "set_lightbar" => {
+            let path = find(true)?.unwrap_or_default();
+            let color: Rgb = params
+                .get("color")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing color"))?
+                .parse()
+                .map_err(|e: String| RpcError::new(INVALID_PARAMS, e))?;
+            send_to_ui(ui_requests, UiRequest::SetLightbar(path, color))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", method),
+        )),
+    }
+}
+
+/// Player numbers may come as JSON numbers; everything else is a string.
+fn json_to_query(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn send_to_ui(
+    ui_requests: &mpsc::Sender<UiRequest>,
+    request: UiRequest,
+) -> Result<Value, RpcError> {
+    ui_requests
+        .send(request)
+        .map(|_| json!(true))
+        .map_err(|_| RpcError::new(INTERNAL_ERROR, "The app is shutting down"))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::net::UnixStream, path::PathBuf, process, time::Duration};
+
+    use super::*;
+    use crate::{
+        controllers::ControllerRegistry,
+        dualsense::{
+            BatteryReport, BatteryStatus, ConnectionType, ControllerEvent, ControllerInfo,
+            ControllerModel,
+        },
+    };
+
+    struct Server {
+        socket: PathBuf,
+        state: Arc<Mutex<WebApiState>>,
+        ui_requests: mpsc::Receiver<UiRequest>,
+    }
+
+    impl Server {
+        fn start(test: &str) -> Self {
+            let socket = std::env::temp_dir().join(format!(
+                "ds-battery-ipc-{}-{}.sock",
+                process::id(),
+                test
+            ));
+            let listener = Listener::bind_at(&socket).unwrap();
+            let mut state = WebApiState::default();
+            state.refresh(&registry());
+            let state = Arc::new(Mutex::new(state));
+            let (sender, ui_requests) = mpsc::channel();
+            spawn(listener, state.clone(), sender).unwrap();
+            Self {
+                socket,
+                state,
+                ui_requests,
+            }
+        }
+
+        fn stream(&self) -> UnixStream {
+            let stream = UnixStream::connect(&self.socket).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        }
+
+        fn client(&self) -> ipc::Client {
+            ipc::Client::new(self.stream())
+        }
+
+        /// Sends raw lines and returns the response to the last one.
+        fn raw(&self, lines: &str) -> Value {
+            let mut stream = self.stream();
+            stream.write_all(lines.as_bytes()).unwrap();
+            let mut response = String::new();
+            BufReader::new(stream).read_line(&mut response).unwrap();
+            serde_json::from_str(&response).unwrap()
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.socket);
+        }
+    }
+
+    fn registry() -> ControllerRegistry {
+        let mut registry = ControllerRegistry::new();
+        registry.connect(
+            "pad-path".to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Bluetooth,
+                serial: Some("aa:bb".to_string()),
+                firmware: None,
+            },
+        );
+        registry.set_name("pad-path", Some("Couch".to_string()));
+        registry.update_battery("pad-path", BatteryReport::new(70, BatteryStatus::Charging));
+        registry
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn lists_controllers() {
+        let server = Server::start("list");
+        let controllers = server
+            .client()
+            .call("list_controllers", Value::Null)
+            .unwrap();
+        assert_eq!(controllers[0]["identity"], "aa:bb");
+        assert_eq!(controllers[0]["name"], "Couch");
+        assert_eq!(controllers.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn gets_the_battery_by_player_number_or_name() {
+        let server = Server::start("battery");
+        let mut client = server.client();
+        let battery = json!({ "capacity": 70, "status": "charging" });
+        assert_eq!(
+            client
+                .call("get_battery", json!({ "controller": 1 }))
+                .unwrap(),
+            battery
+        );
+        assert_eq!(
+            client
+                .call("get_battery", json!({ "controller": "couch" }))
+                .unwrap(),
+            battery
+        );
+        assert_eq!(
+            client.call("get_battery", json!({ "controller": "Desk" })),
+            Err("No controller 'Desk'".to_string())
+        );
+    }
+
+    #[test]
+    fn show_overlay_reaches_the_ui() {
+        let server = Server::start("overlay");
+        let mut client = server.client();
+        assert_eq!(client.call("show_overlay", Value::Null), Ok(json!(true)));
+        assert_eq!(
+            client.call("show_overlay", json!({ "controller": "aa:bb" })),
+            Ok(json!(true))
+        );
+        assert_eq!(
+            server.ui_requests.try_iter().collect::<Vec<_>>(),
+            [
+                UiRequest::ShowOverlay(None),
+                UiRequest::ShowOverlay(Some("pad-path".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_lightbar_reaches_the_ui() {
+        let server = Server::start("lightbar");
+        let result = server.client().call(
+            "set_lightbar",
+            json!({ "controller": 1, "color": "#ff8000" }),
+        );
+        assert_eq!(result, Ok(json!(true)));
+        assert_eq!(
+            server.ui_requests.try_recv(),
+            Ok(UiRequest::SetLightbar(
+                "pad-path".to_string(),
+                Rgb::new(255, 128, 0)
+            ))
+        );
+    }
+
+    #[test]
+    fn subscribers_get_a_snapshot_then_events() {
+        let server = Server::start("subscribe");
+        let mut client = server.client();
+        assert_eq!(client.call("subscribe", Value::Null), Ok(json!(true)));
+        let snapshot = client.next_notification().unwrap();
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["controllers"][0]["identity"], "aa:bb");
+
+        let event = ControllerEvent::BatteryUpdate(
+            "pad-path".to_string(),
+            BatteryReport::new(70, BatteryStatus::Charging),
+        );
+        server.state.lock().unwrap().publish(&event, &registry());
+        let update = client.next_notification().unwrap();
+        assert_eq!(update["type"], "battery_update");
+        assert_eq!(update["path"], "pad-path");
+    }
+
+    #[test]
+    fn unknown_methods_are_not_found() {
+        let server = Server::start("unknown");
+        let response = server.raw("{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"reboot\"}\n");
+        assert_eq!(response["id"], 7);
+        assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn bad_params_are_rejected() {
+        let server = Server::start("params");
+        let missing = server.raw("{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"get_battery\"}\n");
+        assert_eq!(error_code(&missing), INVALID_PARAMS);
+        let color = server.raw(
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"set_lightbar\",\
+             \"params\":{\"controller\":1,\"color\":\"red\"}}\n",
+        );
+        assert_eq!(error_code(&color), INVALID_PARAMS);
+        let unknown = server.raw(
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"get_battery\",\
+             \"params\":{\"controller\":4}}\n",
+        );
+        assert_eq!(error_code(&unknown), UNKNOWN_CONTROLLER);
+    }
+
+    #[test]
+    fn malformed_requests_get_errors() {
+        let server = Server::start("malformed");
+        let response = server.raw("{not json\n");
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        let response = server.raw("{\"jsonrpc\":\"2.0\",\"id\":3}\n");
+        assert_eq!(response["id"], 3);
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let server = Server::start("notification");
+        let response = server.raw(
+            "{\"jsonrpc\":\"2.0\",\"method\":\"list_controllers\"}\n\
+             {\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"reboot\"}\n",
+        );
+        assert_eq!(response["id"], 2);
+    }
+}
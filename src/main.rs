@@ -1,16 +1,96 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+// The overlay itself is Windows-only; elsewhere only the CLI commands are
+// wired up, so modules that only the overlay uses would be dead code there.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod animation;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod battery_alerts;
+mod battery_health;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod battery_history;
+mod bitmap_font;
+mod checksum;
+mod cli;
+mod config;
+mod controllers;
 mod dualsense;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod gestures;
+#[cfg(windows)]
 mod graphics;
+mod history_export;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod hooks;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod http;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod http_client;
+mod ipc;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod ipc_server;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod metrics;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod metrics_server;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod mic;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod mqtt;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod mqtt_publisher;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod net;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod notifications;
+mod output_report;
+mod overlay_layout;
+mod paths;
+mod png;
+#[cfg_attr(not(windows), allow(dead_code))]
 mod polling;
+#[cfg(windows)]
 mod renderer;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod rules;
+mod runtime_estimate;
+mod software_renderer;
+#[cfg(windows)]
+mod text_prompt;
+#[cfg(windows)]
 mod tray;
+mod tray_icon;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod tray_menu;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod tray_tooltip;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod visibility;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod web_api;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod web_server;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod webhook;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod websocket;
+#[cfg(windows)]
 mod window;
+#[cfg(windows)]
 mod window_creator;
+#[cfg(windows)]
 mod window_message_handler;
 
-use std::{collections::HashMap, sync::mpsc, thread, time::Duration};
+use std::process::ExitCode;
+#[cfg(windows)]
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
 
+#[cfg(windows)]
 use windows::{
     Win32::{
         Foundation::{HINSTANCE, HWND},
@@ -24,7 +104,11 @@ use windows::{
             DirectWrite::{IDWriteFactory, IDWriteTextFormat},
             Dxgi::{IDXGIDevice, IDXGIFactory2, IDXGISwapChain1},
         },
-        System::LibraryLoader::GetModuleHandleW,
+        System::{
+            Console::{ATTACH_PARENT_PROCESS, AttachConsole},
+            LibraryLoader::GetModuleHandleW,
+            SystemInformation::GetLocalTime,
+        },
         UI::WindowsAndMessaging::{
             DispatchMessageW, HICON, IMAGE_ICON, LR_DEFAULTSIZE, LR_LOADFROMFILE, LoadImageW, MSG,
             PM_REMOVE, PeekMessageW, TranslateMessage, WM_QUIT, WM_USER,
@@ -33,27 +117,46 @@ use windows::{
     core::w,
 };
 
-pub const WINDOW_WIDTH: i32 = 200;
-pub const WINDOW_HEIGHT: i32 = 150;
-const CORNER_RADIUS: f32 = 10.0;
+#[cfg(windows)]
+use battery_alerts::{AlertLevel, ChargeAlert};
+#[cfg(windows)]
+use visibility::VisibilityState;
+
+#[cfg(windows)]
 const HOTKEY_ID_TOGGLE: i32 = 1;
+#[cfg(windows)]
 const TIMER_ID_FADEOUT: usize = 1;
+#[cfg(windows)]
+const TIMER_ID_DISCONNECTED: usize = 2;
+#[cfg(windows)]
+const TIMER_ID_ANIMATION: usize = 3;
+#[cfg(windows)]
+const TIMER_ID_FADE: usize = 4;
+#[cfg(windows)]
 const SHOW_DURATION_MS: u32 = 3000;
+#[cfg(windows)]
+const ANIMATION_FRAME_MS: u32 = 16;
+#[cfg(windows)]
+const DISCONNECTED_NOTICE_MS: u32 = 1500;
+/// How often a second launch tries to reach the running instance.
+#[cfg(windows)]
+const ACTIVATION_ATTEMPTS: u32 = 5;
+#[cfg(windows)]
+const ACTIVATION_RETRY_DELAY: Duration = Duration::from_millis(200);
+/// How often rules waiting on `for_secs` are checked between events.
+#[cfg(windows)]
+const RULES_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the mute button LEDs catch up with mutes made elsewhere.
+#[cfg(windows)]
+const MIC_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
+#[cfg(windows)]
 pub const WM_APP_TRAYMSG: u32 = WM_USER + 1;
-pub const IDM_CONFIGURE: u16 = 1001;
-pub const IDM_RUN_ON_STARTUP: u16 = 1002;
-pub const IDM_EXIT: u16 = 1003;
 
+#[cfg(windows)]
 pub const APP_REGISTRY_KEY_NAME: &str = "DSBatteryOverlay";
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum VisibilityState {
-    Visible,
-    Hidden,
-    FadingOut,
-}
-
+#[cfg(windows)]
 #[allow(dead_code)]
 struct AppState {
     hwnd: HWND,
@@ -70,16 +173,421 @@ struct AppState {
     dwrite_factory: IDWriteFactory,
     text_format: IDWriteTextFormat,
     dualsense_receiver: mpsc::Receiver<dualsense::ControllerEvent>,
-    battery_status_map: HashMap<String, dualsense::BatteryReport>,
+    controller_commands: mpsc::Sender<dualsense::ControllerCommand>,
+    config: config::Config,
+    /// Set when the config file exists but couldn't be loaded, so saving the
+    /// defaults we fell back to would throw the user's settings away.
+    config_load_failed: bool,
+    controllers: controllers::ControllerRegistry,
     triggering_controller_path: Option<String>,
+    window_size: (i32, i32),
     visibility_state: VisibilityState,
     fadeout_timer_id: Option<usize>,
+    fade_in_animation: Option<IDCompositionAnimation>,
     fade_out_animation: Option<IDCompositionAnimation>,
+    animations: animation::OverlayAnimations,
+    animation_timer_running: bool,
     h_icon: Option<HICON>,
+    battery_icon: Option<HICON>,
+    battery_icon_visual: Option<overlay_layout::BatteryVisual>,
+    tray_tooltip: String,
+    tray_menu: tray_menu::TrayMenu,
+    notifiers: notifications::NotifierSet,
+    history: Option<battery_history::HistoryStore>,
+    /// Recent samples per controller identity, for runtime estimates.
+    battery_samples: HashMap<String, Vec<battery_history::BatterySample>>,
+    metrics: Option<Arc<Mutex<metrics::MetricsRegistry>>>,
+    /// Controller snapshot and event subscribers for the web API and the IPC
+    /// socket.
+    api_state: Arc<Mutex<web_api::WebApiState>>,
+    ipc_requests: mpsc::Receiver<ipc_server::UiRequest>,
+    mqtt: Option<mqtt_publisher::MqttPublisher>,
+    hooks: Option<hooks::HookRunner>,
+    rules: rules::RuleEngine,
+    rules_updated: Instant,
+    /// Only set while a button or gesture toggles the microphone.
+    microphone: Option<Box<dyn mic::Microphone>>,
+    mute_leds: mic::MuteLeds,
+    mic_polled: Instant,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse_args(&args) {
+        Ok(Some(command)) => {
+            attach_parent_console();
+            return match cli::run(command) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Ok(None) => {}
+        Err(e) => {
+            attach_parent_console();
+            cli::print_usage_error(&e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    run_overlay()
+}
+
+/// Restores the name and lightbar colour saved for a controller that just connected.
+#[cfg(windows)]
+fn apply_controller_settings(app_state: &mut AppState, path: &str) {
+    let Some(entry) = app_state.controllers.get(path) else {
+        return;
+    };
+    let Some(settings) = app_state.config.controller(entry.identity()).cloned() else {
+        return;
+    };
+    app_state.controllers.set_name(path, settings.name);
+    if let Some(color) = settings.lightbar {
+        window_message_handler::send_controller_command(
+            app_state,
+            dualsense::ControllerCommand::SetLightbar(path.to_string(), color),
+        );
+    }
+}
+
+/// Adds a reading to the controller's recent samples and the on-disk history.
+#[cfg(windows)]
+fn record_battery_sample(app_state: &mut AppState, path: &str, report: &dualsense::BatteryReport) {
+    let Some(entry) = app_state.controllers.get(path) else {
+        return;
+    };
+    let identity = entry.identity().to_string();
+    let sample = battery_history::BatterySample::now(report, entry.info.connection_type);
+    if let Some(history) = &mut app_state.history
+        && let Err(e) = history.append(&identity, &sample)
+    {
+        eprintln!("Failed to record battery history: {}", e);
+    }
+
+    let samples = app_state.battery_samples.entry(identity).or_default();
+    samples.push(sample);
+    let excess = samples.len().saturating_sub(runtime_estimate::MAX_SAMPLES);
+    samples.drain(..excess);
+}
+
+/// Seeds the runtime estimate for a controller that just connected from its
+/// recorded history.
+#[cfg(windows)]
+fn load_battery_samples(app_state: &mut AppState, path: &str) {
+    let (Some(history), Some(entry)) = (&app_state.history, app_state.controllers.get(path)) else {
+        return;
+    };
+    let identity = entry.identity().to_string();
+    if app_state.battery_samples.contains_key(&identity) {
+        return;
+    }
+    match history.load(&identity) {
+        Ok(mut samples) => {
+            let excess = samples.len().saturating_sub(runtime_estimate::MAX_SAMPLES);
+            samples.drain(..excess);
+            app_state.battery_samples.insert(identity, samples);
+        }
+        Err(e) => eprintln!("Failed to load battery history: {}", e),
+    }
+}
+
+/// Pops the overlay up for the controller and passes the alert to the notifiers.
+#[cfg(windows)]
+fn raise_alert(
+    app_state: &mut AppState,
+    path: &str,
+    notification: impl FnOnce(&controllers::ControllerEntry) -> Option<notifications::Notification>,
+) {
+    if app_state.config.alerts.show_overlay {
+        app_state.triggering_controller_path = Some(path.to_string());
+        // Toggling an overlay that's already up just restarts its hold timer.
+        window_message_handler::toggle_window_visibility(app_state);
+    }
+    let Some(entry) = app_state.controllers.get(path) else {
+        return;
+    };
+    if let Some(notification) = notification(entry) {
+        app_state.notifiers.notify_all(&notification);
+    }
+}
+
+#[cfg(windows)]
+fn local_time_of_day() -> rules::TimeOfDay {
+    let time = unsafe { GetLocalTime() };
+    rules::TimeOfDay::new(time.wHour, time.wMinute)
+}
+
+/// Checks the state rules against the controllers; see
+/// [`rules::RuleEngine::update`].
+#[cfg(windows)]
+fn update_rules(app_state: &mut AppState) {
+    if app_state.rules.is_empty() {
+        return;
+    }
+    let now = Instant::now();
+    app_state.rules_updated = now;
+    let firings = app_state
+        .rules
+        .update(app_state.controllers.iter(), now, local_time_of_day());
+    run_rule_actions(app_state, firings);
+}
+
+#[cfg(windows)]
+fn run_rule_actions(app_state: &mut AppState, firings: Vec<rules::Firing>) {
+    for firing in firings {
+        let path = firing.entry.path.clone();
+        println!("Main: Rule '{}' fired for {}", firing.rule, path);
+        for action in &firing.actions {
+            match action {
+                rules::Action::ShowOverlay => {
+                    let path = app_state.controllers.get(&path).map(|_| path.clone());
+                    window_message_handler::show_overlay_for(app_state, path);
+                }
+                rules::Action::Notify { title, message } => {
+                    let notification =
+                        notifications::Notification::rule(&firing.entry, title, message);
+                    app_state.notifiers.notify_all(&notification);
+                }
+                rules::Action::SetLightbar { color } => {
+                    window_message_handler::send_controller_command(
+                        app_state,
+                        dualsense::ControllerCommand::SetLightbar(path.clone(), *color),
+                    );
+                }
+                rules::Action::SetPlayerLed { player } => {
+                    let leds = output_report::player_led_pattern(*player);
+                    window_message_handler::send_controller_command(
+                        app_state,
+                        dualsense::ControllerCommand::SetPlayerLeds(path.clone(), leds),
+                    );
+                }
+                rules::Action::RunHook(command) => {
+                    if let Some(hooks) = &app_state.hooks {
+                        hooks.run_for_rule(command, &firing.rule, &firing.entry);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Carries out a button action for the controller it was pressed on, either by
+/// a gesture or, without one, by the mute button.
+#[cfg(windows)]
+fn run_button_action(
+    app_state: &mut AppState,
+    path: &str,
+    action: &config::ButtonAction,
+    gesture: Option<&gestures::Gesture>,
+) {
+    match action {
+        config::ButtonAction::ToggleOverlay => {
+            app_state.triggering_controller_path = Some(path.to_string());
+            window_message_handler::toggle_window_visibility(app_state);
+        }
+        config::ButtonAction::ToggleMicrophone => {
+            let Some(microphone) = &app_state.microphone else {
+                eprintln!("Main: No microphone to mute");
+                return;
+            };
+            match app_state.mute_leds.toggle(microphone.as_ref()) {
+                Ok(muted) => {
+                    println!(
+                        "Main: Microphone {}",
+                        if muted { "muted" } else { "unmuted" }
+                    );
+                    show_mic_state(app_state, muted);
+                }
+                Err(e) => eprintln!("Main: {}", e),
+            }
+        }
+        config::ButtonAction::RunHook(command) => {
+            let (Some(hooks), Some(entry)) = (&app_state.hooks, app_state.controllers.get(path))
+            else {
+                return;
+            };
+            match gesture {
+                Some(gesture) => hooks.run_for_gesture(command, gesture, entry),
+                None => hooks.run(
+                    command,
+                    &hooks::HookEvent::new(hooks::HookKind::MutePressed, entry),
+                ),
+            }
+        }
+        config::ButtonAction::None => {}
+    }
 }
 
-fn main() -> Result<(), ()> {
-    let dualsense_receiver = polling::setup_controller_polling().unwrap();
+/// Picks up mutes made outside the app, so the LEDs follow the microphone.
+#[cfg(windows)]
+fn poll_microphone(app_state: &mut AppState) {
+    app_state.mic_polled = Instant::now();
+    let Some(microphone) = &app_state.microphone else {
+        return;
+    };
+    match app_state.mute_leds.poll(microphone.as_ref()) {
+        Some(Ok(muted)) => show_mic_state(app_state, muted),
+        Some(Err(e)) => {
+            eprintln!("Main: {}", e);
+            show_mic_state(app_state, false);
+        }
+        None => {}
+    }
+}
+
+/// Updates the mute button LEDs on every connected controller.
+#[cfg(windows)]
+fn show_mic_state(app_state: &AppState, muted: bool) {
+    if !app_state.config.mute_button.mic_led {
+        return;
+    }
+    let paths: Vec<String> = app_state
+        .controllers
+        .iter()
+        .filter(|entry| entry.connected)
+        .map(|entry| entry.path.clone())
+        .collect();
+    for path in paths {
+        window_message_handler::send_controller_command(
+            app_state,
+            dualsense::ControllerCommand::SetMicLed(path, muted),
+        );
+    }
+}
+
+/// Asks the instance holding the IPC socket to show its overlay. It may still
+/// be starting up or busy with another client, so this retries briefly.
+#[cfg(windows)]
+fn activate_running_instance() -> Result<(), String> {
+    let mut client = (0..ACTIVATION_ATTEMPTS)
+        .find_map(|attempt| {
+            if attempt > 0 {
+                thread::sleep(ACTIVATION_RETRY_DELAY);
+            }
+            ipc::Client::connect()
+        })
+        .ok_or("No running instance answered")?;
+    client.call("show_overlay", serde_json::Value::Null)?;
+    Ok(())
+}
+
+/// Carries out an IPC client's request on the UI thread.
+#[cfg(windows)]
+fn handle_ipc_request(app_state: &mut AppState, request: ipc_server::UiRequest) {
+    match request {
+        ipc_server::UiRequest::ShowOverlay(path) => {
+            window_message_handler::show_overlay_for(app_state, path);
+        }
+        ipc_server::UiRequest::SetLightbar(path, color) => {
+            window_message_handler::set_lightbar(app_state, path, color);
+        }
+    }
+}
+
+/// Release builds use the GUI subsystem, so CLI output needs the console of the
+/// shell that launched us.
+#[cfg(windows)]
+fn attach_parent_console() {
+    let _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+#[cfg(not(windows))]
+fn attach_parent_console() {}
+
+#[cfg(not(windows))]
+fn run_overlay() -> ExitCode {
+    eprintln!("The battery overlay is only available on Windows; see `ds-battery help`.");
+    ExitCode::FAILURE
+}
+
+#[cfg(windows)]
+fn run_overlay() -> ExitCode {
+    // The IPC socket doubles as the single-instance lock, so take it before
+    // registering the hotkey or the tray icon.
+    let ipc_listener = match ipc::Listener::bind() {
+        Ok(listener) => Some(listener),
+        Err(ipc::BindError::InUse(bind_error)) => {
+            return match activate_running_instance() {
+                Ok(()) => {
+                    println!("Already running; showed the running overlay instead");
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("{}, but it didn't respond: {}", bind_error, e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Err(bind_error) => {
+            eprintln!("{}; running without the IPC socket", bind_error);
+            None
+        }
+    };
+
+    let (config, config_load_failed) = match config::Config::load() {
+        Ok(config) => (config, false),
+        Err(e) => {
+            eprintln!("Failed to load config, using defaults: {}", e);
+            (config::Config::default(), true)
+        }
+    };
+    let (dualsense_receiver, controller_commands) =
+        polling::setup_controller_polling(config.alerts.thresholds(), config.gestures.detector())
+            .unwrap();
+
+    let history = config
+        .history
+        .enabled
+        .then(|| battery_history::HistoryStore::open_default(config.history.retention()))
+        .flatten();
+
+    let metrics = config.metrics.enabled.then(|| {
+        let registry = Arc::new(Mutex::new(metrics::MetricsRegistry::default()));
+        let started = config
+            .metrics
+            .listen
+            .parse()
+            .map_err(|e| format!("Invalid metrics address '{}': {}", config.metrics.listen, e))
+            .and_then(|address| metrics_server::spawn(address, registry.clone()));
+        if let Err(e) = started {
+            eprintln!("{}", e);
+        }
+        registry
+    });
+
+    let api_state = Arc::new(Mutex::new(web_api::WebApiState::default()));
+    if config.web.enabled
+        && let Err(e) = web_server::spawn(config.web.address(), api_state.clone())
+    {
+        eprintln!("{}", e);
+    }
+    let (ipc_sender, ipc_requests) = mpsc::channel();
+    if let Some(listener) = ipc_listener
+        && let Err(e) = ipc_server::spawn(listener, api_state.clone(), ipc_sender)
+    {
+        eprintln!("{}", e);
+    }
+
+    let mqtt = config
+        .mqtt
+        .enabled
+        .then(|| mqtt_publisher::MqttPublisher::spawn(config.mqtt.clone()))
+        .and_then(|publisher| publisher.map_err(|e| eprintln!("{}", e)).ok());
+
+    let rules_run_hooks = config.rules.iter().any(|rule| {
+        rule.actions
+            .iter()
+            .any(|action| matches!(action, rules::Action::RunHook(_)))
+    });
+    let buttons_run_hooks = config
+        .button_actions()
+        .any(|action| matches!(action, config::ButtonAction::RunHook(_)));
+    let hooks = (!config.hooks.is_empty() || rules_run_hooks || buttons_run_hooks)
+        .then(|| hooks::HookRunner::spawn(config.hooks.clone()))
+        .and_then(|runner| runner.map_err(|e| eprintln!("{}", e)).ok());
 
     let hinstance: HINSTANCE = unsafe { GetModuleHandleW(None).unwrap().into() };
     let (hwnd, window_creator) = window::create_overlay_window(hinstance).unwrap();
@@ -110,15 +618,27 @@ fn main() -> Result<(), ()> {
         .ok()
     };
 
+    let window_size = window::overlay_window_size(0);
     let graphics_resources =
-        graphics::initialize_graphics(hwnd, WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32).unwrap();
+        graphics::initialize_graphics(hwnd, window_size.0 as u32, window_size.1 as u32).unwrap();
 
+    let rules = rules::RuleEngine::new(config.rules.clone());
+    let microphone = config
+        .button_actions()
+        .any(|action| *action == config::ButtonAction::ToggleMicrophone)
+        .then(mic::SystemMicrophone::new)
+        .and_then(|microphone| microphone.map_err(|e| eprintln!("{}", e)).ok())
+        .map(|microphone| Box::new(microphone) as Box<dyn mic::Microphone>);
     let mut app_state = AppState {
         hwnd,
         dualsense_receiver,
+        controller_commands,
+        config,
+        config_load_failed,
         visibility_state: VisibilityState::Hidden,
-        battery_status_map: HashMap::new(),
+        controllers: controllers::ControllerRegistry::new(),
         triggering_controller_path: None,
+        window_size,
         fadeout_timer_id: None,
         d3d_device: graphics_resources.d3d_device,
         dxgi_device: graphics_resources.dxgi_device,
@@ -132,17 +652,52 @@ fn main() -> Result<(), ()> {
         d2d_device_context: graphics_resources.d2d_device_context,
         dwrite_factory: graphics_resources.dwrite_factory,
         text_format: graphics_resources.text_format,
+        fade_in_animation: graphics_resources.fade_in_animation,
         fade_out_animation: graphics_resources.fade_out_animation,
+        animations: animation::OverlayAnimations::new(Instant::now()),
+        animation_timer_running: false,
         h_icon,
+        battery_icon: None,
+        battery_icon_visual: None,
+        tray_tooltip: String::new(),
+        tray_menu: tray_menu::TrayMenu::default(),
+        notifiers: notifications::NotifierSet::default(),
+        history,
+        battery_samples: HashMap::new(),
+        metrics,
+        api_state,
+        ipc_requests,
+        mqtt,
+        hooks,
+        rules,
+        rules_updated: Instant::now(),
+        microphone,
+        mute_leds: mic::MuteLeds::default(),
+        mic_polled: Instant::now(),
     };
 
     window_creator.associate_appstate_with_hwnd(app_state.hwnd, &mut app_state);
     if let Some(icon) = app_state.h_icon {
         tray::add_tray_icon(app_state.hwnd, icon, WM_APP_TRAYMSG).unwrap();
+        tray::refresh_battery_icon(&mut app_state);
+        tray::refresh_tooltip(&mut app_state);
+        if app_state.config.notifications.desktop {
+            app_state
+                .notifiers
+                .add(Box::new(tray::TrayBalloonNotifier::new(app_state.hwnd)));
+        }
     } else {
         eprintln!("Failed to load icon, not adding to tray");
     }
-    window::register_app_hotkey(app_state.hwnd).unwrap();
+    if app_state.config.webhook.enabled {
+        match webhook::WebhookNotifier::spawn(app_state.config.webhook.clone()) {
+            Ok(notifier) => app_state.notifiers.add(Box::new(notifier)),
+            Err(e) => eprintln!("Webhook: {}", e),
+        }
+    }
+    if let Err(e) = window::register_app_hotkey(app_state.hwnd) {
+        eprintln!("Failed to register hotkey: {}", e);
+    }
 
     unsafe { app_state.dcomp_device.Commit().unwrap() };
     println!("Initial dcomp commit succesful");
@@ -152,11 +707,12 @@ fn main() -> Result<(), ()> {
         while unsafe { PeekMessageW(&mut msg, Some(HWND::default()), 0, 0, PM_REMOVE) }.as_bool() {
             if msg.message == WM_QUIT {
                 println!("Received WM_QUIT");
-                window::unregister_app_hotkey(app_state.hwnd).unwrap();
+                let _ = window::unregister_app_hotkey(app_state.hwnd);
                 tray::remove_tray_icon(app_state.hwnd).unwrap_or_else(|_| {
                     eprintln!("Failed to remove tray icon");
                 });
-                return Ok(());
+                tray::destroy_battery_icon(&mut app_state);
+                return ExitCode::SUCCESS;
             }
 
             unsafe {
@@ -166,42 +722,176 @@ fn main() -> Result<(), ()> {
         }
 
         match app_state.dualsense_receiver.try_recv() {
-            Ok(event) => match event {
-                dualsense::ControllerEvent::BatteryUpdate(path, report) => {
-                    app_state
-                        .battery_status_map
-                        .insert(path.clone(), report.clone());
-
-                    if Some(&path) == app_state.triggering_controller_path.as_ref()
-                        && app_state.visibility_state != VisibilityState::Hidden
-                    {
-                        renderer::draw_content(&app_state);
+            Ok(event) => {
+                if let Some(metrics) = &app_state.metrics
+                    && let Ok(mut metrics) = metrics.lock()
+                {
+                    metrics.observe(&event, Instant::now());
+                }
+                let published = event.clone();
+                // Hooks and rules for disconnects need the entry before it's removed.
+                let entry_before = (app_state.hooks.is_some() || !app_state.rules.is_empty())
+                    .then(|| app_state.controllers.get(event.path()).cloned())
+                    .flatten();
+                match event {
+                    dualsense::ControllerEvent::BatteryUpdate(path, report) => {
+                        record_battery_sample(&mut app_state, &path, &report);
+                        let fill_level =
+                            overlay_layout::BatteryVisual::for_report(&report).fill_capacity as f32;
+                        if !app_state.controllers.update_battery(&path, report) {
+                            eprintln!("Main: Battery update for unknown device: {}", path);
+                        } else if app_state.visibility_state.is_shown() {
+                            app_state
+                                .animations
+                                .animate_fill(&path, fill_level, Instant::now());
+                            renderer::draw_content(&app_state);
+                            window_message_handler::update_animation_timer(&mut app_state);
+                        } else {
+                            app_state
+                                .animations
+                                .set_fill(&path, fill_level, Instant::now());
+                        }
+                    }
+                    dualsense::ControllerEvent::MuteButtonPressed(path) => {
+                        println!("Main: Mute button pressed on {}", path);
+                        let action = app_state.config.mute_button.action.clone();
+                        run_button_action(&mut app_state, &path, &action, None);
+                    }
+                    dualsense::ControllerEvent::Gesture(path, gesture) => {
+                        println!("Main: {} on {}", gesture, path);
+                        let actions: Vec<config::ButtonAction> = app_state
+                            .config
+                            .gestures
+                            .bindings
+                            .iter()
+                            .filter(|binding| binding.gesture() == gesture)
+                            .map(|binding| binding.action.clone())
+                            .collect();
+                        for action in &actions {
+                            run_button_action(&mut app_state, &path, action, Some(&gesture));
+                        }
+                    }
+                    dualsense::ControllerEvent::LowBattery(path, report) => {
+                        println!("Main: Low battery on {}", path);
+                        raise_alert(&mut app_state, &path, |entry| {
+                            notifications::Notification::battery_alert(
+                                entry,
+                                &report,
+                                AlertLevel::Low,
+                            )
+                        });
+                    }
+                    dualsense::ControllerEvent::CriticalBattery(path, report) => {
+                        println!("Main: Critical battery on {}", path);
+                        raise_alert(&mut app_state, &path, |entry| {
+                            notifications::Notification::battery_alert(
+                                entry,
+                                &report,
+                                AlertLevel::Critical,
+                            )
+                        });
+                    }
+                    dualsense::ControllerEvent::ChargeCeilingReached(path, report) => {
+                        println!("Main: Charge ceiling reached on {}", path);
+                        raise_alert(&mut app_state, &path, |entry| {
+                            Some(notifications::Notification::charge_alert(
+                                entry,
+                                &report,
+                                ChargeAlert::CeilingReached,
+                            ))
+                        });
+                    }
+                    dualsense::ControllerEvent::FullyCharged(path, report) => {
+                        println!("Main: Fully charged: {}", path);
+                        raise_alert(&mut app_state, &path, |entry| {
+                            Some(notifications::Notification::charge_alert(
+                                entry,
+                                &report,
+                                ChargeAlert::FullyCharged,
+                            ))
+                        });
+                    }
+                    dualsense::ControllerEvent::ReportStats(..) => {}
+                    dualsense::ControllerEvent::DeviceConnected(path, info) => {
+                        let player_number = app_state.controllers.connect(path.clone(), info);
+                        println!(
+                            "Main: Device connected as player {}: {}",
+                            player_number, path
+                        );
+                        apply_controller_settings(&mut app_state, &path);
+                        load_battery_samples(&mut app_state, &path);
+                        if app_state.config.mute_button.mic_led
+                            && let Some(muted) = app_state.mute_leds.muted()
+                        {
+                            window_message_handler::send_controller_command(
+                                &app_state,
+                                dualsense::ControllerCommand::SetMicLed(path.clone(), muted),
+                            );
+                        }
+                        window::fit_overlay_to_controllers(&mut app_state);
+                    }
+                    dualsense::ControllerEvent::DeviceDisconnected(path) => {
+                        println!("Main: Device disconnected: {}", path);
+                        if let Some(entry) = app_state.controllers.get(&path) {
+                            let notification = notifications::Notification::disconnected(entry);
+                            app_state.notifiers.notify_all(&notification);
+                        }
+                        if Some(&path) == app_state.triggering_controller_path.as_ref() {
+                            app_state.triggering_controller_path = None;
+                        }
+                        if !app_state.visibility_state.is_shown() {
+                            app_state.controllers.disconnect(&path);
+                            app_state.animations.remove(&path);
+                            window::fit_overlay_to_controllers(&mut app_state);
+                        } else if app_state.controllers.mark_disconnected(&path) {
+                            window_message_handler::show_disconnected_notice(&mut app_state);
+                        }
                     }
                 }
-                dualsense::ControllerEvent::MuteButtonPressed(path) => {
-                    println!("Main: Mute button pressed on {}", path);
-                    app_state.triggering_controller_path = Some(path.clone());
-                    window_message_handler::toggle_window_visibility(&mut app_state);
+                if let Ok(mut api_state) = app_state.api_state.lock() {
+                    api_state.publish(&published, &app_state.controllers);
                 }
-                dualsense::ControllerEvent::DeviceConnected(path) => {
-                    println!("Main: Device connected: {}", path);
+                if let Some(mqtt) = &app_state.mqtt {
+                    mqtt.update(&app_state.controllers);
                 }
-                dualsense::ControllerEvent::DeviceDisconnected(path) => {
-                    println!("Main: Device disconnected: {}", path);
-                    app_state.battery_status_map.remove(&path);
-                    if Some(&path) == app_state.triggering_controller_path.as_ref() {
-                        app_state.triggering_controller_path = None;
-                        app_state.visibility_state = VisibilityState::Hidden;
+                let event_entry = hooks::event_kind(
+                    &published,
+                    entry_before.as_ref(),
+                    app_state.controllers.get(published.path()),
+                )
+                .map(|(kind, entry)| (kind, entry.clone()));
+                if let Some((kind, entry)) = event_entry {
+                    let now = Instant::now();
+                    if let Some(hooks) = &mut app_state.hooks {
+                        hooks.fire(&hooks::HookEvent::new(kind, &entry), now);
                     }
+                    let firings = app_state
+                        .rules
+                        .on_event(kind, &entry, now, local_time_of_day());
+                    run_rule_actions(&mut app_state, firings);
                 }
-            },
+                update_rules(&mut app_state);
+                // Any event can change what the tray icon and its tooltip show.
+                tray::refresh_battery_icon(&mut app_state);
+                tray::refresh_tooltip(&mut app_state);
+            }
             Err(mpsc::TryRecvError::Disconnected) => {
                 eprintln!("Battery receiver disconnected");
-                break Err(());
+                break ExitCode::FAILURE;
             }
             _ => {}
         }
 
+        while let Ok(request) = app_state.ipc_requests.try_recv() {
+            handle_ipc_request(&mut app_state, request);
+        }
+        if app_state.rules_updated.elapsed() >= RULES_UPDATE_INTERVAL {
+            update_rules(&mut app_state);
+        }
+        if app_state.mic_polled.elapsed() >= MIC_POLL_INTERVAL {
+            poll_microphone(&mut app_state);
+        }
+
         thread::sleep(Duration::from_millis(50));
     }
 }
@@ -0,0 +1,408 @@
+//! Controller metrics in the Prometheus text exposition format.
+//!
+//! [`MetricsRegistry`] is fed every [`ControllerEvent`] and keeps one set of
+//! values per controller identity, so counters carry on across reconnects and
+//! switches between USB and Bluetooth.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::Instant,
+};
+
+use crate::{
+    controllers,
+    dualsense::{BatteryReport, BatteryStatus, ControllerEvent, ReportStats},
+};
+
+#[derive(Clone, Debug)]
+struct ControllerMetrics {
+    model: &'static str,
+    transport: &'static str,
+    battery: Option<BatteryReport>,
+    connected: bool,
+    last_report: Option<Instant>,
+    reports_total: u64,
+    read_errors_total: u64,
+    crc_failures_total: u64,
+    /// Input reports per second over the last stats interval.
+    report_rate: f64,
+    last_stats: Instant,
+}
+
+#[derive(Default)]
+pub struct MetricsRegistry {
+    /// HID path to identity, for the controllers currently connected.
+    identities: HashMap<String, String>,
+    controllers: BTreeMap<String, ControllerMetrics>,
+}
+
+impl MetricsRegistry {
+    pub fn observe(&mut self, event: &ControllerEvent, now: Instant) {
+        match event {
+            ControllerEvent::DeviceConnected(path, info) => {
+                let identity = controllers::identity(path, info).to_string();
+                let metrics =
+                    self.controllers
+                        .entry(identity.clone())
+                        .or_insert_with(|| ControllerMetrics {
+                            model: info.model.name(),
+                            transport: info.connection_type.short_name(),
+                            battery: None,
+                            connected: true,
+                            last_report: None,
+                            reports_total: 0,
+                            read_errors_total: 0,
+                            crc_failures_total: 0,
+                            report_rate: 0.0,
+                            last_stats: now,
+                        });
+                metrics.model = info.model.name();
+                metrics.transport = info.connection_type.short_name();
+                metrics.connected = true;
+                metrics.last_stats = now;
+                self.identities.insert(path.clone(), identity);
+            }
+            ControllerEvent::DeviceDisconnected(path) => {
+                if let Some(metrics) = self.controller_mut(path) {
+                    metrics.connected = false;
+                    metrics.report_rate = 0.0;
+                }
+                self.identities.remove(path);
+            }
+            ControllerEvent::BatteryUpdate(path, report) => {
+                if let Some(metrics) = self.controller_mut(path) {
+                    metrics.battery = Some(report.clone());
+                }
+            }
+            ControllerEvent::ReportStats(path, stats) => {
+                if let Some(metrics) = self.controller_mut(path) {
+                    metrics.add_stats(stats, now);
+                }
+            }
+            ControllerEvent::MuteButtonPressed(_)
+            | ControllerEvent::Gesture(..)
+            | ControllerEvent::LowBattery(..)
+            | ControllerEvent::CriticalBattery(..)
+            | ControllerEvent::ChargeCeilingReached(..)
+            | ControllerEvent::FullyCharged(..) => {}
+        }
+    }
+
+    fn controller_mut(&mut self, path: &str) -> Option<&mut ControllerMetrics> {
+        let identity = self.identities.get(path)?;
+        self.controllers.get_mut(identity)
+    }
+
+    /// Every metric as Prometheus text, with ages measured at `now`.
+    pub fn render(&self, now: Instant) -> String {
+        let mut text = String::new();
+        self.family(
+            &mut text,
+            "ds_battery_percent",
+            "gauge",
+            "Battery level reported by the controller, in 10% steps.",
+            |metrics| {
+                metrics
+                    .battery
+                    .as_ref()
+                    .map(|b| f64::from(b.battery_capacity))
+            },
+        );
+        self.family(
+            &mut text,
+            "ds_battery_charging",
+            "gauge",
+            "1 while the controller is charging, 0 otherwise.",
+            |metrics| {
+                let report = metrics.battery.as_ref()?;
+                Some(f64::from(u8::from(
+                    report.battery_status == BatteryStatus::Charging,
+                )))
+            },
+        );
+        self.family(
+            &mut text,
+            "ds_controller_connected",
+            "gauge",
+            "1 while the controller is connected.",
+            |metrics| Some(f64::from(u8::from(metrics.connected))),
+        );
+        self.family(
+            &mut text,
+            "ds_controller_last_report_age_seconds",
+            "gauge",
+            "Seconds since the last input report from the controller.",
+            |metrics| {
+                let last = metrics.last_report?;
+                Some(now.saturating_duration_since(last).as_secs_f64())
+            },
+        );
+        self.family(
+            &mut text,
+            "ds_controller_reports_total",
+            "counter",
+            "Input reports received.",
+            |metrics| Some(metrics.reports_total as f64),
+        );
+        self.family(
+            &mut text,
+            "ds_controller_read_errors_total",
+            "counter",
+            "Failed reads and reports that couldn't be parsed.",
+            |metrics| Some(metrics.read_errors_total as f64),
+        );
+        self.family(
+            &mut text,
+            "ds_controller_crc_failures_total",
+            "counter",
+            "Bluetooth input reports dropped for a bad checksum.",
+            |metrics| Some(metrics.crc_failures_total as f64),
+        );
+        self.family(
+            &mut text,
+            "ds_controller_report_rate_hz",
+            "gauge",
+            "Input reports per second over the last few seconds.",
+            |metrics| Some(metrics.report_rate),
+        );
+        text
+    }
+
+    fn family(
+        &self,
+        text: &mut String,
+        name: &str,
+        kind: &str,
+        help: &str,
+        value: impl Fn(&ControllerMetrics) -> Option<f64>,
+    ) {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for (identity, metrics) in &self.controllers {
+            if let Some(value) = value(metrics) {
+                let _ = writeln!(
+                    text,
+                    "{}{{controller=\"{}\",model=\"{}\",transport=\"{}\"}} {}",
+                    name,
+                    escape_label(identity),
+                    escape_label(metrics.model),
+                    escape_label(metrics.transport),
+                    value
+                );
+            }
+        }
+    }
+}
+
+impl ControllerMetrics {
+    fn add_stats(&mut self, stats: &ReportStats, now: Instant) {
+        self.reports_total += stats.reports;
+        self.read_errors_total += stats.read_errors;
+        self.crc_failures_total += stats.crc_failures;
+        if let Some(age) = stats.last_report_age {
+            self.last_report = now.checked_sub(age);
+        }
+        let elapsed = now.saturating_duration_since(self.last_stats).as_secs_f64();
+        if elapsed > 0.0 {
+            self.report_rate = stats.reports as f64 / elapsed;
+        }
+        self.last_stats = now;
+    }
+}
+
+/// Label values escape backslashes, quotes and newlines.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::dualsense::{ConnectionType, ControllerInfo, ControllerModel};
+
+    fn connected(
+        path: &str,
+        serial: Option<&str>,
+        connection_type: ConnectionType,
+    ) -> ControllerEvent {
+        ControllerEvent::DeviceConnected(
+            path.to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSenseEdge,
+                connection_type,
+                serial: serial.map(str::to_string),
+                firmware: None,
+            },
+        )
+    }
+
+    fn stats(reports: u64, read_errors: u64, crc_failures: u64) -> ReportStats {
+        ReportStats {
+            reports,
+            read_errors,
+            crc_failures,
+            last_report_age: Some(Duration::from_millis(500)),
+        }
+    }
+
+    /// The sample lines of one family.
+    fn samples<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+        text.lines()
+            .filter(|line| line.split('{').next() == Some(name))
+            .collect()
+    }
+
+    #[test]
+    fn empty_registry_still_declares_every_family() {
+        let text = MetricsRegistry::default().render(Instant::now());
+        let types: Vec<&str> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "ds_battery_percent gauge",
+                "ds_battery_charging gauge",
+                "ds_controller_connected gauge",
+                "ds_controller_last_report_age_seconds gauge",
+                "ds_controller_reports_total counter",
+                "ds_controller_read_errors_total counter",
+                "ds_controller_crc_failures_total counter",
+                "ds_controller_report_rate_hz gauge",
+            ]
+        );
+        assert_eq!(text.lines().count(), 16);
+    }
+
+    #[test]
+    fn counters_carry_over_a_reconnect() {
+        let mut registry = MetricsRegistry::default();
+        let start = Instant::now();
+        registry.observe(
+            &connected("usb-path", Some("serial"), ConnectionType::Usb),
+            start,
+        );
+        registry.observe(
+            &ControllerEvent::ReportStats("usb-path".to_string(), stats(1000, 1, 0)),
+            start + Duration::from_secs(4),
+        );
+        registry.observe(
+            &ControllerEvent::DeviceDisconnected("usb-path".to_string()),
+            start + Duration::from_secs(5),
+        );
+
+        let text = registry.render(start + Duration::from_secs(5));
+        let labels = r#"{controller="serial",model="DualSense Edge",transport="USB"}"#;
+        assert_eq!(
+            samples(&text, "ds_controller_connected"),
+            [format!("ds_controller_connected{} 0", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_controller_report_rate_hz"),
+            [format!("ds_controller_report_rate_hz{} 0", labels)]
+        );
+
+        // The same pad comes back over Bluetooth with a new HID path.
+        let later = start + Duration::from_secs(10);
+        registry.observe(
+            &connected("bt-path", Some("serial"), ConnectionType::Bluetooth),
+            later,
+        );
+        registry.observe(
+            &ControllerEvent::ReportStats("bt-path".to_string(), stats(500, 2, 3)),
+            later + Duration::from_secs(2),
+        );
+
+        let text = registry.render(later + Duration::from_secs(2));
+        let labels = r#"{controller="serial",model="DualSense Edge",transport="BT"}"#;
+        assert_eq!(
+            samples(&text, "ds_controller_reports_total"),
+            [format!("ds_controller_reports_total{} 1500", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_controller_read_errors_total"),
+            [format!("ds_controller_read_errors_total{} 3", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_controller_crc_failures_total"),
+            [format!("ds_controller_crc_failures_total{} 3", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_controller_report_rate_hz"),
+            [format!("ds_controller_report_rate_hz{} 250", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_controller_last_report_age_seconds"),
+            [format!(
+                "ds_controller_last_report_age_seconds{} 0.5",
+                labels
+            )]
+        );
+    }
+
+    #[test]
+    fn battery_is_left_out_until_reported() {
+        let mut registry = MetricsRegistry::default();
+        let now = Instant::now();
+        registry.observe(&connected("path", None, ConnectionType::Usb), now);
+        let text = registry.render(now);
+        assert!(samples(&text, "ds_battery_percent").is_empty());
+        assert!(samples(&text, "ds_battery_charging").is_empty());
+
+        registry.observe(
+            &ControllerEvent::BatteryUpdate(
+                "path".to_string(),
+                BatteryReport::new(40, BatteryStatus::Discharging),
+            ),
+            now,
+        );
+        let text = registry.render(now);
+        let labels = r#"{controller="path",model="DualSense Edge",transport="USB"}"#;
+        assert_eq!(
+            samples(&text, "ds_battery_percent"),
+            [format!("ds_battery_percent{} 40", labels)]
+        );
+        assert_eq!(
+            samples(&text, "ds_battery_charging"),
+            [format!("ds_battery_charging{} 0", labels)]
+        );
+    }
+
+    #[test]
+    fn events_for_unknown_paths_are_ignored() {
+        let mut registry = MetricsRegistry::default();
+        let now = Instant::now();
+        registry.observe(
+            &ControllerEvent::ReportStats("nowhere".to_string(), stats(10, 0, 0)),
+            now,
+        );
+        assert_eq!(registry.render(now), MetricsRegistry::default().render(now));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label(r#"a\b"c"#), r#"a\\b\"c"#);
+        assert_eq!(escape_label("line\nbreak"), "line\\nbreak");
+
+        let mut registry = MetricsRegistry::default();
+        let now = Instant::now();
+        registry.observe(
+            &connected("\\\\?\\hid#\"pad\"", None, ConnectionType::Usb),
+            now,
+        );
+        let text = registry.render(now);
+        assert_eq!(
+            samples(&text, "ds_controller_connected"),
+            [
+                r#"ds_controller_connected{controller="\\\\?\\hid#\"pad\"",model="DualSense Edge",transport="USB"} 1"#
+            ]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Serves [`MetricsRegistry`] over HTTP for Prometheus to scrape.
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::{http, metrics::MetricsRegistry};
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Starts listening on `address` and answers `GET /metrics` from a background
+/// thread until the process exits. Returns the address it's listening on,
+/// which differs from `address` when that asked for any free port.
+pub fn spawn(
+    address: SocketAddr,
+    registry: Arc<Mutex<MetricsRegistry>>,
+) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    println!("Serving metrics on http://{}/metrics", address);
+
+    thread::Builder::new()
+        .name("metrics_server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let registry = registry.clone();
+                        let spawned = thread::Builder::new()
+                            .name("metrics_connection".to_string())
+                            .spawn(move || handle_connection(&stream, &registry));
+                        if let Err(e) = spawned {
+                            eprintln!("Metrics server: Failed to start connection thread: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Metrics server: Failed to accept connection: {}", e),
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start metrics server: {}", e))?;
+    Ok(address)
+}
+
+/// Answers one request, on a thread of its own so a slow client can't hold
+/// up other scrapes.
+fn handle_connection(stream: &TcpStream, registry: &Mutex<MetricsRegistry>) {
+    let request = match http::read_request(stream) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Metrics server: Bad request: {}", e);
+            return;
+        }
+    };
+    let result = if request.method == "GET" && request.path == "/metrics" {
+        let body = match registry.lock() {
+            Ok(registry) => registry.render(Instant::now()),
+            Err(_) => String::new(),
+        };
+        http::write_response(stream, "200 OK", PROMETHEUS_CONTENT_TYPE, body.as_bytes())
+    } else {
+        http::write_not_found(stream)
+    };
+    if let Err(e) = result {
+        eprintln!("Metrics server: Failed to send response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::dualsense::{
+        BatteryReport, BatteryStatus, ConnectionType, ControllerEvent, ControllerInfo,
+        ControllerModel,
+    };
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_the_exposition_format() {
+        let registry = Arc::new(Mutex::new(MetricsRegistry::default()));
+        let now = Instant::now();
+        {
+            let mut registry = registry.lock().unwrap();
+            registry.observe(
+                &ControllerEvent::DeviceConnected(
+                    "pad-path".to_string(),
+                    ControllerInfo {
+                        model: ControllerModel::DualSense,
+                        connection_type: ConnectionType::Usb,
+                        serial: Some("aa:bb".to_string()),
+                        firmware: None,
+                    },
+                ),
+                now,
+            );
+            registry.observe(
+                &ControllerEvent::BatteryUpdate(
+                    "pad-path".to_string(),
+                    BatteryReport::new(70, BatteryStatus::Charging),
+                ),
+                now,
+            );
+        }
+        let address = spawn("127.0.0.1:0".parse().unwrap(), registry).unwrap();
+        assert_ne!(address.port(), 0);
+
+        let response = get(address, "/metrics");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Type: {}", PROMETHEUS_CONTENT_TYPE)));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+
+        let labels = r#"{controller="aa:bb",model="DualSense",transport="USB"}"#;
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(
+            lines[..6],
+            [
+                "# HELP ds_battery_percent Battery level reported by the controller, in 10% steps.",
+                "# TYPE ds_battery_percent gauge",
+                &format!("ds_battery_percent{} 70", labels),
+                "# HELP ds_battery_charging 1 while the controller is charging, 0 otherwise.",
+                "# TYPE ds_battery_charging gauge",
+                &format!("ds_battery_charging{} 1", labels),
+            ]
+        );
+        assert!(lines.contains(&format!("ds_controller_reports_total{} 0", labels).as_str()));
+        // Every sample belongs to a family declared before it.
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let name = line.split('{').next().unwrap();
+            assert!(
+                lines.contains(&format!("# TYPE {} gauge", name).as_str())
+                    || lines.contains(&format!("# TYPE {} counter", name).as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn other_paths_are_not_found() {
+        let registry = Arc::new(Mutex::new(MetricsRegistry::default()));
+        let address = spawn("127.0.0.1:0".parse().unwrap(), registry).unwrap();
+        assert!(get(address, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn an_idle_client_does_not_block_scrapes() {
+        let registry = Arc::new(Mutex::new(MetricsRegistry::default()));
+        let address = spawn("127.0.0.1:0".parse().unwrap(), registry).unwrap();
+        let _idle = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+        assert!(get(address, "/metrics").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
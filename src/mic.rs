@@ -0,0 +1,224 @@
+//! Muting the system's default microphone from the controller's mute button.
+//!
+//! The app talks to a [`Microphone`], so the mute handling doesn't depend on
+//! the platform's audio API, and [`MuteLeds`] keeps the controllers' mute
+//! button LEDs following it.
+
+pub trait Microphone {
+    fn is_muted(&self) -> Result<bool, String>;
+    fn set_muted(&self, muted: bool) -> Result<(), String>;
+}
+
+/// Flips the mute state and returns the new one.
+pub fn toggle(microphone: &dyn Microphone) -> Result<bool, String> {
+    let muted = !microphone.is_muted()?;
+    microphone.set_muted(muted)?;
+    Ok(muted)
+}
+
+/// The mute state the mute button LEDs show, so they're only sent changes.
+#[derive(Debug, Default)]
+pub struct MuteLeds {
+    /// `None` until the microphone has been read, and while it can't be.
+    muted: Option<bool>,
+}
+
+impl MuteLeds {
+    /// What to light a controller that just connected with, if known.
+    pub fn muted(&self) -> Option<bool> {
+        self.muted
+    }
+
+    /// Toggles `microphone` and returns whether it's now muted, which the
+    /// LEDs should show.
+    pub fn toggle(&mut self, microphone: &dyn Microphone) -> Result<bool, String> {
+        let muted = toggle(microphone)?;
+        self.muted = Some(muted);
+        Ok(muted)
+    }
+
+    /// Rereads `microphone` to pick up mutes made outside the app. Returns
+    /// `None` when nothing changed, or else what the LEDs should show. A
+    /// microphone that stops answering turns them off and returns its error,
+    /// once rather than on every poll.
+    pub fn poll(&mut self, microphone: &dyn Microphone) -> Option<Result<bool, String>> {
+        match microphone.is_muted() {
+            Ok(muted) if self.muted == Some(muted) => None,
+            Ok(muted) => {
+                self.muted = Some(muted);
+                Some(Ok(muted))
+            }
+            Err(_) if self.muted.is_none() => None,
+            Err(e) => {
+                self.muted = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The default communications capture device, looked up on every call so it
+/// follows the user switching headsets.
+#[cfg(windows)]
+pub struct SystemMicrophone {
+    enumerator: windows::Win32::Media::Audio::IMMDeviceEnumerator,
+}
+
+#[cfg(windows)]
+impl SystemMicrophone {
+    /// Must be created and used on one thread; it initializes COM there.
+    pub fn new() -> Result<Self, String> {
+        use windows::Win32::{
+            Media::Audio::MMDeviceEnumerator,
+            System::Com::{CLSCTX_ALL, COINIT_APARTMENTTHREADED, CoCreateInstance, CoInitializeEx},
+        };
+
+        // Fails harmlessly if the thread already joined another apartment.
+        let _ = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) };
+        let enumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL) }
+            .map_err(|e| format!("Failed to open the audio device list: {}", e))?;
+        Ok(Self { enumerator })
+    }
+
+    fn endpoint_volume(
+        &self,
+    ) -> Result<windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume, String> {
+        use windows::Win32::{
+            Media::Audio::{eCapture, eCommunications},
+            System::Com::CLSCTX_ALL,
+        };
+
+        let device = unsafe {
+            self.enumerator
+                .GetDefaultAudioEndpoint(eCapture, eCommunications)
+        }
+        .map_err(|e| format!("No default microphone: {}", e))?;
+        unsafe { device.Activate(CLSCTX_ALL, None) }
+            .map_err(|e| format!("Failed to open the microphone's volume control: {}", e))
+    }
+}
+
+#[cfg(windows)]
+impl Microphone for SystemMicrophone {
+    fn is_muted(&self) -> Result<bool, String> {
+        let muted = unsafe { self.endpoint_volume()?.GetMute() }
+            .map_err(|e| format!("Failed to read the microphone mute: {}", e))?;
+        Ok(muted.as_bool())
+    }
+
+    fn set_muted(&self, muted: bool) -> Result<(), String> {
+        unsafe { self.endpoint_volume()?.SetMute(muted, std::ptr::null()) }
+            .map_err(|e| format!("Failed to mute the microphone: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Only remembers its mute state. Set `fail` to make every call error.
+    #[derive(Debug, Default)]
+    struct MockMicrophone {
+        muted: Cell<bool>,
+        fail: Cell<bool>,
+    }
+
+    impl Microphone for MockMicrophone {
+        fn is_muted(&self) -> Result<bool, String> {
+            if self.fail.get() {
+                return Err("Microphone unavailable".to_string());
+            }
+            Ok(self.muted.get())
+        }
+
+        fn set_muted(&self, muted: bool) -> Result<(), String> {
+            if self.fail.get() {
+                return Err("Microphone unavailable".to_string());
+            }
+            self.muted.set(muted);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn toggle_flips_the_mute() {
+        let microphone = MockMicrophone::default();
+        assert_eq!(toggle(&microphone), Ok(true));
+        assert!(microphone.muted.get());
+        assert_eq!(toggle(&microphone), Ok(false));
+        assert!(!microphone.muted.get());
+    }
+
+    #[test]
+    fn toggle_passes_errors_on() {
+        let microphone = MockMicrophone::default();
+        microphone.fail.set(true);
+        assert_eq!(
+            toggle(&microphone),
+            Err("Microphone unavailable".to_string())
+        );
+        assert!(!microphone.muted.get());
+    }
+
+    #[test]
+    fn leds_follow_toggles() {
+        let microphone = MockMicrophone::default();
+        let mut leds = MuteLeds::default();
+        assert_eq!(leds.muted(), None);
+        assert_eq!(leds.toggle(&microphone), Ok(true));
+        assert_eq!(leds.muted(), Some(true));
+        // The toggle already lit them, so the next poll has nothing to send.
+        assert_eq!(leds.poll(&microphone), None);
+    }
+
+    #[test]
+    fn failed_toggle_leaves_leds_alone() {
+        let microphone = MockMicrophone::default();
+        let mut leds = MuteLeds::default();
+        assert_eq!(leds.poll(&microphone), Some(Ok(false)));
+        microphone.fail.set(true);
+        assert!(leds.toggle(&microphone).is_err());
+        assert_eq!(leds.muted(), Some(false));
+    }
+
+    #[test]
+    fn poll_only_reports_changes() {
+        let microphone = MockMicrophone::default();
+        let mut leds = MuteLeds::default();
+        assert_eq!(leds.poll(&microphone), Some(Ok(false)));
+        assert_eq!(leds.poll(&microphone), None);
+        // Muted from the system tray.
+        microphone.muted.set(true);
+        assert_eq!(leds.poll(&microphone), Some(Ok(true)));
+        assert_eq!(leds.poll(&microphone), None);
+    }
+
+    #[test]
+    fn lost_microphone_turns_leds_off_once() {
+        let microphone = MockMicrophone::default();
+        microphone.muted.set(true);
+        let mut leds = MuteLeds::default();
+        assert_eq!(leds.poll(&microphone), Some(Ok(true)));
+
+        microphone.fail.set(true);
+        assert_eq!(
+            leds.poll(&microphone),
+            Some(Err("Microphone unavailable".to_string()))
+        );
+        assert_eq!(leds.muted(), None);
+        assert_eq!(leds.poll(&microphone), None);
+
+        microphone.fail.set(false);
+        assert_eq!(leds.poll(&microphone), Some(Ok(true)));
+    }
+
+    #[test]
+    fn missing_microphone_at_start_is_quiet() {
+        let microphone = MockMicrophone::default();
+        microphone.fail.set(true);
+        let mut leds = MuteLeds::default();
+        assert_eq!(leds.poll(&microphone), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! The slice of MQTT 3.1.1 a publish-only client needs: CONNECT with a last
+//! will, QoS 0 PUBLISH, keep-alive pings and DISCONNECT.
+
+use std::io::{self, Read, Write};
+
+const PROTOCOL_LEVEL: u8 = 4;
+
+const PACKET_CONNECT: u8 = 0x10;
+const PACKET_CONNACK: u8 = 0x20;
+const PACKET_PUBLISH: u8 = 0x30;
+const PACKET_PINGREQ: u8 = 0xC0;
+const PACKET_PINGRESP: u8 = 0xD0;
+const PACKET_DISCONNECT: u8 = 0xE0;
+
+const PUBLISH_RETAIN: u8 = 0x01;
+const CONNECT_CLEAN_SESSION: u8 = 0x02;
+const CONNECT_WILL: u8 = 0x04;
+const CONNECT_WILL_RETAIN: u8 = 0x20;
+const CONNECT_PASSWORD: u8 = 0x40;
+const CONNECT_USERNAME: u8 = 0x80;
+
+/// Largest packet the broker may send; publish-only clients only get acks.
+const MAX_INCOMING_LENGTH: usize = 1024;
+
+/// Message the broker publishes for us if the connection drops.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectOptions {
+    pub client_id: String,
+    pub keep_alive_secs: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub will: Option<Will>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Packet {
+    /// CONNACK with its return code; 0 means accepted.
+    ConnAck(u8),
+    PingResp,
+    /// Anything else; a publish-only client has no use for it.
+    Other(u8),
+}
+
+/// Fails if a string in `options` is too long for MQTT.
+pub fn connect_packet(options: &ConnectOptions) -> Result<Vec<u8>, String> {
+    let mut flags = CONNECT_CLEAN_SESSION;
+    let mut body = Vec::new();
+    push_string(&mut body, "MQTT")?;
+    body.push(PROTOCOL_LEVEL);
+    // Flags are filled in once the payload is known.
+    let flags_index = body.len();
+    body.push(0);
+    body.extend_from_slice(&options.keep_alive_secs.to_be_bytes());
+
+    push_string(&mut body, &options.client_id)?;
+    if let Some(will) = &options.will {
+        flags |= CONNECT_WILL;
+        if will.retain {
+            flags |= CONNECT_WILL_RETAIN;
+        }
+        push_string(&mut body, &will.topic)?;
+        push_string(&mut body, &will.payload)?;
+    }
+    if let Some(username) = &options.username {
+        flags |= CONNECT_USERNAME;
+        push_string(&mut body, username)?;
+        if let Some(password) = &options.password {
+            flags |= CONNECT_PASSWORD;
+            push_string(&mut body, password)?;
+        }
+    }
+    body[flags_index] = flags;
+    Ok(packet(PACKET_CONNECT, &body))
+}
+
+/// A QoS 0 publish. Fails if the topic is too long for MQTT.
+pub fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    push_string(&mut body, topic)?;
+    body.extend_from_slice(payload);
+    let flags = if retain { PUBLISH_RETAIN } else { 0 };
+    Ok(packet(PACKET_PUBLISH | flags, &body))
+}
+
+pub fn ping_packet() -> Vec<u8> {
+    packet(PACKET_PINGREQ, &[])
+}
+
+pub fn disconnect_packet() -> Vec<u8> {
+    packet(PACKET_DISCONNECT, &[])
+}
+
+pub fn read_packet(stream: &mut impl Read) -> io::Result<Packet> {
+    let mut header = [0u8; 1];
+    stream.read_exact(&mut header)?;
+
+    let mut length = 0usize;
+    for shift in (0..28).step_by(7) {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        length |= usize::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > MAX_INCOMING_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Broker sent a {} byte packet", length),
+        ));
+    }
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body)?;
+
+    Ok(match header[0] & 0xF0 {
+        PACKET_CONNACK if body.len() == 2 => Packet::ConnAck(body[1]),
+        PACKET_PINGRESP => Packet::PingResp,
+        kind => Packet::Other(kind),
+    })
+}
+
+pub fn write_packet(stream: &mut impl Write, packet: &[u8]) -> io::Result<()> {
+    stream.write_all(packet)?;
+    stream.flush()
+}
+
+/// What a non-zero CONNACK return code means.
+pub fn connack_error(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown error",
+    }
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Appends a length-prefixed string, which MQTT caps at 65535 bytes.
+fn push_string(buffer: &mut Vec<u8>, text: &str) -> Result<(), String> {
+    // The text may be a password, so it stays out of the error.
+    let length = u16::try_from(text.len())
+        .map_err(|_| format!("A {} byte string is longer than MQTT allows", text.len()))?;
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_with_will_and_credentials() {
+        let options = ConnectOptions {
+            client_id: "id".to_string(),
+            keep_alive_secs: 60,
+            username: Some("u".to_string()),
+            password: Some("p".to_string()),
+            will: Some(Will {
+                topic: "t".to_string(),
+                payload: "off".to_string(),
+                retain: true,
+            }),
+        };
+        let mut expected = vec![PACKET_CONNECT, 28, 0, 4];
+        expected.extend_from_slice(b"MQTT");
+        expected.extend_from_slice(&[PROTOCOL_LEVEL, 0xE6, 0, 60]);
+        expected.extend_from_slice(b"\0\x02id\0\x01t\0\x03off\0\x01u\0\x01p");
+        assert_eq!(connect_packet(&options), Ok(expected));
+    }
+
+    #[test]
+    fn anonymous_connect_only_asks_for_a_clean_session() {
+        let options = ConnectOptions {
+            client_id: "id".to_string(),
+            ..ConnectOptions::default()
+        };
+        let packet = connect_packet(&options).unwrap();
+        assert_eq!(packet[..2], [PACKET_CONNECT, 14]);
+        assert_eq!(packet[9], CONNECT_CLEAN_SESSION);
+        assert_eq!(packet[12..], *b"\0\x02id");
+    }
+
+    #[test]
+    fn publish_encoding() {
+        assert_eq!(
+            publish_packet("a/b", b"42", true),
+            Ok(b"\x31\x07\0\x03a/b42".to_vec())
+        );
+        assert_eq!(
+            publish_packet("a/b", b"42", false).unwrap()[0],
+            PACKET_PUBLISH
+        );
+    }
+
+    #[test]
+    fn oversized_strings_are_rejected() {
+        let topic = "t".repeat(65536);
+        assert!(publish_packet(&topic, b"42", true).is_err());
+        assert!(publish_packet(&topic[1..], b"42", true).is_ok());
+
+        let options = ConnectOptions {
+            client_id: "id".to_string(),
+            password: Some("p".repeat(70000)),
+            username: Some("u".to_string()),
+            ..ConnectOptions::default()
+        };
+        assert!(connect_packet(&options).is_err());
+    }
+
+    #[test]
+    fn remaining_length_uses_continuation_bytes() {
+        assert_eq!(
+            packet(PACKET_PUBLISH, &[0; 127])[..2],
+            [PACKET_PUBLISH, 0x7F]
+        );
+        assert_eq!(
+            packet(PACKET_PUBLISH, &[0; 128])[..3],
+            [PACKET_PUBLISH, 0x80, 0x01]
+        );
+        assert_eq!(
+            packet(PACKET_PUBLISH, &[0; 16_384])[..4],
+            [PACKET_PUBLISH, 0x80, 0x80, 0x01]
+        );
+    }
+
+    #[test]
+    fn control_packets() {
+        assert_eq!(ping_packet(), [PACKET_PINGREQ, 0]);
+        assert_eq!(disconnect_packet(), [PACKET_DISCONNECT, 0]);
+    }
+
+    #[test]
+    fn reads_broker_packets() {
+        let mut input: &[u8] = &[0x20, 2, 0, 0, 0x20, 2, 0, 5, 0xD0, 0, 0x90, 3, 0, 1, 0];
+        assert_eq!(read_packet(&mut input).unwrap(), Packet::ConnAck(0));
+        assert_eq!(read_packet(&mut input).unwrap(), Packet::ConnAck(5));
+        assert_eq!(read_packet(&mut input).unwrap(), Packet::PingResp);
+        assert_eq!(read_packet(&mut input).unwrap(), Packet::Other(0x90));
+        assert!(read_packet(&mut input).is_err());
+    }
+
+    #[test]
+    fn refuses_oversized_packets() {
+        // 2000 bytes, more than a publish-only client should ever be sent.
+        let mut input: &[u8] = &[0x30, 0xD0, 0x0F];
+        let error = read_packet(&mut input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,540 @@
+//! Publishes controller state to an MQTT broker, with Home Assistant discovery.
+//!
+//! Every value is a retained message, so subscribers that connect later still
+//! see the current state:
+//!
+//! - `<prefix>/status`: `online`, or `offline` once we're gone (last will).
+//! - `<prefix>/<controller>/battery`: capacity in percent.
+//! - `<prefix>/<controller>/status`: `discharging`, `charging`, `full`, ...
+//! - `<prefix>/<controller>/connected`: `ON` or `OFF`.
+//!
+//! `<controller>` is the controller identity with anything but letters and
+//! digits replaced by `_`. Only changed values are sent; after a reconnect
+//! everything is sent again.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    battery_history,
+    config::MqttSettings,
+    controllers::ControllerRegistry,
+    dualsense::BatteryReport,
+    mqtt::{self, ConnectOptions, Packet, Will},
+    net::{self, Stream},
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// How long connecting and each read or write may take, which is also how
+/// long the broker gets to answer a CONNECT or a ping.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// What gets published about one controller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControllerState {
+    pub identity: String,
+    pub name: String,
+    pub model: &'static str,
+    pub battery: Option<BatteryReport>,
+    pub connected: bool,
+}
+
+impl ControllerState {
+    pub fn from_registry(registry: &ControllerRegistry) -> Vec<Self> {
+        registry
+            .iter()
+            .map(|entry| Self {
+                identity: entry.identity().to_string(),
+                name: entry.display_name().to_string(),
+                model: entry.info.model.name(),
+                battery: entry.battery.clone(),
+                connected: entry.connected,
+            })
+            .collect()
+    }
+}
+
+/// Handle to the publishing thread, which owns the broker connection.
+pub struct MqttPublisher {
+    updates: mpsc::Sender<Vec<ControllerState>>,
+}
+
+impl MqttPublisher {
+    pub fn spawn(settings: MqttSettings) -> Result<Self, String> {
+        let (updates, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("mqtt_publisher".to_string())
+            .spawn(move || run(&settings, &receiver))
+            .map_err(|e| format!("Failed to start MQTT publisher: {}", e))?;
+        Ok(Self { updates })
+    }
+
+    /// Hands the current controllers to the publishing thread.
+    pub fn update(&self, registry: &ControllerRegistry) {
+        let _ = self.updates.send(ControllerState::from_registry(registry));
+    }
+}
+
+/// Connects, publishes until the connection fails, and tries again with
+/// exponential backoff. Returns once the app drops its [`MqttPublisher`].
+fn run(settings: &MqttSettings, updates: &mpsc::Receiver<Vec<ControllerState>>) {
+    let broker = format!("{}:{}", settings.host, settings.port);
+    // Every controller seen since startup, so pads that were removed from the
+    // overlay still end up marked as disconnected.
+    let mut controllers = BTreeMap::new();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match connect(settings) {
+            Ok(mut stream) => {
+                println!("MQTT: Connected to {}", broker);
+                backoff = MIN_BACKOFF;
+                match publish_until_closed(&mut stream, settings, updates, &mut controllers) {
+                    Ok(()) => return,
+                    Err(e) => eprintln!("MQTT: Lost connection to {}: {}", broker, e),
+                }
+            }
+            Err(e) => eprintln!("MQTT: Couldn't connect to {}: {}", broker, e),
+        }
+
+        // Keep taking updates while waiting, so the reconnect starts current.
+        let retry_at = Instant::now() + backoff;
+        while let Some(wait) = retry_at.checked_duration_since(Instant::now()) {
+            match updates.recv_timeout(wait) {
+                Ok(states) => merge_states(&mut controllers, states),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn connect(settings: &MqttSettings) -> Result<Box<dyn Stream>, String> {
+    let mut stream = net::connect(&settings.host, settings.port, settings.tls, TIMEOUT)?;
+
+    let options = ConnectOptions {
+        client_id: settings.client_id.clone(),
+        keep_alive_secs: KEEP_ALIVE.as_secs() as u16,
+        username: Some(settings.username.clone()).filter(|name| !name.is_empty()),
+        password: Some(settings.password.clone()).filter(|password| !password.is_empty()),
+        will: Some(Will {
+            topic: bridge_status_topic(settings),
+            payload: OFFLINE.to_string(),
+            retain: true,
+        }),
+    };
+    mqtt::write_packet(&mut stream, &mqtt::connect_packet(&options)?).map_err(|e| e.to_string())?;
+    match mqtt::read_packet(&mut stream).map_err(|e| e.to_string())? {
+        Packet::ConnAck(0) => Ok(stream),
+        Packet::ConnAck(code) => Err(format!("Broker refused: {}", mqtt::connack_error(code))),
+        _ => Err("Broker didn't acknowledge the connection".to_string()),
+    }
+}
+
+/// Publishes state changes and keeps the connection alive. `Ok` means the app
+/// is shutting down; `Err` means the connection failed.
+fn publish_until_closed(
+    stream: &mut Box<dyn Stream>,
+    settings: &MqttSettings,
+    updates: &mpsc::Receiver<Vec<ControllerState>>,
+    controllers: &mut BTreeMap<String, ControllerState>,
+) -> Result<(), String> {
+    let mut published = HashMap::new();
+    publish(stream, &bridge_status_topic(settings), ONLINE)?;
+    publish_changes(stream, settings, controllers, &mut published)?;
+
+    // Pings go out at half the keep-alive, so a late one doesn't get us dropped.
+    let ping_interval = KEEP_ALIVE / 2;
+    let mut next_ping = Instant::now() + ping_interval;
+    loop {
+        let wait = next_ping.saturating_duration_since(Instant::now());
+        match updates.recv_timeout(wait) {
+            Ok(states) => {
+                merge_states(controllers, states);
+                publish_changes(stream, settings, controllers, &mut published)?;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                publish(stream, &bridge_status_topic(settings), OFFLINE)?;
+                return mqtt::write_packet(stream, &mqtt::disconnect_packet())
+                    .map_err(|e| e.to_string());
+            }
+        }
+
+        if Instant::now() >= next_ping {
+            mqtt::write_packet(stream, &mqtt::ping_packet()).map_err(|e| e.to_string())?;
+            loop {
+                match mqtt::read_packet(stream).map_err(|e| e.to_string())? {
+                    Packet::PingResp => break,
+                    _ => continue,
+                }
+            }
+            next_ping = Instant::now() + ping_interval;
+        }
+    }
+}
+
+fn merge_states(controllers: &mut BTreeMap<String, ControllerState>, states: Vec<ControllerState>) {
+    for controller in controllers.values_mut() {
+        controller.connected = false;
+    }
+    for state in states {
+        controllers.insert(state.identity.clone(), state);
+    }
+}
+
+fn publish_changes(
+    stream: &mut Box<dyn Stream>,
+    settings: &MqttSettings,
+    controllers: &BTreeMap<String, ControllerState>,
+    published: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    for controller in controllers.values() {
+        for (topic, payload) in retained_messages(settings, controller) {
+            if published.get(&topic) != Some(&payload) {
+                publish(stream, &topic, &payload)?;
+                published.insert(topic, payload);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn publish(stream: &mut Box<dyn Stream>, topic: &str, payload: &str) -> Result<(), String> {
+    let packet = mqtt::publish_packet(topic, payload.as_bytes(), true)?;
+    mqtt::write_packet(stream, &packet).map_err(|e| e.to_string())
+}
+
+fn bridge_status_topic(settings: &MqttSettings) -> String {
+    format!("{}/status", settings.topic_prefix)
+}
+
+fn topic_id(identity: &str) -> String {
+    identity
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Every retained topic and payload for one controller, discovery configs
+/// included.
+fn retained_messages(
+    settings: &MqttSettings,
+    controller: &ControllerState,
+) -> Vec<(String, String)> {
+    let id = topic_id(&controller.identity);
+    let base = format!("{}/{}", settings.topic_prefix, id);
+    let mut messages = Vec::new();
+
+    if settings.discovery {
+        for (component, key, config) in discovery_configs(settings, controller, &id, &base) {
+            messages.push((
+                format!(
+                    "{}/{}/ds_battery_{}/{}/config",
+                    settings.discovery_prefix, component, id, key
+                ),
+                config.to_string(),
+            ));
+        }
+    }
+
+    if let Some(report) = &controller.battery {
+        messages.push((
+            format!("{}/battery", base),
+            report.battery_capacity.to_string(),
+        ));
+        messages.push((
+            format!("{}/status", base),
+            battery_history::status_name(&report.battery_status).to_string(),
+        ));
+    }
+    let connected = if controller.connected { "ON" } else { "OFF" };
+    messages.push((format!("{}/connected", base), connected.to_string()));
+    messages
+}
+
+/// Home Assistant discovery configs: a battery sensor, a charging status
+/// sensor and a connectivity binary sensor, grouped under one device.
+fn discovery_configs(
+    settings: &MqttSettings,
+    controller: &ControllerState,
+    id: &str,
+    base: &str,
+) -> [(&'static str, &'static str, Value); 3] {
+    let device = json!({
+        "identifiers": [format!("ds_battery_{}", id)],
+        "name": controller.name,
+        "model": controller.model,
+        "manufacturer": "Sony",
+    });
+    let bridge_availability = json!({ "topic": bridge_status_topic(settings) });
+    // Readings are stale while the pad is away, so they go unavailable too.
+    let reading_availability = json!([
+        bridge_availability,
+        {
+            "topic": format!("{}/connected", base),
+            "payload_available": "ON",
+            "payload_not_available": "OFF",
+        },
+    ]);
+    [
+        (
+            "sensor",
+            "battery",
+            json!({
+                "name": "Battery",
+                "unique_id": format!("ds_battery_{}_battery", id),
+                "state_topic": format!("{}/battery", base),
+                "device_class": "battery",
+                "unit_of_measurement": "%",
+                "state_class": "measurement",
+                "availability": reading_availability,
+                "availability_mode": "all",
+                "device": device,
+            }),
+        ),
+        (
+            "sensor",
+            "status",
+            json!({
+                "name": "Charging status",
+                "unique_id": format!("ds_battery_{}_status", id),
+                "state_topic": format!("{}/status", base),
+                "device_class": "enum",
+                "options": ["discharging", "charging", "full", "charging_error", "unknown"],
+                "availability": reading_availability,
+                "availability_mode": "all",
+                "device": device,
+            }),
+        ),
+        (
+            "binary_sensor",
+            "connected",
+            json!({
+                "name": "Connected",
+                "unique_id": format!("ds_battery_{}_connected", id),
+                "state_topic": format!("{}/connected", base),
+                "device_class": "connectivity",
+                "payload_on": "ON",
+                "payload_off": "OFF",
+                "availability": [bridge_availability],
+                "device": device,
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::dualsense::{BatteryStatus, ConnectionType, ControllerInfo, ControllerModel};
+
+    /// One packet as the broker sees it: the first header byte and the body.
+    fn read_raw(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 1];
+        stream.read_exact(&mut header).unwrap();
+        let mut length = 0usize;
+        for shift in (0..28).step_by(7) {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            length |= usize::from(byte[0] & 0x7F) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    /// Splits a length-prefixed string off the front of `body`.
+    fn take_string(body: &mut &[u8]) -> String {
+        let length = usize::from(u16::from_be_bytes([body[0], body[1]]));
+        let text = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+        *body = &body[2 + length..];
+        text
+    }
+
+    /// A retained PUBLISH, as topic and payload.
+    fn read_publish(stream: &mut TcpStream) -> (String, String) {
+        let (header, body) = read_raw(stream);
+        assert_eq!(header, 0x31, "expected a retained publish");
+        let mut body = body.as_slice();
+        let topic = take_string(&mut body);
+        (topic, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Accepts the publisher, checks its CONNECT and lets it in.
+    fn accept(listener: &TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (header, body) = read_raw(&mut stream);
+        assert_eq!(header, 0x10);
+        let mut body = &body[6..];
+        let (level, flags) = (body[0], body[1]);
+        assert_eq!(level, 4);
+        // Clean session, and a retained will but no credentials.
+        assert_eq!(flags, 0x26);
+        body = &body[4..];
+        assert_eq!(take_string(&mut body), "test-client");
+        assert_eq!(take_string(&mut body), "pads/status");
+        assert_eq!(take_string(&mut body), "offline");
+        assert!(body.is_empty());
+
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        assert_eq!(
+            read_publish(&mut stream),
+            ("pads/status".to_string(), "online".to_string())
+        );
+        stream
+    }
+
+    fn settings(listener: &TcpListener) -> MqttSettings {
+        MqttSettings {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            client_id: "test-client".to_string(),
+            topic_prefix: "pads".to_string(),
+            discovery_prefix: "ha".to_string(),
+            ..MqttSettings::default()
+        }
+    }
+
+    fn registry(capacity: u8) -> ControllerRegistry {
+        let mut registry = ControllerRegistry::new();
+        registry.connect(
+            "pad-path".to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Bluetooth,
+                serial: Some("AA:BB:CC:DD:EE:FF".to_string()),
+                firmware: None,
+            },
+        );
+        registry.update_battery(
+            "pad-path",
+            BatteryReport::new(capacity, BatteryStatus::Discharging),
+        );
+        registry
+    }
+
+    /// Every retained message for the registry from [`registry`].
+    fn full_state(capacity: u8) -> Vec<(String, String)> {
+        let expected = [
+            "ha/sensor/ds_battery_aa_bb_cc_dd_ee_ff/battery/config",
+            "ha/sensor/ds_battery_aa_bb_cc_dd_ee_ff/status/config",
+            "ha/binary_sensor/ds_battery_aa_bb_cc_dd_ee_ff/connected/config",
+            "pads/aa_bb_cc_dd_ee_ff/battery",
+            "pads/aa_bb_cc_dd_ee_ff/status",
+            "pads/aa_bb_cc_dd_ee_ff/connected",
+        ];
+        let values = [&capacity.to_string(), "discharging", "ON"];
+        expected
+            .iter()
+            .enumerate()
+            .map(|(index, topic)| {
+                let payload = index.checked_sub(3).map(|value| values[value].to_string());
+                (topic.to_string(), payload.unwrap_or_default())
+            })
+            .collect()
+    }
+
+    fn assert_state(stream: &mut TcpStream, capacity: u8) {
+        for (topic, payload) in full_state(capacity) {
+            let (published_topic, published_payload) = read_publish(stream);
+            assert_eq!(published_topic, topic);
+            if payload.is_empty() {
+                // A discovery config.
+                let config: Value = serde_json::from_str(&published_payload).unwrap();
+                assert_eq!(
+                    config["device"]["identifiers"][0],
+                    "ds_battery_aa_bb_cc_dd_ee_ff"
+                );
+            } else {
+                assert_eq!(published_payload, payload);
+            }
+        }
+    }
+
+    #[test]
+    fn publishes_retained_state_and_only_changes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let publisher = MqttPublisher::spawn(settings(&listener)).unwrap();
+        let mut stream = accept(&listener);
+
+        publisher.update(&registry(80));
+        assert_state(&mut stream, 80);
+
+        // Nothing changed, so nothing is sent until the battery drops.
+        publisher.update(&registry(80));
+        publisher.update(&registry(75));
+        assert_eq!(
+            read_publish(&mut stream),
+            (
+                "pads/aa_bb_cc_dd_ee_ff/battery".to_string(),
+                "75".to_string()
+            )
+        );
+
+        // Shutting down says goodbye.
+        drop(publisher);
+        assert_eq!(
+            read_publish(&mut stream),
+            ("pads/status".to_string(), "offline".to_string())
+        );
+        assert_eq!(read_raw(&mut stream), (0xE0, vec![]));
+    }
+
+    #[test]
+    fn republishes_everything_after_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let publisher = MqttPublisher::spawn(settings(&listener)).unwrap();
+        let mut stream = accept(&listener);
+        publisher.update(&registry(80));
+        assert_state(&mut stream, 80);
+
+        // The broker goes away. The first publish after that still goes
+        // through, the next one fails and the publisher reconnects, sending
+        // what changed meanwhile.
+        drop(stream);
+        publisher.update(&registry(70));
+        thread::sleep(Duration::from_millis(100));
+        publisher.update(&registry(60));
+        let mut stream = accept(&listener);
+        assert_state(&mut stream, 60);
+    }
+
+    #[test]
+    fn removed_controllers_are_marked_disconnected() {
+        let mut controllers = BTreeMap::new();
+        let states = ControllerState::from_registry(&registry(80));
+        merge_states(&mut controllers, states);
+        merge_states(&mut controllers, Vec::new());
+        assert!(!controllers["AA:BB:CC:DD:EE:FF"].connected);
+    }
+}
@@ -0,0 +1,78 @@
+//! Outgoing connections for the webhook and MQTT clients, over plain TCP or
+//! TLS.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Connects to the first address `host` resolves to that answers, and wraps
+/// the connection in TLS if asked. `timeout` covers connecting and every read
+/// and write after that.
+pub fn connect(
+    host: &str,
+    port: u16,
+    tls: bool,
+    timeout: Duration,
+) -> Result<Box<dyn Stream>, String> {
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?;
+    let mut last_error = "no addresses found".to_string();
+    let mut tcp = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                tcp = Some(stream);
+                break;
+            }
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    let tcp = tcp.ok_or_else(|| format!("Failed to connect to {}: {}", host, last_error))?;
+    tcp.set_read_timeout(Some(timeout))
+        .and_then(|_| tcp.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    if !tls {
+        return Ok(Box::new(tcp));
+    }
+    let connector = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+    let stream = connector
+        .connect(host, tcp)
+        .map_err(|e| format!("TLS handshake with {} failed: {}", host, e))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn connects_over_plain_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut stream = connect("127.0.0.1", port, false, Duration::from_secs(5)).unwrap();
+        stream.write_all(b"ping").unwrap();
+
+        let (mut accepted, _) = listener.accept().unwrap();
+        let mut received = [0; 4];
+        accepted.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+    }
+
+    #[test]
+    fn unresolvable_hosts_fail() {
+        let error = connect("host.invalid", 80, false, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(error.starts_with("Failed to resolve host.invalid"));
+    }
+}
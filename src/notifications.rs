@@ -0,0 +1,180 @@
+//! Telling the user about battery, charging and connection alerts outside the
+//! overlay.
+//!
+//! Each way of delivering a message is a [`Notifier`]; the app builds a
+//! [`NotifierSet`] from the config and hands every [`Notification`] to all of them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery_alerts::{AlertLevel, ChargeAlert},
+    controllers::ControllerEntry,
+    dualsense::BatteryReport,
+};
+
+/// Written in snake case in the config, e.g. `low_battery`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    LowBattery,
+    CriticalBattery,
+    ChargeCeilingReached,
+    FullyCharged,
+    Disconnected,
+    /// Sent by a rule's `notify` action.
+    Rule,
+}
+
+impl NotificationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NotificationKind::LowBattery => "low_battery",
+            NotificationKind::CriticalBattery => "critical_battery",
+            NotificationKind::ChargeCeilingReached => "charge_ceiling_reached",
+            NotificationKind::FullyCharged => "fully_charged",
+            NotificationKind::Disconnected => "disconnected",
+            NotificationKind::Rule => "rule",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Urgency {
+    Info,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub urgency: Urgency,
+    pub controller_path: String,
+    pub controller_name: String,
+    /// `None` for rule notifications about a pad with no reading yet.
+    pub report: Option<BatteryReport>,
+    pub title: String,
+    pub message: String,
+}
+
+impl Notification {
+    pub fn battery_alert(
+        entry: &ControllerEntry,
+        report: &BatteryReport,
+        level: AlertLevel,
+    ) -> Option<Self> {
+        let (kind, title) = match level {
+            AlertLevel::Normal => return None,
+            AlertLevel::Low => (NotificationKind::LowBattery, "Controller battery low"),
+            AlertLevel::Critical => (
+                NotificationKind::CriticalBattery,
+                "Controller battery critical",
+            ),
+        };
+        Some(Self {
+            kind,
+            urgency: Urgency::Warning,
+            controller_path: entry.path.clone(),
+            controller_name: entry.display_name().to_string(),
+            report: Some(report.clone()),
+            title: title.to_string(),
+            message: format!(
+                "Player {} ({}) is at {}%.",
+                entry.player_number,
+                entry.display_name(),
+                report.battery_capacity
+            ),
+        })
+    }
+
+    pub fn charge_alert(
+        entry: &ControllerEntry,
+        report: &BatteryReport,
+        alert: ChargeAlert,
+    ) -> Self {
+        let (kind, urgency, title, message) = match alert {
+            ChargeAlert::CeilingReached => (
+                NotificationKind::ChargeCeilingReached,
+                Urgency::Warning,
+                "Unplug your controller",
+                format!(
+                    "Player {} ({}) has charged to {}%.",
+                    entry.player_number,
+                    entry.display_name(),
+                    report.battery_capacity
+                ),
+            ),
+            ChargeAlert::FullyCharged => (
+                NotificationKind::FullyCharged,
+                Urgency::Info,
+                "Controller fully charged",
+                format!(
+                    "Player {} ({}) is fully charged.",
+                    entry.player_number,
+                    entry.display_name()
+                ),
+            ),
+        };
+        Self {
+            kind,
+            urgency,
+            controller_path: entry.path.clone(),
+            controller_name: entry.display_name().to_string(),
+            report: Some(report.clone()),
+            title: title.to_string(),
+            message,
+        }
+    }
+
+    pub fn disconnected(entry: &ControllerEntry) -> Self {
+        Self {
+            kind: NotificationKind::Disconnected,
+            urgency: Urgency::Warning,
+            controller_path: entry.path.clone(),
+            controller_name: entry.display_name().to_string(),
+            report: entry.battery.clone(),
+            title: "Controller disconnected".to_string(),
+            message: format!(
+                "Player {} ({}) disconnected.",
+                entry.player_number,
+                entry.display_name()
+            ),
+        }
+    }
+
+    pub fn rule(entry: &ControllerEntry, title: &str, message: &str) -> Self {
+        Self {
+            kind: NotificationKind::Rule,
+            urgency: Urgency::Info,
+            controller_path: entry.path.clone(),
+            controller_name: entry.display_name().to_string(),
+            report: entry.battery.clone(),
+            title: title.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+pub trait Notifier {
+    fn name(&self) -> &str;
+    fn notify(&mut self, notification: &Notification) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct NotifierSet {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl NotifierSet {
+    pub fn add(&mut self, notifier: Box<dyn Notifier>) {
+        self.notifiers.push(notifier);
+    }
+
+    /// Delivers to every notifier. One failing doesn't stop the others.
+    pub fn notify_all(&mut self, notification: &Notification) {
+        for notifier in &mut self.notifiers {
+            if let Err(e) = notifier.notify(notification) {
+                eprintln!("Notifier '{}' failed: {}", notifier.name(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Output reports that drive the controller's lights.
+//!
+//! The USB and Bluetooth reports share one block of settings; Bluetooth wraps it
+//! in a sequence tag and a trailing CRC-32 the controller checks before acting.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::crc32_update;
+
+const USB_OUTPUT_REPORT_ID: u8 = 0x02;
+const USB_OUTPUT_REPORT_SIZE: usize = 63;
+const USB_COMMON_OFFSET: usize = 1;
+
+const BLUETOOTH_OUTPUT_REPORT_ID: u8 = 0x31;
+const BLUETOOTH_OUTPUT_REPORT_SIZE: usize = 78;
+const BLUETOOTH_COMMON_OFFSET: usize = 3;
+const BLUETOOTH_OUTPUT_TAG: u8 = 0x10;
+/// Prepended to the report when computing its CRC, but never sent.
+const BLUETOOTH_OUTPUT_CRC_SEED: u8 = 0xA2;
+
+// Offsets inside the shared settings block.
+const VALID_FLAG1_OFFSET: usize = 1;
+const MUTE_BUTTON_LED_OFFSET: usize = 8;
+const PLAYER_LEDS_OFFSET: usize = 43;
+const LIGHTBAR_RED_OFFSET: usize = 44;
+
+const VALID_FLAG1_MIC_MUTE_LED_CONTROL_ENABLE: u8 = 1 << 0;
+const VALID_FLAG1_LIGHTBAR_CONTROL_ENABLE: u8 = 1 << 2;
+const VALID_FLAG1_PLAYER_INDICATOR_CONTROL_ENABLE: u8 = 1 << 4;
+
+/// The five player LEDs under the touchpad, one bit each, lit the way the PS5
+/// lights them for players 1 to 4, and all of them for 5.
+#[cfg_attr(not(windows), allow(dead_code))]
+const PLAYER_LED_PATTERNS: [u8; 5] = [0b00100, 0b01010, 0b10101, 0b11011, 0b11111];
+
+/// A lightbar colour, written as `#rrggbb` in the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// The blue the controller shows out of the box.
+pub const DEFAULT_LIGHTBAR: Rgb = Rgb::new(0, 0, 255);
+pub const LIGHTBAR_OFF: Rgb = Rgb::new(0, 0, 0);
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        let channel = |index: usize| {
+            hex.get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Rgb::new(r, g, b)),
+            _ => Err(format!("Invalid colour '{}', expected #rrggbb", value)),
+        }
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(color: Rgb) -> Self {
+        color.to_string()
+    }
+}
+
+/// What an output report should change. Anything left as `None` is left alone
+/// by the controller.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OutputState {
+    pub lightbar: Option<Rgb>,
+    /// Bit mask of the player LEDs to light.
+    pub player_leds: Option<u8>,
+    /// The orange LED in the mute button.
+    pub mic_led: Option<bool>,
+}
+
+/// Player LEDs for a player number; 0 or anything past 5 turns them off.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn player_led_pattern(player: u8) -> u8 {
+    usize::from(player)
+        .checked_sub(1)
+        .and_then(|index| PLAYER_LED_PATTERNS.get(index))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Builds a complete output report, including the report ID. `sequence` only
+/// matters over Bluetooth, where it should count up with every report sent.
+pub fn build_output_report(state: &OutputState, is_bluetooth: bool, sequence: u8) -> Vec<u8> {
+    let (mut report, common_offset) = if is_bluetooth {
+        let mut report = vec![0u8; BLUETOOTH_OUTPUT_REPORT_SIZE];
+        report[0] = BLUETOOTH_OUTPUT_REPORT_ID;
+        report[1] = (sequence & 0x0F) << 4;
+        report[2] = BLUETOOTH_OUTPUT_TAG;
+        (report, BLUETOOTH_COMMON_OFFSET)
+    } else {
+        let mut report = vec![0u8; USB_OUTPUT_REPORT_SIZE];
+        report[0] = USB_OUTPUT_REPORT_ID;
+        (report, USB_COMMON_OFFSET)
+    };
+
+    let common = &mut report[common_offset..];
+    if let Some(color) = state.lightbar {
+        common[VALID_FLAG1_OFFSET] |= VALID_FLAG1_LIGHTBAR_CONTROL_ENABLE;
+        common[LIGHTBAR_RED_OFFSET..LIGHTBAR_RED_OFFSET + 3]
+            .copy_from_slice(&[color.r, color.g, color.b]);
+    }
+    if let Some(leds) = state.player_leds {
+        common[VALID_FLAG1_OFFSET] |= VALID_FLAG1_PLAYER_INDICATOR_CONTROL_ENABLE;
+        common[PLAYER_LEDS_OFFSET] = leds;
+    }
+    if let Some(lit) = state.mic_led {
+        common[VALID_FLAG1_OFFSET] |= VALID_FLAG1_MIC_MUTE_LED_CONTROL_ENABLE;
+        common[MUTE_BUTTON_LED_OFFSET] = u8::from(lit);
+    }
+
+    if is_bluetooth {
+        let crc_offset = report.len() - 4;
+        let crc = crc32_update(
+            crc32_update(0, &[BLUETOOTH_OUTPUT_CRC_SEED]),
+            &report[..crc_offset],
+        );
+        report[crc_offset..].copy_from_slice(&crc.to_le_bytes());
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mic_led_report(lit: Option<bool>, is_bluetooth: bool) -> Vec<u8> {
+        let state = OutputState {
+            mic_led: lit,
+            ..OutputState::default()
+        };
+        build_output_report(&state, is_bluetooth, 0)
+    }
+
+    #[test]
+    fn mic_led_sets_its_flag_and_byte() {
+        for (is_bluetooth, common) in [(false, USB_COMMON_OFFSET), (true, BLUETOOTH_COMMON_OFFSET)]
+        {
+            for lit in [false, true] {
+                let report = mic_led_report(Some(lit), is_bluetooth);
+                assert_eq!(
+                    report[common + VALID_FLAG1_OFFSET],
+                    VALID_FLAG1_MIC_MUTE_LED_CONTROL_ENABLE
+                );
+                assert_eq!(report[common + MUTE_BUTTON_LED_OFFSET], u8::from(lit));
+            }
+        }
+    }
+
+    #[test]
+    fn mic_led_left_alone_when_unset() {
+        let report = mic_led_report(None, false);
+        assert_eq!(report[USB_COMMON_OFFSET + VALID_FLAG1_OFFSET], 0);
+        assert_eq!(report[USB_COMMON_OFFSET + MUTE_BUTTON_LED_OFFSET], 0);
+    }
+}
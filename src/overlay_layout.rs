@@ -0,0 +1,858 @@
+//! Renderer-independent overlay geometry.
+//!
+//! Turns the overlay size, a scale factor and the battery reports to show into a
+//! flat list of drawing primitives. Backends only have to know how to draw a
+//! [`Scene`], so the layout itself never touches Direct2D.
+
+use crate::{
+    dualsense::{BatteryReport, BatteryStatus, ControllerInfo},
+    runtime_estimate::Estimate,
+};
+
+const CORNER_RADIUS: f32 = 10.0;
+const OUTLINE_THICKNESS: f32 = 5.0;
+const BATTERY_CORNER_RADIUS: f32 = 4.0;
+const TEXT_MARGIN: f32 = 5.0;
+const FONT_SIZE: f32 = 22.0;
+
+// Single-controller card
+const CARD_WIDTH: f32 = 200.0;
+const CARD_HEIGHT: f32 = 150.0;
+
+// Multi-controller list, in logical pixels
+const LIST_WIDTH: f32 = 320.0;
+const LIST_PADDING: f32 = 10.0;
+const ROW_HEIGHT: f32 = 48.0;
+const ROW_GAP: f32 = 6.0;
+const ROW_CORNER_RADIUS: f32 = 6.0;
+const ROW_ACCENT_WIDTH: f32 = 4.0;
+const PLAYER_COLUMN_WIDTH: f32 = 44.0;
+const ROW_ICON_WIDTH: f32 = 52.0;
+const ROW_ICON_HEIGHT: f32 = 24.0;
+const ROW_OUTLINE_THICKNESS: f32 = 3.0;
+const ROW_BATTERY_CORNER_RADIUS: f32 = 3.0;
+const ROW_TEXT_LEFT: f32 = 118.0;
+const PLAYER_FONT_SIZE: f32 = 18.0;
+const PRIMARY_FONT_SIZE: f32 = 16.0;
+const SECONDARY_FONT_SIZE: f32 = 12.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = Color::new(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn from_rgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self {
+            r: r as f32 / 255.0,
+            g: g as f32 / 255.0,
+            b: b as f32 / 255.0,
+            a: a as f32 / 255.0,
+        }
+    }
+}
+
+pub const BACKGROUND_COLOR: Color = Color::new(0.0, 0.0, 0.0, 0.7);
+pub const OUTLINE_COLOR: Color = Color::new(0.8, 0.8, 0.8, 1.0);
+pub const LOW_BATTERY_COLOR: Color = Color::from_rgba8(242, 27, 63, 255);
+pub const MEDIUM_BATTERY_COLOR: Color = Color::from_rgba8(255, 198, 10, 255);
+pub const HIGH_BATTERY_COLOR: Color = Color::from_rgba8(43, 192, 22, 255);
+pub const SECONDARY_TEXT_COLOR: Color = Color::new(0.6, 0.6, 0.6, 1.0);
+pub const HIGHLIGHT_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.12);
+pub const ACCENT_COLOR: Color = Color::from_rgba8(0, 112, 209, 255);
+pub const GLYPH_COLOR: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+pub const GLYPH_SHADOW_COLOR: Color = Color::new(0.0, 0.0, 0.0, 0.85);
+pub const CHARGING_SWEEP_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.35);
+/// Width of the charging sweep band relative to the fill area.
+const CHARGING_SWEEP_WIDTH: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Rect {
+    pub const fn new(left: f32, top: f32, right: f32, bottom: f32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.bottom - self.top
+    }
+
+    /// Shrinks the rect by `amount` on every side.
+    pub fn inset(&self, amount: f32) -> Self {
+        Self::new(
+            self.left + amount,
+            self.top + amount,
+            self.right - amount,
+            self.bottom - amount,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Primitive {
+    FillRoundedRect {
+        rect: Rect,
+        radius: f32,
+        color: Color,
+    },
+    StrokeRoundedRect {
+        rect: Rect,
+        radius: f32,
+        stroke_width: f32,
+        color: Color,
+    },
+    FillRect {
+        rect: Rect,
+        color: Color,
+    },
+    /// A closed polygon filled with the non-zero winding rule.
+    FillPolygon {
+        points: Vec<Point>,
+        color: Color,
+    },
+    /// A single line of text, vertically centered inside `rect`.
+    Text {
+        rect: Rect,
+        text: String,
+        font_size: f32,
+        color: Color,
+        alignment: TextAlignment,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlignment {
+    Leading,
+    Center,
+}
+
+/// Everything a backend needs to draw one overlay frame, in physical pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub width: f32,
+    pub height: f32,
+    pub clear_color: Color,
+    pub primitives: Vec<Primitive>,
+}
+
+/// What the overlay shows for one connected controller.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControllerRow {
+    pub player_number: u8,
+    pub info: ControllerInfo,
+    /// Name the user gave the pad, if any.
+    pub name: Option<String>,
+    /// `None` until the first battery report arrives.
+    pub report: Option<BatteryReport>,
+    /// Runtime or time-to-full, when there's enough history for one.
+    pub estimate: Option<Estimate>,
+    /// `false` while a pad that just dropped is still shown as disconnected.
+    pub connected: bool,
+    pub highlighted: bool,
+    pub animation: RowAnimation,
+}
+
+/// Animated values for one row at the frame being drawn. The default draws the
+/// row statically.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RowAnimation {
+    /// Fill percentage currently on screen, overriding the reported capacity
+    /// while it eases towards it.
+    pub fill_level: Option<f32>,
+    /// Position of the charging sweep across the fill, `0.0..1.0`.
+    pub charging_phase: Option<f32>,
+}
+
+/// Symbol drawn on top of the battery icon to tell states apart at a glance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusGlyph {
+    /// Charging
+    LightningBolt,
+    /// Full and still on the cable
+    Plug,
+    /// Charging error (voltage or temperature)
+    Warning,
+    /// The controller reported a status we don't understand
+    QuestionMark,
+    /// The pad just dropped off
+    Cross,
+}
+
+/// How one controller's battery is presented: fill level, glyph and caption.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatteryVisual {
+    /// Fill percentage the icon settles on.
+    pub fill_capacity: u8,
+    /// Fill percentage drawn in this frame; differs from `fill_capacity` mid-transition.
+    pub fill_level: f32,
+    pub charging_phase: Option<f32>,
+    pub glyph: Option<StatusGlyph>,
+    pub text: String,
+    /// Draws the outline in the secondary colour, for pads that are gone.
+    pub dimmed: bool,
+}
+
+impl BatteryVisual {
+    pub fn for_row(row: &ControllerRow) -> Self {
+        if !row.connected {
+            return Self {
+                glyph: Some(StatusGlyph::Cross),
+                dimmed: true,
+                ..Self::message("Disconnected")
+            };
+        }
+        let Some(report) = &row.report else {
+            return Self::message("Reading...");
+        };
+
+        let mut visual = Self::for_report(report);
+        if let Some(level) = row.animation.fill_level {
+            visual.fill_level = level.clamp(0.0, 100.0);
+        }
+        if visual.glyph == Some(StatusGlyph::LightningBolt) {
+            visual.charging_phase = row.animation.charging_phase;
+        }
+        visual
+    }
+
+    pub fn for_report(report: &BatteryReport) -> Self {
+        let capacity = report.battery_capacity;
+        let (fill_capacity, glyph, text) = match report.battery_status {
+            BatteryStatus::Discharging => (capacity, None, format!("{}%", capacity)),
+            BatteryStatus::Charging => (
+                capacity,
+                Some(StatusGlyph::LightningBolt),
+                format!("{}% - Charging", capacity),
+            ),
+            BatteryStatus::Full => (100, Some(StatusGlyph::Plug), "Fully charged".to_string()),
+            BatteryStatus::ChargingError => {
+                (0, Some(StatusGlyph::Warning), "Charging error".to_string())
+            }
+            BatteryStatus::Unknown => (0, Some(StatusGlyph::QuestionMark), "Unknown".to_string()),
+        };
+        Self {
+            fill_capacity,
+            fill_level: fill_capacity as f32,
+            charging_phase: None,
+            glyph,
+            text,
+            dimmed: false,
+        }
+    }
+
+    pub fn message(text: &str) -> Self {
+        Self {
+            fill_capacity: 0,
+            fill_level: 0.0,
+            charging_phase: None,
+            glyph: None,
+            text: text.to_string(),
+            dimmed: false,
+        }
+    }
+}
+
+/// Logical overlay size needed to show `row_count` controllers. Zero or one
+/// controller uses the classic single card, more switch to the row list.
+pub fn overlay_size(row_count: usize) -> (f32, f32) {
+    if row_count <= 1 {
+        return (CARD_WIDTH, CARD_HEIGHT);
+    }
+    let rows = row_count as f32;
+    (
+        LIST_WIDTH,
+        LIST_PADDING * 2.0 + rows * ROW_HEIGHT + (rows - 1.0) * ROW_GAP,
+    )
+}
+
+pub struct OverlayLayout {
+    width: f32,
+    height: f32,
+    scale: f32,
+}
+
+impl OverlayLayout {
+    /// `width` and `height` are the logical overlay size, `scale` the DPI factor
+    /// applied to every length in the resulting scene.
+    pub fn new(width: f32, height: f32, scale: f32) -> Self {
+        Self {
+            width,
+            height,
+            scale,
+        }
+    }
+
+    /// Sized for `row_count` controllers, see [`overlay_size`].
+    pub fn for_rows(row_count: usize, scale: f32) -> Self {
+        let (width, height) = overlay_size(row_count);
+        Self::new(width, height, scale)
+    }
+
+    pub fn build_scene(&self, rows: &[ControllerRow]) -> Scene {
+        let target_width = self.width * self.scale;
+        let target_height = self.height * self.scale;
+        let mut primitives = Vec::new();
+
+        // Background
+        primitives.push(Primitive::FillRoundedRect {
+            rect: Rect::new(0.0, 0.0, target_width, target_height),
+            radius: CORNER_RADIUS * self.scale,
+            color: BACKGROUND_COLOR,
+        });
+
+        match rows {
+            [] => self.push_card(
+                &mut primitives,
+                &BatteryVisual::message("No controller"),
+                None,
+            ),
+            [row] => self.push_card(
+                &mut primitives,
+                &BatteryVisual::for_row(row),
+                row.estimate.map(|estimate| estimate.text()).as_deref(),
+            ),
+            rows => self.push_rows(&mut primitives, rows),
+        }
+
+        Scene {
+            width: target_width,
+            height: target_height,
+            clear_color: Color::TRANSPARENT,
+            primitives,
+        }
+    }
+
+    /// The large single-controller card: a battery icon with a caption below it,
+    /// and the estimate under that when there is one.
+    fn push_card(
+        &self,
+        primitives: &mut Vec<Primitive>,
+        visual: &BatteryVisual,
+        estimate: Option<&str>,
+    ) {
+        let target_width = self.width * self.scale;
+        let target_height = self.height * self.scale;
+
+        let icon_height = target_height * 0.4; // Icon takes 40% of overlay height
+        let icon_width = icon_height * 1.8;
+        let icon_center_x = target_width / 2.0;
+        let icon_top_y = target_height * 0.15; // Position icon 15% from the top
+        let body_rect = Rect::new(
+            icon_center_x - icon_width / 2.0,
+            icon_top_y,
+            icon_center_x + icon_width / 2.0,
+            icon_top_y + icon_height,
+        );
+        push_battery_icon(
+            primitives,
+            body_rect,
+            OUTLINE_THICKNESS * self.scale,
+            BATTERY_CORNER_RADIUS * self.scale,
+            visual,
+        );
+
+        // Text below the icon, centered across the whole width
+        let text_margin = TEXT_MARGIN * self.scale;
+        let text_top = body_rect.bottom + text_margin;
+        let text_bottom = target_height - text_margin;
+        let caption_bottom = match estimate {
+            Some(_) => text_top + (text_bottom - text_top) * 0.55,
+            None => text_bottom,
+        };
+        primitives.push(Primitive::Text {
+            rect: Rect::new(0.0, text_top, target_width, caption_bottom),
+            text: visual.text.clone(),
+            font_size: FONT_SIZE * self.scale,
+            color: OUTLINE_COLOR,
+            alignment: TextAlignment::Center,
+        });
+        if let Some(estimate) = estimate {
+            primitives.push(Primitive::Text {
+                rect: Rect::new(0.0, caption_bottom, target_width, text_bottom),
+                text: estimate.to_string(),
+                font_size: SECONDARY_FONT_SIZE * self.scale,
+                color: SECONDARY_TEXT_COLOR,
+                alignment: TextAlignment::Center,
+            });
+        }
+    }
+
+    /// One row per controller: player number, a small battery icon, the battery
+    /// text and the model and connection underneath it.
+    fn push_rows(&self, primitives: &mut Vec<Primitive>, rows: &[ControllerRow]) {
+        let scale = self.scale;
+        let left = LIST_PADDING * scale;
+        let right = (self.width - LIST_PADDING) * scale;
+
+        for (index, row) in rows.iter().enumerate() {
+            let top = (LIST_PADDING + index as f32 * (ROW_HEIGHT + ROW_GAP)) * scale;
+            let row_rect = Rect::new(left, top, right, top + ROW_HEIGHT * scale);
+            let center_y = top + ROW_HEIGHT * scale / 2.0;
+            let visual = BatteryVisual::for_row(row);
+
+            if row.highlighted {
+                primitives.push(Primitive::FillRoundedRect {
+                    rect: row_rect,
+                    radius: ROW_CORNER_RADIUS * scale,
+                    color: HIGHLIGHT_COLOR,
+                });
+                primitives.push(Primitive::FillRect {
+                    rect: Rect::new(
+                        left,
+                        top + ROW_CORNER_RADIUS * scale,
+                        left + ROW_ACCENT_WIDTH * scale,
+                        row_rect.bottom - ROW_CORNER_RADIUS * scale,
+                    ),
+                    color: ACCENT_COLOR,
+                });
+            }
+
+            primitives.push(Primitive::Text {
+                rect: Rect::new(
+                    left,
+                    top,
+                    left + PLAYER_COLUMN_WIDTH * scale,
+                    row_rect.bottom,
+                ),
+                text: format!("P{}", row.player_number),
+                font_size: PLAYER_FONT_SIZE * scale,
+                color: OUTLINE_COLOR,
+                alignment: TextAlignment::Center,
+            });
+
+            let icon_left = left + PLAYER_COLUMN_WIDTH * scale;
+            let body_rect = Rect::new(
+                icon_left,
+                center_y - ROW_ICON_HEIGHT * scale / 2.0,
+                icon_left + ROW_ICON_WIDTH * scale,
+                center_y + ROW_ICON_HEIGHT * scale / 2.0,
+            );
+            push_battery_icon(
+                primitives,
+                body_rect,
+                ROW_OUTLINE_THICKNESS * scale,
+                ROW_BATTERY_CORNER_RADIUS * scale,
+                &visual,
+            );
+
+            let text_left = left + ROW_TEXT_LEFT * scale;
+            let text_right = right - TEXT_MARGIN * scale;
+            primitives.push(Primitive::Text {
+                rect: Rect::new(text_left, top, text_right, center_y),
+                text: match (&row.estimate, row.connected) {
+                    (Some(estimate), true) => {
+                        format!("{}% - {}", visual.fill_capacity, estimate.short_text())
+                    }
+                    _ => visual.text,
+                },
+                font_size: PRIMARY_FONT_SIZE * scale,
+                color: OUTLINE_COLOR,
+                alignment: TextAlignment::Leading,
+            });
+            primitives.push(Primitive::Text {
+                rect: Rect::new(text_left, center_y, text_right, row_rect.bottom),
+                text: format!(
+                    "{} ({})",
+                    row.name.as_deref().unwrap_or(row.info.model.name()),
+                    row.info.connection_type.short_name()
+                ),
+                font_size: SECONDARY_FONT_SIZE * scale,
+                color: SECONDARY_TEXT_COLOR,
+                alignment: TextAlignment::Leading,
+            });
+        }
+    }
+}
+
+/// Battery outline with its terminal on the right, a fill proportional to the
+/// capacity and the status glyph on top.
+pub fn push_battery_icon(
+    primitives: &mut Vec<Primitive>,
+    body_rect: Rect,
+    outline_thickness: f32,
+    corner_radius: f32,
+    visual: &BatteryVisual,
+) {
+    let outline_color = if visual.dimmed {
+        SECONDARY_TEXT_COLOR
+    } else {
+        OUTLINE_COLOR
+    };
+    let icon_height = body_rect.height();
+    let terminal_height = icon_height * 0.4;
+    let terminal_width = body_rect.width() * 0.1;
+    let terminal_rect = Rect::new(
+        body_rect.right,
+        body_rect.top + (icon_height - terminal_height) / 2.0,
+        body_rect.right + terminal_width,
+        body_rect.top + (icon_height + terminal_height) / 2.0,
+    );
+
+    // Inset by half the outline thickness to align with the inside edge of the stroke
+    let fill_area_rect = body_rect.inset(outline_thickness / 2.0);
+    let fill_width = fill_area_rect.width() * (visual.fill_level / 100.0);
+    if fill_width > 0.0 {
+        let fill_rect = Rect::new(
+            fill_area_rect.left,
+            fill_area_rect.top,
+            fill_area_rect.left + fill_width,
+            fill_area_rect.bottom,
+        );
+        primitives.push(Primitive::FillRect {
+            rect: fill_rect,
+            color: fill_color(visual.fill_level.round() as u8),
+        });
+
+        // A lighter band that travels across the fill while charging, entering on
+        // the left and leaving past the fill edge.
+        if let Some(phase) = visual.charging_phase {
+            let band_width = fill_area_rect.width() * CHARGING_SWEEP_WIDTH;
+            let band_left = fill_rect.left - band_width + phase * (fill_width + band_width);
+            let band_rect = Rect::new(
+                band_left.max(fill_rect.left),
+                fill_rect.top,
+                (band_left + band_width).min(fill_rect.right),
+                fill_rect.bottom,
+            );
+            if band_rect.width() > 0.0 {
+                primitives.push(Primitive::FillRect {
+                    rect: band_rect,
+                    color: CHARGING_SWEEP_COLOR,
+                });
+            }
+        }
+    }
+
+    primitives.push(Primitive::StrokeRoundedRect {
+        rect: body_rect,
+        radius: corner_radius,
+        stroke_width: outline_thickness,
+        color: outline_color,
+    });
+    primitives.push(Primitive::FillRect {
+        rect: terminal_rect,
+        color: outline_color,
+    });
+
+    if let Some(glyph) = visual.glyph {
+        push_status_glyph(primitives, fill_area_rect, glyph);
+    }
+}
+
+/// Draws `glyph` in a square centered on `area`, sized from the area's height.
+/// Shapes are authored in a unit square and scaled into place.
+fn push_status_glyph(primitives: &mut Vec<Primitive>, area: Rect, glyph: StatusGlyph) {
+    let size = area.height() * 0.8;
+    let left = area.left + (area.width() - size) / 2.0;
+    let top = area.top + (area.height() - size) / 2.0;
+    let polygon = |points: &[(f32, f32)], color: Color| Primitive::FillPolygon {
+        points: points
+            .iter()
+            .map(|(x, y)| Point::new(left + x * size, top + y * size))
+            .collect(),
+        color,
+    };
+    let rect = |l: f32, t: f32, r: f32, b: f32, color: Color| Primitive::FillRect {
+        rect: Rect::new(
+            left + l * size,
+            top + t * size,
+            left + r * size,
+            top + b * size,
+        ),
+        color,
+    };
+
+    match glyph {
+        StatusGlyph::LightningBolt => {
+            const BOLT: [(f32, f32); 6] = [
+                (0.62, 0.0),
+                (0.22, 0.58),
+                (0.48, 0.58),
+                (0.38, 1.0),
+                (0.80, 0.40),
+                (0.54, 0.40),
+            ];
+            // A slightly offset dark copy keeps the bolt readable on a bright fill.
+            let shadow: Vec<(f32, f32)> = BOLT.iter().map(|(x, y)| (x + 0.04, y + 0.03)).collect();
+            primitives.push(polygon(&shadow, GLYPH_SHADOW_COLOR));
+            primitives.push(polygon(&BOLT, GLYPH_COLOR));
+        }
+        StatusGlyph::Plug => {
+            primitives.push(rect(0.30, 0.02, 0.40, 0.30, GLYPH_COLOR));
+            primitives.push(rect(0.60, 0.02, 0.70, 0.30, GLYPH_COLOR));
+            primitives.push(polygon(
+                &[
+                    (0.18, 0.30),
+                    (0.82, 0.30),
+                    (0.82, 0.50),
+                    (0.60, 0.76),
+                    (0.40, 0.76),
+                    (0.18, 0.50),
+                ],
+                GLYPH_COLOR,
+            ));
+            primitives.push(rect(0.44, 0.76, 0.56, 0.98, GLYPH_COLOR));
+        }
+        StatusGlyph::Warning => {
+            primitives.push(polygon(
+                &[(0.5, 0.02), (0.98, 0.94), (0.02, 0.94)],
+                MEDIUM_BATTERY_COLOR,
+            ));
+            primitives.push(rect(0.44, 0.32, 0.56, 0.64, GLYPH_SHADOW_COLOR));
+            primitives.push(rect(0.44, 0.72, 0.56, 0.84, GLYPH_SHADOW_COLOR));
+        }
+        StatusGlyph::QuestionMark => primitives.push(Primitive::Text {
+            rect: Rect::new(left, top, left + size, top + size),
+            text: "?".to_string(),
+            font_size: size,
+            color: OUTLINE_COLOR,
+            alignment: TextAlignment::Center,
+        }),
+        StatusGlyph::Cross => {
+            primitives.push(polygon(
+                &[(0.12, 0.0), (1.0, 0.88), (0.88, 1.0), (0.0, 0.12)],
+                LOW_BATTERY_COLOR,
+            ));
+            primitives.push(polygon(
+                &[(0.88, 0.0), (1.0, 0.12), (0.12, 1.0), (0.0, 0.88)],
+                LOW_BATTERY_COLOR,
+            ));
+        }
+    }
+}
+
+pub fn fill_color(battery_capacity: u8) -> Color {
+    match battery_capacity {
+        0..=20 => LOW_BATTERY_COLOR,
+        21..=50 => MEDIUM_BATTERY_COLOR,
+        _ => HIGH_BATTERY_COLOR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{ConnectionType, ControllerModel};
+
+    fn row(player_number: u8, capacity: u8) -> ControllerRow {
+        ControllerRow {
+            player_number,
+            info: ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Usb,
+                serial: None,
+                firmware: None,
+            },
+            name: None,
+            report: Some(BatteryReport::new(capacity, BatteryStatus::Discharging)),
+            estimate: None,
+            connected: true,
+            highlighted: player_number == 1,
+            animation: RowAnimation::default(),
+        }
+    }
+
+    fn assert_rect(actual: Rect, expected: Rect) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        assert!(
+            close(actual.left, expected.left)
+                && close(actual.top, expected.top)
+                && close(actual.right, expected.right)
+                && close(actual.bottom, expected.bottom),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn texts(scene: &Scene) -> Vec<(&str, Rect, f32)> {
+        scene
+            .primitives
+            .iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::Text {
+                    rect,
+                    text,
+                    font_size,
+                    ..
+                } => Some((text.as_str(), *rect, *font_size)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `rect` given in logical pixels, scaled like the layout scales it.
+    fn scaled(scale: f32, left: f32, top: f32, right: f32, bottom: f32) -> Rect {
+        Rect::new(left * scale, top * scale, right * scale, bottom * scale)
+    }
+
+    #[test]
+    fn card_geometry() {
+        for scale in [1.0, 2.0] {
+            let scene = OverlayLayout::for_rows(1, scale).build_scene(&[row(2, 50)]);
+            assert_eq!((scene.width, scene.height), (200.0 * scale, 150.0 * scale));
+
+            let [
+                Primitive::FillRoundedRect {
+                    rect: background, ..
+                },
+                Primitive::FillRect {
+                    rect: fill,
+                    color: fill_color,
+                },
+                Primitive::StrokeRoundedRect {
+                    rect: body,
+                    stroke_width,
+                    ..
+                },
+                Primitive::FillRect { rect: terminal, .. },
+                Primitive::Text { .. },
+            ] = scene.primitives.as_slice()
+            else {
+                panic!("unexpected card primitives: {:?}", scene.primitives);
+            };
+            assert_rect(*background, scaled(scale, 0.0, 0.0, 200.0, 150.0));
+            // 40% of the height tall, 1.8 times as wide, 15% from the top.
+            assert_rect(*body, scaled(scale, 46.0, 22.5, 154.0, 82.5));
+            assert_eq!(*stroke_width, OUTLINE_THICKNESS * scale);
+            assert_rect(*terminal, scaled(scale, 154.0, 40.5, 164.8, 64.5));
+            // Half of the inside of the outline.
+            assert_rect(*fill, scaled(scale, 48.5, 25.0, 100.0, 80.0));
+            assert_eq!(*fill_color, MEDIUM_BATTERY_COLOR);
+
+            let texts = texts(&scene);
+            assert_eq!(texts.len(), 1);
+            assert_eq!(texts[0].0, "50%");
+            assert_rect(texts[0].1, scaled(scale, 0.0, 87.5, 200.0, 145.0));
+            assert_eq!(texts[0].2, FONT_SIZE * scale);
+        }
+    }
+
+    #[test]
+    fn card_with_estimate_splits_the_caption() {
+        let mut row = row(1, 50);
+        row.estimate = Some(Estimate {
+            kind: crate::runtime_estimate::EstimateKind::Remaining,
+            seconds: 90 * 60,
+            confidence: 1.0,
+        });
+        let scene = OverlayLayout::for_rows(1, 1.0).build_scene(&[row]);
+        let texts = texts(&scene);
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[0].0, "50%");
+        assert_rect(texts[0].1, Rect::new(0.0, 87.5, 200.0, 119.125));
+        assert_rect(texts[1].1, Rect::new(0.0, 119.125, 200.0, 145.0));
+        assert_eq!(texts[1].2, SECONDARY_FONT_SIZE);
+    }
+
+    #[test]
+    fn no_controllers_shows_a_message() {
+        let scene = OverlayLayout::for_rows(0, 1.0).build_scene(&[]);
+        assert_eq!((scene.width, scene.height), (200.0, 150.0));
+        let texts = texts(&scene);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].0, "No controller");
+    }
+
+    #[test]
+    fn list_has_one_row_per_controller() {
+        let rows: Vec<ControllerRow> = (1..=4).map(|player| row(player, 80)).collect();
+        for scale in [1.0, 2.0] {
+            let scene = OverlayLayout::for_rows(rows.len(), scale).build_scene(&rows);
+            // Padding, four rows and the three gaps between them.
+            assert_eq!((scene.width, scene.height), (320.0 * scale, 230.0 * scale));
+            // Background, six primitives a row, and the highlight and accent
+            // for player 1.
+            assert_eq!(scene.primitives.len(), 1 + 4 * 6 + 2);
+            let players: Vec<&str> = texts(&scene)
+                .iter()
+                .map(|(text, ..)| *text)
+                .filter(|text| text.starts_with('P'))
+                .collect();
+            assert_eq!(players, ["P1", "P2", "P3", "P4"]);
+        }
+    }
+
+    #[test]
+    fn list_text_placement() {
+        let rows: Vec<ControllerRow> = (1..=4).map(|player| row(player, 80)).collect();
+        for scale in [1.0, 2.0] {
+            let scene = OverlayLayout::for_rows(rows.len(), scale).build_scene(&rows);
+            let texts = texts(&scene);
+            assert_eq!(texts.len(), 4 * 3);
+            for (index, row_texts) in texts.chunks(3).enumerate() {
+                let top = 10.0 + index as f32 * 54.0;
+                let [player, primary, secondary] = row_texts else {
+                    unreachable!()
+                };
+                assert_eq!(player.0, format!("P{}", index + 1));
+                assert_rect(player.1, scaled(scale, 10.0, top, 54.0, top + 48.0));
+                assert_eq!(player.2, PLAYER_FONT_SIZE * scale);
+                assert_eq!(primary.0, "80%");
+                assert_rect(primary.1, scaled(scale, 128.0, top, 305.0, top + 24.0));
+                assert_eq!(secondary.0, "DualSense (USB)");
+                assert_rect(
+                    secondary.1,
+                    scaled(scale, 128.0, top + 24.0, 305.0, top + 48.0),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn highlighted_row_gets_an_accent() {
+        let rows = [row(1, 80), row(2, 80)];
+        let scene = OverlayLayout::for_rows(2, 1.0).build_scene(&rows);
+        assert_eq!(
+            scene.primitives[1],
+            Primitive::FillRoundedRect {
+                rect: Rect::new(10.0, 10.0, 310.0, 58.0),
+                radius: ROW_CORNER_RADIUS,
+                color: HIGHLIGHT_COLOR,
+            }
+        );
+        assert_eq!(
+            scene.primitives[2],
+            Primitive::FillRect {
+                rect: Rect::new(10.0, 16.0, 14.0, 52.0),
+                color: ACCENT_COLOR,
+            }
+        );
+    }
+}
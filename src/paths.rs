@@ -0,0 +1,77 @@
+//! Where the app keeps its files.
+//!
+//! Windows uses `%APPDATA%` for config and `%LOCALAPPDATA%` for data; other
+//! platforms follow the XDG base directory spec.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const APP_DIR_NAME: &str = "ds-battery";
+
+/// Directory for the config file.
+pub fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env_dir("APPDATA")
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| home_dir().map(|home| home.join(".config")))
+    }
+    .map(|dir| dir.join(APP_DIR_NAME))
+}
+
+/// Directory for data the app collects, such as battery history.
+pub fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env_dir("LOCALAPPDATA")
+    } else {
+        env_dir("XDG_DATA_HOME").or_else(|| home_dir().map(|home| home.join(".local/share")))
+    }
+    .map(|dir| dir.join(APP_DIR_NAME))
+}
+
+/// Replaces the file at `path` with `contents`. They're written beside it and
+/// swapped in, so a crash part way through leaves the old file intact.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    fs::rename(&temp_path, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env_dir("HOME")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomically_replaces_the_file() {
+        let path = std::env::temp_dir().join(format!(
+            "ds-battery-paths-{}-atomic.json",
+            std::process::id()
+        ));
+        write_atomically(&path, b"old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_atomically_reports_missing_directories() {
+        let path = std::env::temp_dir()
+            .join(format!("ds-battery-paths-{}-missing", std::process::id()))
+            .join("file.json");
+        let error = write_atomically(&path, b"data").unwrap_err();
+        assert!(error.starts_with("Failed to write "), "{}", error);
+    }
+}
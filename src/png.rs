@@ -0,0 +1,174 @@
+//! Minimal PNG writer for RGBA8 images.
+//!
+//! Uses stored (uncompressed) deflate blocks, which keeps the encoder tiny and the
+//! output byte-for-byte deterministic. Overlay snapshots are small enough that the
+//! missing compression does not matter.
+
+use crate::checksum::{adler32, crc32_update};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const BIT_DEPTH_8: u8 = 8;
+const COLOR_TYPE_RGBA: u8 = 6;
+const FILTER_NONE: u8 = 0;
+const MAX_STORED_BLOCK_LEN: usize = 0xFFFF;
+
+pub fn encode_rgba8(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgba.len(),
+        width as usize * height as usize * 4,
+        "RGBA buffer does not match image size"
+    );
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[BIT_DEPTH_8, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    // Every scanline is prefixed with its filter type.
+    let row_len = width as usize * 4;
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in rgba.chunks(row_len.max(1)).take(height as usize) {
+        raw.push(FILTER_NONE);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&PNG_SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // Deflate, 32K window, no preset dictionary
+    let mut blocks = data.chunks(MAX_STORED_BLOCK_LEN).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(is_final as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a PNG into its chunks, checking each CRC on the way.
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32_update(crc32_update(0, &kind), data));
+            chunks.push((kind, data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// Undoes [`zlib_stored`], checking the block headers and the Adler-32.
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let mut out = Vec::new();
+        let mut rest = &zlib[2..];
+        loop {
+            let is_final = rest[0] == 1;
+            let len = u16::from_le_bytes([rest[1], rest[2]]);
+            let nlen = u16::from_le_bytes([rest[3], rest[4]]);
+            assert_eq!(nlen, !len);
+            out.extend_from_slice(&rest[5..5 + len as usize]);
+            rest = &rest[5 + len as usize..];
+            if is_final {
+                break;
+            }
+        }
+        assert_eq!(rest, adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn empty_data_gets_one_empty_final_block() {
+        assert_eq!(
+            zlib_stored(&[]),
+            [0x78, 0x01, 1, 0x00, 0x00, 0xFF, 0xFF, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn stored_blocks_split_at_the_limit() {
+        let data: Vec<u8> = (0..MAX_STORED_BLOCK_LEN + 1).map(|i| i as u8).collect();
+
+        let one_block = zlib_stored(&data[..MAX_STORED_BLOCK_LEN]);
+        assert_eq!(one_block[2..7], [1, 0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(one_block.len(), 2 + 5 + MAX_STORED_BLOCK_LEN + 4);
+
+        let two_blocks = zlib_stored(&data);
+        assert_eq!(two_blocks[2..7], [0, 0xFF, 0xFF, 0x00, 0x00]);
+        let second = 7 + MAX_STORED_BLOCK_LEN;
+        assert_eq!(two_blocks[second..second + 5], [1, 0x01, 0x00, 0xFE, 0xFF]);
+        assert_eq!(inflate_stored(&two_blocks), data);
+    }
+
+    #[test]
+    fn image_round_trips() {
+        let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| i as u8 * 10).collect();
+        let png = encode_rgba8(3, 2, &rgba);
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(
+            chunks[0].1,
+            [
+                0,
+                0,
+                0,
+                3,
+                0,
+                0,
+                0,
+                2,
+                BIT_DEPTH_8,
+                COLOR_TYPE_RGBA,
+                0,
+                0,
+                0
+            ]
+        );
+        assert!(chunks[2].1.is_empty());
+
+        let raw = inflate_stored(&chunks[1].1);
+        let mut expected = vec![FILTER_NONE];
+        expected.extend_from_slice(&rgba[..12]);
+        expected.push(FILTER_NONE);
+        expected.extend_from_slice(&rgba[12..]);
+        assert_eq!(raw, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match image size")]
+    fn mismatched_buffer_panics() {
+        encode_rgba8(2, 2, &[0; 4]);
+    }
+}
@@ -1,14 +1,21 @@
 //! Handles HID device discovery, polling loop, and event generation.
 
+use crate::battery_alerts::{AlertLevel, AlertThresholds, ChargeAlert};
 use crate::dualsense::{
-    ConnectedControllerState, ControllerEvent, PRODUCT_ID_DUALSENSE, PRODUCT_ID_DUALSENSE_EDGE,
-    VENDOR_ID_SONY, c_str_to_string, mute_button_pressed, parse_battery,
+    BLUETOOTH_INPUT_REPORT_SIZE, BatteryReport, Button, ConnectedControllerState, ConnectionType,
+    ControllerCommand, ControllerEvent, ControllerInfo, ControllerModel,
+    FEATURE_REPORT_FIRMWARE_INFO, FEATURE_REPORT_FIRMWARE_INFO_SIZE, FEATURE_REPORT_PAIRING_INFO,
+    FEATURE_REPORT_PAIRING_INFO_SIZE, PRODUCT_ID_DUALSENSE, PRODUCT_ID_DUALSENSE_EDGE,
+    VENDOR_ID_SONY, bluetooth_report_crc_valid, c_str_to_string, parse_battery, parse_buttons,
+    parse_firmware_info, parse_mac_address,
 };
-use hidapi::{BusType, HidApi, HidError};
+use crate::gestures::GestureDetector;
+use crate::output_report::{self, LIGHTBAR_OFF, OutputState, Rgb};
+use hidapi::{BusType, HidApi, HidDevice, HidError};
 use std::{
     collections::{HashMap, HashSet},
     ffi::{CStr, CString},
-    sync::mpsc::{SendError, Sender},
+    sync::mpsc::{self, Receiver, SendError, Sender},
     thread,
     time::{Duration, Instant},
 };
@@ -18,6 +25,10 @@ const DEVICE_SCAN_INTERVAL: Duration = Duration::from_secs(3);
 const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(10);
 const DEVICE_READ_TIMEOUT_MS: i32 = 20; // Short timeout for non-blocking reads
 const POLLING_THREAD_SLEEP_MS: u64 = 20; // Main loop sleep duration
+const IDENTIFY_DURATION: Duration = Duration::from_secs(2);
+const IDENTIFY_FLASH_INTERVAL: Duration = Duration::from_millis(200);
+const IDENTIFY_FLASH_COLOR: Rgb = Rgb::new(255, 255, 255);
+const REPORT_STATS_INTERVAL: Duration = Duration::from_secs(5);
 
 // --- Error Type ---
 
@@ -44,19 +55,31 @@ impl From<SendError<ControllerEvent>> for PollError {
 struct ControllerPollingManager {
     hid_api: HidApi,
     event_sender: Sender<ControllerEvent>,
+    command_receiver: Receiver<ControllerCommand>,
     connected_devices: HashMap<CString, ConnectedControllerState>,
     last_scan_time: Instant,
+    alert_thresholds: AlertThresholds,
+    /// Cloned for each controller that connects.
+    gestures: GestureDetector,
 }
 
 impl ControllerPollingManager {
-    fn new(event_sender: Sender<ControllerEvent>) -> Result<Self, PollError> {
+    fn new(
+        event_sender: Sender<ControllerEvent>,
+        command_receiver: Receiver<ControllerCommand>,
+        alert_thresholds: AlertThresholds,
+        gestures: GestureDetector,
+    ) -> Result<Self, PollError> {
         let hid_api = HidApi::new().map_err(|_| PollError::ApiInitFailed)?;
         Ok(Self {
             hid_api,
             event_sender,
+            command_receiver,
             connected_devices: HashMap::new(),
             // Start scan immediately
             last_scan_time: Instant::now() - DEVICE_SCAN_INTERVAL,
+            alert_thresholds,
+            gestures,
         })
     }
 
@@ -82,6 +105,13 @@ impl ControllerPollingManager {
                 self.last_scan_time = now;
             }
 
+            self.handle_commands();
+            self.update_identify_flashes();
+            if let Err(PollError::Send(_)) = self.send_due_report_stats() {
+                eprintln!("Polling Thread: Event channel closed while sending stats. Exiting.");
+                break;
+            }
+
             if let Err(e) = self.poll_connected_devices() {
                 match e {
                     PollError::Send(_) => {
@@ -135,15 +165,7 @@ impl ControllerPollingManager {
     }
 
     fn find_dualsense_device_paths(&self) -> HashSet<CString> {
-        self.hid_api
-            .device_list()
-            .filter(|dev| {
-                dev.vendor_id() == VENDOR_ID_SONY
-                    && (dev.product_id() == PRODUCT_ID_DUALSENSE
-                        || dev.product_id() == PRODUCT_ID_DUALSENSE_EDGE)
-            })
-            .map(|dev| dev.path().to_owned())
-            .collect()
+        find_dualsense_device_paths(&self.hid_api)
     }
 
     fn handle_new_connection(&mut self, path: CString) -> Result<(), PollError> {
@@ -152,28 +174,34 @@ impl ControllerPollingManager {
                 let path_str = c_str_to_string(&path);
                 println!("Polling Thread: Device connected: {}", path_str);
 
-                let info = device.get_device_info()?;
-                let is_bluetooth = matches!(info.bus_type(), BusType::Bluetooth);
+                let controller_info = read_controller_info(&device)?;
+                let is_bluetooth = controller_info.connection_type == ConnectionType::Bluetooth;
 
                 device.set_blocking_mode(false)?;
 
-                let state = ConnectedControllerState::new(device, is_bluetooth);
+                let state =
+                    ConnectedControllerState::new(device, is_bluetooth, self.gestures.clone());
                 self.connected_devices.insert(path.clone(), state);
 
                 // Send connected event *after* adding to map
                 self.event_sender
-                    .send(ControllerEvent::DeviceConnected(path_str))?;
+                    .send(ControllerEvent::DeviceConnected(path_str, controller_info))?;
 
                 // Perform an initial poll immediately if possible (best effort)
-                if let Some(state_mut) = self.connected_devices.get_mut(&path) {
-                    if let Err(e) = poll_single_device(&self.event_sender, &path, state_mut) {
-                        eprintln!(
-                            "Polling Thread: Error during initial poll for {}: {:?}",
-                            c_str_to_string(&path),
-                            e
-                        );
-                        // Don't remove the device yet, maybe it's temporary
-                    }
+                if let Some(state_mut) = self.connected_devices.get_mut(&path)
+                    && let Err(e) = poll_single_device(
+                        &self.event_sender,
+                        &path,
+                        state_mut,
+                        &self.alert_thresholds,
+                    )
+                {
+                    eprintln!(
+                        "Polling Thread: Error during initial poll for {}: {:?}",
+                        c_str_to_string(&path),
+                        e
+                    );
+                    // Don't remove the device yet, maybe it's temporary
                 }
             }
             Err(e) => {
@@ -187,6 +215,89 @@ impl ControllerPollingManager {
         Ok(())
     }
 
+    fn handle_commands(&mut self) {
+        loop {
+            let command = match self.command_receiver.try_recv() {
+                Ok(command) => command,
+                Err(mpsc::TryRecvError::Empty | mpsc::TryRecvError::Disconnected) => return,
+            };
+            let path = match &command {
+                ControllerCommand::SetLightbar(path, _)
+                | ControllerCommand::SetPlayerLeds(path, _)
+                | ControllerCommand::SetMicLed(path, _)
+                | ControllerCommand::Identify(path) => path,
+            };
+            let Some(state) = self
+                .connected_devices
+                .iter_mut()
+                .find(|(device_path, _)| c_str_to_string(device_path) == *path)
+                .map(|(_, state)| state)
+            else {
+                eprintln!("Polling Thread: Command for unknown device: {}", path);
+                continue;
+            };
+
+            match command {
+                ControllerCommand::SetLightbar(_, color) => {
+                    state.lightbar = color;
+                    // An identify in progress puts the new colour back when it ends.
+                    if state.identify_started.is_none() {
+                        send_lightbar(state, color);
+                    }
+                }
+                ControllerCommand::SetPlayerLeds(_, leds) => {
+                    let output = OutputState {
+                        player_leds: Some(leds),
+                        ..OutputState::default()
+                    };
+                    send_output(state, &output);
+                }
+                ControllerCommand::SetMicLed(_, lit) => {
+                    let output = OutputState {
+                        mic_led: Some(lit),
+                        ..OutputState::default()
+                    };
+                    send_output(state, &output);
+                }
+                ControllerCommand::Identify(_) => {
+                    state.identify_started = Some(Instant::now());
+                    state.identify_color = None;
+                }
+            }
+        }
+    }
+
+    fn update_identify_flashes(&mut self) {
+        let now = Instant::now();
+        for state in self.connected_devices.values_mut() {
+            let Some(started) = state.identify_started else {
+                continue;
+            };
+            match identify_flash_color(now.duration_since(started)) {
+                Some(color) if state.identify_color != Some(color) => {
+                    send_lightbar(state, color);
+                    state.identify_color = Some(color);
+                }
+                Some(_) => {}
+                None => {
+                    state.identify_started = None;
+                    state.identify_color = None;
+                    send_lightbar(state, state.lightbar);
+                }
+            }
+        }
+    }
+
+    fn send_due_report_stats(&mut self) -> Result<(), PollError> {
+        let now = Instant::now();
+        for (path, state) in self.connected_devices.iter_mut() {
+            if now.duration_since(state.last_stats_sent) >= REPORT_STATS_INTERVAL {
+                send_report_stats(&self.event_sender, path, state, now)?;
+            }
+        }
+        Ok(())
+    }
+
     fn poll_connected_devices(&mut self) -> Result<(), PollError> {
         let mut failed_paths = HashSet::new();
 
@@ -194,7 +305,7 @@ impl ControllerPollingManager {
 
         // Iterate mutably to update state
         for (path, state) in self.connected_devices.iter_mut() {
-            if let Err(e) = poll_single_device(&sender, path, state) {
+            if let Err(e) = poll_single_device(&sender, path, state, &self.alert_thresholds) {
                 match e {
                     PollError::Hid(HidError::HidApiError { message })
                         if message == "No data read from device" =>
@@ -208,6 +319,7 @@ impl ControllerPollingManager {
                             "Polling Thread: HID error polling device {}: {:?}. Marking for removal.",
                             path_str, e
                         );
+                        state.pending_stats.read_errors += 1;
                         failed_paths.insert(path.clone());
                     }
                     PollError::Send(send_err) => {
@@ -228,12 +340,14 @@ impl ControllerPollingManager {
 
         if !failed_paths.is_empty() {
             for path in failed_paths {
-                if self.connected_devices.remove(&path).is_some() {
+                if let Some(mut state) = self.connected_devices.remove(&path) {
                     let path_str = c_str_to_string(&path);
                     println!(
                         "Polling Thread: Device disconnected (detected by poll failure): {}",
                         path_str
                     );
+                    // Flush the error that took it down before it's gone.
+                    send_report_stats(&self.event_sender, &path, &mut state, Instant::now())?;
                     self.event_sender
                         .send(ControllerEvent::DeviceDisconnected(path_str))?;
                 }
@@ -248,8 +362,10 @@ fn poll_single_device(
     sender: &Sender<ControllerEvent>,
     path: &CStr,
     state: &mut ConnectedControllerState,
+    alert_thresholds: &AlertThresholds,
 ) -> Result<(), PollError> {
-    let mut buf = [0u8; 64]; // Standard HID report size for DualSense
+    // Big enough for a full Bluetooth report; USB reports are 64 bytes.
+    let mut buf = [0u8; BLUETOOTH_INPUT_REPORT_SIZE];
     let bytes_read = state
         .device
         .read_timeout(&mut buf, DEVICE_READ_TIMEOUT_MS)?;
@@ -262,6 +378,12 @@ fn poll_single_device(
     let path_str = c_str_to_string(path);
 
     let now = Instant::now();
+    state.pending_stats.reports += 1;
+    state.last_report = Some(now);
+    if bluetooth_report_crc_valid(report) == Some(false) {
+        state.pending_stats.crc_failures += 1;
+        return Ok(());
+    }
 
     if now.duration_since(state.last_battery_poll) >= BATTERY_POLL_INTERVAL {
         if let Some(battery_report) = parse_battery(report, state.is_bluetooth) {
@@ -271,38 +393,244 @@ fn poll_single_device(
                     path_str.clone(),
                     battery_report.clone(),
                 ))?;
+                match state
+                    .battery_alerts
+                    .update(&battery_report, alert_thresholds)
+                {
+                    Some(AlertLevel::Low) => sender.send(ControllerEvent::LowBattery(
+                        path_str.clone(),
+                        battery_report.clone(),
+                    ))?,
+                    Some(AlertLevel::Critical) => sender.send(ControllerEvent::CriticalBattery(
+                        path_str.clone(),
+                        battery_report.clone(),
+                    ))?,
+                    Some(AlertLevel::Normal) | None => {}
+                }
+                match state
+                    .charge_alerts
+                    .update(&battery_report, alert_thresholds)
+                {
+                    Some(ChargeAlert::CeilingReached) => {
+                        sender.send(ControllerEvent::ChargeCeilingReached(
+                            path_str.clone(),
+                            battery_report.clone(),
+                        ))?
+                    }
+                    Some(ChargeAlert::FullyCharged) => sender.send(
+                        ControllerEvent::FullyCharged(path_str.clone(), battery_report.clone()),
+                    )?,
+                    None => {}
+                }
                 state.last_battery_report = Some(battery_report);
             }
             state.last_battery_poll = now;
         } else {
             eprintln!("Polling Thread: Failed to parse battery for {}", path_str);
+            state.pending_stats.read_errors += 1;
         }
     }
 
-    if let Some(current_mute_state) = mute_button_pressed(report, state.is_bluetooth) {
-        if current_mute_state && !state.previous_mute_state {
+    if let Some(buttons) = parse_buttons(report, state.is_bluetooth) {
+        if buttons.contains(Button::Mute) && !state.previous_buttons.contains(Button::Mute) {
             println!("Mute button pressed on {}", path_str);
             sender.send(ControllerEvent::MuteButtonPressed(path_str.clone()))?;
         }
-        state.previous_mute_state = current_mute_state;
+        state.previous_buttons = buttons;
+        if !state.gestures.is_empty() {
+            for gesture in state.gestures.update(buttons, now) {
+                sender.send(ControllerEvent::Gesture(path_str.clone(), gesture))?;
+            }
+        }
     } else {
-        eprintln!(
-            "Polling Thread: Failed to parse mute button for {}",
-            path_str
-        );
+        eprintln!("Polling Thread: Failed to parse buttons for {}", path_str);
+        state.pending_stats.read_errors += 1;
     }
 
     Ok(())
 }
 
-pub fn setup_controller_polling() -> Result<std::sync::mpsc::Receiver<ControllerEvent>, String> {
-    let (sender, receiver) = std::sync::mpsc::channel::<ControllerEvent>();
-    spawn_polling_thread(sender).map_err(|e| format!("{:?}", e))?;
-    Ok(receiver)
+fn send_report_stats(
+    sender: &Sender<ControllerEvent>,
+    path: &CStr,
+    state: &mut ConnectedControllerState,
+    now: Instant,
+) -> Result<(), PollError> {
+    let mut stats = std::mem::take(&mut state.pending_stats);
+    stats.last_report_age = state.last_report.map(|last| now.duration_since(last));
+    state.last_stats_sent = now;
+    sender.send(ControllerEvent::ReportStats(c_str_to_string(path), stats))?;
+    Ok(())
+}
+
+/// Lightbar colour `elapsed` into an identify flash, or `None` once it's over.
+fn identify_flash_color(elapsed: Duration) -> Option<Rgb> {
+    if elapsed >= IDENTIFY_DURATION {
+        return None;
+    }
+    let flash = elapsed.as_millis() / IDENTIFY_FLASH_INTERVAL.as_millis();
+    Some(if flash.is_multiple_of(2) {
+        IDENTIFY_FLASH_COLOR
+    } else {
+        LIGHTBAR_OFF
+    })
+}
+
+fn send_lightbar(state: &mut ConnectedControllerState, color: Rgb) {
+    let output = OutputState {
+        lightbar: Some(color),
+        ..OutputState::default()
+    };
+    send_output(state, &output);
+}
+
+fn send_output(state: &mut ConnectedControllerState, output: &OutputState) {
+    let report =
+        output_report::build_output_report(output, state.is_bluetooth, state.output_sequence);
+    state.output_sequence = state.output_sequence.wrapping_add(1) & 0x0F;
+    if let Err(e) = state.device.write(&report) {
+        eprintln!("Polling Thread: Failed to write output report: {}", e);
+    }
+}
+
+fn find_dualsense_device_paths(hid_api: &HidApi) -> HashSet<CString> {
+    hid_api
+        .device_list()
+        .filter(|dev| {
+            dev.vendor_id() == VENDOR_ID_SONY
+                && (dev.product_id() == PRODUCT_ID_DUALSENSE
+                    || dev.product_id() == PRODUCT_ID_DUALSENSE_EDGE)
+        })
+        .map(|dev| dev.path().to_owned())
+        .collect()
+}
+
+fn read_controller_info(device: &HidDevice) -> Result<ControllerInfo, HidError> {
+    let info = device.get_device_info()?;
+    Ok(ControllerInfo {
+        model: ControllerModel::from_product_id(info.product_id())
+            .unwrap_or(ControllerModel::DualSense),
+        connection_type: if matches!(info.bus_type(), BusType::Bluetooth) {
+            ConnectionType::Bluetooth
+        } else {
+            ConnectionType::Usb
+        },
+        serial: read_feature_report(
+            device,
+            FEATURE_REPORT_PAIRING_INFO,
+            FEATURE_REPORT_PAIRING_INFO_SIZE,
+        )
+        .and_then(|report| parse_mac_address(&report)),
+        firmware: read_feature_report(
+            device,
+            FEATURE_REPORT_FIRMWARE_INFO,
+            FEATURE_REPORT_FIRMWARE_INFO_SIZE,
+        )
+        .and_then(|report| parse_firmware_info(&report)),
+    })
+}
+
+/// A controller opened once, for commands that run without the overlay.
+pub struct DirectController {
+    pub path: String,
+    pub info: ControllerInfo,
+    device: HidDevice,
+}
+
+impl DirectController {
+    /// Opens every connected controller. This competes with any other process
+    /// reading them, so it's only for when the overlay isn't running.
+    pub fn open_all() -> Result<Vec<Self>, String> {
+        let hid_api = HidApi::new().map_err(|e| format!("Failed to open HID API: {}", e))?;
+        let mut paths: Vec<CString> = find_dualsense_device_paths(&hid_api).into_iter().collect();
+        paths.sort();
+        let mut controllers = Vec::new();
+        for path in paths {
+            let opened = hid_api
+                .open_path(&path)
+                .and_then(|device| Ok((read_controller_info(&device)?, device)));
+            match opened {
+                Ok((info, device)) => controllers.push(Self {
+                    path: c_str_to_string(&path),
+                    info,
+                    device,
+                }),
+                Err(e) => eprintln!("Failed to open {}: {}", c_str_to_string(&path), e),
+            }
+        }
+        Ok(controllers)
+    }
+
+    /// Waits up to `timeout` for an input report and reads the battery from it.
+    pub fn read_battery(&self, timeout: Duration) -> Option<BatteryReport> {
+        let is_bluetooth = self.info.connection_type == ConnectionType::Bluetooth;
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; BLUETOOTH_INPUT_REPORT_SIZE];
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let bytes_read = self
+                .device
+                .read_timeout(&mut buf, remaining.as_millis() as i32)
+                .ok()?;
+            let report = &buf[..bytes_read];
+            if bytes_read > 0 && bluetooth_report_crc_valid(report) != Some(false) {
+                return parse_battery(report, is_bluetooth);
+            }
+        }
+        None
+    }
+
+    pub fn set_lightbar(&self, color: Rgb) -> Result<(), String> {
+        let output = OutputState {
+            lightbar: Some(color),
+            ..OutputState::default()
+        };
+        let is_bluetooth = self.info.connection_type == ConnectionType::Bluetooth;
+        let report = output_report::build_output_report(&output, is_bluetooth, 0);
+        self.device
+            .write(&report)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to write output report: {}", e))
+    }
 }
 
-fn spawn_polling_thread(event_sender: Sender<ControllerEvent>) -> Result<(), PollError> {
-    let manager = ControllerPollingManager::new(event_sender)?;
+/// Reads a feature report, returning it with the report ID in the first byte.
+fn read_feature_report(device: &HidDevice, report_id: u8, size: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; size];
+    buf[0] = report_id;
+    match device.get_feature_report(&mut buf) {
+        Ok(bytes_read) => {
+            buf.truncate(bytes_read);
+            Some(buf)
+        }
+        Err(e) => {
+            eprintln!(
+                "Polling Thread: Failed to read feature report {:#04x}: {}",
+                report_id, e
+            );
+            None
+        }
+    }
+}
+
+pub fn setup_controller_polling(
+    alert_thresholds: AlertThresholds,
+    gestures: GestureDetector,
+) -> Result<(Receiver<ControllerEvent>, Sender<ControllerCommand>), String> {
+    let (sender, receiver) = mpsc::channel::<ControllerEvent>();
+    let (command_sender, command_receiver) = mpsc::channel::<ControllerCommand>();
+    spawn_polling_thread(sender, command_receiver, alert_thresholds, gestures)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok((receiver, command_sender))
+}
+
+fn spawn_polling_thread(
+    event_sender: Sender<ControllerEvent>,
+    command_receiver: Receiver<ControllerCommand>,
+    alert_thresholds: AlertThresholds,
+    gestures: GestureDetector,
+) -> Result<(), PollError> {
+    let manager =
+        ControllerPollingManager::new(event_sender, command_receiver, alert_thresholds, gestures)?;
 
     thread::Builder::new()
         .name("dualsense_poll".to_string())
@@ -1,26 +1,45 @@
+use std::time::Instant;
+
 use windows_numerics::Vector2;
 
 use windows::Win32::Graphics::{
     Direct2D::{
-        Common::{D2D_RECT_F, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_COLOR_F, D2D1_PIXEL_FORMAT},
-        D2D1_FEATURE_LEVEL_DEFAULT, D2D1_RENDER_TARGET_PROPERTIES, D2D1_RENDER_TARGET_TYPE_DEFAULT,
-        D2D1_RENDER_TARGET_USAGE_NONE, D2D1_ROUNDED_RECT, ID2D1SolidColorBrush,
+        Common::{
+            D2D_RECT_F, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_COLOR_F, D2D1_FIGURE_BEGIN_FILLED,
+            D2D1_FIGURE_END_CLOSED, D2D1_FILL_MODE_WINDING, D2D1_PIXEL_FORMAT,
+        },
+        D2D1_DRAW_TEXT_OPTIONS_NONE, D2D1_FEATURE_LEVEL_DEFAULT, D2D1_RENDER_TARGET_PROPERTIES,
+        D2D1_RENDER_TARGET_TYPE_DEFAULT, D2D1_RENDER_TARGET_USAGE_NONE, D2D1_ROUNDED_RECT,
+        ID2D1PathGeometry, ID2D1RenderTarget, ID2D1SolidColorBrush,
+    },
+    DirectWrite::{
+        DWRITE_TEXT_ALIGNMENT_CENTER, DWRITE_TEXT_ALIGNMENT_LEADING, DWRITE_TEXT_RANGE,
+        IDWriteTextLayout,
     },
-    DirectWrite::IDWriteTextLayout,
     Dxgi::{Common::DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_PRESENT},
 };
 
-use crate::{
-    CORNER_RADIUS, WINDOW_HEIGHT, WINDOW_WIDTH,
-    dualsense::{BatteryReport, BatteryStatus},
-};
+use crate::battery_history;
+use crate::overlay_layout::{Color, OverlayLayout, Point, Primitive, Rect, Scene, TextAlignment};
+use crate::runtime_estimate;
 
 pub fn draw_content(app_state: &crate::AppState) {
-    let default = BatteryReport::new(0, BatteryStatus::Unknown);
-    let battery_report = match &app_state.triggering_controller_path {
-        Some(path) => app_state.battery_status_map.get(path).unwrap_or(&default),
-        None => &default,
-    };
+    let now = Instant::now();
+    let mut rows = app_state
+        .controllers
+        .overlay_rows(app_state.triggering_controller_path.as_deref());
+    let unix_now = battery_history::unix_now();
+    for (row, entry) in rows.iter_mut().zip(app_state.controllers.iter()) {
+        row.animation = app_state.animations.row_animation(&entry.path, row, now);
+        if entry.connected {
+            row.estimate = app_state
+                .battery_samples
+                .get(entry.identity())
+                .and_then(|samples| runtime_estimate::estimate(samples, unix_now));
+        }
+    }
+    let layout = OverlayLayout::for_rows(rows.len(), 1.0);
+    let scene = layout.build_scene(&rows);
 
     // --- Get Render Target ---
     let surface: windows::Win32::Graphics::Dxgi::IDXGISurface = unsafe {
@@ -54,191 +73,165 @@ pub fn draw_content(app_state: &crate::AppState) {
             .expect("Failed to create render target")
     };
 
-    let clear_color = D2D1_COLOR_F {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0,
-        a: 0.0,
-    }; // Transparent
-    let background_color = D2D1_COLOR_F {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0,
-        a: 0.7,
-    }; // Opaque Black bg
-    let outline_color = D2D1_COLOR_F {
-        r: 0.8,
-        g: 0.8,
-        b: 0.8,
-        a: 1.0,
-    }; // Light gray outline/text
-    let fill_color = match battery_report.battery_capacity {
-        0..=20 => rgba_to_d2d1_color_f(242, 27, 63, 255),
-        21..=50 => rgba_to_d2d1_color_f(255, 198, 10, 255),
-        _ => rgba_to_d2d1_color_f(43, 192, 22, 255),
-    };
-
-    // --- Create Brushes ---
-    let bg_brush: ID2D1SolidColorBrush = unsafe {
-        render_target
-            .CreateSolidColorBrush(&background_color, None)
-            .expect("Failed to create bg brush")
-    };
-    let outline_brush: ID2D1SolidColorBrush = unsafe {
-        render_target
-            .CreateSolidColorBrush(&outline_color, None)
-            .expect("Failed to create outline brush")
-    };
-    let fill_brush: ID2D1SolidColorBrush = unsafe {
-        render_target
-            .CreateSolidColorBrush(&fill_color, None)
-            .expect("Failed to create fill brush")
-    };
-
-    // --- Define Geometry ---
-    let target_width = WINDOW_WIDTH as f32;
-    let target_height = WINDOW_HEIGHT as f32;
-
-    // Background Rounded Rect
-    let bg_rect = D2D_RECT_F {
-        left: 0.0,
-        top: 0.0,
-        right: target_width,
-        bottom: target_height,
-    };
-    let bg_rounded_rect = D2D1_ROUNDED_RECT {
-        rect: bg_rect,
-        radiusX: CORNER_RADIUS,
-        radiusY: CORNER_RADIUS,
-    };
-
-    let icon_height = target_height * 0.4; // Icon takes 40% of overlay height
-    let icon_width = icon_height * 1.8;
-    let icon_center_x = target_width / 2.0;
-    let icon_top_y = target_height * 0.15; // Position icon 15% from the top
-    let icon_bottom_y = icon_top_y + icon_height;
-    let outline_thickness = 5.0;
-    let battery_corner_radius = 4.0;
-
-    // Battery Body
-    let body_rect = D2D_RECT_F {
-        left: icon_center_x - icon_width / 2.0,
-        top: icon_top_y,
-        right: icon_center_x + icon_width / 2.0,
-        bottom: icon_bottom_y,
-    };
-
-    let body_rounded_rect = D2D1_ROUNDED_RECT {
-        rect: body_rect,
-        radiusX: battery_corner_radius,
-        radiusY: battery_corner_radius,
-    };
-
-    // Battery Terminal
-    let terminal_height = icon_height * 0.4;
-    let terminal_width = icon_width * 0.1;
-    let terminal_rect = D2D_RECT_F {
-        left: body_rect.right,
-        top: icon_top_y + (icon_height - terminal_height) / 2.0,
-        right: body_rect.right + terminal_width,
-        bottom: icon_top_y + (icon_height + terminal_height) / 2.0,
-    };
-
-    // Battery Fill Area
-    // Inset by half the outline thickness to align with the inside edge of the stroke
-    let inset = outline_thickness / 2.0;
-    let fill_area_rect = D2D_RECT_F {
-        left: body_rect.left + inset,
-        top: body_rect.top + inset,
-        right: body_rect.right - inset,
-        bottom: body_rect.bottom - inset,
-    };
-    let max_fill_width = fill_area_rect.right - fill_area_rect.left;
-    let fill_width = max_fill_width * (battery_report.battery_capacity as f32 / 100.0);
-    // The actual rectangle to fill
-    let fill_rect = D2D_RECT_F {
-        left: fill_area_rect.left,
-        top: fill_area_rect.top,
-        right: fill_area_rect.left + fill_width, // Calculate end based on percentage
-        bottom: fill_area_rect.bottom,
-    };
-
-    // Text Layout Area (below the icon)
-    let text_top_y = icon_bottom_y + 5.0; // Space below icon
-    let text_layout_rect = D2D_RECT_F {
-        left: 0.0, // Allow text to center across the whole width
-        top: text_top_y,
-        right: target_width,
-        bottom: target_height - 5.0, // Space at bottom
-    };
-
-    // --- Draw Commands ---
     unsafe {
         render_target.BeginDraw();
-        render_target.Clear(Some(&clear_color)); // Clear transparent
+        draw_scene(&render_target, app_state, &scene);
+        render_target
+            .EndDraw(None, None)
+            .expect("Failed to end draw");
 
-        // 1. Draw Background
-        render_target.FillRoundedRectangle(&bg_rounded_rect, &bg_brush);
+        let _ = app_state.swap_chain.Present(1, DXGI_PRESENT::default());
+    }
+}
 
-        // Fill
-        if fill_width > 0.0 {
-            render_target.FillRectangle(&fill_rect, &fill_brush);
+/// Replays a layout scene onto a render target that is already inside `BeginDraw`.
+unsafe fn draw_scene(
+    render_target: &ID2D1RenderTarget,
+    app_state: &crate::AppState,
+    scene: &Scene,
+) {
+    unsafe {
+        render_target.Clear(Some(&to_d2d1_color_f(scene.clear_color)));
+
+        for primitive in &scene.primitives {
+            match primitive {
+                Primitive::FillRoundedRect {
+                    rect,
+                    radius,
+                    color,
+                } => {
+                    let brush = create_brush(render_target, *color);
+                    render_target
+                        .FillRoundedRectangle(&to_d2d1_rounded_rect(rect, *radius), &brush);
+                }
+                Primitive::StrokeRoundedRect {
+                    rect,
+                    radius,
+                    stroke_width,
+                    color,
+                } => {
+                    let brush = create_brush(render_target, *color);
+                    render_target.DrawRoundedRectangle(
+                        &to_d2d1_rounded_rect(rect, *radius),
+                        &brush,
+                        *stroke_width,
+                        None, // No stroke style needed
+                    );
+                }
+                Primitive::FillRect { rect, color } => {
+                    let brush = create_brush(render_target, *color);
+                    render_target.FillRectangle(&to_d2d_rect_f(rect), &brush);
+                }
+                Primitive::FillPolygon { points, color } => {
+                    let brush = create_brush(render_target, *color);
+                    if let Some(geometry) = create_polygon_geometry(render_target, points) {
+                        render_target.FillGeometry(&geometry, &brush, None);
+                    }
+                }
+                Primitive::Text {
+                    rect,
+                    text,
+                    font_size,
+                    color,
+                    alignment,
+                } => {
+                    let text_utf16 = text.encode_utf16().collect::<Vec<u16>>();
+                    let text_layout: IDWriteTextLayout = app_state
+                        .dwrite_factory
+                        .CreateTextLayout(
+                            &text_utf16,
+                            &app_state.text_format,
+                            rect.width(),
+                            rect.height(),
+                        )
+                        .expect("Failed to create text layout");
+                    let _ = text_layout.SetTextAlignment(match alignment {
+                        TextAlignment::Leading => DWRITE_TEXT_ALIGNMENT_LEADING,
+                        TextAlignment::Center => DWRITE_TEXT_ALIGNMENT_CENTER,
+                    });
+                    let _ = text_layout.SetFontSize(
+                        *font_size,
+                        DWRITE_TEXT_RANGE {
+                            startPosition: 0,
+                            length: text_utf16.len() as u32,
+                        },
+                    );
+
+                    let brush = create_brush(render_target, *color);
+                    render_target.DrawTextLayout(
+                        Vector2 {
+                            X: rect.left,
+                            Y: rect.top,
+                        },
+                        &text_layout,
+                        &brush,
+                        D2D1_DRAW_TEXT_OPTIONS_NONE,
+                    );
+                }
+            }
         }
+    }
+}
 
-        // 2. Draw Battery Icon
-        // Outline
-        render_target.DrawRoundedRectangle(
-            &body_rounded_rect, // Use the rounded rect definition
-            &outline_brush,
-            outline_thickness,
-            None, // No stroke style needed
-        );
-        render_target.FillRectangle(&terminal_rect, &outline_brush); // Solid terminal
-
-        // 3. Draw Text
-        let text = if battery_report.battery_status.eq(&BatteryStatus::Charging) {
-            format!("{}% - Charging", battery_report.battery_capacity)
-        } else {
-            format!("{}%", battery_report.battery_capacity)
-        };
-        let text_pcwstr = text.encode_utf16().collect::<Vec<u16>>(); // Convert to UTF-16
-
-        // Create Text Layout
-        let text_layout: IDWriteTextLayout = app_state
-            .dwrite_factory
-            .CreateTextLayout(
-                &text_pcwstr,
-                &app_state.text_format,
-                text_layout_rect.right - text_layout_rect.left, // Max width
-                text_layout_rect.bottom - text_layout_rect.top, // Max height
-            )
-            .expect("Failed to create text layout");
-
-        // Draw the layout using the outline brush color
-        render_target.DrawTextLayout(
-            Vector2 {
-                X: text_layout_rect.left,
-                Y: text_layout_rect.top,
-            }, // Origin point
-            &text_layout,
-            &outline_brush, // Use outline brush for text color
-            windows::Win32::Graphics::Direct2D::D2D1_DRAW_TEXT_OPTIONS_NONE,
-        );
+fn create_brush(render_target: &ID2D1RenderTarget, color: Color) -> ID2D1SolidColorBrush {
+    unsafe {
+        render_target
+            .CreateSolidColorBrush(&to_d2d1_color_f(color), None)
+            .expect("Failed to create brush")
+    }
+}
 
+fn create_polygon_geometry(
+    render_target: &ID2D1RenderTarget,
+    points: &[Point],
+) -> Option<ID2D1PathGeometry> {
+    let (first, rest) = points.split_first()?;
+    let to_vector = |point: &Point| Vector2 {
+        X: point.x,
+        Y: point.y,
+    };
+    let result = unsafe {
         render_target
-            .EndDraw(None, None)
-            .expect("Failed to end draw");
+            .GetFactory()
+            .and_then(|factory| factory.CreatePathGeometry())
+            .and_then(|geometry| {
+                let sink = geometry.Open()?;
+                sink.SetFillMode(D2D1_FILL_MODE_WINDING);
+                sink.BeginFigure(to_vector(first), D2D1_FIGURE_BEGIN_FILLED);
+                sink.AddLines(&rest.iter().map(to_vector).collect::<Vec<_>>());
+                sink.EndFigure(D2D1_FIGURE_END_CLOSED);
+                sink.Close()?;
+                Ok(geometry)
+            })
+    };
+    result
+        .map_err(|e| eprintln!("Failed to create polygon geometry: {}", e))
+        .ok()
+}
 
-        let _ = app_state.swap_chain.Present(1, DXGI_PRESENT::default());
+fn to_d2d_rect_f(rect: &Rect) -> D2D_RECT_F {
+    D2D_RECT_F {
+        left: rect.left,
+        top: rect.top,
+        right: rect.right,
+        bottom: rect.bottom,
+    }
+}
+
+fn to_d2d1_rounded_rect(rect: &Rect, radius: f32) -> D2D1_ROUNDED_RECT {
+    D2D1_ROUNDED_RECT {
+        rect: to_d2d_rect_f(rect),
+        radiusX: radius,
+        radiusY: radius,
     }
 }
 
 #[inline]
-pub(crate) fn rgba_to_d2d1_color_f(r: u8, g: u8, b: u8, a: u8) -> D2D1_COLOR_F {
+fn to_d2d1_color_f(color: Color) -> D2D1_COLOR_F {
     D2D1_COLOR_F {
-        r: (r as f32) / 255.0,
-        g: (g as f32) / 255.0,
-        b: (b as f32) / 255.0,
-        a: (a as f32) / 255.0,
+        r: color.r,
+        g: color.g,
+        b: color.b,
+        a: color.a,
     }
 }
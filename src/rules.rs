@@ -0,0 +1,522 @@
+//! Rules from the config that turn controller state and events into actions.
+//!
+//! ```toml
+//! [[rules]]
+//! name = "Low battery"
+//! when = { below = 15, status = "discharging", for_secs = 120 }
+//! actions = [{ type = "show_overlay" }, { type = "set_lightbar", color = "#ff0000" }]
+//!
+//! [[rules]]
+//! name = "Couch pad"
+//! when = { event = "connected", controller = "couch-2" }
+//! actions = [{ type = "set_player_led", player = 2 }]
+//! ```
+//!
+//! A rule with an `event` fires each time that event happens to a controller
+//! that meets the other conditions. A rule without one fires once a connected
+//! controller has met its conditions for `for_secs`, and only fires again for
+//! that controller after they stop holding. Evaluation is pure: the caller
+//! passes the controllers, the clock and the time of day, and carries out the
+//! [`Firing`]s it gets back.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery_history, config::HookCommand, controllers::ControllerEntry, hooks::HookKind,
+    output_report::Rgb,
+};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub when: Conditions,
+    pub actions: Vec<Action>,
+}
+
+/// Everything set here has to hold; an empty table matches any controller.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conditions {
+    /// Fire on this event instead of on a change of state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<HookKind>,
+    /// Player number, identity, HID path or name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controller: Option<String>,
+    /// `DualSense` or `DualSense Edge`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// `usb` or `bluetooth`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
+    /// Battery percentage strictly below this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<u8>,
+    /// Battery percentage strictly above this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option<u8>,
+    /// `discharging`, `charging`, `full`, `charging_error` or `unknown`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Local time of day, as `HH:MM-HH:MM`; may wrap past midnight.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub between: Option<TimeRange>,
+    /// How long the other conditions must have held before the rule fires.
+    pub for_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    ShowOverlay,
+    Notify {
+        title: String,
+        #[serde(default)]
+        message: String,
+    },
+    /// Sets the lightbar until something else changes it; unlike the tray
+    /// menu, this isn't saved.
+    SetLightbar {
+        color: Rgb,
+    },
+    /// Lights the player LEDs the way the console would for this player;
+    /// 0 turns them off.
+    SetPlayerLed {
+        player: u8,
+    },
+    RunHook(HookCommand),
+}
+
+/// Minutes since local midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(pub u16);
+
+impl TimeOfDay {
+    pub fn new(hour: u16, minute: u16) -> Self {
+        Self((hour * 60 + minute) % MINUTES_PER_DAY)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parsed = value.trim().split_once(':').and_then(|(hour, minute)| {
+            let hour = hour.parse::<u16>().ok().filter(|hour| *hour < 24)?;
+            let minute = minute.parse::<u16>().ok().filter(|minute| *minute < 60)?;
+            Some(Self::new(hour, minute))
+        });
+        parsed.ok_or_else(|| format!("Invalid time '{}', expected HH:MM", value))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// From `start` up to, but not including, `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeRange {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid time range '{}', expected HH:MM-HH:MM", value))?;
+        Ok(Self {
+            start: start.parse()?,
+            end: end.parse()?,
+        })
+    }
+}
+
+impl From<TimeRange> for String {
+    fn from(range: TimeRange) -> Self {
+        format!("{}-{}", range.start, range.end)
+    }
+}
+
+impl Conditions {
+    /// Whether the controller and the time of day meet everything but
+    /// `event` and `for_secs`.
+    pub fn matches(&self, entry: &ControllerEntry, time: TimeOfDay) -> bool {
+        let capacity = entry.battery.as_ref().map(|report| report.battery_capacity);
+        let status = entry
+            .battery
+            .as_ref()
+            .map(|report| battery_history::status_name(&report.battery_status));
+
+        self.controller
+            .as_deref()
+            .is_none_or(|query| controller_matches(entry, query))
+            && self
+                .model
+                .as_deref()
+                .is_none_or(|model| model.eq_ignore_ascii_case(entry.info.model.name()))
+            && self.transport.as_deref().is_none_or(|transport| {
+                transport.eq_ignore_ascii_case(battery_history::transport_name(
+                    entry.info.connection_type,
+                ))
+            })
+            && self
+                .below
+                .is_none_or(|below| capacity.is_some_and(|capacity| capacity < below))
+            && self
+                .above
+                .is_none_or(|above| capacity.is_some_and(|capacity| capacity > above))
+            && self.status.as_deref().is_none_or(|wanted| {
+                status.is_some_and(|status| wanted.eq_ignore_ascii_case(status))
+            })
+            && self.between.is_none_or(|range| range.contains(time))
+    }
+}
+
+/// The same lookup the IPC socket uses for `controller` parameters.
+fn controller_matches(entry: &ControllerEntry, query: &str) -> bool {
+    query.parse::<u8>().ok() == Some(entry.player_number)
+        || entry.identity().eq_ignore_ascii_case(query)
+        || entry.path == query
+        || entry.display_name().eq_ignore_ascii_case(query)
+}
+
+/// A rule that fired for one controller, with the actions to carry out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Firing {
+    pub rule: String,
+    pub entry: ControllerEntry,
+    pub actions: Vec<Action>,
+}
+
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    /// When each rule's conditions started holding, per rule index and path.
+    holding_since: HashMap<(usize, String), Instant>,
+    /// State rules that already fired during the current stretch.
+    fired: HashSet<(usize, String)>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules,
+            holding_since: HashMap::new(),
+            fired: HashSet::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Brings every rule up to date with the connected controllers and fires
+    /// the state rules that are due. Call it after each event and every so
+    /// often in between, so `for_secs` runs out without new events.
+    pub fn update<'a>(
+        &mut self,
+        controllers: impl IntoIterator<Item = &'a ControllerEntry>,
+        now: Instant,
+        time: TimeOfDay,
+    ) -> Vec<Firing> {
+        let connected: Vec<&ControllerEntry> = controllers
+            .into_iter()
+            .filter(|entry| entry.connected)
+            .collect();
+        let mut firings = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            for entry in &connected {
+                let key = (index, entry.path.clone());
+                if !rule.when.matches(entry, time) {
+                    self.holding_since.remove(&key);
+                    self.fired.remove(&key);
+                    continue;
+                }
+                let since = *self.holding_since.entry(key.clone()).or_insert(now);
+                let held = now.saturating_duration_since(since);
+                if rule.when.event.is_none()
+                    && held >= Duration::from_secs(rule.when.for_secs)
+                    && self.fired.insert(key)
+                {
+                    firings.push(Firing::new(rule, entry));
+                }
+            }
+        }
+
+        // Forget controllers that went away, so they start afresh if they return.
+        self.holding_since
+            .retain(|(_, path), _| connected.iter().any(|entry| entry.path == *path));
+        self.fired
+            .retain(|(_, path)| connected.iter().any(|entry| entry.path == *path));
+        firings
+    }
+
+    /// Fires the rules waiting for this event on this controller. `entry` is
+    /// the controller as the event left it, or as it was before it
+    /// disconnected.
+    pub fn on_event(
+        &mut self,
+        kind: HookKind,
+        entry: &ControllerEntry,
+        now: Instant,
+        time: TimeOfDay,
+    ) -> Vec<Firing> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.when.event == Some(kind) && rule.when.matches(entry, time))
+            .filter(|(index, rule)| {
+                let since = self
+                    .holding_since
+                    .get(&(*index, entry.path.clone()))
+                    .copied()
+                    .unwrap_or(now);
+                now.saturating_duration_since(since) >= Duration::from_secs(rule.when.for_secs)
+            })
+            .map(|(_, rule)| Firing::new(rule, entry))
+            .collect()
+    }
+}
+
+impl Firing {
+    fn new(rule: &Rule, entry: &ControllerEntry) -> Self {
+        Self {
+            rule: rule.name.clone(),
+            entry: entry.clone(),
+            actions: rule.actions.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{
+        BatteryReport, BatteryStatus, ConnectionType, ControllerInfo, ControllerModel,
+    };
+
+    const NOON: TimeOfDay = TimeOfDay(12 * 60);
+
+    fn entry(path: &str, capacity: u8, status: BatteryStatus) -> ControllerEntry {
+        ControllerEntry {
+            path: path.to_string(),
+            info: ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Bluetooth,
+                serial: None,
+                firmware: None,
+            },
+            player_number: 1,
+            name: None,
+            battery: Some(BatteryReport::new(capacity, status)),
+            connected: true,
+        }
+    }
+
+    fn rule(when: Conditions) -> Rule {
+        Rule {
+            name: "test".to_string(),
+            when,
+            actions: vec![Action::ShowOverlay],
+        }
+    }
+
+    fn low_battery(for_secs: u64) -> RuleEngine {
+        RuleEngine::new(vec![rule(Conditions {
+            below: Some(15),
+            status: Some("discharging".to_string()),
+            for_secs,
+            ..Conditions::default()
+        })])
+    }
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn state_rule_fires_once_conditions_held_for_secs() {
+        let mut engine = low_battery(120);
+        let pad = entry("pad", 10, BatteryStatus::Discharging);
+        let start = Instant::now();
+        assert_eq!(engine.update([&pad], start, NOON), []);
+        assert_eq!(engine.update([&pad], secs(start, 119), NOON), []);
+
+        let firings = engine.update([&pad], secs(start, 120), NOON);
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].rule, "test");
+        assert_eq!(firings[0].entry, pad);
+        assert_eq!(firings[0].actions, [Action::ShowOverlay]);
+
+        // Still holding, but it already fired.
+        assert_eq!(engine.update([&pad], secs(start, 500), NOON), []);
+    }
+
+    #[test]
+    fn interruption_restarts_the_hold() {
+        let mut engine = low_battery(120);
+        let low = entry("pad", 10, BatteryStatus::Discharging);
+        let charging = entry("pad", 10, BatteryStatus::Charging);
+        let start = Instant::now();
+        engine.update([&low], start, NOON);
+        engine.update([&charging], secs(start, 100), NOON);
+        engine.update([&low], secs(start, 110), NOON);
+        assert_eq!(engine.update([&low], secs(start, 200), NOON), []);
+        assert_eq!(engine.update([&low], secs(start, 230), NOON).len(), 1);
+    }
+
+    #[test]
+    fn state_rule_rearms_after_conditions_clear() {
+        let mut engine = low_battery(0);
+        let low = entry("pad", 10, BatteryStatus::Discharging);
+        let charged = entry("pad", 50, BatteryStatus::Discharging);
+        let start = Instant::now();
+        assert_eq!(engine.update([&low], start, NOON).len(), 1);
+        assert_eq!(engine.update([&low], secs(start, 10), NOON), []);
+        assert_eq!(engine.update([&charged], secs(start, 20), NOON), []);
+        assert_eq!(engine.update([&low], secs(start, 30), NOON).len(), 1);
+    }
+
+    #[test]
+    fn state_rules_track_each_controller() {
+        let mut engine = low_battery(0);
+        let first = entry("first", 10, BatteryStatus::Discharging);
+        let second = entry("second", 10, BatteryStatus::Discharging);
+        let start = Instant::now();
+        assert_eq!(engine.update([&first], start, NOON).len(), 1);
+        let firings = engine.update([&first, &second], secs(start, 1), NOON);
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].entry.path, "second");
+    }
+
+    #[test]
+    fn reconnected_controller_starts_afresh() {
+        let mut engine = low_battery(0);
+        let mut pad = entry("pad", 10, BatteryStatus::Discharging);
+        let start = Instant::now();
+        assert_eq!(engine.update([&pad], start, NOON).len(), 1);
+        pad.connected = false;
+        assert_eq!(engine.update([&pad], secs(start, 1), NOON), []);
+        pad.connected = true;
+        assert_eq!(engine.update([&pad], secs(start, 2), NOON).len(), 1);
+    }
+
+    #[test]
+    fn event_rules_fire_on_every_matching_event() {
+        let mut engine = RuleEngine::new(vec![rule(Conditions {
+            event: Some(HookKind::Connected),
+            transport: Some("bluetooth".to_string()),
+            ..Conditions::default()
+        })]);
+        let pad = entry("pad", 80, BatteryStatus::Discharging);
+        let now = Instant::now();
+        // Never from state updates alone.
+        assert_eq!(engine.update([&pad], now, NOON), []);
+        assert_eq!(
+            engine.on_event(HookKind::Connected, &pad, now, NOON).len(),
+            1
+        );
+        assert_eq!(
+            engine.on_event(HookKind::Connected, &pad, now, NOON).len(),
+            1
+        );
+        assert_eq!(engine.on_event(HookKind::Disconnected, &pad, now, NOON), []);
+
+        let mut usb = pad.clone();
+        usb.info.connection_type = ConnectionType::Usb;
+        assert_eq!(engine.on_event(HookKind::Connected, &usb, now, NOON), []);
+    }
+
+    #[test]
+    fn event_rules_respect_for_secs() {
+        let mut engine = RuleEngine::new(vec![rule(Conditions {
+            event: Some(HookKind::MutePressed),
+            below: Some(50),
+            for_secs: 60,
+            ..Conditions::default()
+        })]);
+        let pad = entry("pad", 10, BatteryStatus::Discharging);
+        let start = Instant::now();
+        engine.update([&pad], start, NOON);
+        assert_eq!(
+            engine.on_event(HookKind::MutePressed, &pad, secs(start, 30), NOON),
+            []
+        );
+        assert_eq!(
+            engine
+                .on_event(HookKind::MutePressed, &pad, secs(start, 60), NOON)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn time_range_within_a_day() {
+        let range = TimeRange::try_from("09:00-17:00".to_string()).unwrap();
+        assert!(!range.contains(TimeOfDay::new(8, 59)));
+        assert!(range.contains(TimeOfDay::new(9, 0)));
+        assert!(range.contains(TimeOfDay::new(16, 59)));
+        assert!(!range.contains(TimeOfDay::new(17, 0)));
+    }
+
+    #[test]
+    fn time_range_wraps_past_midnight() {
+        let range = TimeRange::try_from("22:00-06:00".to_string()).unwrap();
+        assert!(range.contains(TimeOfDay::new(22, 0)));
+        assert!(range.contains(TimeOfDay::new(23, 59)));
+        assert!(range.contains(TimeOfDay::new(0, 0)));
+        assert!(range.contains(TimeOfDay::new(5, 59)));
+        assert!(!range.contains(TimeOfDay::new(6, 0)));
+        assert!(!range.contains(TimeOfDay::new(12, 0)));
+        assert!(!range.contains(TimeOfDay::new(21, 59)));
+    }
+
+    #[test]
+    fn between_limits_when_rules_fire() {
+        let mut engine = RuleEngine::new(vec![rule(Conditions {
+            between: Some(TimeRange::try_from("22:00-06:00".to_string()).unwrap()),
+            ..Conditions::default()
+        })]);
+        let pad = entry("pad", 80, BatteryStatus::Discharging);
+        let start = Instant::now();
+        assert_eq!(engine.update([&pad], start, NOON), []);
+        assert_eq!(engine.update([&pad], start, TimeOfDay::new(1, 30)).len(), 1);
+    }
+
+    #[test]
+    fn time_parsing() {
+        assert_eq!("07:05".parse(), Ok(TimeOfDay(7 * 60 + 5)));
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("12:60".parse::<TimeOfDay>().is_err());
+        assert!(TimeRange::try_from("12:00".to_string()).is_err());
+        let range = TimeRange::try_from("22:00-06:30".to_string()).unwrap();
+        assert_eq!(String::from(range), "22:00-06:30");
+    }
+}
@@ -0,0 +1,338 @@
+//! Remaining runtime and time-to-full estimates from battery history.
+//!
+//! The controller only reports its level in 10% steps, so a single reading
+//! says little about the rate. What does carry timing is the moment the level
+//! moves from one step to the next: the time between two such transitions is
+//! how long ten points took. Rates are averaged over the most recent of those
+//! intervals, and the spread between them sets the confidence.
+
+use crate::{battery_history::BatterySample, dualsense::BatteryStatus};
+
+/// Samples worth keeping around for an estimate.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const MAX_SAMPLES: usize = 500;
+/// Step intervals averaged into a rate, newest first.
+const MAX_INTERVALS: usize = 12;
+/// A gap longer than this between readings means the pad was off, unplugged or
+/// the app wasn't running, so timing across it is meaningless.
+const MAX_STEP_SECS: u64 = 4 * 60 * 60;
+/// Reported capacity is the middle of its 10% step.
+const HALF_STEP: f64 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EstimateKind {
+    Remaining,
+    UntilFull,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub kind: EstimateKind,
+    pub seconds: u64,
+    /// `0.0..=1.0`; grows with the number of timed steps and shrinks when they
+    /// disagree.
+    pub confidence: f32,
+}
+
+impl Estimate {
+    /// "≈2h 10m remaining" or "full in ≈35m"
+    pub fn text(&self) -> String {
+        match self.kind {
+            EstimateKind::Remaining => format!("≈{} remaining", format_duration(self.seconds)),
+            EstimateKind::UntilFull => format!("full in ≈{}", format_duration(self.seconds)),
+        }
+    }
+
+    /// "≈2h 10m left" or "full in ≈35m", for where space is tight.
+    pub fn short_text(&self) -> String {
+        match self.kind {
+            EstimateKind::Remaining => format!("≈{} left", format_duration(self.seconds)),
+            EstimateKind::UntilFull => self.text(),
+        }
+    }
+}
+
+/// "2h 10m", "35m"; rounded to whole minutes, never below one.
+pub fn format_duration(seconds: u64) -> String {
+    let minutes = (seconds + 30) / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes.max(1)),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// Estimates from `samples` (oldest first, the last one being the current
+/// reading) as of `now`, both in Unix seconds. `None` when the pad is neither
+/// charging nor discharging, nothing has been timed yet, or the last reading is
+/// too old to say anything about the present.
+pub fn estimate(samples: &[BatterySample], now: u64) -> Option<Estimate> {
+    let current = samples.last()?;
+    let kind = match current.status {
+        BatteryStatus::Discharging => EstimateKind::Remaining,
+        BatteryStatus::Charging => EstimateKind::UntilFull,
+        _ => return None,
+    };
+    let elapsed = now.saturating_sub(current.timestamp);
+    if elapsed > MAX_STEP_SECS {
+        return None;
+    }
+
+    let intervals: Vec<f64> = step_intervals(samples, &current.status)
+        .iter()
+        .map(StepInterval::seconds_per_point)
+        .collect();
+    let recent = &intervals[intervals.len().saturating_sub(MAX_INTERVALS)..];
+    if recent.is_empty() {
+        return None;
+    }
+    let count = recent.len() as f64;
+    let mean = recent.iter().sum::<f64>() / count;
+    let variance = recent.iter().map(|rate| (rate - mean).powi(2)).sum::<f64>() / count;
+    let variation = variance.sqrt() / mean;
+    let confidence = (count / (count + 2.0)) * (1.0 / (1.0 + variation));
+
+    // Right after a step change the true level sits at the edge of the step
+    // rather than in its middle.
+    let capacity = f64::from(current.capacity);
+    let just_stepped = samples.len() >= 2 && {
+        let previous = &samples[samples.len() - 2];
+        previous.status == current.status && moved(previous, current)
+    };
+    let points_left = match (kind, just_stepped) {
+        (EstimateKind::Remaining, true) => capacity + HALF_STEP,
+        (EstimateKind::Remaining, false) => capacity,
+        (EstimateKind::UntilFull, true) => 100.0 - (capacity - HALF_STEP),
+        (EstimateKind::UntilFull, false) => 100.0 - capacity,
+    };
+    let seconds = (mean * points_left - elapsed as f64).max(0.0);
+
+    Some(Estimate {
+        kind,
+        seconds: seconds.round() as u64,
+        confidence: confidence as f32,
+    })
+}
+
+/// The time between two consecutive step transitions in one charging or
+/// discharging run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepInterval {
+    /// When the run this interval belongs to started, which groups intervals
+    /// by run.
+    pub run_started: u64,
+    pub started: u64,
+    pub ended: u64,
+    pub points: u32,
+}
+
+impl StepInterval {
+    pub fn seconds_per_point(&self) -> f64 {
+        (self.ended - self.started) as f64 / f64::from(self.points)
+    }
+}
+
+/// Every timed step interval with `status`, oldest first.
+pub fn step_intervals(samples: &[BatterySample], status: &BatteryStatus) -> Vec<StepInterval> {
+    let mut intervals = Vec::new();
+    let mut run_started = None;
+    // The last transition in the current run, if any.
+    let mut last_transition: Option<&BatterySample> = None;
+    for pair in samples.windows(2) {
+        let (previous, sample) = (&pair[0], &pair[1]);
+        if !continues_run(previous, sample, status) {
+            run_started = None;
+            last_transition = None;
+            continue;
+        }
+        let run_started = *run_started.get_or_insert(previous.timestamp);
+        if !moved(previous, sample) {
+            continue;
+        }
+        if let Some(transition) = last_transition {
+            let points = u32::from(transition.capacity.abs_diff(sample.capacity));
+            if points > 0 && sample.timestamp > transition.timestamp {
+                intervals.push(StepInterval {
+                    run_started,
+                    started: transition.timestamp,
+                    ended: sample.timestamp,
+                    points,
+                });
+            }
+        }
+        last_transition = Some(sample);
+    }
+    intervals
+}
+
+/// Whether two consecutive readings belong to one uninterrupted run with
+/// `status`: neither has another status and there's no gap long enough for the
+/// pad to have been off in between.
+pub fn continues_run(
+    previous: &BatterySample,
+    sample: &BatterySample,
+    status: &BatteryStatus,
+) -> bool {
+    previous.status == *status
+        && sample.status == *status
+        && sample.timestamp.saturating_sub(previous.timestamp) <= MAX_STEP_SECS
+}
+
+/// Whether the level went the way the status says it should between two readings.
+fn moved(previous: &BatterySample, sample: &BatterySample) -> bool {
+    match sample.status {
+        BatteryStatus::Discharging => sample.capacity < previous.capacity,
+        BatteryStatus::Charging => sample.capacity > previous.capacity,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::ConnectionType;
+
+    /// Readings of `(timestamp, capacity)` all with `status`.
+    fn series(status: BatteryStatus, readings: &[(u64, u8)]) -> Vec<BatterySample> {
+        readings
+            .iter()
+            .map(|&(timestamp, capacity)| BatterySample {
+                timestamp,
+                capacity,
+                status: status.clone(),
+                transport: ConnectionType::Usb,
+            })
+            .collect()
+    }
+
+    /// Ten points every ten minutes, read every five.
+    fn steady_discharge() -> Vec<BatterySample> {
+        series(
+            BatteryStatus::Discharging,
+            &[
+                (0, 80),
+                (300, 80),
+                (600, 70),
+                (900, 70),
+                (1200, 60),
+                (1500, 60),
+                (1800, 50),
+            ],
+        )
+    }
+
+    #[test]
+    fn steady_discharge_counts_down() {
+        let samples = steady_discharge();
+        // Just stepped to 50%, so about 55 points at a minute each are left.
+        let estimate = estimate(&samples, 1800).unwrap();
+        assert_eq!(estimate.kind, EstimateKind::Remaining);
+        assert_eq!(estimate.seconds, 55 * 60);
+        // Two intervals that agree exactly.
+        assert_eq!(estimate.confidence, 0.5);
+
+        assert_eq!(
+            super::estimate(&samples, 1900).unwrap().seconds,
+            55 * 60 - 100
+        );
+        assert_eq!(estimate.text(), "≈55m remaining");
+        assert_eq!(estimate.short_text(), "≈55m left");
+    }
+
+    #[test]
+    fn charging_counts_up_to_full() {
+        let samples = series(
+            BatteryStatus::Charging,
+            &[(0, 20), (300, 20), (600, 30), (900, 30), (1500, 40)],
+        );
+        let estimate = estimate(&samples, 1500).unwrap();
+        assert_eq!(estimate.kind, EstimateKind::UntilFull);
+        // 90 seconds a point, with 100 - 35 points to go.
+        assert_eq!(estimate.seconds, 65 * 90);
+        assert_eq!(estimate.text(), "full in ≈1h 38m");
+        assert_eq!(estimate.short_text(), estimate.text());
+    }
+
+    #[test]
+    fn too_few_samples_give_nothing() {
+        assert_eq!(estimate(&[], 0), None);
+        let samples = steady_discharge();
+        assert_eq!(estimate(&samples[..1], 0), None);
+        // One transition starts the clock but doesn't time a step yet.
+        assert_eq!(estimate(&samples[..4], 900), None);
+        assert!(estimate(&samples[..5], 1200).is_some());
+    }
+
+    #[test]
+    fn status_change_resets_the_estimate() {
+        let mut samples = steady_discharge();
+        samples.extend(series(BatteryStatus::Charging, &[(2100, 50), (2400, 60)]));
+        // Charging hasn't timed a step yet, and discharge rates don't apply.
+        assert_eq!(estimate(&samples, 2400), None);
+
+        // Unplugged again: the old run's intervals still count, but none spans
+        // the time spent charging.
+        samples.extend(series(
+            BatteryStatus::Discharging,
+            &[(2700, 60), (3000, 50), (3300, 50), (4200, 40)],
+        ));
+        let intervals = step_intervals(&samples, &BatteryStatus::Discharging);
+        assert_eq!(
+            intervals
+                .iter()
+                .map(|i| (i.run_started, i.started, i.ended))
+                .collect::<Vec<_>>(),
+            [(0, 600, 1200), (0, 1200, 1800), (2700, 3000, 4200)]
+        );
+        assert_eq!(
+            estimate(&samples, 4200).unwrap().kind,
+            EstimateKind::Remaining
+        );
+    }
+
+    #[test]
+    fn uneven_steps_lower_the_confidence() {
+        let even = estimate(&steady_discharge(), 1800).unwrap();
+        let uneven = series(
+            BatteryStatus::Discharging,
+            &[(0, 80), (600, 70), (900, 60), (1800, 50)],
+        );
+        let uneven = estimate(&uneven, 1800).unwrap();
+        assert!(uneven.confidence < even.confidence);
+        // The mean of 30 and 90 seconds a point.
+        assert_eq!(uneven.seconds, 55 * 60);
+    }
+
+    #[test]
+    fn stale_or_idle_readings_give_nothing() {
+        let samples = steady_discharge();
+        assert_eq!(estimate(&samples, 1800 + MAX_STEP_SECS + 1), None);
+
+        let mut full = samples.clone();
+        full.extend(series(BatteryStatus::Full, &[(2000, 100)]));
+        assert_eq!(estimate(&full, 2000), None);
+    }
+
+    #[test]
+    fn gaps_break_runs() {
+        let samples = series(
+            BatteryStatus::Discharging,
+            &[
+                (0, 80),
+                (600, 70),
+                (600 + MAX_STEP_SECS + 1, 60),
+                (20_000, 50),
+            ],
+        );
+        assert!(step_intervals(&samples, &BatteryStatus::Discharging).is_empty());
+    }
+
+    #[test]
+    fn durations_round_to_minutes() {
+        assert_eq!(format_duration(0), "1m");
+        assert_eq!(format_duration(89), "1m");
+        assert_eq!(format_duration(90), "2m");
+        assert_eq!(format_duration(3600), "1h");
+        assert_eq!(format_duration(7800), "2h 10m");
+    }
+}
@@ -0,0 +1,580 @@
+//! CPU rendering backend for overlay scenes.
+//!
+//! Draws the same [`Scene`] the Direct2D renderer consumes into an RGBA buffer,
+//! so the overlay can be snapshotted to PNG on any platform. Shapes are
+//! anti-aliased with signed distances; text uses the built-in bitmap font, so
+//! output is deterministic but will not match DirectWrite glyph for glyph.
+
+use crate::{
+    bitmap_font,
+    overlay_layout::{Color, Point, Primitive, Rect, Scene, TextAlignment},
+    png,
+};
+
+/// Sub-samples per axis when filling polygons, so 16 samples per pixel.
+const POLYGON_SUBSAMPLES: u32 = 4;
+
+/// Premultiplied RGBA pixels in linear 0.0..=1.0 floats.
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Straight (non-premultiplied) RGBA8, row-major from the top-left corner.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pixels.len() * 4);
+        for &[r, g, b, a] in &self.pixels {
+            if a <= 0.0 {
+                out.extend_from_slice(&[0, 0, 0, 0]);
+                continue;
+            }
+            out.extend_from_slice(&[to_u8(r / a), to_u8(g / a), to_u8(b / a), to_u8(a)]);
+        }
+        out
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_rgba8(self.width, self.height, &self.to_rgba8())
+    }
+
+    fn clear(&mut self, color: Color) {
+        let premultiplied = [
+            color.r * color.a,
+            color.g * color.a,
+            color.b * color.a,
+            color.a,
+        ];
+        self.pixels.fill(premultiplied);
+    }
+
+    /// Source-over blends `color` into one pixel, scaled by `coverage`.
+    fn blend(&mut self, x: u32, y: u32, color: Color, coverage: f32) {
+        let alpha = color.a * coverage.clamp(0.0, 1.0);
+        if alpha <= 0.0 {
+            return;
+        }
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        let inverse = 1.0 - alpha;
+        pixel[0] = color.r * alpha + pixel[0] * inverse;
+        pixel[1] = color.g * alpha + pixel[1] * inverse;
+        pixel[2] = color.b * alpha + pixel[2] * inverse;
+        pixel[3] = alpha + pixel[3] * inverse;
+    }
+
+    /// Pixel bounds touched by `rect` grown by `margin`, clipped to the canvas.
+    fn pixel_bounds(&self, rect: &Rect, margin: f32) -> (u32, u32, u32, u32) {
+        let clamp_x = |v: f32| (v.max(0.0) as u32).min(self.width);
+        let clamp_y = |v: f32| (v.max(0.0) as u32).min(self.height);
+        (
+            clamp_x((rect.left - margin).floor()),
+            clamp_y((rect.top - margin).floor()),
+            clamp_x((rect.right + margin).ceil()),
+            clamp_y((rect.bottom + margin).ceil()),
+        )
+    }
+
+    pub fn fill_rect(&mut self, rect: &Rect, color: Color) {
+        let (x0, y0, x1, y1) = self.pixel_bounds(rect, 0.0);
+        for y in y0..y1 {
+            let coverage_y = overlap(y as f32, rect.top, rect.bottom);
+            for x in x0..x1 {
+                let coverage = coverage_y * overlap(x as f32, rect.left, rect.right);
+                self.blend(x, y, color, coverage);
+            }
+        }
+    }
+
+    pub fn fill_rounded_rect(&mut self, rect: &Rect, radius: f32, color: Color) {
+        let (x0, y0, x1, y1) = self.pixel_bounds(rect, 1.0);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let distance = rounded_rect_distance(rect, radius, x as f32 + 0.5, y as f32 + 0.5);
+                self.blend(x, y, color, 0.5 - distance);
+            }
+        }
+    }
+
+    /// Strokes the outline of a rounded rect, centered on its edge like Direct2D does.
+    pub fn stroke_rounded_rect(
+        &mut self,
+        rect: &Rect,
+        radius: f32,
+        stroke_width: f32,
+        color: Color,
+    ) {
+        let half_width = stroke_width / 2.0;
+        let (x0, y0, x1, y1) = self.pixel_bounds(rect, half_width + 1.0);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let distance = rounded_rect_distance(rect, radius, x as f32 + 0.5, y as f32 + 0.5);
+                self.blend(x, y, color, 0.5 - (distance.abs() - half_width));
+            }
+        }
+    }
+
+    /// Fills a closed polygon with the non-zero winding rule, anti-aliased by
+    /// sampling a grid of points inside every pixel.
+    pub fn fill_polygon(&mut self, points: &[Point], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        let bounds = points.iter().fold(
+            Rect::new(f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |bounds, point| {
+                Rect::new(
+                    bounds.left.min(point.x),
+                    bounds.top.min(point.y),
+                    bounds.right.max(point.x),
+                    bounds.bottom.max(point.y),
+                )
+            },
+        );
+
+        let (x0, y0, x1, y1) = self.pixel_bounds(&bounds, 0.0);
+        let step = 1.0 / POLYGON_SUBSAMPLES as f32;
+        let samples = (POLYGON_SUBSAMPLES * POLYGON_SUBSAMPLES) as f32;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let mut inside = 0;
+                for sub_y in 0..POLYGON_SUBSAMPLES {
+                    for sub_x in 0..POLYGON_SUBSAMPLES {
+                        let sample = Point::new(
+                            x as f32 + (sub_x as f32 + 0.5) * step,
+                            y as f32 + (sub_y as f32 + 0.5) * step,
+                        );
+                        if winding_number(points, sample) != 0 {
+                            inside += 1;
+                        }
+                    }
+                }
+                self.blend(x, y, color, inside as f32 / samples);
+            }
+        }
+    }
+
+    /// Draws a single line of text, vertically centered inside `rect`.
+    pub fn draw_text(
+        &mut self,
+        rect: &Rect,
+        text: &str,
+        font_size: f32,
+        color: Color,
+        alignment: TextAlignment,
+    ) {
+        let (text_width, text_height) = bitmap_font::measure(text, font_size);
+        let cell = bitmap_font::cell_size(font_size);
+        let origin_x = match alignment {
+            TextAlignment::Leading => rect.left,
+            TextAlignment::Center => rect.left + (rect.width() - text_width) / 2.0,
+        };
+        let origin_y = rect.top + (rect.height() - text_height) / 2.0;
+
+        for (index, c) in text.chars().enumerate() {
+            let glyph_x = origin_x + (index as u32 * bitmap_font::GLYPH_ADVANCE) as f32 * cell;
+            for (row, bits) in bitmap_font::glyph(c).iter().enumerate() {
+                for column in 0..bitmap_font::GLYPH_COLUMNS {
+                    if bits & (1 << (bitmap_font::GLYPH_COLUMNS - 1 - column)) == 0 {
+                        continue;
+                    }
+                    let left = glyph_x + column as f32 * cell;
+                    let top = origin_y + row as f32 * cell;
+                    self.fill_rect(&Rect::new(left, top, left + cell, top + cell), color);
+                }
+            }
+        }
+    }
+}
+
+pub fn render_scene(scene: &Scene) -> Canvas {
+    let mut canvas = Canvas::new(scene.width.ceil() as u32, scene.height.ceil() as u32);
+    canvas.clear(scene.clear_color);
+
+    for primitive in &scene.primitives {
+        match primitive {
+            Primitive::FillRoundedRect {
+                rect,
+                radius,
+                color,
+            } => canvas.fill_rounded_rect(rect, *radius, *color),
+            Primitive::StrokeRoundedRect {
+                rect,
+                radius,
+                stroke_width,
+                color,
+            } => canvas.stroke_rounded_rect(rect, *radius, *stroke_width, *color),
+            Primitive::FillRect { rect, color } => canvas.fill_rect(rect, *color),
+            Primitive::FillPolygon { points, color } => canvas.fill_polygon(points, *color),
+            Primitive::Text {
+                rect,
+                text,
+                font_size,
+                color,
+                alignment,
+            } => canvas.draw_text(rect, text, *font_size, *color, *alignment),
+        }
+    }
+
+    canvas
+}
+
+/// Fraction of the pixel span `[pixel, pixel + 1)` covered by `[start, end)`.
+fn overlap(pixel: f32, start: f32, end: f32) -> f32 {
+    (end.min(pixel + 1.0) - start.max(pixel)).clamp(0.0, 1.0)
+}
+
+/// Winding number of a closed polygon around `point`; zero means outside.
+fn winding_number(points: &[Point], point: Point) -> i32 {
+    let mut winding = 0;
+    for (index, start) in points.iter().enumerate() {
+        let end = points[(index + 1) % points.len()];
+        let side =
+            (end.x - start.x) * (point.y - start.y) - (point.x - start.x) * (end.y - start.y);
+        if start.y <= point.y {
+            if end.y > point.y && side > 0.0 {
+                winding += 1;
+            }
+        } else if end.y <= point.y && side < 0.0 {
+            winding -= 1;
+        }
+    }
+    winding
+}
+
+/// Signed distance from a point to a rounded rect; negative inside.
+fn rounded_rect_distance(rect: &Rect, radius: f32, x: f32, y: f32) -> f32 {
+    let half_width = rect.width() / 2.0;
+    let half_height = rect.height() / 2.0;
+    let radius = radius.min(half_width).min(half_height).max(0.0);
+    let center_x = rect.left + half_width;
+    let center_y = rect.top + half_height;
+
+    let qx = (x - center_x).abs() - (half_width - radius);
+    let qy = (y - center_y).abs() - (half_height - radius);
+    let outside = qx.max(0.0).hypot(qy.max(0.0));
+    let inside = qx.max(qy).min(0.0);
+    outside + inside - radius
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dualsense::{
+            BatteryReport, BatteryStatus, ConnectionType, ControllerInfo, ControllerModel,
+        },
+        overlay_layout::{ControllerRow, OverlayLayout, RowAnimation},
+    };
+
+    const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+
+    /// One character per pixel by coverage: empty, faint, partial or solid.
+    /// Rows are fenced with `|` so their spaces survive in the source.
+    fn alpha_art(canvas: &Canvas) -> String {
+        canvas
+            .pixels
+            .chunks(canvas.width as usize)
+            .map(|row| {
+                let line: String = row
+                    .iter()
+                    .map(|pixel| match pixel[3] {
+                        a if a <= 0.0 => ' ',
+                        a if a < 0.34 => '.',
+                        a if a < 0.99 => '+',
+                        _ => '#',
+                    })
+                    .collect();
+                format!("|{}|", line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The frame as it would look over black, averaged over `block`-sized
+    /// squares, darkest to brightest.
+    fn brightness_art(canvas: &Canvas, block: u32) -> String {
+        const RAMP: &[u8] = b" .:-=+*#%@";
+        let mut lines = Vec::new();
+        for block_y in (0..canvas.height).step_by(block as usize) {
+            let mut line = String::new();
+            for block_x in (0..canvas.width).step_by(block as usize) {
+                let mut sum = 0.0;
+                let mut count = 0.0;
+                for y in block_y..(block_y + block).min(canvas.height) {
+                    for x in block_x..(block_x + block).min(canvas.width) {
+                        let [r, g, b, _] = canvas.pixels[(y * canvas.width + x) as usize];
+                        sum += 0.299 * r + 0.587 * g + 0.114 * b;
+                        count += 1.0;
+                    }
+                }
+                let level = ((sum / count) * (RAMP.len() - 1) as f32).round() as usize;
+                line.push(RAMP[level.min(RAMP.len() - 1)] as char);
+            }
+            lines.push(format!("|{}|", line));
+        }
+        lines.join("\n")
+    }
+
+    fn assert_art(actual: String, expected: &str) {
+        let expected = expected
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(
+            actual == expected,
+            "image doesn't match its golden\nexpected:\n{}\nactual:\n{}",
+            expected,
+            actual
+        );
+    }
+
+    fn row(player_number: u8, report: BatteryReport) -> ControllerRow {
+        ControllerRow {
+            player_number,
+            info: ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Usb,
+                serial: None,
+                firmware: None,
+            },
+            name: None,
+            report: Some(report),
+            estimate: None,
+            connected: true,
+            highlighted: player_number == 1,
+            animation: RowAnimation::default(),
+        }
+    }
+
+    #[test]
+    fn fill_rect_covers_partial_pixels() {
+        let mut canvas = Canvas::new(6, 4);
+        canvas.fill_rect(&Rect::new(1.5, 1.0, 4.0, 3.0), WHITE);
+        assert_art(
+            alpha_art(&canvas),
+            "
+            |      |
+            | +##  |
+            | +##  |
+            |      |
+            ",
+        );
+    }
+
+    #[test]
+    fn rounded_rect_golden() {
+        let mut canvas = Canvas::new(12, 8);
+        canvas.fill_rounded_rect(&Rect::new(1.0, 1.0, 11.0, 7.0), 3.0, WHITE);
+        assert_art(
+            alpha_art(&canvas),
+            "
+            |            |
+            |  ++####++  |
+            | +########+ |
+            | +########+ |
+            | +########+ |
+            | +########+ |
+            |  ++####++  |
+            |            |
+            ",
+        );
+    }
+
+    #[test]
+    fn stroke_golden() {
+        let mut canvas = Canvas::new(12, 8);
+        canvas.stroke_rounded_rect(&Rect::new(2.0, 2.0, 10.0, 6.0), 1.0, 1.0, WHITE);
+        assert_art(
+            alpha_art(&canvas),
+            "
+            |            |
+            |  ++++++++  |
+            | ++++++++++ |
+            | ++      ++ |
+            | ++      ++ |
+            | ++++++++++ |
+            |  ++++++++  |
+            |            |
+            ",
+        );
+    }
+
+    #[test]
+    fn polygon_golden() {
+        let mut canvas = Canvas::new(10, 8);
+        let triangle = [
+            Point::new(1.0, 7.0),
+            Point::new(5.0, 1.0),
+            Point::new(9.0, 7.0),
+        ];
+        canvas.fill_polygon(&triangle, WHITE);
+        assert_art(
+            alpha_art(&canvas),
+            "
+            |          |
+            |    ..    |
+            |   .++.   |
+            |   +##+   |
+            |  .####.  |
+            | .+####+. |
+            | +######+ |
+            |          |
+            ",
+        );
+    }
+
+    #[test]
+    fn text_draws_font_cells() {
+        // A 10px font has 1px cells, so the glyph comes out bit for bit.
+        let mut canvas = Canvas::new(7, 9);
+        canvas.draw_text(
+            &Rect::new(1.0, 0.0, 7.0, 9.0),
+            "4",
+            10.0,
+            WHITE,
+            TextAlignment::Leading,
+        );
+        assert_art(
+            alpha_art(&canvas),
+            "
+            |       |
+            |    #  |
+            |   ##  |
+            |  # #  |
+            | #  #  |
+            | ##### |
+            |    #  |
+            |    #  |
+            |       |
+            ",
+        );
+    }
+
+    #[test]
+    fn rgba8_is_straight_alpha() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.clear(Color::new(1.0, 0.5, 0.0, 0.5));
+        canvas.blend(1, 0, Color::TRANSPARENT, 1.0);
+        assert_eq!(canvas.to_rgba8(), vec![255, 128, 0, 128, 255, 128, 0, 128]);
+    }
+
+    #[test]
+    fn single_controller_card_golden() {
+        let rows = [row(1, BatteryReport::new(65, BatteryStatus::Charging))];
+        let scene = OverlayLayout::for_rows(rows.len(), 1.0).build_scene(&rows);
+        let canvas = render_scene(&scene);
+        assert_eq!((canvas.width(), canvas.height()), (200, 150));
+        assert_art(
+            brightness_art(&canvas, 8),
+            "
+            |                         |
+            |                         |
+            |     .=============.     |
+            |     =++++++++-....=     |
+            |     =++++++*=:    =     |
+            |     =++++++#=:    #=    |
+            |     =+++++%@#:    #=    |
+            |     =+++++=#=:    #=    |
+            |     =+++++++=:    =     |
+            |     =++++++=+:    =     |
+            |     :=============:     |
+            |                         |
+            |                         |
+            | ..::      :.     .    . |
+            | -:::. .:  ::::--:=:.--= |
+            | :.:.:     ::..:. :....: |
+            |                         |
+            |                         |
+            |                         |
+            ",
+        );
+    }
+
+    #[test]
+    fn controller_list_golden() {
+        let rows = [
+            row(1, BatteryReport::new(85, BatteryStatus::Discharging)),
+            row(2, BatteryReport::new(15, BatteryStatus::Discharging)),
+        ];
+        let scene = OverlayLayout::for_rows(rows.len(), 1.0).build_scene(&rows);
+        let canvas = render_scene(&scene);
+        assert_eq!((canvas.width(), canvas.height()), (320, 122));
+        assert_art(
+            brightness_art(&canvas, 8),
+            "
+            |                                        |
+            |  ....................................  |
+            | :....:------:..=--:................... |
+            | ::::.-+++++:=..::::................... |
+            | :::-.-+++++:*......................... |
+            | :....-+++++=-..::::::::.::::.......... |
+            | ...............:::::.:...::........... |
+            |                                        |
+            |                 ..                     |
+            |      :=-----:  :::.                    |
+            |  .::.--     +  .. .                    |
+            |  . : --     +                          |
+            |      :=-----:  :..::.:: .::.           |
+            |                                        |
+            |                                        |
+            |                                        |
+            ",
+        );
+    }
+
+    #[test]
+    fn scaled_card_golden() {
+        // Twice the pixels, but the same picture at twice the block size.
+        let rows = [row(1, BatteryReport::new(65, BatteryStatus::Charging))];
+        let scene = OverlayLayout::for_rows(rows.len(), 2.0).build_scene(&rows);
+        let canvas = render_scene(&scene);
+        assert_eq!((canvas.width(), canvas.height()), (400, 300));
+        assert_art(
+            brightness_art(&canvas, 16),
+            "
+            |                         |
+            |                         |
+            |     .=============.     |
+            |     =++++++++-....=     |
+            |     =++++++*=:    =     |
+            |     =++++++#=:    #=    |
+            |     =+++++%@#:    #=    |
+            |     =+++++=#=:    #=    |
+            |     =+++++++=:    =     |
+            |     =++++++=+:   .=     |
+            |     :=+++++++++++=:     |
+            |                         |
+            |                         |
+            | ..::      :.     .    . |
+            | -:::. .:  ::-:--:=:.--= |
+            | :.:.:     ::..:. :....: |
+            |                         |
+            |                         |
+            |                         |
+            ",
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! A small modal window that asks for one line of text.
+//!
+//! Win32 has no ready-made input box, and a dialog template would need a
+//! resource file, so this builds the window from stock controls by hand.
+
+use windows::{
+    Win32::{
+        Foundation::{
+            ERROR_CLASS_ALREADY_EXISTS, GetLastError, HINSTANCE, HWND, LPARAM, LRESULT, WPARAM,
+        },
+        Graphics::Gdi::{COLOR_BTNFACE, DEFAULT_GUI_FONT, GetStockObject, HBRUSH},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                BS_DEFPUSHBUTTON, BS_PUSHBUTTON, CreateWindowExW, DefWindowProcW, DestroyWindow,
+                DispatchMessageW, ES_AUTOHSCROLL, GWLP_USERDATA, GetMessageW, GetSystemMetrics,
+                GetWindowLongPtrW, GetWindowTextLengthW, GetWindowTextW, HMENU, IDC_ARROW,
+                IDCANCEL, IDOK, IsDialogMessageW, LoadCursorW, MSG, PostQuitMessage,
+                RegisterClassExW, SM_CXSCREEN, SM_CYSCREEN, SW_SHOW, SendMessageW,
+                SetForegroundWindow, SetWindowLongPtrW, ShowWindow, TranslateMessage, WINDOW_STYLE,
+                WM_CLOSE, WM_COMMAND, WM_DESTROY, WM_SETFONT, WNDCLASSEXW, WS_BORDER, WS_CAPTION,
+                WS_CHILD, WS_EX_DLGMODALFRAME, WS_EX_TOPMOST, WS_POPUP, WS_SYSMENU, WS_TABSTOP,
+                WS_VISIBLE,
+            },
+        },
+    },
+    core::{HSTRING, PCWSTR, w},
+};
+
+const PROMPT_WINDOW_CLASS_NAME: PCWSTR = w!("ds_battery_text_prompt");
+const PROMPT_WIDTH: i32 = 340;
+const PROMPT_HEIGHT: i32 = 150;
+const EDIT_CONTROL_ID: usize = 100;
+/// `EM_SETSEL`, from the common controls headers.
+const EM_SETSEL: u32 = 0x00B1;
+
+struct PromptState {
+    edit: HWND,
+    result: Option<String>,
+    finished: bool,
+}
+
+/// Shows the prompt and blocks until it is closed. Returns the entered text, or
+/// `None` if the user cancelled.
+pub fn prompt_for_text(title: &str, label: &str, initial: &str) -> Option<String> {
+    let hinstance: HINSTANCE = unsafe { GetModuleHandleW(None).ok()?.into() };
+    if let Err(e) = register_prompt_class(hinstance) {
+        eprintln!("Failed to register prompt window class: {}", e);
+        return None;
+    }
+
+    let x = (unsafe { GetSystemMetrics(SM_CXSCREEN) } - PROMPT_WIDTH) / 2;
+    let y = (unsafe { GetSystemMetrics(SM_CYSCREEN) } - PROMPT_HEIGHT) / 2;
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WS_EX_DLGMODALFRAME | WS_EX_TOPMOST,
+            PROMPT_WINDOW_CLASS_NAME,
+            &HSTRING::from(title),
+            WS_POPUP | WS_CAPTION | WS_SYSMENU,
+            x,
+            y,
+            PROMPT_WIDTH,
+            PROMPT_HEIGHT,
+            None,
+            None,
+            Some(hinstance),
+            None,
+        )
+    };
+    let hwnd = match hwnd {
+        Ok(hwnd) => hwnd,
+        Err(e) => {
+            eprintln!("Failed to create prompt window: {}", e);
+            return None;
+        }
+    };
+
+    let child = |class: PCWSTR, text: &str, style: WINDOW_STYLE, rect: (i32, i32, i32, i32), id| unsafe {
+        CreateWindowExW(
+            Default::default(),
+            class,
+            &HSTRING::from(text),
+            WS_CHILD | WS_VISIBLE | style,
+            rect.0,
+            rect.1,
+            rect.2,
+            rect.3,
+            Some(hwnd),
+            Some(HMENU(id as *mut _)),
+            Some(hinstance),
+            None,
+        )
+        .ok()
+    };
+    let controls = [
+        child(w!("STATIC"), label, WINDOW_STYLE(0), (12, 12, 300, 20), 0),
+        child(
+            w!("EDIT"),
+            initial,
+            WS_BORDER | WS_TABSTOP | WINDOW_STYLE(ES_AUTOHSCROLL as u32),
+            (12, 36, 300, 24),
+            EDIT_CONTROL_ID,
+        ),
+        child(
+            w!("BUTTON"),
+            "OK",
+            WS_TABSTOP | WINDOW_STYLE(BS_DEFPUSHBUTTON as u32),
+            (146, 72, 80, 26),
+            IDOK.0 as usize,
+        ),
+        child(
+            w!("BUTTON"),
+            "Cancel",
+            WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32),
+            (232, 72, 80, 26),
+            IDCANCEL.0 as usize,
+        ),
+    ];
+    let Some(edit) = controls[1] else {
+        eprintln!("Failed to create prompt edit control");
+        let _ = unsafe { DestroyWindow(hwnd) };
+        return None;
+    };
+
+    let font = unsafe { GetStockObject(DEFAULT_GUI_FONT) };
+    for control in controls.into_iter().flatten() {
+        unsafe {
+            SendMessageW(
+                control,
+                WM_SETFONT,
+                Some(WPARAM(font.0 as usize)),
+                Some(LPARAM(1)),
+            )
+        };
+    }
+
+    // The window procedure writes to this while our loop below reads it, so it
+    // is only touched through the raw pointer until the window is gone.
+    let state = Box::into_raw(Box::new(PromptState {
+        edit,
+        result: None,
+        finished: false,
+    }));
+    unsafe {
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, state as isize);
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let _ = SetForegroundWindow(hwnd);
+        let _ = SetFocus(Some(edit));
+        SendMessageW(edit, EM_SETSEL, Some(WPARAM(0)), Some(LPARAM(-1)));
+    }
+
+    // Our own loop until the prompt closes; other windows keep getting their messages.
+    let mut msg = MSG::default();
+    loop {
+        if unsafe { (*state).finished } {
+            break;
+        }
+        match unsafe { GetMessageW(&mut msg, None, 0, 0) }.0 {
+            0 => {
+                // Leave WM_QUIT for the main loop.
+                unsafe { PostQuitMessage(msg.wParam.0 as i32) };
+                let _ = unsafe { DestroyWindow(hwnd) };
+                break;
+            }
+            -1 => break,
+            _ => unsafe {
+                if !IsDialogMessageW(hwnd, &msg).as_bool() {
+                    let _ = TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            },
+        }
+    }
+    unsafe { Box::from_raw(state) }.result
+}
+
+fn register_prompt_class(hinstance: HINSTANCE) -> windows::core::Result<()> {
+    let wc = WNDCLASSEXW {
+        cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+        hInstance: hinstance,
+        hCursor: unsafe { LoadCursorW(None, IDC_ARROW)? },
+        // System colour brushes are the colour index plus one.
+        hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as *mut _),
+        lpszClassName: PROMPT_WINDOW_CLASS_NAME,
+        lpfnWndProc: Some(prompt_wndproc),
+        ..Default::default()
+    };
+    if unsafe { RegisterClassExW(&wc) } == 0 {
+        let error = unsafe { GetLastError() };
+        if error != ERROR_CLASS_ALREADY_EXISTS {
+            return Err(windows::core::Error::from(error));
+        }
+    }
+    Ok(())
+}
+
+extern "system" fn prompt_wndproc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let state = unsafe { (GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut PromptState).as_mut() };
+    let Some(state) = state else {
+        return unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) };
+    };
+
+    match msg {
+        WM_COMMAND => {
+            let id = (wparam.0 & 0xFFFF) as i32;
+            if id == IDOK.0 {
+                let mut text = vec![0u16; unsafe { GetWindowTextLengthW(state.edit) } as usize + 1];
+                let len = unsafe { GetWindowTextW(state.edit, &mut text) } as usize;
+                state.result = Some(String::from_utf16_lossy(&text[..len]));
+                let _ = unsafe { DestroyWindow(hwnd) };
+            } else if id == IDCANCEL.0 {
+                let _ = unsafe { DestroyWindow(hwnd) };
+            }
+            LRESULT(0)
+        }
+        WM_CLOSE => {
+            let _ = unsafe { DestroyWindow(hwnd) };
+            LRESULT(0)
+        }
+        WM_DESTROY => {
+            state.finished = true;
+            unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0) };
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+    }
+}
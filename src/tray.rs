@@ -2,7 +2,9 @@ use std::os::windows::ffi::OsStrExt;
 use std::{ffi::OsStr, slice};
 
 use windows::Win32::System::Registry::RegDeleteValueW;
-use windows::Win32::UI::WindowsAndMessaging::{MF_CHECKED, MF_UNCHECKED};
+use windows::Win32::UI::WindowsAndMessaging::{
+    MF_CHECKED, MF_GRAYED, MF_POPUP, MF_SEPARATOR, MF_UNCHECKED,
+};
 use windows::{
     Win32::{
         Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS, GetLastError, HWND, POINT},
@@ -12,19 +14,26 @@ use windows::{
         },
         UI::{
             Shell::{
-                NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_SETVERSION,
-                NOTIFYICONDATAW, Shell_NotifyIconW,
+                NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_INFO, NIIF_WARNING, NIM_ADD,
+                NIM_DELETE, NIM_MODIFY, NIM_SETVERSION, NOTIFYICONDATAW, Shell_NotifyIconW,
             },
             WindowsAndMessaging::{
-                AppendMenuW, CreatePopupMenu, DestroyMenu, GetCursorPos, HICON, HMENU, MF_STRING,
-                SetForegroundWindow, TPM_LEFTALIGN, TPM_RIGHTBUTTON, TrackPopupMenu,
+                AppendMenuW, CreateIconFromResourceEx, CreatePopupMenu, DestroyIcon, DestroyMenu,
+                GetCursorPos, GetSystemMetrics, HICON, HMENU, LR_DEFAULTCOLOR, MF_STRING,
+                SM_CXSMICON, SetForegroundWindow, TPM_LEFTALIGN, TPM_RIGHTBUTTON, TrackPopupMenu,
             },
         },
     },
-    core::{HSTRING, PCWSTR, w},
+    core::{HSTRING, PCWSTR},
 };
 
-use crate::{APP_REGISTRY_KEY_NAME, IDM_CONFIGURE, IDM_EXIT, IDM_RUN_ON_STARTUP};
+use crate::{
+    APP_REGISTRY_KEY_NAME, AppState,
+    notifications::{Notification, NotificationKind, Notifier, Urgency},
+    tray_icon,
+    tray_menu::{MenuEntry, TrayMenu},
+    tray_tooltip,
+};
 
 const TRAY_ICON_ID: u32 = 1;
 const TRAY_TOOLTIP: &str = "DualSense Battery Overlay";
@@ -34,6 +43,9 @@ const RUN_KEY_PATH: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
 const REG_QUERY_SUCCESS: u32 = ERROR_SUCCESS.0 as u32;
 const REG_QUERY_ERROR: u32 = ERROR_FILE_NOT_FOUND.0 as u32;
 
+/// Version number `CreateIconFromResourceEx` expects for icon resources.
+const ICON_RESOURCE_VERSION: u32 = 0x0003_0000;
+
 pub fn add_tray_icon(
     hwnd: HWND,
     h_icon: HICON,
@@ -49,12 +61,7 @@ pub fn add_tray_icon(
         szTip: [0; 128],
         ..Default::default()
     };
-
-    let mut wide_chars = TRAY_TOOLTIP.encode_utf16().collect::<Vec<_>>();
-    wide_chars.push(0);
-    let len_to_copy = std::cmp::min(wide_chars.len() - 1, nid.szTip.len() - 1);
-    nid.szTip[..len_to_copy].copy_from_slice(&wide_chars[..len_to_copy]);
-    nid.szTip[len_to_copy] = 0; // Null terminate
+    copy_wide_text(&mut nid.szTip, TRAY_TOOLTIP);
 
     if !unsafe { Shell_NotifyIconW(NIM_ADD, &nid).as_bool() } {
         return Err(windows::core::Error::from_win32());
@@ -69,6 +76,152 @@ pub fn add_tray_icon(
     Ok(())
 }
 
+pub fn set_tray_icon(hwnd: HWND, h_icon: HICON) -> Result<(), windows::core::Error> {
+    let nid = NOTIFYICONDATAW {
+        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: hwnd,
+        uID: TRAY_ICON_ID,
+        uFlags: NIF_ICON,
+        hIcon: h_icon,
+        ..Default::default()
+    };
+
+    if !unsafe { Shell_NotifyIconW(NIM_MODIFY, &nid).as_bool() } {
+        return Err(windows::core::Error::from_win32());
+    }
+    Ok(())
+}
+
+pub fn set_tray_tooltip(hwnd: HWND, tooltip: &str) -> Result<(), windows::core::Error> {
+    let mut nid = NOTIFYICONDATAW {
+        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: hwnd,
+        uID: TRAY_ICON_ID,
+        uFlags: NIF_TIP,
+        ..Default::default()
+    };
+    copy_wide_text(&mut nid.szTip, tooltip);
+
+    if !unsafe { Shell_NotifyIconW(NIM_MODIFY, &nid).as_bool() } {
+        return Err(windows::core::Error::from_win32());
+    }
+    Ok(())
+}
+
+/// Copies `text` into a fixed `NOTIFYICONDATAW` buffer, truncating it and null
+/// terminating.
+fn copy_wide_text(buffer: &mut [u16], text: &str) {
+    let text = tray_tooltip::truncate_utf16(text, buffer.len() - 1);
+    let wide_chars = text.encode_utf16().collect::<Vec<_>>();
+    buffer[..wide_chars.len()].copy_from_slice(&wide_chars);
+    buffer[wide_chars.len()] = 0; // Null terminate
+}
+
+/// Shows notifications as balloons from the tray icon, which Windows 10 and
+/// later turn into toast notifications.
+pub struct TrayBalloonNotifier {
+    hwnd: HWND,
+}
+
+impl TrayBalloonNotifier {
+    pub fn new(hwnd: HWND) -> Self {
+        Self { hwnd }
+    }
+}
+
+impl Notifier for TrayBalloonNotifier {
+    fn name(&self) -> &str {
+        "tray balloon"
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), String> {
+        // Pads disconnect whenever they go to sleep; a balloon each time is noise.
+        if notification.kind == NotificationKind::Disconnected {
+            return Ok(());
+        }
+        let mut nid = NOTIFYICONDATAW {
+            cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+            hWnd: self.hwnd,
+            uID: TRAY_ICON_ID,
+            uFlags: NIF_INFO,
+            dwInfoFlags: match notification.urgency {
+                Urgency::Info => NIIF_INFO,
+                Urgency::Warning => NIIF_WARNING,
+            },
+            ..Default::default()
+        };
+        copy_wide_text(&mut nid.szInfoTitle, &notification.title);
+        copy_wide_text(&mut nid.szInfo, &notification.message);
+
+        if !unsafe { Shell_NotifyIconW(NIM_MODIFY, &nid).as_bool() } {
+            return Err(format!("{:?}", windows::core::Error::from_win32()));
+        }
+        Ok(())
+    }
+}
+
+/// Rebuilds the tooltip from the current controllers, skipping the shell call
+/// when the text hasn't changed.
+pub fn refresh_tooltip(app_state: &mut AppState) {
+    let tooltip = tray_tooltip::tooltip_text(&app_state.controllers);
+    if tooltip == app_state.tray_tooltip {
+        return;
+    }
+    match set_tray_tooltip(app_state.hwnd, &tooltip) {
+        Ok(()) => app_state.tray_tooltip = tooltip,
+        Err(e) => eprintln!("Failed to update tray tooltip: {:?}", e),
+    }
+}
+
+/// Redraws the tray icon for the current battery state. Does nothing when the
+/// icon would look the same as the one already shown.
+pub fn refresh_battery_icon(app_state: &mut AppState) {
+    let visual = tray_icon::tray_visual(
+        &app_state.controllers,
+        app_state.triggering_controller_path.as_deref(),
+    );
+    if app_state.battery_icon_visual.as_ref() == Some(&visual) {
+        return;
+    }
+
+    let size = unsafe { GetSystemMetrics(SM_CXSMICON) }.max(16) as u32;
+    let dib = tray_icon::encode_icon_dib(size, &tray_icon::render_rgba(&visual, size));
+    let h_icon = match unsafe {
+        CreateIconFromResourceEx(
+            &dib,
+            true,
+            ICON_RESOURCE_VERSION,
+            size as i32,
+            size as i32,
+            LR_DEFAULTCOLOR,
+        )
+    } {
+        Ok(h_icon) => h_icon,
+        Err(e) => {
+            eprintln!("Failed to create battery tray icon: {:?}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = set_tray_icon(app_state.hwnd, h_icon) {
+        eprintln!("Failed to update tray icon: {:?}", e);
+        let _ = unsafe { DestroyIcon(h_icon) };
+        return;
+    }
+    if let Some(old_icon) = app_state.battery_icon.replace(h_icon) {
+        let _ = unsafe { DestroyIcon(old_icon) };
+    }
+    app_state.battery_icon_visual = Some(visual);
+}
+
+/// Frees the generated battery icon, if one was ever shown.
+pub fn destroy_battery_icon(app_state: &mut AppState) {
+    if let Some(h_icon) = app_state.battery_icon.take() {
+        let _ = unsafe { DestroyIcon(h_icon) };
+    }
+    app_state.battery_icon_visual = None;
+}
+
 pub fn remove_tray_icon(hwnd: HWND) -> Result<(), ()> {
     let nid = NOTIFYICONDATAW {
         cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
@@ -85,7 +238,15 @@ pub fn remove_tray_icon(hwnd: HWND) -> Result<(), ()> {
     Ok(())
 }
 
-pub fn show_context_menu(hwnd: HWND) -> Result<(), windows::core::Error> {
+pub fn show_context_menu(app_state: &mut AppState) -> Result<(), windows::core::Error> {
+    let hwnd = app_state.hwnd;
+    let is_startup_enabled = is_run_on_startup_enabled().unwrap_or(false);
+    app_state.tray_menu = TrayMenu::build(
+        &app_state.controllers,
+        &app_state.config,
+        is_startup_enabled,
+    );
+
     unsafe {
         let hmenu = CreatePopupMenu().unwrap();
 
@@ -99,22 +260,7 @@ pub fn show_context_menu(hwnd: HWND) -> Result<(), windows::core::Error> {
         }
         let _guard = MenuGuard(hmenu);
 
-        let is_startup_enabled = is_run_on_startup_enabled().unwrap_or(false);
-        let startup_flags = if is_startup_enabled {
-            MF_STRING | MF_CHECKED
-        } else {
-            MF_STRING | MF_UNCHECKED
-        };
-
-        AppendMenuW(hmenu, MF_STRING, IDM_CONFIGURE as usize, w!("Configure")).unwrap();
-        AppendMenuW(
-            hmenu,
-            startup_flags,
-            IDM_RUN_ON_STARTUP as usize,
-            w!("Run on Startup"),
-        )
-        .unwrap();
-        AppendMenuW(hmenu, MF_STRING, IDM_EXIT as usize, w!("Exit")).unwrap();
+        append_menu_entries(hmenu, app_state.tray_menu.entries())?;
 
         let mut point = POINT::default();
         GetCursorPos(&mut point).unwrap();
@@ -144,6 +290,48 @@ pub fn show_context_menu(hwnd: HWND) -> Result<(), windows::core::Error> {
     Ok(())
 }
 
+/// Adds `entries` to `hmenu`, creating popup menus for submenus. Submenus are
+/// owned by their parent and destroyed along with it.
+unsafe fn append_menu_entries(
+    hmenu: HMENU,
+    entries: &[MenuEntry],
+) -> Result<(), windows::core::Error> {
+    for entry in entries {
+        unsafe {
+            match entry {
+                MenuEntry::Command { id, label, checked } => {
+                    let flags = if *checked {
+                        MF_STRING | MF_CHECKED
+                    } else {
+                        MF_STRING | MF_UNCHECKED
+                    };
+                    AppendMenuW(hmenu, flags, *id as usize, &menu_label(label))?;
+                }
+                MenuEntry::Info(label) => {
+                    AppendMenuW(hmenu, MF_STRING | MF_GRAYED, 0, &menu_label(label))?;
+                }
+                MenuEntry::Submenu { label, entries } => {
+                    let submenu = CreatePopupMenu()?;
+                    if let Err(e) = append_menu_entries(submenu, entries) {
+                        let _ = DestroyMenu(submenu);
+                        return Err(e);
+                    }
+                    AppendMenuW(hmenu, MF_POPUP, submenu.0 as usize, &menu_label(label))?;
+                }
+                MenuEntry::Separator => {
+                    AppendMenuW(hmenu, MF_SEPARATOR, 0, PCWSTR::null())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Menus treat `&` as a mnemonic marker, so user-supplied names need escaping.
+fn menu_label(label: &str) -> HSTRING {
+    HSTRING::from(label.replace('&', "&&"))
+}
+
 pub fn is_run_on_startup_enabled() -> Result<bool, windows::core::Error> {
     unsafe {
         let mut hkey = HKEY::default();
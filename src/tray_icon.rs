@@ -0,0 +1,311 @@
+//! Pixels for the battery tray icon.
+//!
+//! The icon is drawn with the same battery shape as the overlay and rendered on
+//! the CPU, so the Windows side only has to turn the bytes into an `HICON`.
+
+use crate::{
+    controllers::{ControllerEntry, ControllerRegistry},
+    overlay_layout::{self, BatteryVisual, Color, Rect, Scene},
+    software_renderer,
+};
+
+const BITMAPINFOHEADER_SIZE: u32 = 40;
+const ICONDIR_SIZE: u32 = 6;
+const ICONDIRENTRY_SIZE: u32 = 16;
+
+/// Picks the controller the icon should show: the one last used to bring up the
+/// overlay, or else the one with the least charge left.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn tray_controller<'a>(
+    controllers: &'a ControllerRegistry,
+    recent_path: Option<&str>,
+) -> Option<&'a ControllerEntry> {
+    let candidates = || {
+        controllers
+            .iter()
+            .filter(|entry| entry.connected && entry.battery.is_some())
+    };
+    candidates()
+        .find(|entry| Some(entry.path.as_str()) == recent_path)
+        .or_else(|| {
+            candidates().min_by_key(|entry| {
+                entry
+                    .battery
+                    .as_ref()
+                    .map_or(u8::MAX, |report| report.battery_capacity)
+            })
+        })
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn tray_visual(controllers: &ControllerRegistry, recent_path: Option<&str>) -> BatteryVisual {
+    match tray_controller(controllers, recent_path).and_then(|entry| entry.battery.as_ref()) {
+        Some(report) => BatteryVisual::for_report(report),
+        None => BatteryVisual {
+            dimmed: true,
+            ..BatteryVisual::message("No controller")
+        },
+    }
+}
+
+/// A horizontal battery filling a `size` x `size` square.
+pub fn icon_scene(visual: &BatteryVisual, size: u32) -> Scene {
+    let size = size as f32;
+    let unit = size / 16.0;
+    let body_height = size * 0.625;
+    let top = (size - body_height) / 2.0;
+    let body_rect = Rect::new(unit * 0.75, top, size * 0.86, top + body_height);
+
+    let mut primitives = Vec::new();
+    overlay_layout::push_battery_icon(&mut primitives, body_rect, unit * 1.5, unit * 2.0, visual);
+    Scene {
+        width: size,
+        height: size,
+        clear_color: Color::TRANSPARENT,
+        primitives,
+    }
+}
+
+/// Straight RGBA8 pixels of the icon, row-major from the top-left corner.
+pub fn render_rgba(visual: &BatteryVisual, size: u32) -> Vec<u8> {
+    software_renderer::render_scene(&icon_scene(visual, size)).to_rgba8()
+}
+
+/// The icon image as it appears inside an .ico file or an icon resource: a
+/// 32-bit bottom-up DIB followed by the 1-bit transparency mask.
+pub fn encode_icon_dib(size: u32, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgba.len(),
+        size as usize * size as usize * 4,
+        "RGBA buffer does not match icon size"
+    );
+    let row_len = size as usize * 4;
+    let mask_row_len = (size as usize).div_ceil(32) * 4;
+    let image_size = (row_len + mask_row_len) * size as usize;
+
+    let mut dib = Vec::with_capacity(BITMAPINFOHEADER_SIZE as usize + image_size);
+    dib.extend_from_slice(&BITMAPINFOHEADER_SIZE.to_le_bytes());
+    dib.extend_from_slice(&(size as i32).to_le_bytes());
+    // Icon DIBs count the mask rows in the height.
+    dib.extend_from_slice(&(size as i32 * 2).to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes()); // planes
+    dib.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+    dib.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+    dib.extend_from_slice(&(image_size as u32).to_le_bytes());
+    dib.extend_from_slice(&[0; 16]); // resolution and palette fields
+
+    for row in rgba.chunks(row_len).rev() {
+        for pixel in row.chunks(4) {
+            dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+    // Alpha does the real work; the mask only matters to very old consumers.
+    for row in rgba.chunks(row_len).rev() {
+        let mut mask = vec![0u8; mask_row_len];
+        for (x, pixel) in row.chunks(4).enumerate() {
+            if pixel[3] == 0 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        dib.extend_from_slice(&mask);
+    }
+    dib
+}
+
+/// A single-image .ico file.
+pub fn encode_ico(size: u32, rgba: &[u8]) -> Vec<u8> {
+    let dib = encode_icon_dib(size, rgba);
+    // A zero dimension in the directory means 256 pixels.
+    let dimension = if size >= 256 { 0 } else { size as u8 };
+
+    let mut ico = Vec::with_capacity((ICONDIR_SIZE + ICONDIRENTRY_SIZE) as usize + dib.len());
+    ico.extend_from_slice(&0u16.to_le_bytes()); // reserved
+    ico.extend_from_slice(&1u16.to_le_bytes()); // type: icon
+    ico.extend_from_slice(&1u16.to_le_bytes()); // image count
+    ico.extend_from_slice(&[dimension, dimension, 0, 0]);
+    ico.extend_from_slice(&1u16.to_le_bytes()); // planes
+    ico.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+    ico.extend_from_slice(&(dib.len() as u32).to_le_bytes());
+    ico.extend_from_slice(&(ICONDIR_SIZE + ICONDIRENTRY_SIZE).to_le_bytes());
+    ico.extend_from_slice(&dib);
+    ico
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dualsense::{
+            BatteryReport, BatteryStatus, ConnectionType, ControllerInfo, ControllerModel,
+        },
+        overlay_layout::{Primitive, StatusGlyph, fill_color},
+    };
+
+    const SIZE: u32 = 32;
+
+    fn visual(capacity: u8, status: BatteryStatus) -> BatteryVisual {
+        BatteryVisual::for_report(&BatteryReport::new(capacity, status))
+    }
+
+    fn rgb8(color: Color) -> [u8; 3] {
+        [color.r, color.g, color.b].map(|channel| (channel * 255.0).round() as u8)
+    }
+
+    /// Pixels in the icon's middle row drawn in exactly `color`.
+    fn count_in_middle_row(rgba: &[u8], color: Color) -> usize {
+        let row_len = SIZE as usize * 4;
+        let middle = &rgba[row_len * (SIZE as usize / 2)..][..row_len];
+        middle
+            .chunks(4)
+            .filter(|pixel| pixel[..3] == rgb8(color) && pixel[3] == 255)
+            .count()
+    }
+
+    /// The battery fill, the first rectangle in the scene, if any.
+    fn fill_rect(scene: &Scene) -> Option<(Rect, Color)> {
+        match scene.primitives.first()? {
+            Primitive::FillRect { rect, color } => Some((*rect, *color)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn fill_width_follows_the_level() {
+        let full = fill_rect(&icon_scene(&visual(100, BatteryStatus::Discharging), SIZE))
+            .unwrap()
+            .0;
+        for capacity in [10, 20, 50, 80, 100] {
+            let visual = visual(capacity, BatteryStatus::Discharging);
+            let (rect, color) = fill_rect(&icon_scene(&visual, SIZE)).unwrap();
+            let expected = full.width() * f32::from(capacity) / 100.0;
+            assert!((rect.width() - expected).abs() < 0.01, "{}%", capacity);
+            assert_eq!(rect.left, full.left);
+            assert_eq!(color, fill_color(capacity));
+
+            // Rendered, the fill covers about as many whole pixels.
+            let drawn = count_in_middle_row(&render_rgba(&visual, SIZE), color) as f32;
+            assert!(
+                (drawn - expected).abs() <= 1.0,
+                "{}%: {} px",
+                capacity,
+                drawn
+            );
+        }
+        assert!(full.width() > SIZE as f32 * 0.6);
+    }
+
+    #[test]
+    fn empty_battery_has_no_fill() {
+        let scene = icon_scene(&visual(0, BatteryStatus::Discharging), SIZE);
+        assert!(matches!(
+            scene.primitives.first(),
+            Some(Primitive::StrokeRoundedRect { .. })
+        ));
+        let rgba = render_rgba(&visual(0, BatteryStatus::Discharging), SIZE);
+        assert_eq!(count_in_middle_row(&rgba, fill_color(0)), 0);
+    }
+
+    #[test]
+    fn charging_and_unknown_draw_their_glyphs() {
+        let charging = visual(50, BatteryStatus::Charging);
+        assert_eq!(charging.glyph, Some(StatusGlyph::LightningBolt));
+        let scene = icon_scene(&charging, SIZE);
+        assert!(fill_rect(&scene).is_some());
+        assert!(
+            scene
+                .primitives
+                .iter()
+                .any(|primitive| matches!(primitive, Primitive::FillPolygon { .. }))
+        );
+
+        let unknown = visual(50, BatteryStatus::Unknown);
+        assert_eq!(unknown.glyph, Some(StatusGlyph::QuestionMark));
+        let scene = icon_scene(&unknown, SIZE);
+        assert!(fill_rect(&scene).is_none());
+
+        // The glyphs change the pixels, not just the scene.
+        let plain = render_rgba(&visual(50, BatteryStatus::Discharging), SIZE);
+        assert_ne!(render_rgba(&charging, SIZE), plain);
+        assert_ne!(
+            render_rgba(&unknown, SIZE),
+            render_rgba(&visual(0, BatteryStatus::Discharging), SIZE)
+        );
+    }
+
+    #[test]
+    fn no_controller_is_dimmed() {
+        let visual = tray_visual(&ControllerRegistry::new(), None);
+        assert!(visual.dimmed);
+        let outline = icon_scene(&visual, SIZE)
+            .primitives
+            .into_iter()
+            .find_map(|primitive| match primitive {
+                Primitive::StrokeRoundedRect { color, .. } => Some(color),
+                _ => None,
+            });
+        assert_eq!(outline, Some(overlay_layout::SECONDARY_TEXT_COLOR));
+    }
+
+    #[test]
+    fn prefers_the_recent_pad_then_the_emptiest() {
+        let mut registry = ControllerRegistry::new();
+        for (path, capacity) in [("a", 60), ("b", 20), ("c", 40)] {
+            registry.connect(
+                path.to_string(),
+                ControllerInfo {
+                    model: ControllerModel::DualSense,
+                    connection_type: ConnectionType::Usb,
+                    serial: None,
+                    firmware: None,
+                },
+            );
+            registry.update_battery(
+                path,
+                BatteryReport::new(capacity, BatteryStatus::Discharging),
+            );
+        }
+        let path = |recent| tray_controller(&registry, recent).map(|entry| entry.path.as_str());
+        assert_eq!(path(None), Some("b"));
+        assert_eq!(path(Some("c")), Some("c"));
+        assert_eq!(path(Some("gone")), Some("b"));
+    }
+
+    #[test]
+    fn ico_header_and_directory() {
+        let rgba = render_rgba(&visual(50, BatteryStatus::Discharging), SIZE);
+        let ico = encode_ico(SIZE, &rgba);
+        let dib_len = 40 + 32 * 32 * 4 + 32 * 4;
+        assert_eq!(ico.len(), 6 + 16 + dib_len);
+        assert_eq!(ico[..6], [0, 0, 1, 0, 1, 0]);
+        assert_eq!(ico[6..10], [32, 32, 0, 0]);
+        assert_eq!(ico[10..14], [1, 0, 32, 0]);
+        assert_eq!(ico[14..18], (dib_len as u32).to_le_bytes());
+        assert_eq!(ico[18..22], 22u32.to_le_bytes());
+        assert_eq!(ico[22..], encode_icon_dib(SIZE, &rgba));
+
+        // 256 pixels is written as 0.
+        let big = encode_ico(256, &vec![0; 256 * 256 * 4]);
+        assert_eq!(big[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn dib_is_bottom_up_bgra_with_a_mask() {
+        // Top row: opaque red, transparent. Bottom row: transparent, half-opaque blue.
+        let rgba = [255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 128];
+        let dib = encode_icon_dib(2, &rgba);
+        assert_eq!(dib[..4], 40u32.to_le_bytes());
+        assert_eq!(dib[4..8], 2i32.to_le_bytes());
+        // Height counts the mask too.
+        assert_eq!(dib[8..12], 4i32.to_le_bytes());
+        assert_eq!(dib[12..16], [1, 0, 32, 0]);
+        assert_eq!(dib[20..24], (2 * 2 * 4 + 2 * 4u32).to_le_bytes());
+
+        let pixels = &dib[40..56];
+        assert_eq!(
+            pixels,
+            [0, 0, 0, 0, 255, 0, 0, 128, 0, 0, 255, 255, 0, 0, 0, 0]
+        );
+        let mask = &dib[56..];
+        assert_eq!(mask, [0x80, 0, 0, 0, 0x40, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,381 @@
+//! What the tray's context menu contains and what each item does.
+//!
+//! The menu is rebuilt every time it opens. Command IDs are handed out in build
+//! order, so they are only meaningful for the menu they came from; keep that
+//! [`TrayMenu`] around to resolve the `WM_COMMAND` that follows.
+
+use crate::{
+    config::Config,
+    controllers::{ControllerEntry, ControllerRegistry},
+    dualsense::{BatteryStatus, ConnectionType},
+    output_report::{DEFAULT_LIGHTBAR, Rgb},
+};
+
+/// First command ID handed out. Zero means "nothing chosen" to Win32 menus.
+pub const FIRST_COMMAND_ID: u16 = 1000;
+
+pub const LIGHTBAR_PRESETS: [(&str, Rgb); 8] = [
+    ("Default blue", DEFAULT_LIGHTBAR),
+    ("Red", Rgb::new(255, 0, 0)),
+    ("Green", Rgb::new(0, 255, 0)),
+    ("Yellow", Rgb::new(255, 200, 0)),
+    ("Purple", Rgb::new(160, 0, 255)),
+    ("Pink", Rgb::new(255, 60, 160)),
+    ("White", Rgb::new(255, 255, 255)),
+    ("Off", Rgb::new(0, 0, 0)),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    Configure,
+    ToggleRunOnStartup,
+    Exit,
+    ShowOverlay(String),
+    Identify(String),
+    Rename(String),
+    SetLightbar(String, Rgb),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MenuEntry {
+    Command {
+        id: u16,
+        label: String,
+        checked: bool,
+    },
+    /// Greyed-out text, used for controller details.
+    Info(String),
+    Submenu {
+        label: String,
+        entries: Vec<MenuEntry>,
+    },
+    Separator,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrayMenu {
+    entries: Vec<MenuEntry>,
+    actions: Vec<MenuAction>,
+}
+
+impl TrayMenu {
+    pub fn build(controllers: &ControllerRegistry, config: &Config, run_on_startup: bool) -> Self {
+        let mut menu = Self::default();
+        let mut entries = Vec::new();
+
+        let connected: Vec<&ControllerEntry> =
+            controllers.iter().filter(|entry| entry.connected).collect();
+        if connected.is_empty() {
+            entries.push(MenuEntry::Info("No controllers connected".to_string()));
+        }
+        for entry in connected {
+            let lightbar = config
+                .controller(entry.identity())
+                .and_then(|settings| settings.lightbar);
+            let submenu = menu.controller_entries(entry, lightbar);
+            entries.push(MenuEntry::Submenu {
+                label: controller_label(entry),
+                entries: submenu,
+            });
+        }
+
+        entries.push(MenuEntry::Separator);
+        entries.push(menu.command("Configure", MenuAction::Configure, false));
+        entries.push(menu.command(
+            "Run on Startup",
+            MenuAction::ToggleRunOnStartup,
+            run_on_startup,
+        ));
+        entries.push(menu.command("Exit", MenuAction::Exit, false));
+
+        menu.entries = entries;
+        menu
+    }
+
+    pub fn entries(&self) -> &[MenuEntry] {
+        &self.entries
+    }
+
+    /// The action behind a command ID from this menu.
+    pub fn action(&self, id: u16) -> Option<&MenuAction> {
+        let index = id.checked_sub(FIRST_COMMAND_ID)?;
+        self.actions.get(index as usize)
+    }
+
+    fn command(&mut self, label: &str, action: MenuAction, checked: bool) -> MenuEntry {
+        let id = FIRST_COMMAND_ID + self.actions.len() as u16;
+        self.actions.push(action);
+        MenuEntry::Command {
+            id,
+            label: label.to_string(),
+            checked,
+        }
+    }
+
+    fn controller_entries(
+        &mut self,
+        entry: &ControllerEntry,
+        current_lightbar: Option<Rgb>,
+    ) -> Vec<MenuEntry> {
+        let path = &entry.path;
+        let firmware = entry
+            .info
+            .firmware
+            .map_or("unknown".to_string(), |firmware| firmware.display_version());
+
+        let mut lightbar_entries = Vec::new();
+        for (label, color) in LIGHTBAR_PRESETS {
+            lightbar_entries.push(self.command(
+                label,
+                MenuAction::SetLightbar(path.clone(), color),
+                current_lightbar == Some(color),
+            ));
+        }
+
+        vec![
+            MenuEntry::Info(format!("Battery: {}", battery_text(entry))),
+            MenuEntry::Info(format!("Status: {}", status_text(entry))),
+            MenuEntry::Info(format!(
+                "Connection: {}",
+                connection_text(entry.info.connection_type)
+            )),
+            MenuEntry::Info(format!("Firmware: {}", firmware)),
+            MenuEntry::Separator,
+            self.command(
+                "Show overlay for this pad",
+                MenuAction::ShowOverlay(path.clone()),
+                false,
+            ),
+            self.command(
+                "Identify (flash lightbar)",
+                MenuAction::Identify(path.clone()),
+                false,
+            ),
+            self.command("Rename...", MenuAction::Rename(path.clone()), false),
+            MenuEntry::Submenu {
+                label: "Set lightbar colour".to_string(),
+                entries: lightbar_entries,
+            },
+        ]
+    }
+}
+
+/// "P1 Couch pad - 65%"
+fn controller_label(entry: &ControllerEntry) -> String {
+    format!(
+        "P{} {} - {}",
+        entry.player_number,
+        entry.display_name(),
+        battery_text(entry)
+    )
+}
+
+fn battery_text(entry: &ControllerEntry) -> String {
+    entry.battery.as_ref().map_or("...".to_string(), |report| {
+        format!("{}%", report.battery_capacity)
+    })
+}
+
+fn status_text(entry: &ControllerEntry) -> &'static str {
+    match entry.battery.as_ref().map(|report| &report.battery_status) {
+        None => "Reading...",
+        Some(BatteryStatus::Discharging) => "Discharging",
+        Some(BatteryStatus::Charging) => "Charging",
+        Some(BatteryStatus::Full) => "Fully charged",
+        Some(BatteryStatus::ChargingError) => "Charging error",
+        Some(BatteryStatus::Unknown) => "Unknown",
+    }
+}
+
+fn connection_text(connection_type: ConnectionType) -> &'static str {
+    match connection_type {
+        ConnectionType::Usb => "USB",
+        ConnectionType::Bluetooth => "Bluetooth",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{BatteryReport, ControllerInfo, ControllerModel};
+
+    fn connect(registry: &mut ControllerRegistry, path: &str, serial: &str, capacity: u8) {
+        registry.connect(
+            path.to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Bluetooth,
+                serial: Some(serial.to_string()),
+                firmware: None,
+            },
+        );
+        registry.update_battery(
+            path,
+            BatteryReport::new(capacity, BatteryStatus::Discharging),
+        );
+    }
+
+    /// Every command in the menu, submenus included, in menu order.
+    fn commands(entries: &[MenuEntry]) -> Vec<(u16, &str, bool)> {
+        let mut commands = Vec::new();
+        for entry in entries {
+            match entry {
+                MenuEntry::Command { id, label, checked } => {
+                    commands.push((*id, label.as_str(), *checked))
+                }
+                MenuEntry::Submenu { entries, .. } => commands.extend(self::commands(entries)),
+                MenuEntry::Info(_) | MenuEntry::Separator => {}
+            }
+        }
+        commands
+    }
+
+    fn submenus(menu: &TrayMenu) -> Vec<(&str, &[MenuEntry])> {
+        menu.entries()
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Submenu { label, entries } => Some((label.as_str(), &entries[..])),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn command(id: u16, label: &str, checked: bool) -> MenuEntry {
+        MenuEntry::Command {
+            id,
+            label: label.to_string(),
+            checked,
+        }
+    }
+
+    #[test]
+    fn without_controllers() {
+        let menu = TrayMenu::build(&ControllerRegistry::new(), &Config::default(), true);
+        assert_eq!(
+            menu.entries(),
+            [
+                MenuEntry::Info("No controllers connected".to_string()),
+                MenuEntry::Separator,
+                command(1000, "Configure", false),
+                command(1001, "Run on Startup", true),
+                command(1002, "Exit", false),
+            ]
+        );
+        assert_eq!(menu.action(1000), Some(&MenuAction::Configure));
+        assert_eq!(menu.action(1001), Some(&MenuAction::ToggleRunOnStartup));
+        assert_eq!(menu.action(1002), Some(&MenuAction::Exit));
+        assert_eq!(menu.action(1003), None);
+        assert_eq!(menu.action(FIRST_COMMAND_ID - 1), None);
+        assert_eq!(menu.action(0), None);
+    }
+
+    #[test]
+    fn one_controller_submenu() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "pad", "aa:bb", 60);
+        registry.set_name("pad", Some("Couch".to_string()));
+        let menu = TrayMenu::build(&registry, &Config::default(), false);
+
+        let submenus = submenus(&menu);
+        assert_eq!(submenus.len(), 1);
+        let (label, entries) = submenus[0];
+        assert_eq!(label, "P1 Couch - 60%");
+        assert_eq!(
+            entries[..5],
+            [
+                MenuEntry::Info("Battery: 60%".to_string()),
+                MenuEntry::Info("Status: Discharging".to_string()),
+                MenuEntry::Info("Connection: Bluetooth".to_string()),
+                MenuEntry::Info("Firmware: unknown".to_string()),
+                MenuEntry::Separator,
+            ]
+        );
+        let labels: Vec<&str> = commands(&entries[5..])
+            .into_iter()
+            .map(|(_, label, _)| label)
+            .collect();
+        assert_eq!(
+            labels[..3],
+            [
+                "Show overlay for this pad",
+                "Identify (flash lightbar)",
+                "Rename..."
+            ]
+        );
+        let MenuEntry::Submenu { label, entries } = &entries[8] else {
+            panic!("no lightbar submenu");
+        };
+        assert_eq!(label, "Set lightbar colour");
+        assert_eq!(entries.len(), LIGHTBAR_PRESETS.len());
+        // The app's own commands come after the pad's.
+        assert_eq!(menu.entries()[1], MenuEntry::Separator);
+        assert_eq!(commands(menu.entries()).last().unwrap().1, "Exit");
+    }
+
+    #[test]
+    fn ids_are_unique_and_resolve_to_their_pad() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "pad-1", "aa:aa", 60);
+        connect(&mut registry, "pad-2", "bb:bb", 40);
+        connect(&mut registry, "pad-3", "cc:cc", 20);
+        registry.mark_disconnected("pad-2");
+        let menu = TrayMenu::build(&registry, &Config::default(), false);
+
+        // Disconnected pads get no submenu.
+        let labels: Vec<&str> = submenus(&menu)
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect();
+        assert_eq!(labels, ["P1 DualSense - 60%", "P3 DualSense - 20%"]);
+
+        // IDs follow build order rather than menu order, but are never reused.
+        let commands = commands(menu.entries());
+        let ids: Vec<u16> = commands.iter().map(|(id, _, _)| *id).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        let expected: Vec<u16> = (FIRST_COMMAND_ID..).take(ids.len()).collect();
+        assert_eq!(sorted, expected);
+
+        let per_pad = LIGHTBAR_PRESETS.len() + 3;
+        assert_eq!(commands.len(), 2 * per_pad + 3);
+        for (index, path) in ["pad-1", "pad-3"].into_iter().enumerate() {
+            let pad_ids = &ids[index * per_pad..][..per_pad];
+            for id in pad_ids {
+                let action_path = match menu.action(*id).unwrap() {
+                    MenuAction::ShowOverlay(path)
+                    | MenuAction::Identify(path)
+                    | MenuAction::Rename(path)
+                    | MenuAction::SetLightbar(path, _) => path,
+                    action => panic!("{:?} in a pad's submenu", action),
+                };
+                assert_eq!(action_path, path);
+            }
+        }
+    }
+
+    #[test]
+    fn current_lightbar_is_checked() {
+        let mut registry = ControllerRegistry::new();
+        connect(&mut registry, "pad", "aa:bb", 60);
+        let mut config = Config::default();
+        let (label, color) = LIGHTBAR_PRESETS[2];
+        config.controller_mut("aa:bb").lightbar = Some(color);
+        let menu = TrayMenu::build(&registry, &config, false);
+
+        let checked: Vec<&str> = commands(menu.entries())
+            .into_iter()
+            .filter(|(_, _, checked)| *checked)
+            .map(|(_, label, _)| label)
+            .collect();
+        assert_eq!(checked, [label]);
+        let id = commands(menu.entries())
+            .into_iter()
+            .find(|(_, command, _)| *command == label)
+            .unwrap()
+            .0;
+        assert_eq!(
+            menu.action(id),
+            Some(&MenuAction::SetLightbar("pad".to_string(), color))
+        );
+    }
+}
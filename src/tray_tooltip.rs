@@ -0,0 +1,286 @@
+//! Text for the tray icon's tooltip.
+//!
+//! The shell stores the tooltip in a fixed `szTip` buffer, so the text is
+//! shortened step by step until it fits rather than being cut mid-word.
+
+use crate::{
+    controllers::{ControllerEntry, ControllerRegistry},
+    dualsense::BatteryStatus,
+};
+
+/// `szTip` holds 128 UTF-16 units including the terminating NUL.
+pub const MAX_TOOLTIP_UNITS: usize = 127;
+
+const APP_TITLE: &str = "DualSense Battery Overlay";
+const SEPARATOR: &str = " · ";
+
+/// How much detail each controller gets; tried in order until the text fits.
+#[derive(Clone, Copy)]
+enum Detail {
+    /// "DualSense (BT): 65%"
+    Full,
+    /// "DualSense: 65%", or the name the user gave the pad
+    Model,
+    /// "P1: 65%"
+    Player,
+}
+
+pub fn tooltip_text(controllers: &ControllerRegistry) -> String {
+    let entries: Vec<&ControllerEntry> = controllers.iter().collect();
+    if entries.is_empty() {
+        return format!("{}: no controllers", APP_TITLE);
+    }
+
+    for detail in [Detail::Full, Detail::Model, Detail::Player] {
+        let parts: Vec<String> = entries
+            .iter()
+            .map(|entry| describe(entry, detail))
+            .collect();
+        let text = parts.join(SEPARATOR);
+        if utf16_len(&text) <= MAX_TOOLTIP_UNITS {
+            return text;
+        }
+    }
+
+    // Still too long: list as many pads as fit and count the rest.
+    let parts: Vec<String> = entries
+        .iter()
+        .map(|entry| describe(entry, Detail::Player))
+        .collect();
+    for shown in (0..parts.len()).rev() {
+        let mut text = parts[..shown].join(SEPARATOR);
+        if shown > 0 {
+            text.push_str(SEPARATOR);
+        }
+        text.push_str(&format!("+{} more", parts.len() - shown));
+        if utf16_len(&text) <= MAX_TOOLTIP_UNITS {
+            return text;
+        }
+    }
+    truncate_utf16(&parts.join(SEPARATOR), MAX_TOOLTIP_UNITS)
+}
+
+fn describe(entry: &ControllerEntry, detail: Detail) -> String {
+    let name = match detail {
+        Detail::Full => format!(
+            "{} ({})",
+            short_name(entry),
+            entry.info.connection_type.short_name()
+        ),
+        Detail::Model => short_name(entry).to_string(),
+        Detail::Player => format!("P{}", entry.player_number),
+    };
+    format!("{}: {}", name, status_text(entry))
+}
+
+fn short_name(entry: &ControllerEntry) -> &str {
+    entry
+        .name
+        .as_deref()
+        .unwrap_or(entry.info.model.short_name())
+}
+
+fn status_text(entry: &ControllerEntry) -> String {
+    if !entry.connected {
+        return "Disconnected".to_string();
+    }
+    let Some(report) = &entry.battery else {
+        return "Reading...".to_string();
+    };
+    match report.battery_status {
+        BatteryStatus::Discharging => format!("{}%", report.battery_capacity),
+        BatteryStatus::Charging => format!("Charging {}%", report.battery_capacity),
+        BatteryStatus::Full => "Full".to_string(),
+        BatteryStatus::ChargingError => "Charging error".to_string(),
+        BatteryStatus::Unknown => "Unknown".to_string(),
+    }
+}
+
+pub fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Cuts `text` to at most `max_units` UTF-16 units, ending in an ellipsis when
+/// anything was dropped. Never splits a surrogate pair.
+pub fn truncate_utf16(text: &str, max_units: usize) -> String {
+    if utf16_len(text) <= max_units {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut units = 0;
+    for c in text.chars() {
+        if units + c.len_utf16() + 1 > max_units {
+            break;
+        }
+        units += c.len_utf16();
+        truncated.push(c);
+    }
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dualsense::{BatteryReport, ConnectionType, ControllerInfo, ControllerModel};
+
+    /// Connects pad `path` over `connection_type` with an optional name and
+    /// battery reading.
+    fn add(
+        registry: &mut ControllerRegistry,
+        path: &str,
+        connection_type: ConnectionType,
+        name: Option<&str>,
+        battery: Option<(u8, BatteryStatus)>,
+    ) {
+        registry.connect(
+            path.to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type,
+                serial: None,
+                firmware: None,
+            },
+        );
+        registry.set_name(path, name.map(str::to_string));
+        if let Some((capacity, status)) = battery {
+            registry.update_battery(path, BatteryReport::new(capacity, status));
+        }
+    }
+
+    #[test]
+    fn no_controllers() {
+        assert_eq!(
+            tooltip_text(&ControllerRegistry::new()),
+            "DualSense Battery Overlay: no controllers"
+        );
+    }
+
+    #[test]
+    fn full_detail_when_it_fits() {
+        let mut registry = ControllerRegistry::new();
+        add(
+            &mut registry,
+            "a",
+            ConnectionType::Bluetooth,
+            None,
+            Some((60, BatteryStatus::Discharging)),
+        );
+        add(
+            &mut registry,
+            "b",
+            ConnectionType::Usb,
+            Some("Couch"),
+            Some((40, BatteryStatus::Charging)),
+        );
+        add(&mut registry, "c", ConnectionType::Usb, None, None);
+        assert_eq!(
+            tooltip_text(&registry),
+            "DualSense (BT): 60% · Couch (USB): Charging 40% · DualSense (USB): Reading..."
+        );
+    }
+
+    #[test]
+    fn four_long_names_fall_back_to_player_numbers() {
+        let mut registry = ControllerRegistry::new();
+        for (path, name, battery) in [
+            (
+                "a",
+                "Living room controller on the left",
+                (60, BatteryStatus::Discharging),
+            ),
+            (
+                "b",
+                "Living room controller on the right",
+                (40, BatteryStatus::Charging),
+            ),
+            (
+                "c",
+                "The spare one from the drawer",
+                (100, BatteryStatus::Full),
+            ),
+            (
+                "d",
+                "Borrowed from my neighbour",
+                (0, BatteryStatus::ChargingError),
+            ),
+        ] {
+            add(
+                &mut registry,
+                path,
+                ConnectionType::Bluetooth,
+                Some(name),
+                Some(battery),
+            );
+        }
+        registry.mark_disconnected("c");
+        assert_eq!(
+            tooltip_text(&registry),
+            "P1: 60% · P2: Charging 40% · P3: Disconnected · P4: Charging error"
+        );
+    }
+
+    #[test]
+    fn width_is_counted_in_utf16_units() {
+        // Each name is 28 characters but 56 units, so the full form of both
+        // fits in 127 characters but not in 127 units.
+        let name = "🎮".repeat(28);
+        let mut registry = ControllerRegistry::new();
+        for path in ["a", "b"] {
+            add(
+                &mut registry,
+                path,
+                ConnectionType::Usb,
+                Some(&name),
+                Some((60, BatteryStatus::Discharging)),
+            );
+        }
+        let text = tooltip_text(&registry);
+        assert_eq!(text, format!("{0}: 60% · {0}: 60%", name));
+        assert_eq!(utf16_len(&text), 125);
+    }
+
+    #[test]
+    fn too_many_pads_are_counted() {
+        let mut registry = ControllerRegistry::new();
+        for path in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            add(
+                &mut registry,
+                path,
+                ConnectionType::Usb,
+                None,
+                Some((0, BatteryStatus::ChargingError)),
+            );
+        }
+        let text = tooltip_text(&registry);
+        assert_eq!(
+            text,
+            "P1: Charging error · P2: Charging error · P3: Charging error · \
+             P4: Charging error · P5: Charging error · +3 more"
+        );
+        assert!(utf16_len(&text) <= MAX_TOOLTIP_UNITS);
+    }
+
+    #[test]
+    fn truncation_leaves_short_text_alone() {
+        let text = format!("{}😀", "a".repeat(125));
+        assert_eq!(utf16_len(&text), MAX_TOOLTIP_UNITS);
+        assert_eq!(truncate_utf16(&text, MAX_TOOLTIP_UNITS), text);
+    }
+
+    #[test]
+    fn truncation_never_splits_a_surrogate_pair() {
+        // The pair would take units 126 and 127, leaving no room for the
+        // ellipsis, so it's dropped whole.
+        let text = format!("{}😀b", "a".repeat(125));
+        let truncated = truncate_utf16(&text, MAX_TOOLTIP_UNITS);
+        assert_eq!(truncated, format!("{}…", "a".repeat(125)));
+        assert_eq!(utf16_len(&truncated), 126);
+
+        // One unit earlier it fits together with the ellipsis.
+        let text = format!("{}😀bb", "a".repeat(124));
+        let truncated = truncate_utf16(&text, MAX_TOOLTIP_UNITS);
+        assert_eq!(truncated, format!("{}😀…", "a".repeat(124)));
+        assert_eq!(utf16_len(&truncated), MAX_TOOLTIP_UNITS);
+    }
+}
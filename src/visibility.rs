@@ -0,0 +1,157 @@
+//! When the overlay is shown, held, faded and hidden.
+//!
+//! The state machine is pure: the window code feeds it [`VisibilityInput`]s
+//! from hotkeys, timers and controller events, and carries out the
+//! [`VisibilityEffect`]s it returns, in order.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VisibilityState {
+    Hidden,
+    FadingIn,
+    Visible,
+    FadingOut,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VisibilityInput {
+    /// The hotkey or a controller's mute button asked for the overlay.
+    Toggle,
+    /// The hold timer started by [`VisibilityEffect::StartTimer`] ran out.
+    TimerExpired,
+    /// The animation started by [`VisibilityEffect::Animate`] has finished.
+    FadeComplete,
+    /// A controller dropped and the overlay should stay up long enough to say so.
+    ControllerGone,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Fade {
+    In,
+    Out,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VisibilityEffect {
+    /// Bring the window up at full opacity, on top of everything else.
+    Show,
+    /// (Re)start the hold timer, cancelling any pending one.
+    StartTimer,
+    /// Run a fade and report [`VisibilityInput::FadeComplete`] when it ends.
+    Animate(Fade),
+    /// Hide the window and cancel any pending timers.
+    Hide,
+}
+
+impl VisibilityState {
+    pub fn is_shown(self) -> bool {
+        self != VisibilityState::Hidden
+    }
+
+    /// The state after `input`, and what has to happen to get there. Inputs that
+    /// make no sense in the current state, like a stale timer, change nothing.
+    pub fn next(self, input: VisibilityInput) -> (VisibilityState, Vec<VisibilityEffect>) {
+        use Fade::*;
+        use VisibilityEffect::*;
+        use VisibilityInput::*;
+        use VisibilityState::*;
+
+        match (self, input) {
+            (Hidden, Toggle) => (FadingIn, vec![Show, Animate(In)]),
+            // Already on the way up; the hold timer starts once it gets there.
+            (FadingIn, Toggle | ControllerGone) => (FadingIn, vec![]),
+            (FadingIn, FadeComplete) => (Visible, vec![StartTimer]),
+            (Visible, Toggle | ControllerGone) => (Visible, vec![StartTimer]),
+            (Visible, TimerExpired) => (FadingOut, vec![Animate(Out)]),
+            // Snap back rather than fading in from a partly faded window.
+            (FadingOut, Toggle | ControllerGone) => (Visible, vec![Show, StartTimer]),
+            (FadingOut, FadeComplete) => (Hidden, vec![Hide]),
+            // Disconnects while hidden are dropped without a notice.
+            (Hidden, ControllerGone) => (Hidden, vec![]),
+            (state, TimerExpired | FadeComplete) => (state, vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Fade::*;
+    use VisibilityEffect::*;
+    use VisibilityInput::*;
+    use VisibilityState::*;
+
+    #[test]
+    fn every_state_and_input() {
+        let table = [
+            (Hidden, Toggle, FadingIn, vec![Show, Animate(In)]),
+            (Hidden, TimerExpired, Hidden, vec![]),
+            (Hidden, FadeComplete, Hidden, vec![]),
+            (Hidden, ControllerGone, Hidden, vec![]),
+            (FadingIn, Toggle, FadingIn, vec![]),
+            (FadingIn, TimerExpired, FadingIn, vec![]),
+            (FadingIn, FadeComplete, Visible, vec![StartTimer]),
+            (FadingIn, ControllerGone, FadingIn, vec![]),
+            (Visible, Toggle, Visible, vec![StartTimer]),
+            (Visible, TimerExpired, FadingOut, vec![Animate(Out)]),
+            (Visible, FadeComplete, Visible, vec![]),
+            (Visible, ControllerGone, Visible, vec![StartTimer]),
+            (FadingOut, Toggle, Visible, vec![Show, StartTimer]),
+            (FadingOut, TimerExpired, FadingOut, vec![]),
+            (FadingOut, FadeComplete, Hidden, vec![Hide]),
+            (FadingOut, ControllerGone, Visible, vec![Show, StartTimer]),
+        ];
+        for (state, input, expected_state, expected_effects) in table {
+            assert_eq!(
+                state.next(input),
+                (expected_state, expected_effects),
+                "{:?} + {:?}",
+                state,
+                input
+            );
+        }
+    }
+
+    /// Feeds `inputs` in order, returning the final state.
+    fn run(mut state: VisibilityState, inputs: &[VisibilityInput]) -> VisibilityState {
+        for &input in inputs {
+            state = state.next(input).0;
+        }
+        state
+    }
+
+    #[test]
+    fn full_cycle() {
+        assert_eq!(
+            run(Hidden, &[Toggle, FadeComplete, TimerExpired, FadeComplete]),
+            Hidden
+        );
+    }
+
+    #[test]
+    fn stale_fade_out_after_reshow_keeps_overlay_up() {
+        // Toggled again mid fade-out; the cancelled fade still reports in.
+        let state = run(Hidden, &[Toggle, FadeComplete, TimerExpired, Toggle]);
+        assert_eq!(state, Visible);
+        assert_eq!(state.next(FadeComplete), (Visible, vec![]));
+    }
+
+    #[test]
+    fn stale_timer_while_fading_back_in_is_ignored() {
+        // Hidden and shown again before the old hold timer's message arrived.
+        let state = run(
+            Hidden,
+            &[Toggle, FadeComplete, TimerExpired, FadeComplete, Toggle],
+        );
+        assert_eq!(state, FadingIn);
+        assert_eq!(state.next(TimerExpired), (FadingIn, vec![]));
+        assert_eq!(state.next(FadeComplete), (Visible, vec![StartTimer]));
+    }
+
+    #[test]
+    fn only_hidden_is_not_shown() {
+        assert!(!Hidden.is_shown());
+        assert!(FadingIn.is_shown());
+        assert!(Visible.is_shown());
+        assert!(FadingOut.is_shown());
+    }
+}
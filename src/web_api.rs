@@ -0,0 +1,338 @@
+//! What the local web API and the IPC socket serve: a JSON snapshot of every
+//! controller and a JSON message per [`ControllerEvent`] for subscribers.
+//!
+//! Snapshot entries look like
+//! `{"path", "identity", "name", "model", "transport", "player", "connected",
+//! "battery": {"capacity", "status"} | null}`. Event messages carry a `type`
+//! such as `"battery_update"`, the HID `path`, any event data, and the
+//! controller's entry as it is after the event, or `null` once it's gone.
+
+use std::sync::mpsc;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{
+    battery_history,
+    controllers::{ControllerEntry, ControllerRegistry},
+    dualsense::{BatteryReport, Button, ControllerEvent},
+};
+
+/// Messages a subscriber can fall behind by before it's dropped, so a stalled
+/// client can't make the queue grow without bound.
+const SUBSCRIBER_BACKLOG: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BatteryJson {
+    pub capacity: u8,
+    pub status: &'static str,
+}
+
+impl BatteryJson {
+    fn new(report: &BatteryReport) -> Self {
+        Self {
+            capacity: report.battery_capacity,
+            status: battery_history::status_name(&report.battery_status),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ControllerJson {
+    pub path: String,
+    pub identity: String,
+    pub name: String,
+    pub model: &'static str,
+    pub transport: &'static str,
+    pub player: u8,
+    pub connected: bool,
+    pub battery: Option<BatteryJson>,
+}
+
+impl ControllerJson {
+    pub fn new(entry: &ControllerEntry) -> Self {
+        Self {
+            path: entry.path.clone(),
+            identity: entry.identity().to_string(),
+            name: entry.display_name().to_string(),
+            model: entry.info.model.name(),
+            transport: entry.info.connection_type.short_name(),
+            player: entry.player_number,
+            connected: entry.connected,
+            battery: entry.battery.as_ref().map(BatteryJson::new),
+        }
+    }
+}
+
+/// Shared between the UI thread, which feeds it, and the web and IPC servers.
+#[derive(Default)]
+pub struct WebApiState {
+    controllers: Vec<ControllerJson>,
+    subscribers: Vec<mpsc::SyncSender<String>>,
+}
+
+impl WebApiState {
+    /// Takes in an event the UI has already applied to `registry`, and pushes
+    /// it to every subscriber.
+    pub fn publish(&mut self, event: &ControllerEvent, registry: &ControllerRegistry) {
+        self.controllers = registry.iter().map(ControllerJson::new).collect();
+        let message = event_message(event, registry).to_string();
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(message.clone()).is_ok());
+    }
+
+    /// Picks up changes that don't come with an event, like renames, and sends
+    /// subscribers a fresh `"snapshot"` when anything changed.
+    pub fn refresh(&mut self, registry: &ControllerRegistry) {
+        let controllers: Vec<ControllerJson> = registry.iter().map(ControllerJson::new).collect();
+        if controllers == self.controllers {
+            return;
+        }
+        self.controllers = controllers;
+        let message = self.snapshot_message().to_string();
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(message.clone()).is_ok());
+    }
+
+    pub fn controllers(&self) -> &[ControllerJson] {
+        &self.controllers
+    }
+
+    /// Finds a controller by player number, identity, HID path or name.
+    pub fn find_controller(&self, query: &str) -> Option<&ControllerJson> {
+        let player = query.parse::<u8>().ok();
+        self.controllers.iter().find(|controller| {
+            Some(controller.player) == player
+                || controller.identity.eq_ignore_ascii_case(query)
+                || controller.path == query
+                || controller.name.eq_ignore_ascii_case(query)
+        })
+    }
+
+    pub fn snapshot_json(&self) -> String {
+        json!({ "controllers": self.controllers }).to_string()
+    }
+
+    /// Subscribes to event messages. The first message is a `"snapshot"` with
+    /// the current controllers, so clients don't need a separate request.
+    /// Subscribers that fall [`SUBSCRIBER_BACKLOG`] messages behind are
+    /// dropped, which ends their receiver.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        let _ = sender.send(self.snapshot_message().to_string());
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn snapshot_message(&self) -> Value {
+        json!({ "type": "snapshot", "controllers": self.controllers })
+    }
+}
+
+fn event_message(event: &ControllerEvent, registry: &ControllerRegistry) -> Value {
+    let (kind, path, mut message) = match event {
+        ControllerEvent::DeviceConnected(path, _) => ("connected", path, json!({})),
+        ControllerEvent::DeviceDisconnected(path) => ("disconnected", path, json!({})),
+        ControllerEvent::BatteryUpdate(path, report) => (
+            "battery_update",
+            path,
+            json!({ "battery": BatteryJson::new(report) }),
+        ),
+        ControllerEvent::MuteButtonPressed(path) => ("mute_button", path, json!({})),
+        ControllerEvent::Gesture(path, gesture) => (
+            "gesture",
+            path,
+            json!({
+                "buttons": gesture.buttons.iter().map(Button::name).collect::<Vec<_>>(),
+                "gesture": gesture.kind,
+            }),
+        ),
+        ControllerEvent::LowBattery(path, report) => (
+            "low_battery",
+            path,
+            json!({ "battery": BatteryJson::new(report) }),
+        ),
+        ControllerEvent::CriticalBattery(path, report) => (
+            "critical_battery",
+            path,
+            json!({ "battery": BatteryJson::new(report) }),
+        ),
+        ControllerEvent::ChargeCeilingReached(path, report) => (
+            "charge_ceiling_reached",
+            path,
+            json!({ "battery": BatteryJson::new(report) }),
+        ),
+        ControllerEvent::FullyCharged(path, report) => (
+            "fully_charged",
+            path,
+            json!({ "battery": BatteryJson::new(report) }),
+        ),
+        ControllerEvent::ReportStats(path, stats) => (
+            "report_stats",
+            path,
+            json!({
+                "reports": stats.reports,
+                "read_errors": stats.read_errors,
+                "crc_failures": stats.crc_failures,
+                "last_report_age_seconds": stats.last_report_age.map(|age| age.as_secs_f64()),
+            }),
+        ),
+    };
+    message["type"] = json!(kind);
+    message["path"] = json!(path);
+    message["controller"] = json!(registry.get(path).map(ControllerJson::new));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        dualsense::{
+            BatteryStatus, Buttons, ConnectionType, ControllerInfo, ControllerModel, ReportStats,
+        },
+        gestures::{Gesture, GestureKind},
+    };
+
+    fn registry() -> ControllerRegistry {
+        let mut registry = ControllerRegistry::new();
+        registry.connect(
+            "pad-path".to_string(),
+            ControllerInfo {
+                model: ControllerModel::DualSense,
+                connection_type: ConnectionType::Bluetooth,
+                serial: Some("aa:bb".to_string()),
+                firmware: None,
+            },
+        );
+        registry.set_name("pad-path", Some("Couch".to_string()));
+        registry.update_battery("pad-path", BatteryReport::new(70, BatteryStatus::Charging));
+        registry
+    }
+
+    fn controller_json() -> Value {
+        json!({
+            "path": "pad-path",
+            "identity": "aa:bb",
+            "name": "Couch",
+            "model": "DualSense",
+            "transport": "BT",
+            "player": 1,
+            "connected": true,
+            "battery": { "capacity": 70, "status": "charging" },
+        })
+    }
+
+    #[test]
+    fn battery_updates_carry_the_report_and_controller() {
+        let event = ControllerEvent::BatteryUpdate(
+            "pad-path".to_string(),
+            BatteryReport::new(70, BatteryStatus::Charging),
+        );
+        assert_eq!(
+            event_message(&event, &registry()),
+            json!({
+                "type": "battery_update",
+                "path": "pad-path",
+                "battery": { "capacity": 70, "status": "charging" },
+                "controller": controller_json(),
+            })
+        );
+    }
+
+    #[test]
+    fn gone_controllers_are_null() {
+        let event = ControllerEvent::DeviceDisconnected("pad-path".to_string());
+        assert_eq!(
+            event_message(&event, &ControllerRegistry::new()),
+            json!({ "type": "disconnected", "path": "pad-path", "controller": null })
+        );
+    }
+
+    #[test]
+    fn gestures_list_their_buttons() {
+        let event = ControllerEvent::Gesture(
+            "pad-path".to_string(),
+            Gesture {
+                buttons: [Button::Options, Button::Ps]
+                    .into_iter()
+                    .collect::<Buttons>(),
+                kind: GestureKind::LongPress,
+            },
+        );
+        let message = event_message(&event, &registry());
+        assert_eq!(message["type"], "gesture");
+        assert_eq!(message["buttons"], json!(["options", "ps"]));
+        assert_eq!(message["gesture"], "long_press");
+    }
+
+    #[test]
+    fn report_stats_are_flattened() {
+        let event = ControllerEvent::ReportStats(
+            "pad-path".to_string(),
+            ReportStats {
+                reports: 250,
+                read_errors: 1,
+                crc_failures: 2,
+                last_report_age: Some(Duration::from_millis(1500)),
+            },
+        );
+        let message = event_message(&event, &registry());
+        assert_eq!(message["type"], "report_stats");
+        assert_eq!(message["reports"], 250);
+        assert_eq!(message["read_errors"], 1);
+        assert_eq!(message["crc_failures"], 2);
+        assert_eq!(message["last_report_age_seconds"], 1.5);
+    }
+
+    #[test]
+    fn subscribers_get_a_snapshot_then_events() {
+        let registry = registry();
+        let mut state = WebApiState::default();
+        state.refresh(&registry);
+        let messages = state.subscribe();
+        state.publish(
+            &ControllerEvent::MuteButtonPressed("pad-path".to_string()),
+            &registry,
+        );
+
+        let snapshot: Value = serde_json::from_str(&messages.recv().unwrap()).unwrap();
+        assert_eq!(
+            snapshot,
+            json!({ "type": "snapshot", "controllers": [controller_json()] })
+        );
+        let event: Value = serde_json::from_str(&messages.recv().unwrap()).unwrap();
+        assert_eq!(event["type"], "mute_button");
+    }
+
+    #[test]
+    fn stalled_subscribers_are_dropped() {
+        let registry = registry();
+        let mut state = WebApiState::default();
+        let messages = state.subscribe();
+        let event = ControllerEvent::MuteButtonPressed("pad-path".to_string());
+        // The snapshot already takes one slot.
+        for _ in 0..SUBSCRIBER_BACKLOG {
+            state.publish(&event, &registry);
+        }
+        assert!(state.subscribers.is_empty());
+        assert_eq!(messages.iter().count(), SUBSCRIBER_BACKLOG);
+    }
+
+    #[test]
+    fn finds_controllers_by_any_key() {
+        let mut state = WebApiState::default();
+        state.refresh(&registry());
+        for query in ["1", "AA:BB", "pad-path", "couch"] {
+            assert_eq!(
+                state.find_controller(query).map(|c| c.path.as_str()),
+                Some("pad-path"),
+                "{}",
+                query
+            );
+        }
+        assert_eq!(state.find_controller("2"), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! Local HTTP and WebSocket server for stream overlays.
+//!
+//! - `GET /` serves a page that draws the battery like the native overlay,
+//!   meant for an OBS browser source.
+//! - `GET /api/controllers` returns the JSON snapshot.
+//! - `GET /api/events` upgrades to a WebSocket that pushes every controller
+//!   event as JSON. Browsers may only connect from the overlay page itself;
+//!   see [`origin_allowed`].
+
+use std::{
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use crate::{http, web_api::WebApiState, websocket};
+
+const OVERLAY_PAGE: &str = include_str!("web_overlay.html");
+/// Keeps idle connections from being dropped by proxies, and notices clients
+/// that went away without closing.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Starts listening on `address` and serves connections from background
+/// threads until the process exits. Returns the address it's listening on.
+pub fn spawn(address: SocketAddr, state: Arc<Mutex<WebApiState>>) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    println!("Serving stream overlay on http://{}/", address);
+
+    thread::Builder::new()
+        .name("web_server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = state.clone();
+                        let spawned = thread::Builder::new()
+                            .name("web_connection".to_string())
+                            .spawn(move || handle_connection(stream, &state));
+                        if let Err(e) = spawned {
+                            eprintln!("Web server: Failed to start connection thread: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Web server: Failed to accept connection: {}", e),
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start web server: {}", e))?;
+    Ok(address)
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<WebApiState>) {
+    let request = match http::read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Web server: Bad request: {}", e);
+            return;
+        }
+    };
+    if request.method != "GET" {
+        let _ = http::write_not_found(&stream);
+        return;
+    }
+
+    let result = match request.path.as_str() {
+        "/" => http::write_response(
+            &stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            OVERLAY_PAGE.as_bytes(),
+        ),
+        "/api/controllers" => {
+            let body = match state.lock() {
+                Ok(state) => state.snapshot_json(),
+                Err(_) => return,
+            };
+            http::write_response(&stream, "200 OK", "application/json", body.as_bytes())
+        }
+        "/api/events" if websocket::is_upgrade(&request) => {
+            let allowed = stream
+                .local_addr()
+                .is_ok_and(|local| origin_allowed(&request, local));
+            if !allowed {
+                let _ = http::write_response(
+                    &stream,
+                    "403 Forbidden",
+                    "text/plain; charset=utf-8",
+                    b"Cross-origin WebSocket connections aren't allowed\n",
+                );
+                return;
+            }
+            if let Err(e) = websocket::accept(&stream, &request) {
+                eprintln!("Web server: WebSocket handshake failed: {}", e);
+                return;
+            }
+            let Ok(messages) = state.lock().map(|mut state| state.subscribe()) else {
+                return;
+            };
+            stream_events(stream, messages);
+            return;
+        }
+        "/api/events" => http::write_response(
+            &stream,
+            "426 Upgrade Required",
+            "text/plain; charset=utf-8",
+            b"Connect with a WebSocket\n",
+        ),
+        _ => http::write_not_found(&stream),
+    };
+    if let Err(e) = result {
+        eprintln!("Web server: Failed to send response: {}", e);
+    }
+}
+
+/// Browsers send the page's origin with every WebSocket handshake and let any
+/// site connect anywhere, so without this check any page the user has open
+/// could read the controller events. Only the overlay page served from this
+/// address, reached by IP or as `localhost`, may connect. Requests without an
+/// `Origin` come from tools rather than browsers and are let through.
+fn origin_allowed(request: &http::Request, local: SocketAddr) -> bool {
+    let Some(origin) = request.header("Origin") else {
+        return true;
+    };
+    origin.eq_ignore_ascii_case(&format!("http://{}", local))
+        || (local.ip().is_loopback()
+            && origin.eq_ignore_ascii_case(&format!("http://localhost:{}", local.port())))
+}
+
+/// Pushes messages to the client until either side closes. Writes from both
+/// threads go through one lock so frames never interleave.
+fn stream_events(stream: TcpStream, messages: mpsc::Receiver<String>) {
+    let _ = stream.set_read_timeout(None);
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(e) => {
+            eprintln!("Web server: Failed to clone WebSocket stream: {}", e);
+            return;
+        }
+    };
+
+    let sender = writer.clone();
+    let spawned = thread::Builder::new()
+        .name("web_socket_writer".to_string())
+        .spawn(move || {
+            loop {
+                let message = messages.recv_timeout(PING_INTERVAL);
+                let Ok(stream) = sender.lock() else {
+                    return;
+                };
+                let result = match &message {
+                    Ok(text) => websocket::write_text(&stream, text),
+                    Err(mpsc::RecvTimeoutError::Timeout) => websocket::write_ping(&stream),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        // Dropped for falling behind; ends the reader too.
+                        let _ = websocket::write_close(&stream);
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return;
+                    }
+                };
+                if result.is_err() {
+                    return;
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("Web server: Failed to start WebSocket writer: {}", e);
+        return;
+    }
+
+    loop {
+        let frame = websocket::read_frame(&stream);
+        let Ok(writer) = writer.lock() else {
+            return;
+        };
+        match frame {
+            Ok(websocket::Frame::Ping(payload)) => {
+                let _ = websocket::write_pong(&writer, &payload);
+            }
+            Ok(websocket::Frame::Other) => {}
+            Ok(websocket::Frame::Close) | Err(_) => {
+                let _ = websocket::write_close(&writer);
+                // Makes the writer's next send fail, which ends its thread and
+                // drops the subscription.
+                let _ = writer.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> http::Request {
+        http::Request {
+            method: "GET".to_string(),
+            path: "/api/events".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn connect(address: SocketAddr, raw: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        stream
+    }
+
+    /// Reads the response head, up to and including the blank line.
+    fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    /// Reads one unmasked server frame and returns its opcode and payload.
+    fn read_server_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let length = match header[1] {
+            126 => {
+                let mut bytes = [0u8; 2];
+                stream.read_exact(&mut bytes).unwrap();
+                usize::from(u16::from_be_bytes(bytes))
+            }
+            length => usize::from(length),
+        };
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    fn upgrade(address: SocketAddr, origin: Option<&str>) -> TcpStream {
+        let origin = origin.map_or(String::new(), |origin| format!("Origin: {}\r\n", origin));
+        connect(
+            address,
+            &format!(
+                "GET /api/events HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                 Sec-WebSocket-Version: 13\r\n{}\r\n",
+                address, origin
+            ),
+        )
+    }
+
+    fn start() -> SocketAddr {
+        let state = Arc::new(Mutex::new(WebApiState::default()));
+        spawn("127.0.0.1:0".parse().unwrap(), state).unwrap()
+    }
+
+    #[test]
+    fn only_the_servers_own_origin_is_allowed() {
+        let local: SocketAddr = "127.0.0.1:9478".parse().unwrap();
+        for (origin, allowed) in [
+            (None, true),
+            (Some("http://127.0.0.1:9478"), true),
+            (Some("http://localhost:9478"), true),
+            (Some("HTTP://LOCALHOST:9478"), true),
+            (Some("https://evil.example"), false),
+            (Some("http://127.0.0.1:9479"), false),
+            (Some("http://localhost"), false),
+            (Some("null"), false),
+        ] {
+            let headers: Vec<(&str, &str)> =
+                origin.iter().map(|origin| ("Origin", *origin)).collect();
+            assert_eq!(
+                origin_allowed(&request(&headers), local),
+                allowed,
+                "{:?}",
+                origin
+            );
+        }
+
+        // Reached over the network, "localhost" would be some other machine.
+        let remote: SocketAddr = "192.168.1.5:9478".parse().unwrap();
+        let lan = request(&[("Origin", "http://192.168.1.5:9478")]);
+        assert!(origin_allowed(&lan, remote));
+        let localhost = request(&[("Origin", "http://localhost:9478")]);
+        assert!(!origin_allowed(&localhost, remote));
+    }
+
+    #[test]
+    fn foreign_origins_are_refused() {
+        let address = start();
+        let mut stream = upgrade(address, Some("https://evil.example"));
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[test]
+    fn own_origin_gets_the_snapshot() {
+        let address = start();
+        let mut stream = upgrade(address, Some(&format!("http://{}", address)));
+        let head = read_head(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let (opcode, payload) = read_server_frame(&mut stream);
+        assert_eq!(opcode, 0x1);
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            r#"{"controllers":[],"type":"snapshot"}"#
+        );
+    }
+
+    #[test]
+    fn events_need_an_upgrade() {
+        let address = start();
+        let mut stream = connect(address, "GET /api/events HTTP/1.1\r\n\r\n");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    }
+
+    #[test]
+    fn serves_the_snapshot() {
+        let address = start();
+        let mut stream = connect(address, "GET /api/controllers HTTP/1.1\r\n\r\n");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"controllers\":[]}"));
+    }
+}
@@ -0,0 +1,713 @@
+//! Posts notifications to an HTTP endpoint, for rigs where nobody is watching
+//! the desktop.
+//!
+//! Bodies come from a preset format or a user template. Deliveries wait in a
+//! queue saved to the data directory, so alerts that couldn't be sent survive
+//! a restart; a background thread works through it in order, backing off
+//! while the endpoint is unreachable.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    battery_history::{self, unix_now},
+    config::WebhookSettings,
+    http_client::{self, Url},
+    notifications::{Notification, Notifier, Urgency},
+    paths,
+};
+
+const QUEUE_FILE_NAME: &str = "webhook_queue.json";
+/// Oldest deliveries are dropped past this many.
+const MAX_QUEUED: usize = 200;
+/// Deliveries that still fail after this long are given up on.
+const MAX_AGE_SECS: u64 = 24 * 60 * 60;
+const FIRST_RETRY_SECS: u64 = 10;
+const MAX_RETRY_SECS: u64 = 15 * 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// `{"event", "urgency", "title", "message", "controller", "battery"}`.
+    #[default]
+    Generic,
+    /// An incoming-webhook message with a `text` field.
+    Slack,
+    /// ntfy's JSON publishing; the URL is the topic's, like
+    /// `https://ntfy.sh/my-topic`.
+    Ntfy,
+}
+
+/// A rendered request, as stored in the queue.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delivery {
+    /// Tells deliveries apart while one is being sent. Handed out by the
+    /// queue and not saved.
+    #[serde(skip)]
+    pub id: u64,
+    pub url: String,
+    pub body: String,
+    /// Unix seconds when the notification was raised.
+    pub created: u64,
+    pub attempts: u32,
+    pub next_attempt: u64,
+}
+
+/// Builds the delivery for a notification. A `template` in the settings
+/// wins over the format; its `{placeholders}` are filled in JSON-escaped, so
+/// they belong inside string literals.
+pub fn render(
+    settings: &WebhookSettings,
+    notification: &Notification,
+    now: u64,
+) -> Result<Delivery, String> {
+    let mut url = settings.url.clone();
+    let body = if !settings.template.is_empty() {
+        let body = fill_template(&settings.template, notification);
+        serde_json::from_str::<Value>(&body)
+            .map_err(|e| format!("Webhook template isn't valid JSON once filled in: {}", e))?;
+        body
+    } else {
+        match settings.format {
+            WebhookFormat::Generic => generic_body(notification).to_string(),
+            WebhookFormat::Slack => json!({
+                "text": format!("*{}*\n{}", notification.title, notification.message),
+            })
+            .to_string(),
+            WebhookFormat::Ntfy => {
+                // ntfy takes JSON at the server root, with the topic in the body.
+                let (server, topic) = settings
+                    .url
+                    .trim_end_matches('/')
+                    .rsplit_once('/')
+                    .filter(|(server, _)| server.contains("://"))
+                    .ok_or_else(|| format!("No ntfy topic in '{}'", settings.url))?;
+                url = format!("{}/", server);
+                json!({
+                    "topic": topic,
+                    "title": notification.title,
+                    "message": notification.message,
+                    "priority": match notification.urgency {
+                        Urgency::Info => 3,
+                        Urgency::Warning => 4,
+                    },
+                    "tags": ["video_game"],
+                })
+                .to_string()
+            }
+        }
+    };
+    Ok(Delivery {
+        id: 0,
+        url,
+        body,
+        created: now,
+        attempts: 0,
+        next_attempt: now,
+    })
+}
+
+fn generic_body(notification: &Notification) -> Value {
+    json!({
+        "event": notification.kind.name(),
+        "urgency": urgency_name(notification.urgency),
+        "title": notification.title,
+        "message": notification.message,
+        "controller": {
+            "path": notification.controller_path,
+            "name": notification.controller_name,
+        },
+        "battery": notification.report.as_ref().map(|report| json!({
+            "capacity": report.battery_capacity,
+            "status": battery_history::status_name(&report.battery_status),
+        })),
+    })
+}
+
+fn urgency_name(urgency: Urgency) -> &'static str {
+    match urgency {
+        Urgency::Info => "info",
+        Urgency::Warning => "warning",
+    }
+}
+
+fn fill_template(template: &str, notification: &Notification) -> String {
+    let report = notification.report.as_ref();
+    let values = [
+        ("{event}", notification.kind.name().to_string()),
+        ("{urgency}", urgency_name(notification.urgency).to_string()),
+        ("{title}", notification.title.clone()),
+        ("{message}", notification.message.clone()),
+        ("{controller}", notification.controller_name.clone()),
+        ("{path}", notification.controller_path.clone()),
+        (
+            "{capacity}",
+            report
+                .map(|report| report.battery_capacity.to_string())
+                .unwrap_or_default(),
+        ),
+        (
+            "{status}",
+            report
+                .map(|report| battery_history::status_name(&report.battery_status).to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+    let mut body = template.to_string();
+    for (placeholder, value) in values {
+        let escaped = Value::String(value).to_string();
+        body = body.replace(placeholder, &escaped[1..escaped.len() - 1]);
+    }
+    body
+}
+
+/// What to do with a delivery after an attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Delivered,
+    Retry,
+    /// The endpoint rejected it; sending it again won't help.
+    Reject,
+}
+
+pub fn outcome(result: &Result<u16, String>) -> Outcome {
+    match result {
+        Ok(200..=299) => Outcome::Delivered,
+        Ok(408 | 429 | 500..=599) | Err(_) => Outcome::Retry,
+        Ok(_) => Outcome::Reject,
+    }
+}
+
+/// Seconds to wait before the next attempt, after `attempts` failures.
+pub fn retry_delay(attempts: u32) -> u64 {
+    FIRST_RETRY_SECS
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(MAX_RETRY_SECS)
+}
+
+/// Deliveries in the order they were raised, mirrored to a file when there is
+/// one.
+pub struct RetryQueue {
+    path: Option<PathBuf>,
+    deliveries: VecDeque<Delivery>,
+    next_id: u64,
+}
+
+impl RetryQueue {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut deliveries: VecDeque<Delivery> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        for (id, delivery) in deliveries.iter_mut().enumerate() {
+            delivery.id = id as u64;
+        }
+        let next_id = deliveries.len() as u64;
+        Self {
+            path,
+            deliveries,
+            next_id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deliveries.len()
+    }
+
+    pub fn push(&mut self, mut delivery: Delivery) {
+        delivery.id = self.next_id;
+        self.next_id += 1;
+        self.deliveries.push_back(delivery);
+        while self.deliveries.len() > MAX_QUEUED {
+            self.deliveries.pop_front();
+        }
+        self.save();
+    }
+
+    /// The delivery to attempt next, or how many seconds until it's due.
+    pub fn next(&self, now: u64) -> Option<Result<Delivery, u64>> {
+        let front = self.deliveries.front()?;
+        Some(if front.next_attempt <= now {
+            Ok(front.clone())
+        } else {
+            Err(front.next_attempt - now)
+        })
+    }
+
+    /// Records how the attempt at delivery `id` went. Deliveries dropped from
+    /// a full queue while they were being sent are ignored.
+    pub fn finish(&mut self, id: u64, outcome: Outcome, now: u64) {
+        let Some(index) = self
+            .deliveries
+            .iter()
+            .position(|delivery| delivery.id == id)
+        else {
+            return;
+        };
+        let delivery = &mut self.deliveries[index];
+        match outcome {
+            Outcome::Retry if now.saturating_sub(delivery.created) < MAX_AGE_SECS => {
+                delivery.attempts += 1;
+                delivery.next_attempt = now + retry_delay(delivery.attempts);
+            }
+            Outcome::Retry => {
+                eprintln!(
+                    "Webhook: Giving up on a delivery after {} attempts",
+                    delivery.attempts + 1
+                );
+                self.deliveries.remove(index);
+            }
+            Outcome::Delivered | Outcome::Reject => {
+                self.deliveries.remove(index);
+            }
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string(&self.deliveries)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                paths::write_atomically(path, text.as_bytes())
+            });
+        if let Err(e) = result {
+            eprintln!("Webhook: Failed to save {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub struct WebhookNotifier {
+    settings: WebhookSettings,
+    queue: Arc<(Mutex<RetryQueue>, Condvar)>,
+}
+
+impl WebhookNotifier {
+    /// Loads the saved queue and starts delivering it.
+    pub fn spawn(settings: WebhookSettings) -> Result<Self, String> {
+        let queue_path = paths::data_dir().map(|dir| dir.join(QUEUE_FILE_NAME));
+        Self::spawn_with_queue(settings, queue_path)
+    }
+
+    pub fn spawn_with_queue(
+        settings: WebhookSettings,
+        queue_path: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        Url::parse(&settings.url)?;
+        let queue = RetryQueue::load(queue_path);
+        if queue.len() > 0 {
+            println!("Webhook: {} deliveries left from last time", queue.len());
+        }
+        let queue = Arc::new((Mutex::new(queue), Condvar::new()));
+        let timeout = Duration::from_secs(settings.timeout_secs);
+        let worker_queue = queue.clone();
+        thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || deliver_forever(&worker_queue, timeout))
+            .map_err(|e| format!("Failed to start webhook thread: {}", e))?;
+        Ok(Self { settings, queue })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), String> {
+        if !self.settings.events.contains(&notification.kind) {
+            return Ok(());
+        }
+        let delivery = render(&self.settings, notification, unix_now())?;
+        let (queue, wake) = &*self.queue;
+        queue
+            .lock()
+            .map_err(|_| "Webhook queue unavailable".to_string())?
+            .push(delivery);
+        wake.notify_one();
+        Ok(())
+    }
+}
+
+fn deliver_forever(queue: &(Mutex<RetryQueue>, Condvar), timeout: Duration) {
+    let (queue, wake) = queue;
+    let Ok(mut guard) = queue.lock() else {
+        return;
+    };
+    loop {
+        let delivery = match guard.next(unix_now()) {
+            Some(Ok(delivery)) => delivery,
+            Some(Err(wait_secs)) => {
+                let Ok((next_guard, _)) = wake.wait_timeout(guard, Duration::from_secs(wait_secs))
+                else {
+                    return;
+                };
+                guard = next_guard;
+                continue;
+            }
+            None => {
+                let Ok(next_guard) = wake.wait(guard) else {
+                    return;
+                };
+                guard = next_guard;
+                continue;
+            }
+        };
+
+        // Send without holding the lock, so notifying never waits on the network.
+        drop(guard);
+        let outcome = match Url::parse(&delivery.url) {
+            Ok(url) => {
+                let result = http_client::post_json(&url, &delivery.body, timeout);
+                match &result {
+                    Ok(200..=299) => {}
+                    Ok(status) => eprintln!("Webhook: {} answered {}", delivery.url, status),
+                    Err(e) => eprintln!("Webhook: {}", e),
+                }
+                outcome(&result)
+            }
+            Err(e) => {
+                eprintln!("Webhook: {}", e);
+                Outcome::Reject
+            }
+        };
+        let Ok(next_guard) = queue.lock() else {
+            return;
+        };
+        guard = next_guard;
+        guard.finish(delivery.id, outcome, unix_now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{
+        dualsense::{BatteryReport, BatteryStatus},
+        notifications::NotificationKind,
+    };
+
+    fn notification(message: &str) -> Notification {
+        Notification {
+            kind: NotificationKind::LowBattery,
+            urgency: Urgency::Warning,
+            controller_path: "pad-path".to_string(),
+            controller_name: "Couch pad".to_string(),
+            report: Some(BatteryReport::new(12, BatteryStatus::Discharging)),
+            title: "Controller battery low".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Answers one POST per status in `statuses`, in order, and passes each
+    /// request's path and body on. Returns the URL to post to.
+    fn serve(statuses: Vec<u16>) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Test\r\nContent-Length: 0\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+                let _ = sender.send((path, String::from_utf8(body).unwrap()));
+            }
+        });
+        (url, requests)
+    }
+
+    fn settings(url: &str) -> WebhookSettings {
+        WebhookSettings {
+            enabled: true,
+            url: url.to_string(),
+            timeout_secs: 5,
+            ..WebhookSettings::default()
+        }
+    }
+
+    /// A queue file of its own for each test.
+    fn queue_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ds-battery-webhook-{}-{}.json",
+            std::process::id(),
+            test
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn receive(requests: &mpsc::Receiver<(String, String)>) -> (String, String) {
+        requests.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    /// Waits for the worker to get through its attempt.
+    fn wait_for(notifier: &WebhookNotifier, done: impl Fn(&RetryQueue) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(&notifier.queue.0.lock().unwrap()) {
+            assert!(Instant::now() < deadline, "webhook queue never settled");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn saved_queue(path: &PathBuf) -> Vec<Delivery> {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn posts_the_generic_body() {
+        let (url, requests) = serve(vec![204]);
+        let path = queue_path("generic");
+        let mut notifier =
+            WebhookNotifier::spawn_with_queue(settings(&url), Some(path.clone())).unwrap();
+        notifier
+            .notify(&notification("Player 1 is at 12%"))
+            .unwrap();
+
+        let (request_path, body) = receive(&requests);
+        assert_eq!(request_path, "/hook");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "event": "low_battery",
+                "urgency": "warning",
+                "title": "Controller battery low",
+                "message": "Player 1 is at 12%",
+                "controller": { "path": "pad-path", "name": "Couch pad" },
+                "battery": { "capacity": 12, "status": "discharging" },
+            })
+        );
+        wait_for(&notifier, |queue| queue.len() == 0);
+        assert_eq!(saved_queue(&path), []);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn server_errors_are_retried_later() {
+        let (url, requests) = serve(vec![503]);
+        let path = queue_path("retry");
+        let mut notifier =
+            WebhookNotifier::spawn_with_queue(settings(&url), Some(path.clone())).unwrap();
+        notifier.notify(&notification("Low")).unwrap();
+        receive(&requests);
+
+        wait_for(&notifier, |queue| {
+            queue.deliveries.front().is_some_and(|d| d.attempts == 1)
+        });
+        let saved = saved_queue(&path);
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].attempts, 1);
+        assert!(saved[0].next_attempt >= saved[0].created + FIRST_RETRY_SECS);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn client_errors_are_dropped() {
+        let (url, requests) = serve(vec![400]);
+        let path = queue_path("reject");
+        let mut notifier =
+            WebhookNotifier::spawn_with_queue(settings(&url), Some(path.clone())).unwrap();
+        notifier.notify(&notification("Low")).unwrap();
+        receive(&requests);
+
+        wait_for(&notifier, |queue| queue.len() == 0);
+        assert_eq!(saved_queue(&path), []);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn queued_deliveries_survive_a_restart() {
+        let (url, requests) = serve(vec![200]);
+        let path = queue_path("reload");
+        // Left over from a run that couldn't reach the endpoint.
+        let delivery =
+            render(&settings(&url), &notification("From last time"), unix_now()).unwrap();
+        RetryQueue::load(Some(path.clone())).push(delivery.clone());
+        assert_eq!(saved_queue(&path), std::slice::from_ref(&delivery));
+
+        let notifier =
+            WebhookNotifier::spawn_with_queue(settings(&url), Some(path.clone())).unwrap();
+        assert_eq!(receive(&requests).1, delivery.body);
+        wait_for(&notifier, |queue| queue.len() == 0);
+        assert_eq!(saved_queue(&path), []);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn template_values_are_json_escaped() {
+        let (url, requests) = serve(vec![200]);
+        let path = queue_path("template");
+        let settings = WebhookSettings {
+            template: r#"{"text": "{controller}: {message} ({capacity}%)"}"#.to_string(),
+            ..settings(&url)
+        };
+        let mut notifier = WebhookNotifier::spawn_with_queue(settings, Some(path.clone())).unwrap();
+        let message = "Said \"charge me\"\nC:\\pads\t✓";
+        notifier.notify(&notification(message)).unwrap();
+
+        let body: Value = serde_json::from_str(&receive(&requests).1).unwrap();
+        assert_eq!(body["text"], format!("Couch pad: {} (12%)", message));
+        wait_for(&notifier, |queue| queue.len() == 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unsubscribed_notifications_are_skipped() {
+        let path = queue_path("skipped");
+        let settings = WebhookSettings {
+            events: vec![NotificationKind::Disconnected],
+            ..settings("http://127.0.0.1:9/hook")
+        };
+        let mut notifier = WebhookNotifier::spawn_with_queue(settings, Some(path.clone())).unwrap();
+        notifier.notify(&notification("Low")).unwrap();
+        assert_eq!(notifier.queue.0.lock().unwrap().len(), 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn ntfy_posts_to_the_server_root() {
+        let settings = WebhookSettings {
+            format: WebhookFormat::Ntfy,
+            ..settings("https://ntfy.example/my-topic")
+        };
+        let delivery = render(&settings, &notification("Low"), 0).unwrap();
+        assert_eq!(delivery.url, "https://ntfy.example/");
+        let body: Value = serde_json::from_str(&delivery.body).unwrap();
+        assert_eq!(body["topic"], "my-topic");
+        assert_eq!(body["priority"], 4);
+    }
+
+    #[test]
+    fn outcomes_and_backoff() {
+        assert_eq!(outcome(&Ok(200)), Outcome::Delivered);
+        assert_eq!(outcome(&Ok(429)), Outcome::Retry);
+        assert_eq!(outcome(&Ok(502)), Outcome::Retry);
+        assert_eq!(outcome(&Err("refused".to_string())), Outcome::Retry);
+        assert_eq!(outcome(&Ok(404)), Outcome::Reject);
+        let delays: Vec<u64> = (1..=9).map(retry_delay).collect();
+        assert_eq!(delays, [10, 20, 40, 80, 160, 320, 640, 900, 900]);
+    }
+
+    #[test]
+    fn queue_drops_the_oldest_past_its_limit() {
+        let mut queue = RetryQueue::load(None);
+        for created in 0..MAX_QUEUED as u64 + 5 {
+            queue.push(Delivery {
+                id: 0,
+                url: String::new(),
+                body: String::new(),
+                created,
+                attempts: 0,
+                next_attempt: created,
+            });
+        }
+        assert_eq!(queue.len(), MAX_QUEUED);
+        assert_eq!(queue.next(u64::MAX).unwrap().unwrap().created, 5);
+    }
+
+    fn delivery(created: u64) -> Delivery {
+        Delivery {
+            id: 0,
+            url: String::new(),
+            body: created.to_string(),
+            created,
+            attempts: 0,
+            next_attempt: created,
+        }
+    }
+
+    #[test]
+    fn finishing_a_dropped_delivery_leaves_the_others_alone() {
+        let mut queue = RetryQueue::load(None);
+        for created in 0..MAX_QUEUED as u64 {
+            queue.push(delivery(created));
+        }
+        let in_flight = queue.next(u64::MAX).unwrap().unwrap();
+        // While it's being sent the queue overflows and drops it.
+        queue.push(delivery(1000));
+
+        for outcome in [Outcome::Delivered, Outcome::Reject, Outcome::Retry] {
+            queue.finish(in_flight.id, outcome, 10);
+            assert_eq!(queue.len(), MAX_QUEUED);
+            let next = queue.next(u64::MAX).unwrap().unwrap();
+            assert_eq!((next.created, next.attempts), (1, 0));
+        }
+    }
+
+    #[test]
+    fn finish_applies_to_the_delivery_that_was_sent() {
+        let mut queue = RetryQueue::load(None);
+        queue.push(delivery(0));
+        queue.push(delivery(1));
+        let first = queue.next(0).unwrap().unwrap();
+        assert_ne!(first.id, queue.deliveries[1].id);
+
+        queue.finish(first.id, Outcome::Retry, 0);
+        assert_eq!(queue.deliveries[0].attempts, 1);
+        assert_eq!(queue.deliveries[1].attempts, 0);
+        queue.finish(first.id, Outcome::Delivered, 10);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.next(u64::MAX).unwrap().unwrap().created, 1);
+    }
+
+    #[test]
+    fn loaded_deliveries_get_fresh_ids() {
+        let path = queue_path("ids");
+        let mut queue = RetryQueue::load(Some(path.clone()));
+        queue.push(delivery(0));
+        queue.push(delivery(1));
+        assert!(!fs::read_to_string(&path).unwrap().contains("\"id\""));
+
+        let mut queue = RetryQueue::load(Some(path.clone()));
+        let ids: Vec<u64> = queue.deliveries.iter().map(|d| d.id).collect();
+        assert_eq!(ids, [0, 1]);
+        queue.push(delivery(2));
+        assert_eq!(queue.deliveries[2].id, 2);
+        let _ = fs::remove_file(&path);
+    }
+}
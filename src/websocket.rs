@@ -0,0 +1,276 @@
+//! Server side of RFC 6455 WebSockets, enough to push text messages to
+//! browsers.
+//!
+//! Messages only flow from the server; frames from the client are read just to
+//! answer pings and notice when it closes.
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{checksum, http::Request};
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Clients have nothing to say, so anything bigger is treated as an error.
+const MAX_CLIENT_PAYLOAD: u64 = 4096;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Ping(Vec<u8>),
+    Close,
+    /// Data and pong frames, which the server ignores.
+    Other,
+}
+
+pub fn is_upgrade(request: &Request) -> bool {
+    request
+        .header("Upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Answers the upgrade request; from here on the stream carries frames.
+pub fn accept(mut stream: &TcpStream, request: &Request) -> Result<(), String> {
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .ok_or("Missing Sec-WebSocket-Key header")?;
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )
+    .and_then(|_| stream.flush())
+    .map_err(|e| e.to_string())
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&checksum::sha1(
+        format!("{}{}", key, HANDSHAKE_GUID).as_bytes(),
+    ))
+}
+
+pub fn write_text(stream: &TcpStream, text: &str) -> io::Result<()> {
+    write_frame(stream, OPCODE_TEXT, text.as_bytes())
+}
+
+pub fn write_ping(stream: &TcpStream) -> io::Result<()> {
+    write_frame(stream, OPCODE_PING, &[])
+}
+
+pub fn write_pong(stream: &TcpStream, payload: &[u8]) -> io::Result<()> {
+    write_frame(stream, OPCODE_PONG, payload)
+}
+
+pub fn write_close(stream: &TcpStream) -> io::Result<()> {
+    write_frame(stream, OPCODE_CLOSE, &[])
+}
+
+/// Writes one unfragmented, unmasked frame, as servers must.
+fn write_frame(mut stream: impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads the next frame from the client, unmasking its payload.
+pub fn read_frame(mut stream: impl Read) -> io::Result<Frame> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut bytes = [0u8; 2];
+            stream.read_exact(&mut bytes)?;
+            u64::from(u16::from_be_bytes(bytes))
+        }
+        127 => {
+            let mut bytes = [0u8; 8];
+            stream.read_exact(&mut bytes)?;
+            u64::from_be_bytes(bytes)
+        }
+        length => u64::from(length),
+    };
+    if !masked || length > MAX_CLIENT_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unmasked or oversized client frame",
+        ));
+    }
+
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; length as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(match opcode {
+        OPCODE_PING => Frame::Ping(payload),
+        OPCODE_CLOSE => Frame::Close,
+        _ => Frame::Other,
+    })
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - i * 6) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client frame with `payload` masked by `mask`.
+    fn client_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_6455() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn writes_short_frames() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, OPCODE_TEXT, b"hi").unwrap();
+        assert_eq!(frame, [0x81, 2, b'h', b'i']);
+
+        let mut frame = Vec::new();
+        write_frame(&mut frame, OPCODE_CLOSE, &[]).unwrap();
+        assert_eq!(frame, [0x88, 0]);
+    }
+
+    #[test]
+    fn writes_extended_lengths() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, OPCODE_TEXT, &[b'a'; 126]).unwrap();
+        assert_eq!(frame[..4], [0x81, 126, 0, 126]);
+        assert_eq!(frame.len(), 4 + 126);
+
+        let mut frame = Vec::new();
+        write_frame(&mut frame, OPCODE_TEXT, &[b'a'; 0x1_0000]).unwrap();
+        assert_eq!(frame[..10], [0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame.len(), 10 + 0x1_0000);
+    }
+
+    #[test]
+    fn reads_masked_frames() {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        // The masked "Hello" from RFC 6455 section 5.7.
+        let frame = [
+            0x89, 0x85, 0x37, 0xFA, 0x21, 0x3D, 0x7F, 0x9F, 0x4D, 0x51, 0x58,
+        ];
+        assert_eq!(
+            read_frame(&frame[..]).unwrap(),
+            Frame::Ping(b"Hello".to_vec())
+        );
+        assert_eq!(
+            read_frame(&client_frame(OPCODE_CLOSE, &[], mask)[..]).unwrap(),
+            Frame::Close
+        );
+        assert_eq!(
+            read_frame(&client_frame(OPCODE_TEXT, b"ignored", mask)[..]).unwrap(),
+            Frame::Other
+        );
+    }
+
+    #[test]
+    fn reads_extended_lengths() {
+        let payload: Vec<u8> = (0..=255).cycle().take(300).collect();
+        let frame = client_frame(OPCODE_PING, &payload, [1, 2, 3, 4]);
+        assert_eq!(frame[1..4], [0x80 | 126, 1, 44]);
+        assert_eq!(read_frame(&frame[..]).unwrap(), Frame::Ping(payload));
+    }
+
+    #[test]
+    fn rejects_unmasked_and_oversized_frames() {
+        let unmasked = [0x89, 0x02, b'h', b'i'];
+        assert_eq!(
+            read_frame(&unmasked[..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let oversized = client_frame(OPCODE_TEXT, &[0; 0x1_0000], [1, 2, 3, 4]);
+        assert_eq!(oversized[1], 0x80 | 127);
+        assert_eq!(
+            read_frame(&oversized[..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let frame = client_frame(OPCODE_PING, b"Hello", [1, 2, 3, 4]);
+        assert_eq!(
+            read_frame(&frame[..frame.len() - 1]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
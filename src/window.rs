@@ -1,4 +1,7 @@
-use crate::{AppState, HOTKEY_ID_TOGGLE, window_creator::WindowCreator, window_message_handler};
+use crate::{
+    AppState, HOTKEY_ID_TOGGLE, graphics, overlay_layout, window_creator::WindowCreator,
+    window_message_handler,
+};
 use windows::{
     Win32::{
         Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
@@ -36,6 +39,37 @@ pub fn show_and_set_topmost(hwnd: &HWND) {
     }
 }
 
+/// Window size in pixels for an overlay listing `controller_count` controllers.
+pub fn overlay_window_size(controller_count: usize) -> (i32, i32) {
+    let (width, height) = overlay_layout::overlay_size(controller_count);
+    (width.ceil() as i32, height.ceil() as i32)
+}
+
+/// Grows or shrinks the overlay window and its swap chain to fit every listed
+/// controller, keeping it anchored at the same spot on screen. Callers redraw.
+pub fn fit_overlay_to_controllers(app_state: &mut AppState) {
+    let (width, height) = overlay_window_size(app_state.controllers.len());
+    if (width, height) == app_state.window_size {
+        return;
+    }
+
+    println!("Resizing overlay to {}x{}", width, height);
+    let (x, y) = WindowCreator::calculate_window_position(width, height);
+    unsafe {
+        let _ = SetWindowPos(
+            app_state.hwnd,
+            Some(HWND_TOPMOST),
+            x,
+            y,
+            width,
+            height,
+            SWP_NOACTIVATE,
+        );
+    }
+    graphics::resize_swap_chain(&app_state.swap_chain, width as u32, height as u32);
+    app_state.window_size = (width, height);
+}
+
 pub fn register_app_hotkey(hwnd: HWND) -> Result<()> {
     let modifiers = MOD_CONTROL | MOD_ALT;
     let vk = VK_B.0 as u32;
@@ -1,4 +1,7 @@
-use crate::{AppState, WINDOW_HEIGHT, WINDOW_WIDTH, window::wndproc};
+use crate::{
+    AppState,
+    window::{overlay_window_size, wndproc},
+};
 use windows::{
     Win32::{
         Foundation::{GetLastError, HINSTANCE, HWND},
@@ -58,18 +61,20 @@ impl WindowCreator {
         }
     }
 
-    fn calculate_window_position() -> (i32, i32) {
+    /// Horizontally centered, with the overlay's middle at 80% of the screen height.
+    pub fn calculate_window_position(width: i32, height: i32) -> (i32, i32) {
         let screen_width = unsafe { GetSystemMetrics(SM_CXSCREEN) };
         let screen_height = unsafe { GetSystemMetrics(SM_CYSCREEN) };
 
-        let x = (screen_width - WINDOW_WIDTH) / 2;
-        let y = (screen_height * 4) / 5 - (WINDOW_HEIGHT / 2);
+        let x = (screen_width - width) / 2;
+        let y = (screen_height * 4) / 5 - (height / 2);
 
         (x.max(0), y.max(0))
     }
 
     fn create_window_instance(&self) -> Result<HWND, Error> {
-        let (x, y) = Self::calculate_window_position();
+        let (width, height) = overlay_window_size(0);
+        let (x, y) = Self::calculate_window_position(width, height);
 
         let hwnd = unsafe {
             CreateWindowExW(
@@ -79,8 +84,8 @@ impl WindowCreator {
                 WS_POPUP,
                 x,
                 y,
-                WINDOW_WIDTH,
-                WINDOW_HEIGHT,
+                width,
+                height,
                 None,
                 None,
                 Some(self.hinstance),
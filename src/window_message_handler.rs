@@ -1,6 +1,15 @@
+use std::time::Instant;
+
 use crate::{
-    AppState, HOTKEY_ID_TOGGLE, IDM_CONFIGURE, IDM_EXIT, IDM_RUN_ON_STARTUP, SHOW_DURATION_MS,
-    TIMER_ID_FADEOUT, VisibilityState, WM_APP_TRAYMSG, graphics, renderer, tray,
+    ANIMATION_FRAME_MS, AppState, DISCONNECTED_NOTICE_MS, HOTKEY_ID_TOGGLE, SHOW_DURATION_MS,
+    TIMER_ID_ANIMATION, TIMER_ID_DISCONNECTED, TIMER_ID_FADE, TIMER_ID_FADEOUT, WM_APP_TRAYMSG,
+    dualsense::{BatteryStatus, ControllerCommand},
+    graphics,
+    output_report::Rgb,
+    renderer, text_prompt, tray,
+    tray_menu::MenuAction,
+    visibility::{Fade, VisibilityEffect, VisibilityInput},
+    window,
 };
 use windows::Win32::{
     Foundation::{GetLastError, HWND, LPARAM, LRESULT, WPARAM},
@@ -21,8 +30,8 @@ pub fn handle_message(
     match msg {
         WM_HOTKEY => handle_hotkey_message(hwnd, wparam, app_state),
         WM_TIMER => handle_timer_message(hwnd, wparam, app_state),
-        WM_COMMAND => handle_command_message(hwnd, wparam),
-        WM_APP_TRAYMSG => handle_tray_message(hwnd, lparam),
+        WM_COMMAND => handle_command_message(hwnd, wparam, app_state),
+        WM_APP_TRAYMSG => handle_tray_message(lparam, app_state),
         WM_PAINT => handle_paint_message(hwnd),
         WM_DESTROY => handle_destroy_message(hwnd),
         _ => None,
@@ -38,23 +47,44 @@ fn handle_hotkey_message(_hwnd: HWND, wparam: WPARAM, app_state: &mut AppState)
     }
 }
 
-fn handle_timer_message(_hwnd: HWND, wparam: WPARAM, app_state: &mut AppState) -> Option<LRESULT> {
-    if wparam.0 == TIMER_ID_FADEOUT {
-        start_fade_out(app_state);
-        Some(LRESULT(0))
-    } else {
-        None
+fn handle_timer_message(hwnd: HWND, wparam: WPARAM, app_state: &mut AppState) -> Option<LRESULT> {
+    match wparam.0 {
+        TIMER_ID_FADEOUT => {
+            kill_existing_timer(app_state);
+            dispatch_visibility(app_state, VisibilityInput::TimerExpired);
+            Some(LRESULT(0))
+        }
+        TIMER_ID_FADE => {
+            unsafe {
+                let _ = KillTimer(Some(hwnd), TIMER_ID_FADE);
+            };
+            dispatch_visibility(app_state, VisibilityInput::FadeComplete);
+            Some(LRESULT(0))
+        }
+        TIMER_ID_DISCONNECTED => {
+            unsafe {
+                let _ = KillTimer(Some(hwnd), TIMER_ID_DISCONNECTED);
+            };
+            clear_disconnected_controllers(app_state);
+            Some(LRESULT(0))
+        }
+        TIMER_ID_ANIMATION => {
+            renderer::draw_content(app_state);
+            update_animation_timer(app_state);
+            Some(LRESULT(0))
+        }
+        _ => None,
     }
 }
 
-fn handle_command_message(hwnd: HWND, wparam: WPARAM) -> Option<LRESULT> {
+fn handle_command_message(hwnd: HWND, wparam: WPARAM, app_state: &mut AppState) -> Option<LRESULT> {
     let menu_id = (wparam.0 & 0xFFFF) as u16;
-    match menu_id {
-        IDM_CONFIGURE => {
+    let action = app_state.tray_menu.action(menu_id)?.clone();
+    match action {
+        MenuAction::Configure => {
             println!("Configure menu item clicked");
-            Some(LRESULT(0))
         }
-        IDM_RUN_ON_STARTUP => {
+        MenuAction::ToggleRunOnStartup => {
             println!("Run on Startup menu item clicked");
             match tray::is_run_on_startup_enabled() {
                 Ok(current_enabled) => match tray::set_run_on_startup(!current_enabled) {
@@ -63,24 +93,100 @@ fn handle_command_message(hwnd: HWND, wparam: WPARAM) -> Option<LRESULT> {
                 },
                 Err(e) => eprintln!("Failed to check run on startup: {}", e),
             }
-            Some(LRESULT(0))
         }
-        IDM_EXIT => {
+        MenuAction::Exit => {
             println!("Exit menu item clicked");
             unsafe {
                 let _ = DestroyWindow(hwnd);
             };
-            Some(LRESULT(0))
         }
-        _ => None,
+        MenuAction::ShowOverlay(path) => show_overlay_for(app_state, Some(path)),
+        MenuAction::Identify(path) => {
+            send_controller_command(app_state, ControllerCommand::Identify(path));
+        }
+        MenuAction::Rename(path) => rename_controller(app_state, &path),
+        MenuAction::SetLightbar(path, color) => set_lightbar(app_state, path, color),
+    }
+    Some(LRESULT(0))
+}
+
+/// Pops the overlay up, highlighting the controller at `path` if given.
+pub fn show_overlay_for(app_state: &mut AppState, path: Option<String>) {
+    if path.is_some() {
+        app_state.triggering_controller_path = path;
     }
+    toggle_window_visibility(app_state);
 }
 
-fn handle_tray_message(hwnd: HWND, lparam: LPARAM) -> Option<LRESULT> {
+/// Sets a controller's lightbar and remembers the colour for next time.
+pub fn set_lightbar(app_state: &mut AppState, path: String, color: Rgb) {
+    if let Some(entry) = app_state.controllers.get(&path) {
+        let identity = entry.identity().to_string();
+        app_state.config.controller_mut(&identity).lightbar = Some(color);
+        save_config(app_state);
+    }
+    send_controller_command(app_state, ControllerCommand::SetLightbar(path, color));
+}
+
+fn rename_controller(app_state: &mut AppState, path: &str) {
+    let Some(entry) = app_state.controllers.get(path) else {
+        return;
+    };
+    let identity = entry.identity().to_string();
+    let current_name = entry.display_name().to_string();
+    let Some(name) = text_prompt::prompt_for_text(
+        "Rename controller",
+        &format!("New name for player {}:", entry.player_number),
+        &current_name,
+    ) else {
+        return;
+    };
+
+    // An empty name goes back to the model name.
+    let name = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+    app_state.controllers.set_name(path, name.clone());
+    app_state.config.controller_mut(&identity).name = name;
+    save_config(app_state);
+
+    refresh_controller_feeds(app_state);
+    tray::refresh_tooltip(app_state);
+    if app_state.visibility_state.is_shown() {
+        renderer::draw_content(app_state);
+    }
+}
+
+/// Passes changes that don't come with a controller event, like renames, on to
+/// the web API and the MQTT broker.
+fn refresh_controller_feeds(app_state: &AppState) {
+    if let Ok(mut api_state) = app_state.api_state.lock() {
+        api_state.refresh(&app_state.controllers);
+    }
+    if let Some(mqtt) = &app_state.mqtt {
+        mqtt.update(&app_state.controllers);
+    }
+}
+
+pub fn send_controller_command(app_state: &AppState, command: ControllerCommand) {
+    if let Err(e) = app_state.controller_commands.send(command) {
+        eprintln!("Failed to send controller command: {}", e);
+    }
+}
+
+fn save_config(app_state: &AppState) {
+    if app_state.config_load_failed {
+        eprintln!("Not saving config over the file that failed to load; fix it and restart");
+        return;
+    }
+    if let Err(e) = app_state.config.save() {
+        eprintln!("Failed to save config: {}", e);
+    }
+}
+
+fn handle_tray_message(lparam: LPARAM, app_state: &mut AppState) -> Option<LRESULT> {
     let mouse_msg = (lparam.0 & 0xFFFF) as u32;
     if mouse_msg == WM_RBUTTONUP {
         println!("Tray icon right-clicked");
-        tray::show_context_menu(hwnd).unwrap_or_else(|e| {
+        tray::show_context_menu(app_state).unwrap_or_else(|e| {
             eprintln!("Failed to show context menu: {}", e);
         });
         Some(LRESULT(0))
@@ -103,6 +209,9 @@ fn handle_destroy_message(hwnd: HWND) -> Option<LRESULT> {
     println!("WM_DESTROY received");
     unsafe {
         let _ = KillTimer(Some(hwnd), TIMER_ID_FADEOUT);
+        let _ = KillTimer(Some(hwnd), TIMER_ID_DISCONNECTED);
+        let _ = KillTimer(Some(hwnd), TIMER_ID_ANIMATION);
+        let _ = KillTimer(Some(hwnd), TIMER_ID_FADE);
         PostQuitMessage(0);
     };
     Some(LRESULT(0))
@@ -111,69 +220,156 @@ fn handle_destroy_message(hwnd: HWND) -> Option<LRESULT> {
 // --- Visibility and Timer Logic ---
 
 pub fn toggle_window_visibility(app_state: &mut AppState) {
-    match app_state.visibility_state {
-        VisibilityState::Hidden | VisibilityState::FadingOut => {
-            show_window_and_start_timer(app_state);
-        }
-        VisibilityState::Visible => {
-            reset_window_timer(app_state);
+    dispatch_visibility(app_state, VisibilityInput::Toggle);
+}
+
+/// Runs `input` through the visibility state machine and carries out the result.
+fn dispatch_visibility(app_state: &mut AppState, input: VisibilityInput) {
+    let (next_state, effects) = app_state.visibility_state.next(input);
+    println!(
+        "Visibility: {:?} + {:?} -> {:?} {:?}",
+        app_state.visibility_state, input, next_state, effects
+    );
+    app_state.visibility_state = next_state;
+
+    for effect in effects {
+        match effect {
+            VisibilityEffect::Show => show_window(app_state),
+            VisibilityEffect::StartTimer => {
+                kill_existing_timer(app_state);
+                start_new_timer(app_state);
+            }
+            VisibilityEffect::Animate(fade) => start_fade(app_state, fade),
+            VisibilityEffect::Hide => hide_window(app_state),
         }
     }
-}
 
-fn show_window_and_start_timer(app_state: &mut AppState) {
-    println!("Showing window");
-    app_state.visibility_state = VisibilityState::Visible;
+    if app_state.visibility_state.is_shown() {
+        renderer::draw_content(app_state);
+    }
+    commit_dcomp_changes(app_state);
+    update_animation_timer(app_state);
+}
 
-    kill_existing_timer(app_state);
+fn show_window(app_state: &mut AppState) {
     apply_full_opacity(app_state);
-    commit_dcomp_changes(app_state); // Commit opacity change
     crate::window::show_and_set_topmost(&app_state.hwnd);
-    renderer::draw_content(app_state);
-    start_new_timer(app_state);
-    commit_dcomp_changes(app_state); // Commit potential draw changes? Maybe redundant
 }
 
-fn reset_window_timer(app_state: &mut AppState) {
-    println!("Window already visible, resetting timer");
-    renderer::draw_content(app_state);
+fn start_fade(app_state: &mut AppState, fade: Fade) {
     kill_existing_timer(app_state);
-    start_new_timer(app_state);
-}
-
-fn start_fade_out(app_state: &mut AppState) {
-    println!(
-        "Fadeout timer triggered. Current state: {:?}",
-        app_state.visibility_state
-    );
-
-    if app_state.visibility_state != VisibilityState::Visible {
-        println!("State is not Visible, hiding immediately.");
-        hide_window_immediately(app_state);
-        return;
+    let (animation, duration_sec) = match fade {
+        Fade::In => (&app_state.fade_in_animation, graphics::FADE_IN_DURATION_SEC),
+        Fade::Out => (
+            &app_state.fade_out_animation,
+            graphics::FADE_OUT_DURATION_SEC,
+        ),
+    };
+    match animation {
+        Some(animation) => {
+            graphics::apply_opacity_animation(&app_state.dcomp_effect_group, animation)
+        }
+        None => eprintln!("Error: no {:?} fade animation, skipping the fade", fade),
     }
 
-    println!("Fading out window");
-    app_state.visibility_state = VisibilityState::FadingOut;
-    kill_existing_timer(app_state); // Should already be killed by WM_TIMER, but belt-and-suspenders
-
-    if let Some(anim) = &app_state.fade_out_animation {
-        graphics::apply_opacity_animation(&app_state.dcomp_effect_group, anim);
-        commit_dcomp_changes(app_state);
-    } else {
-        eprintln!("Error: fade_out_animation object is None! Hiding immediately.");
-        hide_window_immediately(app_state);
+    // DirectComposition doesn't say when an animation ends, so time it ourselves.
+    let timer_id = unsafe {
+        SetTimer(
+            Some(app_state.hwnd),
+            TIMER_ID_FADE,
+            (duration_sec * 1000.0) as u32,
+            None,
+        )
+    };
+    if timer_id == 0 {
+        eprintln!("Failed to set fade timer! Error: {:?}", unsafe {
+            GetLastError()
+        });
+        dispatch_visibility(app_state, VisibilityInput::FadeComplete);
     }
 }
 
-fn hide_window_immediately(app_state: &mut AppState) {
-    app_state.visibility_state = VisibilityState::Hidden;
-    kill_existing_timer(app_state); // Ensure timer is gone
+fn hide_window(app_state: &mut AppState) {
+    kill_existing_timer(app_state);
+    unsafe {
+        let _ = KillTimer(Some(app_state.hwnd), TIMER_ID_FADE);
+    };
     apply_zero_opacity(app_state);
-    commit_dcomp_changes(app_state);
     unsafe {
         let _ = ShowWindow(app_state.hwnd, SW_HIDE);
     };
+    clear_disconnected_controllers(app_state);
+}
+
+/// Shows a pad that just dropped as disconnected for a moment, keeping the
+/// overlay up long enough for the notice to be read.
+pub fn show_disconnected_notice(app_state: &mut AppState) {
+    println!("Showing disconnected notice");
+    dispatch_visibility(app_state, VisibilityInput::ControllerGone);
+
+    let timer_id = unsafe {
+        SetTimer(
+            Some(app_state.hwnd),
+            TIMER_ID_DISCONNECTED,
+            DISCONNECTED_NOTICE_MS,
+            None,
+        )
+    };
+    if timer_id == 0 {
+        eprintln!(
+            "Failed to set disconnected notice timer! Error: {:?}",
+            unsafe { GetLastError() }
+        );
+        clear_disconnected_controllers(app_state);
+    }
+}
+
+fn clear_disconnected_controllers(app_state: &mut AppState) {
+    let removed = app_state.controllers.remove_disconnected();
+    for entry in &removed {
+        app_state.animations.remove(&entry.path);
+    }
+    if !removed.is_empty() {
+        refresh_controller_feeds(app_state);
+        tray::refresh_tooltip(app_state);
+        window::fit_overlay_to_controllers(app_state);
+        if app_state.visibility_state.is_shown() {
+            renderer::draw_content(app_state);
+        }
+    }
+}
+
+/// Runs the frame timer while the overlay is up and something is animating.
+/// Animation progress comes from the clock, not from how often this fires.
+pub fn update_animation_timer(app_state: &mut AppState) {
+    let any_charging = app_state.controllers.iter().any(|entry| {
+        entry.connected
+            && entry
+                .battery
+                .as_ref()
+                .is_some_and(|report| report.battery_status == BatteryStatus::Charging)
+    });
+    let needs_frames = app_state.visibility_state.is_shown()
+        && app_state
+            .animations
+            .needs_frames(any_charging, Instant::now());
+
+    if needs_frames && !app_state.animation_timer_running {
+        let timer_id = unsafe {
+            SetTimer(
+                Some(app_state.hwnd),
+                TIMER_ID_ANIMATION,
+                ANIMATION_FRAME_MS,
+                None,
+            )
+        };
+        app_state.animation_timer_running = timer_id != 0;
+    } else if !needs_frames && app_state.animation_timer_running {
+        unsafe {
+            let _ = KillTimer(Some(app_state.hwnd), TIMER_ID_ANIMATION);
+        };
+        app_state.animation_timer_running = false;
+    }
 }
 
 fn kill_existing_timer(app_state: &mut AppState) {